@@ -0,0 +1,527 @@
+use std::{path::PathBuf, sync::Arc};
+
+#[cfg(any(feature = "elf", feature = "script"))]
+use crate::PackedExecutable;
+#[cfg(feature = "script")]
+use crate::ScriptConfig;
+use crate::{
+    diagnostics::{InvalidAutopackConfig, InvalidGlobPattern},
+    AttachedMetadata, AutopackConfig, AutopackInputs, AutopackOutput, ForeignArchPolicy,
+    OutputPermissions, OutputTimestamps, PrivilegePolicy, RepackConfig, RetryPolicy,
+    SizeOverheadLimit, SymlinkPolicy, WarningCallback, WarningKind, WarningPolicy,
+};
+#[cfg(feature = "elf")]
+use crate::{ClosurePolicy, DynamicBinaryConfig, LibraryConflicts, SharedLibraryConfig};
+
+impl AutopackConfig {
+    /// Start building a config that autopacks `inputs`, adding resources to
+    /// `resource_dir`. The config is validated when it's built, so
+    /// mistakes are caught before any files get written.
+    pub fn builder(resource_dir: PathBuf, inputs: AutopackInputs) -> AutopackConfigBuilder {
+        AutopackConfigBuilder {
+            config: AutopackConfig {
+                resource_dir,
+                all_resource_dirs: vec![],
+                inputs,
+                output: AutopackOutput::InPlace,
+                quiet: false,
+                symlink_limit: brioche_resources::DEFAULT_SYMLINK_LIMIT,
+                target: None,
+                foreign_arch: ForeignArchPolicy::default(),
+                link_dependencies: vec![],
+                #[cfg(feature = "elf")]
+                library_conflicts: LibraryConflicts::default(),
+                #[cfg(feature = "elf")]
+                glibc_hwcaps: vec![],
+                #[cfg(feature = "elf")]
+                default_libc_dependency: None,
+                #[cfg(feature = "elf")]
+                library_map: None,
+                #[cfg(feature = "elf")]
+                closure_policy: None,
+                #[cfg(feature = "elf")]
+                candidate_chooser: None,
+                #[cfg(feature = "elf")]
+                dynamic_binary: None,
+                #[cfg(feature = "elf")]
+                shared_library: None,
+                #[cfg(feature = "script")]
+                script: None,
+                repack: None,
+                symlink_policy: SymlinkPolicy::default(),
+                output_permissions: OutputPermissions::default(),
+                timestamps: OutputTimestamps::default(),
+                privileges: PrivilegePolicy::default(),
+                follow_symlinks: false,
+                max_depth: None,
+                prune_dirs: vec![],
+                size_overhead_limit: None,
+                shared_launcher: false,
+                sidecar_pack_globs: vec![],
+                attached_metadata: vec![],
+                retry: None,
+                #[cfg(any(feature = "elf", feature = "script"))]
+                resource_filter: None,
+                #[cfg(any(feature = "elf", feature = "script"))]
+                resource_namespace: None,
+                #[cfg(any(feature = "elf", feature = "script"))]
+                content_hasher: Arc::new(brioche_resources::Blake3ContentHasher::default()),
+                #[cfg(any(feature = "elf", feature = "script"))]
+                stat_cache_dir: None,
+                warnings: WarningPolicy::default(),
+                jobs: std::num::NonZeroUsize::MIN,
+            },
+        }
+    }
+}
+
+/// Builds an [`AutopackConfig`], see [`AutopackConfig::builder`].
+#[derive(Debug, Clone)]
+pub struct AutopackConfigBuilder {
+    config: AutopackConfig,
+}
+
+impl AutopackConfigBuilder {
+    pub fn all_resource_dirs(mut self, all_resource_dirs: Vec<PathBuf>) -> Self {
+        self.config.all_resource_dirs = all_resource_dirs;
+        self
+    }
+
+    pub fn output(mut self, output: AutopackOutput) -> Self {
+        self.config.output = output;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.config.quiet = quiet;
+        self
+    }
+
+    pub fn symlink_limit(mut self, symlink_limit: u32) -> Self {
+        self.config.symlink_limit = symlink_limit;
+        self
+    }
+
+    pub fn target(mut self, target: target_lexicon::Triple) -> Self {
+        self.config.target = Some(target);
+        self
+    }
+
+    pub fn foreign_arch(mut self, foreign_arch: ForeignArchPolicy) -> Self {
+        self.config.foreign_arch = foreign_arch;
+        self
+    }
+
+    pub fn link_dependencies(mut self, link_dependencies: Vec<PathBuf>) -> Self {
+        self.config.link_dependencies = link_dependencies;
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn library_conflicts(mut self, library_conflicts: LibraryConflicts) -> Self {
+        self.config.library_conflicts = library_conflicts;
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn glibc_hwcaps(mut self, glibc_hwcaps: Vec<String>) -> Self {
+        self.config.glibc_hwcaps = glibc_hwcaps;
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn default_libc_dependency(mut self, default_libc_dependency: PathBuf) -> Self {
+        self.config.default_libc_dependency = Some(default_libc_dependency);
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn library_map(mut self, library_map: std::collections::HashMap<String, PathBuf>) -> Self {
+        self.config.library_map = Some(library_map);
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn candidate_chooser(
+        mut self,
+        candidate_chooser: crate::resolve::CandidateChooser,
+    ) -> Self {
+        self.config.candidate_chooser = Some(candidate_chooser);
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn closure_policy(mut self, closure_policy: ClosurePolicy) -> Self {
+        self.config.closure_policy = Some(closure_policy);
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn dynamic_binary(mut self, dynamic_binary: DynamicBinaryConfig) -> Self {
+        self.config.dynamic_binary = Some(dynamic_binary);
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn shared_library(mut self, shared_library: SharedLibraryConfig) -> Self {
+        self.config.shared_library = Some(shared_library);
+        self
+    }
+
+    #[cfg(feature = "script")]
+    pub fn script(mut self, script: ScriptConfig) -> Self {
+        self.config.script = Some(script);
+        self
+    }
+
+    pub fn repack(mut self, repack: RepackConfig) -> Self {
+        self.config.repack = Some(repack);
+        self
+    }
+
+    pub fn privileges(mut self, privileges: PrivilegePolicy) -> Self {
+        self.config.privileges = privileges;
+        self
+    }
+
+    pub fn symlink_policy(mut self, symlink_policy: SymlinkPolicy) -> Self {
+        self.config.symlink_policy = symlink_policy;
+        self
+    }
+
+    pub fn output_permissions(mut self, output_permissions: OutputPermissions) -> Self {
+        self.config.output_permissions = output_permissions;
+        self
+    }
+
+    pub fn timestamps(mut self, timestamps: OutputTimestamps) -> Self {
+        self.config.timestamps = timestamps;
+        self
+    }
+
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.config.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.config.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn prune_dirs(mut self, prune_dirs: Vec<String>) -> Self {
+        self.config.prune_dirs = prune_dirs;
+        self
+    }
+
+    /// Attach metadata to every autopacked file.
+    pub fn attach_metadata(mut self, format: impl Into<String>, metadata: Vec<u8>) -> Self {
+        self.config.attached_metadata.push(AttachedMetadata {
+            format: format.into(),
+            metadata,
+        });
+        self
+    }
+
+    pub fn size_overhead_limit(mut self, size_overhead_limit: SizeOverheadLimit) -> Self {
+        self.config.size_overhead_limit = Some(size_overhead_limit);
+        self
+    }
+
+    pub fn shared_launcher(mut self, shared_launcher: bool) -> Self {
+        self.config.shared_launcher = shared_launcher;
+        self
+    }
+
+    pub fn sidecar_pack_globs(mut self, sidecar_pack_globs: Vec<String>) -> Self {
+        self.config.sidecar_pack_globs = sidecar_pack_globs;
+        self
+    }
+
+    /// Retry filesystem operations that fail with a transient error.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = Some(retry);
+        self
+    }
+
+    /// Check each resource before it's added, see
+    /// [`ResourceFilter`](crate::resources::ResourceFilter).
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pub fn resource_filter(mut self, resource_filter: crate::resources::ResourceFilter) -> Self {
+        self.config.resource_filter = Some(resource_filter);
+        self
+    }
+
+    /// Add new resources under a namespace within the resource dir, see
+    /// [`AutopackConfig::resource_namespace`].
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pub fn resource_namespace(mut self, resource_namespace: String) -> Self {
+        self.config.resource_namespace = Some(resource_namespace);
+        self
+    }
+
+    /// Name new resources with a different hash function, see
+    /// [`AutopackConfig::content_hasher`].
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pub fn content_hasher(
+        mut self,
+        content_hasher: Arc<dyn brioche_resources::ContentHasher>,
+    ) -> Self {
+        self.config.content_hasher = content_hasher;
+        self
+    }
+
+    /// Reuse resources added from unchanged files by earlier runs, see
+    /// [`AutopackConfig::stat_cache_dir`].
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pub fn stat_cache_dir(mut self, stat_cache_dir: PathBuf) -> Self {
+        self.config.stat_cache_dir = Some(stat_cache_dir);
+        self
+    }
+
+    pub fn warning_callback(mut self, callback: Arc<WarningCallback>) -> Self {
+        self.config.warnings.callback = Some(callback);
+        self
+    }
+
+    /// Fail autopacking on warnings of this kind.
+    pub fn deny_warning(mut self, kind: WarningKind) -> Self {
+        self.config.warnings.denied.insert(kind);
+        self
+    }
+
+    /// Autopack up to `jobs` files at once.
+    pub fn jobs(mut self, jobs: std::num::NonZeroUsize) -> Self {
+        self.config.jobs = jobs;
+        self
+    }
+
+    /// Validate and return the config. This only reads from the
+    /// filesystem, so nothing has been written if validation fails.
+    pub fn build(self) -> Result<AutopackConfig, InvalidAutopackConfig> {
+        let config = self.config;
+
+        match &config.inputs {
+            AutopackInputs::Paths(_) => {}
+            AutopackInputs::Globs {
+                base_path,
+                patterns,
+                exclude_patterns,
+            } => {
+                check_recipe_dir(base_path)?;
+                for pattern in patterns.iter().chain(exclude_patterns) {
+                    globset::Glob::new(pattern)
+                        .map_err(|error| InvalidGlobPattern::new(pattern, error))?;
+                }
+            }
+        }
+
+        if let AutopackOutput::CopyOnWrite { input_root, .. } = &config.output {
+            check_recipe_dir(input_root)?;
+        }
+
+        for pattern in config.sidecar_pack_globs.iter().chain(&config.prune_dirs) {
+            globset::Glob::new(pattern).map_err(|error| InvalidGlobPattern::new(pattern, error))?;
+        }
+
+        #[cfg(feature = "elf")]
+        if let Some(dynamic_binary) = config
+            .dynamic_binary
+            .as_ref()
+            .filter(|dynamic_binary| dynamic_binary.enabled)
+        {
+            check_packed_executable(&dynamic_binary.packed_executable, config.target.as_ref())?;
+
+            // `PT_INTERP` can't be relative to the binary, so it has to
+            // point to where the resource dir is installed
+            let runtime_resource_dir = &dynamic_binary.runtime_resource_dir;
+            if dynamic_binary.mode == crate::DynamicBinaryMode::PatchInterpreter
+                && !runtime_resource_dir
+                    .as_ref()
+                    .is_some_and(|path| path.is_absolute())
+            {
+                return Err(InvalidAutopackConfig::InvalidRuntimeResourceDir {
+                    path: runtime_resource_dir.clone(),
+                });
+            }
+        }
+
+        #[cfg(any(feature = "elf", feature = "script"))]
+        if let Some(namespace) = &config.resource_namespace {
+            check_dir_name(namespace, |name| {
+                InvalidAutopackConfig::InvalidResourceNamespace { name }
+            })?;
+        }
+
+        // The cache depends on the build machine, so it can't be written
+        // to the output
+        #[cfg(any(feature = "elf", feature = "script"))]
+        if let Some(stat_cache_dir) = &config.stat_cache_dir {
+            if stat_cache_dir.starts_with(&config.resource_dir) {
+                return Err(InvalidAutopackConfig::InvalidStatCacheDir {
+                    path: stat_cache_dir.clone(),
+                });
+            }
+        }
+
+        #[cfg(feature = "elf")]
+        for hwcap in &config.glibc_hwcaps {
+            check_dir_name(hwcap, |name| InvalidAutopackConfig::InvalidGlibcHwcap {
+                name,
+            })?;
+        }
+
+        #[cfg(feature = "elf")]
+        for dynamic_linking in config
+            .dynamic_binary
+            .iter()
+            .map(|dynamic_binary| &dynamic_binary.dynamic_linking)
+            .chain(
+                config
+                    .shared_library
+                    .iter()
+                    .map(|shared_library| &shared_library.dynamic_linking),
+            )
+        {
+            for pattern in &dynamic_linking.optional_library_globs {
+                globset::Glob::new(pattern)
+                    .map_err(|error| InvalidGlobPattern::new(pattern, error))?;
+            }
+        }
+
+        #[cfg(feature = "script")]
+        if let Some(script) = config.script.as_ref().filter(|script| script.enabled) {
+            check_packed_executable(&script.packed_executable, config.target.as_ref())?;
+            for (name, env_value) in &script.env {
+                check_env_value(name, env_value).map_err(|reason| {
+                    InvalidAutopackConfig::InvalidEnvTemplate {
+                        name: name.clone(),
+                        reason,
+                    }
+                })?;
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn check_recipe_dir(path: &std::path::Path) -> Result<(), InvalidAutopackConfig> {
+    if path.is_dir() {
+        Ok(())
+    } else {
+        Err(InvalidAutopackConfig::RecipeNotDir {
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// Check that `name` is a single directory name, returning the error from
+/// `invalid` otherwise.
+#[cfg(any(feature = "elf", feature = "script"))]
+fn check_dir_name(
+    name: &str,
+    invalid: impl FnOnce(String) -> InvalidAutopackConfig,
+) -> Result<(), InvalidAutopackConfig> {
+    let components: Vec<_> = std::path::Path::new(name).components().collect();
+    if matches!(components.as_slice(), [std::path::Component::Normal(_)]) {
+        Ok(())
+    } else {
+        Err(invalid(name.to_owned()))
+    }
+}
+
+#[cfg(any(feature = "elf", feature = "script"))]
+fn check_packed_executable(
+    packed_executable: &PackedExecutable,
+    target: Option<&target_lexicon::Triple>,
+) -> Result<(), InvalidAutopackConfig> {
+    if let PackedExecutable::Path(path) = packed_executable {
+        // Opening the file checks that it exists and is readable, but
+        // opening a directory succeeds, so check for that separately
+        let result = std::fs::File::open(path).and_then(|file| {
+            if file.metadata()?.is_dir() {
+                Err(std::io::Error::other("is a directory"))
+            } else {
+                Ok(())
+            }
+        });
+        result.map_err(|error| InvalidAutopackConfig::PackedExecutableNotReadable {
+            path: path.clone(),
+            error,
+        })?;
+    }
+
+    // Packed executables can only be checked against the target with
+    // ELF support
+    #[cfg(feature = "elf")]
+    if let Some(target) = target {
+        let mismatch = packed_executable.target_mismatch(target).map_err(|error| {
+            InvalidAutopackConfig::PackedExecutableNotReadable {
+                path: match packed_executable {
+                    PackedExecutable::Path(path) => path.clone(),
+                    PackedExecutable::Bytes(_) => PathBuf::new(),
+                },
+                error,
+            }
+        })?;
+        if let Some(mismatch) = mismatch {
+            let packed_executable = match packed_executable {
+                PackedExecutable::Path(path) => format!("{path:?}"),
+                PackedExecutable::Bytes(_) => "(embedded)".to_string(),
+            };
+            return Err(InvalidAutopackConfig::PackedExecutableTargetMismatch {
+                packed_executable,
+                mismatch,
+            });
+        }
+    }
+    #[cfg(not(feature = "elf"))]
+    let _ = target;
+
+    Ok(())
+}
+
+#[cfg(feature = "script")]
+fn check_env_value(name: &str, env_value: &runnable_core::EnvValue) -> Result<(), String> {
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err("env var names must be non-empty and can't contain '=' or NUL".to_string());
+    }
+
+    let (template, separator) = match env_value {
+        runnable_core::EnvValue::Clear | runnable_core::EnvValue::Inherit => return Ok(()),
+        runnable_core::EnvValue::Set { value } | runnable_core::EnvValue::Fallback { value } => {
+            (value, None)
+        }
+        runnable_core::EnvValue::Prepend { value, separator }
+        | runnable_core::EnvValue::Append { value, separator } => (value, Some(separator)),
+    };
+
+    if separator.is_some_and(|separator| separator.contains(&0)) {
+        return Err("separator contains a NUL byte".to_string());
+    }
+
+    for component in &template.components {
+        match component {
+            runnable_core::TemplateComponent::Literal { value } => {
+                if value.contains(&0) {
+                    return Err("literal contains a NUL byte".to_string());
+                }
+            }
+            runnable_core::TemplateComponent::RelativePath { path }
+            | runnable_core::TemplateComponent::Resource { resource: path } => {
+                use bstr::ByteSlice as _;
+
+                let path = path
+                    .to_path()
+                    .map_err(|_| format!("invalid path {}", bstr::BStr::new(path)))?;
+                if path.is_absolute() {
+                    return Err(format!("expected a relative path, got {path:?}"));
+                }
+            }
+            runnable_core::TemplateComponent::SelfPath => {}
+        }
+    }
+
+    Ok(())
+}
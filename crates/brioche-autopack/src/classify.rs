@@ -0,0 +1,124 @@
+//! Classifying files without autopacking them.
+
+use std::path::Path;
+#[cfg(feature = "elf")]
+use std::path::PathBuf;
+
+use crate::detect::SourceFile;
+
+/// How a file would be autopacked, along with the facts autopacking reads
+/// from it. This only depends on the file itself, so it doesn't check
+/// whether its dependencies can be found.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Classification {
+    /// A dynamically-linked program.
+    #[cfg(feature = "elf")]
+    DynamicBinary {
+        /// The interpreter the program requests, such as
+        /// `/lib64/ld-linux-x86-64.so.2`. Mach-O programs don't have one.
+        interpreter: Option<PathBuf>,
+        needed_libraries: Vec<String>,
+    },
+
+    /// A shared library.
+    #[cfg(feature = "elf")]
+    SharedLibrary {
+        /// The library's `DT_SONAME`, or the install name of a dylib.
+        soname: Option<String>,
+        needed_libraries: Vec<String>,
+    },
+
+    /// A statically-linked program that loads libraries with `dlopen`.
+    #[cfg(feature = "elf")]
+    StaticBinary,
+
+    /// A static-PIE program, which is a position-independent program with
+    /// an entry point but no interpreter.
+    #[cfg(feature = "elf")]
+    StaticPie,
+
+    /// A script with a shebang.
+    #[cfg(feature = "script")]
+    Script {
+        /// The command the script runs with. For `env` shebangs, this is
+        /// the command run by `env`.
+        command_name: String,
+
+        /// Args passed to the command before the script.
+        args: Vec<String>,
+    },
+
+    /// A file that already has a pack, which would be repacked.
+    Packed { pack: brioche_pack::Pack },
+
+    /// A file that wouldn't be autopacked.
+    Other,
+}
+
+/// Classify the file at `path` the same way autopacking does, without
+/// needing a config or resource dir. Files for any target are classified.
+/// Returns an error if the file can't be read, or if it's a script with an
+/// invalid shebang.
+pub fn classify(path: &Path) -> eyre::Result<Classification> {
+    let source = SourceFile::read(path)?;
+    let contents = &source.contents[..];
+
+    let pack = brioche_resources::extract_padded_pack(std::io::Cursor::new(contents));
+    if let Ok(extracted) = pack {
+        return Ok(Classification::Packed {
+            pack: extracted.pack,
+        });
+    }
+
+    #[cfg(feature = "script")]
+    if let Some(shebang) = crate::script::Shebang::parse(path, contents)? {
+        return Ok(Classification::Script {
+            command_name: shebang.command_name,
+            args: shebang.args,
+        });
+    }
+
+    #[cfg(feature = "elf")]
+    if let Some(classification) = classify_object(contents) {
+        return Ok(classification);
+    }
+
+    Ok(Classification::Other)
+}
+
+#[cfg(feature = "elf")]
+fn classify_object(contents: &[u8]) -> Option<Classification> {
+    use crate::detect::AutopackKind;
+
+    let kind = crate::elf::autopack_kind(contents, None)?;
+    let (interpreter, soname, needed_libraries) =
+        match crate::elf::parse_object(contents, None).ok()? {
+            crate::elf::Object::Elf(elf) => (
+                elf.interpreter.map(PathBuf::from),
+                elf.soname.map(|soname| soname.to_string()),
+                elf.libraries.iter().map(|lib| lib.to_string()).collect(),
+            ),
+            #[cfg(feature = "macho")]
+            crate::elf::Object::MachO(macho) => (
+                None,
+                macho.name.map(|name| name.to_string()),
+                crate::macho::needed_libraries(&macho),
+            ),
+            _ => return None,
+        };
+
+    match kind {
+        AutopackKind::DynamicBinary => Some(Classification::DynamicBinary {
+            interpreter,
+            needed_libraries,
+        }),
+        AutopackKind::SharedLibrary => Some(Classification::SharedLibrary {
+            soname,
+            needed_libraries,
+        }),
+        AutopackKind::StaticBinary => Some(Classification::StaticBinary),
+        AutopackKind::StaticPie => Some(Classification::StaticPie),
+        _ => None,
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use eyre::Context as _;
+
+/// The libraries an ELF file resolved to while autopacking, passed to the
+/// [`ClosurePolicy`] before the autopacked file gets written.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedClosure {
+    /// The path of the ELF file being autopacked.
+    pub path: PathBuf,
+    pub libraries: Vec<ResolvedLibrary>,
+
+    /// Names of libraries that weren't collected because they're cut
+    /// libraries or past the max depth, so the runtime environment is
+    /// expected to provide them.
+    pub provided: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedLibrary {
+    /// The name the library was needed by, usually its soname.
+    pub name: String,
+
+    /// The version from the library's soname, such as `3` for
+    /// `libssl.so.3`.
+    pub version: Option<String>,
+
+    /// The path the library was found at.
+    pub path: PathBuf,
+
+    /// The library's path within the resource dir.
+    pub resource_path: PathBuf,
+
+    /// The hash of the library's contents from the name of its blob, using
+    /// the content hasher the library was added with (BLAKE3 by default).
+    pub hash: String,
+
+    /// The library's `DT_SONAME`, which is the name it provides.
+    pub soname: Option<String>,
+
+    /// The library's GNU build ID, as a hex string.
+    pub build_id: Option<String>,
+
+    /// The size of the library file in bytes.
+    pub size: u64,
+
+    /// The dynamic symbols the library defines for other files to use.
+    pub symbols: Vec<String>,
+
+    /// The libraries that pulled this library into the closure, starting
+    /// with one the file being autopacked needs directly and ending with
+    /// the one that needs this library. Empty for libraries the file needs
+    /// directly, including extra libraries.
+    pub needed_by: Vec<String>,
+}
+
+impl ResolvedLibrary {
+    pub(crate) fn new(name: String, path: PathBuf, resource_path: PathBuf) -> eyre::Result<Self> {
+        // Named resources are stored as `aliases/<name>/<hash>[.x]/<name>`
+        let blob_name = resource_path
+            .parent()
+            .and_then(|blob_dir| blob_dir.file_name())
+            .and_then(|blob_name| blob_name.to_str())
+            .ok_or_else(|| eyre::eyre!("invalid library resource path {resource_path:?}"))?;
+        let hash = blob_name
+            .strip_suffix(".x")
+            .unwrap_or(blob_name)
+            .to_string();
+
+        let version = name
+            .split_once(".so.")
+            .map(|(_, version)| version.to_string());
+        let size = std::fs::metadata(&path)
+            .with_context(|| format!("failed to get metadata of library {path:?}"))?
+            .len();
+
+        Ok(Self {
+            name,
+            version,
+            path,
+            resource_path,
+            hash,
+            soname: None,
+            build_id: None,
+            size,
+            symbols: vec![],
+            needed_by: vec![],
+        })
+    }
+}
+
+/// The result of checking a [`ResolvedClosure`] against a [`ClosurePolicy`].
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "verdict", rename_all = "camelCase")]
+pub enum ClosureVerdict {
+    /// Autopack the file. Any annotations are shown alongside the
+    /// autopacked file's output.
+    Allow {
+        #[serde(default)]
+        annotations: Vec<String>,
+    },
+
+    /// Fail autopacking with the given reason.
+    Deny { reason: String },
+}
+
+pub type ClosureCallback = dyn Fn(&ResolvedClosure) -> eyre::Result<ClosureVerdict> + Send + Sync;
+
+/// Checks the resolved libraries of each ELF file before it's autopacked,
+/// which can be used to enforce a policy (such as rejecting libraries with
+/// known vulnerabilities).
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum ClosurePolicy {
+    Callback(Arc<ClosureCallback>),
+
+    /// Run a command with the closure as JSON on stdin. The command should
+    /// write a JSON verdict to stdout, like `{"verdict": "allow"}` or
+    /// `{"verdict": "deny", "reason": "..."}`.
+    Command {
+        program: PathBuf,
+        args: Vec<String>,
+    },
+}
+
+impl std::fmt::Debug for ClosurePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Callback(_) => write!(f, "Callback(<fn>)"),
+            Self::Command { program, args } => f
+                .debug_struct("Command")
+                .field("program", program)
+                .field("args", args)
+                .finish(),
+        }
+    }
+}
+
+impl ClosurePolicy {
+    pub fn check(&self, closure: &ResolvedClosure) -> eyre::Result<ClosureVerdict> {
+        match self {
+            Self::Callback(callback) => callback(closure),
+            Self::Command { program, args } => run_policy_command(program, args, closure)
+                .with_context(|| format!("failed to run closure policy command {program:?}")),
+        }
+    }
+}
+
+fn run_policy_command(
+    program: &Path,
+    args: &[String],
+    closure: &ResolvedClosure,
+) -> eyre::Result<ClosureVerdict> {
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+
+    let closure_json = serde_json::to_vec(closure)?;
+    let mut stdin = child.stdin.take().expect("stdin not piped");
+
+    // Write the closure while reading the output, so a command writing its
+    // verdict before reading all of its input can't fill the stdout pipe
+    // and block while stdin is still being written
+    let (write_result, output) = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(&closure_json));
+        let output = child.wait_with_output();
+        let write_result = writer
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        (write_result, output)
+    });
+    let output = output?;
+    eyre::ensure!(output.status.success(), "command failed: {}", output.status);
+    write_result.context("failed to write closure to command")?;
+
+    let verdict = serde_json::from_slice(&output.stdout).context("invalid verdict")?;
+    Ok(verdict)
+}
@@ -0,0 +1,1131 @@
+//! Configuration for autopacking.
+//!
+//! Each kind of file is only autopacked when its config is set, such as
+//! [`AutopackConfig::repack`]. Each kind's config also has an `enabled`
+//! flag, and disabling a kind skips it the same as leaving its config
+//! unset, while keeping the rest of the config around.
+
+#[cfg(any(feature = "elf", feature = "script"))]
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(any(feature = "elf", feature = "script"))]
+use eyre::Context as _;
+
+#[cfg(feature = "elf")]
+use crate::ClosurePolicy;
+use crate::WarningPolicy;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct AutopackConfig {
+    pub resource_dir: PathBuf,
+    pub all_resource_dirs: Vec<PathBuf>,
+    pub inputs: AutopackInputs,
+    pub output: AutopackOutput,
+    pub quiet: bool,
+    /// The maximum number of symlinks to follow when resolving a single
+    /// path, such as a dependency or a `brioche-env.d` entry.
+    pub symlink_limit: u32,
+    /// The target to autopack for. When set, files built for other targets
+    /// are handled by `foreign_arch`, and interpreters and libraries are
+    /// only used if they match the target. FreeBSD and illumos ELF files
+    /// are only autopacked when the target is set to that OS.
+    #[serde(serialize_with = "serialize_target")]
+    pub target: Option<target_lexicon::Triple>,
+    /// What to do with ELF and Mach-O files built for a different target,
+    /// such as 32-bit programs when autopacking for `x86_64`. Only used
+    /// when the target is set.
+    pub foreign_arch: ForeignArchPolicy,
+    /// Dependencies to find interpreters, libraries, and commands from.
+    /// Dependencies are searched in order, so earlier dependencies take
+    /// priority over later ones.
+    pub link_dependencies: Vec<PathBuf>,
+    /// What to do when more than one link dependency provides a library.
+    #[cfg(feature = "elf")]
+    pub library_conflicts: LibraryConflicts,
+    /// Chooses which file to use when link dependencies provide different
+    /// files for the same library or interpreter. When set, this is used
+    /// instead of `library_conflicts`.
+    #[cfg(feature = "elf")]
+    #[serde(skip)]
+    pub candidate_chooser: Option<crate::resolve::CandidateChooser>,
+    /// `glibc-hwcaps` subdirectories to search in each library dir, such
+    /// as `x86-64-v3`, in order of preference. Like glibc, each library dir
+    /// is searched for these optimized variants before the library itself.
+    /// The first variant found is bundled, so only list subdirectories the
+    /// machines running the autopacked programs support.
+    #[cfg(feature = "elf")]
+    pub glibc_hwcaps: Vec<String>,
+    /// A dependency providing libc (such as glibc), used as a fallback for
+    /// the interpreter and libraries when no link dependency provides them.
+    #[cfg(feature = "elf")]
+    pub default_libc_dependency: Option<PathBuf>,
+    /// Library files to use by name, such as `libssl.so.3`. When set, this
+    /// is the only place libraries are found from: rpaths, library paths,
+    /// and the resolver are skipped, and libraries missing from the map
+    /// aren't found. This is for callers that already know each file's
+    /// closure and want control over exactly which files get used.
+    #[cfg(feature = "elf")]
+    #[serde(serialize_with = "serialize_sorted_optional_map")]
+    pub library_map: Option<std::collections::HashMap<String, PathBuf>>,
+    /// Checked with the resolved libraries of each ELF file, which can
+    /// deny autopacking it.
+    #[cfg(feature = "elf")]
+    #[serde(skip)]
+    pub closure_policy: Option<ClosurePolicy>,
+    #[cfg(feature = "elf")]
+    pub dynamic_binary: Option<DynamicBinaryConfig>,
+    #[cfg(feature = "elf")]
+    pub shared_library: Option<SharedLibraryConfig>,
+    #[cfg(feature = "script")]
+    pub script: Option<ScriptConfig>,
+    pub repack: Option<RepackConfig>,
+    pub symlink_policy: SymlinkPolicy,
+    /// The permissions autopacked files are written with.
+    pub output_permissions: OutputPermissions,
+    /// The modification times autopacked files and their resources are
+    /// written with.
+    pub timestamps: OutputTimestamps,
+    /// What to do with files that have setuid or setgid bits or file
+    /// capabilities.
+    pub privileges: PrivilegePolicy,
+    /// Follow symlinks to files and directories when walking globs, such
+    /// as a `bin/` dir symlinked from another package. Files in symlinked
+    /// directories are autopacked through the symlink, and a file reached
+    /// through more than one directory is only autopacked once. Symlinks
+    /// that loop back to one of their parent directories are skipped.
+    pub follow_symlinks: bool,
+    /// How deep to walk into the base path when matching globs, where `1`
+    /// only walks the files directly in the base path. Unlimited by
+    /// default.
+    pub max_depth: Option<usize>,
+    /// Glob patterns for directories to skip entirely when walking globs,
+    /// such as `share/doc`, relative to the base path. Nothing within a
+    /// pruned directory is matched, even by a more specific glob.
+    pub prune_dirs: Vec<String>,
+    /// Fail if autopacking grows a small file by too much, such as a short
+    /// script that would be dwarfed by the packed executable.
+    pub size_overhead_limit: Option<SizeOverheadLimit>,
+    /// Share one copy of each packed executable per directory through hard
+    /// links, with packs written to sidecar files. See
+    /// [`SharedLauncherPackEmitter`](crate::emit::SharedLauncherPackEmitter).
+    pub shared_launcher: bool,
+    /// Glob patterns for files that should keep their original contents,
+    /// with their packs written to a sidecar file instead (see
+    /// [`brioche_resources::sidecar_pack_path`]). This is for files that
+    /// can't have data appended, such as signed binaries or scripts that
+    /// need to stay textual. Patterns are matched against paths relative to
+    /// the recipe, or full paths when autopacking explicit paths in place.
+    pub sidecar_pack_globs: Vec<String>,
+    /// Metadata attached to every autopacked file as extra metadata packs,
+    /// written before the file's own pack, such as build provenance. See
+    /// [`brioche_resources::extract_attached_packs`] for reading them.
+    pub attached_metadata: Vec<AttachedMetadata>,
+    /// Retry reading files and writing resources when they fail with a
+    /// transient error, such as a stale file handle on a network
+    /// filesystem. Each retry is reported as a warning.
+    pub retry: Option<RetryPolicy>,
+    /// Checked with each resource before it's added, which can rewrite or
+    /// deny it.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    #[serde(skip)]
+    pub resource_filter: Option<crate::resources::ResourceFilter>,
+    /// Add new resources under `namespaces/<name>` within the resource
+    /// dir, such as a hash of the recipe, so the resources from one run can
+    /// be removed together. See
+    /// [`ResourceDirSink::with_namespace`](crate::resources::ResourceDirSink::with_namespace).
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pub resource_namespace: Option<String>,
+    /// The hash function new resources are named by. Defaults to
+    /// single-threaded BLAKE3.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    #[serde(serialize_with = "serialize_content_hasher")]
+    pub content_hasher: std::sync::Arc<dyn brioche_resources::ContentHasher>,
+    /// Where to record which files resources were added from, so later
+    /// runs can reuse them without reading unchanged files again. Must be
+    /// outside of the resource dir. See
+    /// [`ResourceDirSink::with_stat_cache_dir`](crate::resources::ResourceDirSink::with_stat_cache_dir).
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pub stat_cache_dir: Option<PathBuf>,
+    pub warnings: WarningPolicy,
+    /// The number of files to autopack at once. With more than one job,
+    /// output is printed in path order once autopacking finishes.
+    pub jobs: std::num::NonZeroUsize,
+}
+
+/// Serialize the target by its triple, such as `x86_64-unknown-linux-gnu`.
+fn serialize_target<S>(
+    target: &Option<target_lexicon::Triple>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match target {
+        Some(target) => serializer.collect_str(target),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serialize the content hasher by its name, such as `blake3`.
+#[cfg(any(feature = "elf", feature = "script"))]
+fn serialize_content_hasher<S>(
+    content_hasher: &std::sync::Arc<dyn brioche_resources::ContentHasher>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(content_hasher.name())
+}
+
+/// Serialize a hash set in sorted order, so the output is stable.
+pub(crate) fn serialize_sorted_set<S, T>(
+    set: &std::collections::HashSet<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: Ord + serde::Serialize,
+{
+    serializer.collect_seq(set.iter().collect::<std::collections::BTreeSet<_>>())
+}
+
+/// Serialize a hash map in sorted order by key, so the output is stable.
+#[cfg(any(feature = "elf", feature = "script"))]
+fn serialize_sorted_map<S, K, V>(
+    map: &std::collections::HashMap<K, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    K: Ord + serde::Serialize,
+    V: serde::Serialize,
+{
+    serializer.collect_map(map.iter().collect::<std::collections::BTreeMap<_, _>>())
+}
+
+/// Serialize an optional hash map like [`serialize_sorted_map`].
+#[cfg(feature = "elf")]
+fn serialize_sorted_optional_map<S, K, V>(
+    map: &Option<std::collections::HashMap<K, V>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    K: Ord + serde::Serialize,
+    V: serde::Serialize,
+{
+    match map {
+        Some(map) => serialize_sorted_map(map, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serialize bytes as a string, replacing invalid UTF-8.
+fn serialize_lossy_string<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&String::from_utf8_lossy(bytes))
+}
+
+/// Metadata attached to autopacked files, see
+/// [`AutopackConfig::attached_metadata`].
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct AttachedMetadata {
+    /// The format of the metadata, which tools use to find their own
+    /// metadata.
+    pub format: String,
+    #[serde(serialize_with = "serialize_lossy_string")]
+    pub metadata: Vec<u8>,
+}
+
+impl AttachedMetadata {
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pub(crate) fn to_pack(&self) -> brioche_pack::Pack {
+        brioche_pack::Pack::Metadata {
+            resource_paths: vec![],
+            format: self.format.clone(),
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+/// A limit on how many bytes autopacking can add to small files. The
+/// overhead is the difference in size between the autopacked file and the
+/// original, which is usually the size of the packed executable and pack.
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct SizeOverheadLimit {
+    /// Only files up to this size (before autopacking) are checked.
+    pub max_file_size: u64,
+
+    /// The most bytes autopacking can add to a checked file.
+    pub max_overhead: u64,
+}
+
+impl SizeOverheadLimit {
+    pub fn new(max_file_size: u64, max_overhead: u64) -> Self {
+        Self {
+            max_file_size,
+            max_overhead,
+        }
+    }
+}
+
+/// How to retry filesystem operations that fail with a transient error,
+/// see [`AutopackConfig::retry`]. The backoff doubles after each retry, up
+/// to `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct RetryPolicy {
+    /// The most times to retry a single operation.
+    pub max_retries: u32,
+
+    /// How long to wait before the first retry.
+    pub initial_backoff: std::time::Duration,
+
+    /// The longest to wait between retries.
+    pub max_backoff: std::time::Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: std::time::Duration::from_millis(100),
+            max_backoff: std::time::Duration::from_secs(10),
+        }
+    }
+}
+
+/// How to handle a library found in more than one link dependency, such
+/// as two dependencies that each bundle their own `libz.so.1`.
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum LibraryConflicts {
+    /// Use the library from the first link dependency that provides it.
+    #[default]
+    FirstMatch,
+
+    /// Fail if link dependencies provide different files for the same
+    /// library. Identical copies of a library aren't a conflict.
+    Error,
+}
+
+/// How input paths that are symlinks get autopacked, such as a script in
+/// `bin/` that links to `libexec/`. This only applies when autopacking in
+/// place, since outputs written to a separate output root are never
+/// written through a symlink.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum SymlinkPolicy {
+    /// Autopack the symlink's target, so every symlink to it stays
+    /// consistent. A target shared by multiple symlinks is only autopacked
+    /// once, and each symlink is reported.
+    #[default]
+    AutopackTarget,
+
+    /// Replace the symlink with an autopacked copy of its target, leaving
+    /// the target and any other symlinks to it unchanged.
+    ReplaceLink,
+}
+
+/// What to do with files built for a different target than the one being
+/// autopacked for. Their class (32-bit or 64-bit), byte order, and machine
+/// are checked against the target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ForeignArchPolicy {
+    /// Leave the file as-is, reporting it as skipped.
+    #[default]
+    Skip,
+
+    /// Autopack the file anyway. Its interpreter and libraries need to
+    /// match the file's own architecture instead of the target.
+    Autopack,
+
+    /// Fail instead of autopacking a file built for another target.
+    Error,
+}
+
+/// What to do with the privileges of files being autopacked, which are
+/// their setuid and setgid bits and their file capabilities (the
+/// `security.capability` xattr).
+///
+/// Privileges of an autopacked program apply to its packed executable,
+/// which then runs the program without the loader's secure mode, so
+/// preserving them can let whoever runs the program use them for
+/// something else.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum PrivilegePolicy {
+    /// Keep the privileges on the autopacked file. Setting file
+    /// capabilities needs `CAP_SETFCAP`, and they can't be included in a
+    /// plan.
+    Preserve,
+
+    /// Remove the privileges from the autopacked file, reporting a
+    /// warning.
+    #[default]
+    Strip,
+
+    /// Fail instead of autopacking a file with privileges.
+    Error,
+}
+
+/// The permissions autopacked files get, which are reported as each
+/// file's mode. Unless the mode is fixed, autopacked programs are also
+/// made executable by everyone who can read them, since they get run
+/// through the packed executable. Autopacked programs that share a packed
+/// executable through hard links (see [`AutopackConfig::shared_launcher`])
+/// also share their permissions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum OutputPermissions {
+    /// Keep the permissions of the file being autopacked.
+    #[default]
+    Source,
+
+    /// Set a fixed mode, such as `0o755`.
+    Fixed(u32),
+
+    /// Keep the permissions of the file being autopacked, clearing the
+    /// bits set in the mask, like a umask such as `0o022`.
+    Masked(u32),
+}
+
+impl OutputPermissions {
+    /// Get the mode for an autopacked file, given the mode of the file
+    /// being autopacked.
+    pub fn mode(&self, source_mode: u32) -> u32 {
+        match self {
+            Self::Source => source_mode,
+            Self::Fixed(mode) => *mode,
+            Self::Masked(mask) => source_mode & !mask,
+        }
+    }
+
+    /// Get the mode for an autopacked program, which is made executable
+    /// wherever it's readable (and always by its owner).
+    pub fn program_mode(&self, source_mode: u32) -> u32 {
+        let executable_mode = source_mode | ((source_mode & 0o444) >> 2) | 0o100;
+        self.mode(executable_mode)
+    }
+}
+
+/// The modification times autopacked files and the resources they add
+/// get, for packaging formats that compare timestamps or builds that need
+/// to be reproducible. Resources are shared between files by their
+/// contents, so a resource gets the time of whichever file added it last.
+/// Timestamps aren't recorded when planning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum OutputTimestamps {
+    /// Leave the time files were written at.
+    #[default]
+    Current,
+
+    /// Keep the modification time of the file being autopacked, or of the
+    /// file a resource was added from.
+    Source,
+
+    /// Set a fixed time, such as one from `$SOURCE_DATE_EPOCH`.
+    Fixed(std::time::SystemTime),
+}
+
+impl OutputTimestamps {
+    /// Get the time to set for a file, given the modification time of the
+    /// file it came from. Returns `None` if the time shouldn't be changed.
+    pub fn time(&self, source_time: std::time::SystemTime) -> Option<std::time::SystemTime> {
+        match self {
+            Self::Current => None,
+            Self::Source => Some(source_time),
+            Self::Fixed(time) => Some(*time),
+        }
+    }
+}
+
+/// The files to autopack.
+///
+/// Inputs are always processed in a stable order: paths are sorted by their
+/// components, compared byte-wise. This order doesn't depend on the locale,
+/// the platform, or the order the filesystem returns directory entries in,
+/// so output logs and any order-dependent results are reproducible.
+///
+/// Symlinks aren't followed when walking globs unless
+/// [`AutopackConfig::follow_symlinks`] is set.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AutopackInputs {
+    Paths(Vec<PathBuf>),
+    Globs {
+        base_path: PathBuf,
+
+        /// Files matching any of these patterns are autopacked. A pattern
+        /// that doesn't match any files is reported with an
+        /// [`UnmatchedGlobPattern`](crate::AutopackWarning::UnmatchedGlobPattern)
+        /// warning.
+        patterns: Vec<String>,
+
+        /// Files matching any of these patterns are skipped, even if they
+        /// match `patterns`, such as `bin/*.debug` with `bin/**`. Patterns
+        /// are relative to the base path, like `patterns`.
+        exclude_patterns: Vec<String>,
+    },
+}
+
+/// Where autopacked files get written.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AutopackOutput {
+    /// Replace each input file in place.
+    #[default]
+    InPlace,
+
+    /// Never modify any inputs, e.g. because they live on a read-only
+    /// mount. Each autopacked file is instead written under `output_root`,
+    /// at the same path it has relative to `input_root`. Files that don't
+    /// get autopacked aren't copied, so `output_root` is meant to be
+    /// layered over `input_root`.
+    CopyOnWrite {
+        input_root: PathBuf,
+        output_root: PathBuf,
+    },
+}
+
+/// How libraries are found for dynamic binaries and shared libraries. The
+/// default config only searches the link dependencies.
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct DynamicLinkingConfig {
+    /// Extra directories to search for libraries, after the file's own
+    /// rpath and before the link dependencies. This is for libraries that
+    /// aren't in a link dependency's `brioche-env.d/env/LIBRARY_PATH`, such
+    /// as a vendored `lib/` dir within the recipe.
+    pub library_paths: Vec<PathBuf>,
+    #[serde(serialize_with = "serialize_sorted_set")]
+    pub skip_libraries: std::collections::HashSet<String>,
+    pub extra_libraries: Vec<String>,
+    /// Like `extra_libraries`, but libraries that can't be found are
+    /// skipped instead of failing. Use these for optional runtime
+    /// components, so the same config works with or without them.
+    pub optional_libraries: Vec<String>,
+    /// Glob patterns for needed libraries that are skipped if they can't
+    /// be found, such as `libnvidia-*.so*`. Unlike `skip_unknown_libraries`,
+    /// other libraries that can't be found still fail, and unlike
+    /// `optional_libraries`, matching libraries are only included when
+    /// something needs them.
+    pub optional_library_globs: Vec<String>,
+    pub skip_unknown_libraries: bool,
+    pub rpath_tokens: RpathTokens,
+    /// Libraries provided by the host at runtime, such as GPU drivers.
+    /// These are left out of the closure entirely.
+    #[serde(serialize_with = "serialize_sorted_set")]
+    pub host_libraries: std::collections::HashSet<String>,
+    /// Absolute paths where host libraries can be found at runtime. These
+    /// are only used by dynamic binaries. Launchers may search these before
+    /// the closure's own libraries, so they should only contain
+    /// host-provided libraries.
+    pub host_library_paths: Vec<PathBuf>,
+    /// Libraries where collecting the closure stops, because the runtime
+    /// environment provides them along with their own dependencies, such
+    /// as libc. Unlike host libraries, these are still listed in the
+    /// closure passed to the closure policy, as provided libraries.
+    #[serde(serialize_with = "serialize_sorted_set")]
+    pub cut_libraries: std::collections::HashSet<String>,
+    /// How many levels of libraries to collect, where `1` only collects
+    /// the libraries a file needs directly. Libraries past this depth are
+    /// treated like cut libraries.
+    pub max_depth: Option<std::num::NonZeroU32>,
+    /// The most libraries a file's closure can have. Autopacking fails with
+    /// the chain that pulled in each library if there are more, to catch
+    /// accidental dependency explosions.
+    pub max_closure_libraries: Option<usize>,
+    /// The most bytes a file's closure can have in total, checked like
+    /// `max_closure_libraries`.
+    pub max_closure_bytes: Option<u64>,
+    /// The libraries the build linked against, such as from a linker map
+    /// file. A resolved library gets a warning if one of these has the
+    /// same name but a different GNU build ID, since the program may not
+    /// work with a different build of the library.
+    pub build_libraries: Vec<PathBuf>,
+    /// `DT_NEEDED` entries to remove from ELF files, for libraries they
+    /// were linked against but don't use. Unlike `skip_libraries`, the file
+    /// no longer needs the library at runtime.
+    #[serde(serialize_with = "serialize_sorted_set")]
+    pub drop_libraries: std::collections::HashSet<String>,
+    /// `DT_NEEDED` entries to rename in ELF files, such as `libssl.so.1.1`
+    /// to `libssl.so.3` when a dependency provides a compatible library
+    /// under a new name. The new names are written over the old ones, so
+    /// they can't be longer.
+    #[serde(serialize_with = "serialize_sorted_map")]
+    pub rename_libraries: std::collections::HashMap<String, String>,
+    /// Libraries that specific files load with `dlopen`, so they aren't
+    /// listed as `DT_NEEDED` entries. Each key is the path of a file,
+    /// either as it's autopacked or relative to the recipe. The libraries
+    /// are collected like `extra_libraries`, but only for that file. Each
+    /// library is either a name to search for, or a path to a library
+    /// file to use as-is if it contains a `/`.
+    #[serde(serialize_with = "serialize_sorted_map")]
+    pub dlopen_libraries: std::collections::HashMap<PathBuf, Vec<String>>,
+    /// After collecting the closure, check that every undefined dynamic
+    /// symbol the file uses (other than weak symbols) is defined by some
+    /// library in the closure. Missing symbols are reported with a
+    /// [`MissingSymbols`](crate::AutopackWarning::MissingSymbols) warning,
+    /// which can be denied to fail instead. Files with libraries provided
+    /// at runtime, such as host or cut libraries, aren't checked.
+    pub check_symbols: bool,
+}
+
+#[cfg(feature = "elf")]
+impl DynamicLinkingConfig {
+    /// Treat GPU driver libraries as host-provided. Drivers need to match
+    /// the host's kernel module, so they can't be included as resources.
+    pub fn add_gpu_drivers(&mut self) {
+        self.host_libraries.extend(
+            GPU_DRIVER_LIBRARIES
+                .iter()
+                .map(|library| library.to_string()),
+        );
+        self.host_library_paths
+            .extend(GPU_DRIVER_LIBRARY_PATHS.iter().map(PathBuf::from));
+    }
+}
+
+/// Vendor GPU driver libraries, which are loaded from the host.
+#[cfg(feature = "elf")]
+pub const GPU_DRIVER_LIBRARIES: &[&str] = &[
+    "libcuda.so.1",
+    "libEGL_mesa.so.0",
+    "libEGL_nvidia.so.0",
+    "libEGL.so.1",
+    "libGL.so.1",
+    "libGLESv1_CM.so.1",
+    "libGLESv2.so.2",
+    "libGLX_mesa.so.0",
+    "libGLX_nvidia.so.0",
+    "libGLX.so.0",
+    "libGLdispatch.so.0",
+    "libnvcuvid.so.1",
+    "libnvidia-encode.so.1",
+    "libnvidia-ml.so.1",
+    "libnvidia-opticalflow.so.1",
+    "libOpenGL.so.0",
+];
+
+/// Well-known host directories that only contain GPU drivers, so they can
+/// be searched without other host libraries taking priority over the
+/// closure's libraries.
+#[cfg(feature = "elf")]
+pub const GPU_DRIVER_LIBRARY_PATHS: &[&str] = &["/run/opengl-driver/lib", "/usr/lib/wsl/lib"];
+
+/// Values substituted for the `$LIB` and `$PLATFORM` dynamic string tokens
+/// when expanding `DT_RPATH` / `DT_RUNPATH` entries during library
+/// resolution. These vary by target (e.g. `lib` vs `lib64`), so they
+/// should match the values the target's dynamic linker would use.
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct RpathTokens {
+    pub lib: String,
+    /// The value for `$PLATFORM`. Defaults to the architecture of the
+    /// target, or of the host if no target is set.
+    pub platform: Option<String>,
+}
+
+#[cfg(feature = "elf")]
+impl Default for RpathTokens {
+    fn default() -> Self {
+        Self {
+            lib: "lib".to_string(),
+            platform: None,
+        }
+    }
+}
+
+#[cfg(feature = "elf")]
+impl RpathTokens {
+    /// Get the value for `$PLATFORM` when autopacking for `target`.
+    pub fn platform(&self, target: Option<&target_lexicon::Triple>) -> String {
+        match (&self.platform, target) {
+            (Some(platform), _) => platform.clone(),
+            (None, Some(target)) => target.architecture.to_string(),
+            (None, None) => std::env::consts::ARCH.to_string(),
+        }
+    }
+}
+
+/// The launcher that autopacked programs are written with, which gets the
+/// pack injected into it.
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum PackedExecutable {
+    /// Read the packed executable from a file when autopacking.
+    Path(PathBuf),
+
+    /// Use the given contents as the packed executable.
+    Bytes(std::borrow::Cow<'static, [u8]>),
+}
+
+impl PackedExecutable {
+    /// The packed executable embedded into this crate at build time. The
+    /// file to embed is set with the `BRIOCHE_AUTOPACK_PACKED_EXECUTABLE`
+    /// env var while building.
+    #[cfg(feature = "embedded-packed-executable")]
+    pub fn embedded() -> Self {
+        Self::Bytes(std::borrow::Cow::Borrowed(include_bytes!(env!(
+            "BRIOCHE_AUTOPACK_PACKED_EXECUTABLE"
+        ))))
+    }
+
+    /// Start writing a new file at `output_path` containing the packed
+    /// executable, which replaces any existing file once it's committed.
+    /// Existing files are replaced rather than written through, since they
+    /// could be a hard link to a shared packed executable. The existing
+    /// file's permissions are kept.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pub(crate) fn create(
+        &self,
+        output_path: &Path,
+    ) -> eyre::Result<brioche_resources::AtomicWriter> {
+        use std::io::Write as _;
+
+        let mut output = crate::emit::create_output(output_path)?;
+        match self {
+            Self::Path(packed_exec_path) => {
+                let mut packed_exec = std::fs::File::open(packed_exec_path).with_context(|| {
+                    format!("failed to open packed executable {packed_exec_path:?}")
+                })?;
+                std::io::copy(&mut packed_exec, &mut output).with_context(|| {
+                    format!("failed to copy packed executable to {output_path:?}")
+                })?;
+            }
+            Self::Bytes(contents) => {
+                output.write_all(contents).with_context(|| {
+                    format!("failed to write packed executable to {output_path:?}")
+                })?;
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Check whether the packed executable was built for `target`,
+    /// returning why it wasn't if not. Packed executables that aren't ELF
+    /// or Mach-O files are assumed to match.
+    #[cfg(feature = "elf")]
+    pub fn target_mismatch(
+        &self,
+        target: &target_lexicon::Triple,
+    ) -> std::io::Result<Option<String>> {
+        let contents = match self {
+            Self::Path(path) => std::borrow::Cow::Owned(std::fs::read(path)?),
+            Self::Bytes(contents) => std::borrow::Cow::Borrowed(&**contents),
+        };
+
+        let mismatch = match crate::elf::parse_object(&contents, Some(target)) {
+            Ok(crate::elf::Object::Elf(elf)) => crate::elf::target_mismatch(&elf, target),
+            #[cfg(feature = "macho")]
+            Ok(crate::elf::Object::MachO(macho)) => (!crate::macho::matches_target(&macho, target))
+                .then(|| format!("Mach-O binary does not match target {target}")),
+            _ => None,
+        };
+        Ok(mismatch)
+    }
+}
+
+impl std::fmt::Debug for PackedExecutable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            Self::Bytes(contents) => write!(f, "Bytes(<{} bytes>)", contents.len()),
+        }
+    }
+}
+
+/// Like with [`Debug`], only the length of the contents gets serialized.
+impl serde::Serialize for PackedExecutable {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Path(path) => {
+                serializer.serialize_newtype_variant("PackedExecutable", 0, "path", path)
+            }
+            Self::Bytes(contents) => serializer.serialize_newtype_variant(
+                "PackedExecutable",
+                1,
+                "bytes",
+                &format!("<{} bytes>", contents.len()),
+            ),
+        }
+    }
+}
+
+impl From<PathBuf> for PackedExecutable {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct DynamicBinaryConfig {
+    /// Whether to autopack dynamic binaries.
+    pub enabled: bool,
+    pub packed_executable: PackedExecutable,
+    /// How dynamic binaries get autopacked. Only Linux ELF binaries support
+    /// modes other than the default.
+    pub mode: DynamicBinaryMode,
+    /// Library paths to search at runtime, for libraries the program loads
+    /// with `dlopen` rather than needing them by name, such as a plugin
+    /// dir within the recipe. These are recorded relative to the
+    /// autopacked binary, as the `LdLinux` pack's runtime library dirs (or
+    /// in `$LD_LIBRARY_PATH` or the runpath, depending on how the binary is
+    /// autopacked). They're optional, so they're only searched if present.
+    pub extra_runtime_library_paths: Vec<PathBuf>,
+    pub dynamic_linking: DynamicLinkingConfig,
+    pub android: AndroidConfig,
+    /// Interpreters to use in place of the one a program requests, such as
+    /// `/lib/ld-musl-x86_64.so.1` to `/lib64/ld-linux-x86-64.so.2`, for
+    /// programs whose libc flavor isn't provided by the link dependencies.
+    /// The requested interpreter is used if its replacement can't be found.
+    /// Needed libraries are still found by name.
+    #[serde(serialize_with = "serialize_sorted_map")]
+    pub interpreter_remaps: std::collections::HashMap<PathBuf, PathBuf>,
+    /// Run the program with the packed executable's absolute path as
+    /// `argv[0]`, for programs (such as daemons) that re-execute themselves
+    /// using `argv[0]`. This uses a runnable pack instead of an `LdLinux`
+    /// pack, so the packed executable needs to support runnable packs.
+    pub self_exec: bool,
+    /// Keep an unwrapped copy of each autopacked binary, which the pack
+    /// runs instead of a copy in the resource dir. Like `self_exec`, this
+    /// uses a runnable pack.
+    pub unwrapped_copy: Option<UnwrappedCopy>,
+    /// Autopack statically-linked programs that load libraries with
+    /// `dlopen`, which would otherwise fail to find them once relocated.
+    /// These get a runnable pack that sets `$LD_LIBRARY_PATH` to the extra
+    /// libraries' dirs and the extra runtime library paths, which both
+    /// glibc and musl search from `dlopen`. Without this, these programs
+    /// are skipped with a warning.
+    pub static_dlopen: bool,
+    /// Autopack static-PIE programs with this kind of pack, whether or not
+    /// they use `dlopen`. Static-PIE programs are position-independent
+    /// programs with an entry point but no interpreter, which look like
+    /// shared libraries to most tools. Without this, they're handled like
+    /// other static programs (see `static_dlopen`).
+    pub static_pie: Option<StaticPiePack>,
+    /// Record where to find the original program of each autopacked
+    /// binary in the resource dir, so debuggers and profilers can find its
+    /// symbols. See [`find_debug_link`](crate::find_debug_link).
+    pub debug_links: bool,
+    /// The absolute path the resource dir is installed at when autopacked
+    /// binaries run, such as `/opt/foo/brioche-resources.d`. This is
+    /// required by [`DynamicBinaryMode::PatchInterpreter`], which writes
+    /// the interpreter's path within this dir, rather than the resource
+    /// dir's path on the build machine.
+    pub runtime_resource_dir: Option<PathBuf>,
+}
+
+#[cfg(feature = "elf")]
+impl DynamicBinaryConfig {
+    /// Autopack dynamic binaries with `packed_executable` as the launcher,
+    /// using the default options for everything else.
+    pub fn new(packed_executable: impl Into<PackedExecutable>) -> Self {
+        Self {
+            enabled: true,
+            packed_executable: packed_executable.into(),
+            mode: DynamicBinaryMode::default(),
+            extra_runtime_library_paths: vec![],
+            dynamic_linking: DynamicLinkingConfig::default(),
+            android: AndroidConfig::default(),
+            interpreter_remaps: std::collections::HashMap::new(),
+            self_exec: false,
+            unwrapped_copy: None,
+            static_dlopen: false,
+            static_pie: None,
+            debug_links: false,
+            runtime_resource_dir: None,
+        }
+    }
+}
+
+/// How dynamic binaries get autopacked, see [`DynamicBinaryConfig::mode`].
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum DynamicBinaryMode {
+    /// Replace the binary with the packed executable, which runs the
+    /// original program from the resource dir.
+    #[default]
+    Launcher,
+
+    /// Keep the original binary, rewriting its `DT_RUNPATH` (or `DT_RPATH`)
+    /// to point to its library dirs in the resource dir, and add a `Static`
+    /// pack recording them. The binary stays a plain ELF file that
+    /// debuggers and other tools can inspect, but its interpreter isn't
+    /// changed. The new runpath is written over the existing one, so the
+    /// binary needs to be linked with a runpath long enough to hold it,
+    /// such as a placeholder padded with `/` characters. `self_exec`,
+    /// `unwrapped_copy`, and `debug_links` are ignored in this mode.
+    PatchRunpath,
+
+    /// Like [`PatchRunpath`](Self::PatchRunpath), also rewriting the
+    /// binary's interpreter (`PT_INTERP`) to the interpreter from its link
+    /// dependencies, added as a resource. The interpreter can't be relative
+    /// to the binary, so this mode needs
+    /// [`runtime_resource_dir`](DynamicBinaryConfig::runtime_resource_dir)
+    /// to know where the resource dir will be when the binary runs. Like the
+    /// runpath, the new path is written over the existing one, so the
+    /// binary needs to be linked with a long enough placeholder, such as
+    /// with `-Wl,--dynamic-linker`.
+    PatchInterpreter,
+}
+
+/// Where to keep the unwrapped copy of an autopacked dynamic binary, for
+/// tools that need to run the original binary rather than the packed
+/// executable. The copy isn't autopacked, but will be if it's matched by
+/// the inputs when autopacking again.
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum UnwrappedCopy {
+    /// Next to the autopacked binary, with a suffix added to its name,
+    /// such as `foo.unwrapped`.
+    Suffix(String),
+
+    /// In a directory relative to the autopacked binary's directory with
+    /// the same name, such as `../libexec/real/foo` for `bin/foo`.
+    Dir(PathBuf),
+}
+
+/// The kind of pack to autopack static-PIE programs with.
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum StaticPiePack {
+    /// Wrap the program in a runnable pack that sets `$LD_LIBRARY_PATH`,
+    /// the same way as static programs autopacked with `static_dlopen`.
+    Metadata,
+
+    /// Add a `Static` pack with the library dirs to the program in place,
+    /// like shared libraries get. The program is still run directly, so
+    /// this is for programs that find their libraries from their own pack.
+    Static,
+}
+
+/// Config for autopacking ELF files linked against Android's bionic libc.
+/// Bionic's linker is part of the OS and doesn't accept glibc-style
+/// `--library-path` arguments, so dynamic binaries are packed as runnables
+/// that run the program directly with `$LD_LIBRARY_PATH` set. This
+/// requires a packed executable that supports runnable metadata.
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct AndroidConfig {
+    /// Libraries provided by Android itself. These are loaded from the
+    /// device at runtime, so they never get added as resources.
+    #[serde(serialize_with = "serialize_sorted_set")]
+    pub system_libraries: std::collections::HashSet<String>,
+}
+
+#[cfg(feature = "elf")]
+impl Default for AndroidConfig {
+    fn default() -> Self {
+        Self {
+            system_libraries: ANDROID_SYSTEM_LIBRARIES
+                .iter()
+                .map(|library| library.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// The libraries from the NDK's stable system APIs.
+#[cfg(feature = "elf")]
+pub const ANDROID_SYSTEM_LIBRARIES: &[&str] = &[
+    "libaaudio.so",
+    "libamidi.so",
+    "libandroid.so",
+    "libbinder_ndk.so",
+    "libc.so",
+    "libcamera2ndk.so",
+    "libdl.so",
+    "libEGL.so",
+    "libGLESv1_CM.so",
+    "libGLESv2.so",
+    "libGLESv3.so",
+    "libjnigraphics.so",
+    "liblog.so",
+    "libm.so",
+    "libmediandk.so",
+    "libnativewindow.so",
+    "libneuralnetworks.so",
+    "libOpenMAXAL.so",
+    "libOpenSLES.so",
+    "libstdc++.so",
+    "libsync.so",
+    "libvulkan.so",
+    "libz.so",
+];
+
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct SharedLibraryConfig {
+    /// Whether to autopack shared libraries.
+    pub enabled: bool,
+    pub dynamic_linking: DynamicLinkingConfig,
+    pub allow_empty: bool,
+    /// Strip directory components from the library's `DT_SONAME`, so files
+    /// linked against it need it by filename.
+    pub normalize_soname: bool,
+    pub android: AndroidConfig,
+}
+
+#[cfg(feature = "elf")]
+impl Default for SharedLibraryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dynamic_linking: DynamicLinkingConfig::default(),
+            allow_empty: false,
+            normalize_soname: false,
+            android: AndroidConfig::default(),
+        }
+    }
+}
+
+#[cfg(feature = "script")]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct ScriptConfig {
+    /// Whether to autopack scripts.
+    pub enabled: bool,
+    pub packed_executable: PackedExecutable,
+    pub base_path: Option<PathBuf>,
+    #[serde(serialize_with = "serialize_sorted_map")]
+    pub env: std::collections::HashMap<String, runnable_core::EnvValue>,
+    pub clear_env: bool,
+    pub forward_args: ForwardArgs,
+
+    /// Commands to try, in order, when a script's shebang command isn't
+    /// found, keyed by the shebang command's name. For example, mapping
+    /// `sh` to `["dash", "bash"]` lets `#!/bin/sh` scripts run with `dash`
+    /// or `bash` from the link dependencies.
+    #[serde(serialize_with = "serialize_sorted_map")]
+    pub interpreter_fallbacks: std::collections::HashMap<String, Vec<String>>,
+
+    /// The shell to run `sh` scripts with (such as `#!/bin/sh`), instead
+    /// of whichever `sh` the link dependencies provide.
+    pub posix_shell: Option<PosixShell>,
+}
+
+#[cfg(feature = "script")]
+impl ScriptConfig {
+    /// Autopack scripts with `packed_executable` as the launcher, using the
+    /// default options for everything else.
+    pub fn new(packed_executable: impl Into<PackedExecutable>) -> Self {
+        Self {
+            enabled: true,
+            packed_executable: packed_executable.into(),
+            base_path: None,
+            env: Default::default(),
+            clear_env: false,
+            forward_args: ForwardArgs::default(),
+            interpreter_fallbacks: Default::default(),
+            posix_shell: None,
+        }
+    }
+}
+
+/// A shell for running POSIX `sh` scripts, see
+/// [`ScriptConfig::posix_shell`].
+#[cfg(feature = "script")]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct PosixShell {
+    /// The name of the shell's command, which is found from the link
+    /// dependencies like a shebang command.
+    pub command: String,
+
+    /// Args that put the shell in POSIX mode, such as `--posix` for
+    /// `bash`. These are passed before any args from the shebang.
+    pub args: Vec<String>,
+}
+
+#[cfg(feature = "script")]
+impl PosixShell {
+    pub fn new(command: String, args: Vec<String>) -> Self {
+        Self { command, args }
+    }
+}
+
+/// How the args an autopacked script is run with get passed to its
+/// command. Args are passed to the command as-is, so they're never split,
+/// quoted, or escaped.
+#[cfg(feature = "script")]
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ForwardArgs {
+    /// Pass args after the script path.
+    #[default]
+    Rest,
+
+    /// Pass args after a separator such as `--`, for commands that would
+    /// otherwise parse options meant for the script. The separator is only
+    /// added if there are any args.
+    RestAfterSeparator(String),
+
+    /// Don't pass any args.
+    None,
+}
+
+/// Config for files that already have a pack. The pack is re-derived from
+/// the file it was created from (the program resource of a dynamic binary,
+/// or the source of a runnable), so the new pack uses the current link
+/// dependencies, interpreter, and packed executable.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct RepackConfig {
+    /// Whether to repack already packed files.
+    pub enabled: bool,
+}
+
+impl Default for RepackConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
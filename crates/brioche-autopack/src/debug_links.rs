@@ -0,0 +1,128 @@
+//! Mapping autopacked dynamic binaries back to their original programs.
+//!
+//! Debuggers and profilers see the packed executable when looking at an
+//! autopacked process, so they can't find the program's symbols on their
+//! own. With [`DynamicBinaryConfig::debug_links`](crate::DynamicBinaryConfig::debug_links),
+//! each autopacked binary is recorded in `debug-links.json` in the resource
+//! dir, keyed by its path relative to the resource dir's parent (usually
+//! the recipe). Helpers can then look up the original program with
+//! [`find_debug_link`].
+
+use std::{
+    collections::BTreeMap,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use eyre::{Context as _, OptionExt as _};
+
+/// The name of the debug link file within a resource dir.
+pub const DEBUG_LINKS_FILE: &str = "debug-links.json";
+
+/// Where to find the original program of an autopacked dynamic binary.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugLink {
+    /// The original program's path within the resource dir.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<PathBuf>,
+
+    /// The path of the program's unwrapped copy, relative to the
+    /// autopacked binary's directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unwrapped_copy: Option<PathBuf>,
+
+    /// The program's GNU build ID, as a hex string. The program's resource
+    /// is also indexed by it, see [`brioche_resources::find_by_build_id`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+}
+
+/// Read the debug links recorded in a resource dir. Returns an empty map
+/// if there aren't any.
+pub fn read_debug_links(resource_dir: &Path) -> eyre::Result<BTreeMap<PathBuf, DebugLink>> {
+    let path = resource_dir.join(DEBUG_LINKS_FILE);
+    let contents = match std::fs::read(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(BTreeMap::new());
+        }
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", path.display()));
+        }
+    };
+
+    let debug_links = serde_json::from_slice(&contents)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(debug_links)
+}
+
+/// Find the debug link for an autopacked dynamic binary, from the resource
+/// dir it was autopacked into.
+pub fn find_debug_link(path: &Path) -> eyre::Result<Option<DebugLink>> {
+    let resource_dirs = brioche_resources::find_resource_dirs(path, true)?;
+    for resource_dir in resource_dirs {
+        let Some(key) = debug_link_key(&resource_dir, path) else {
+            continue;
+        };
+        let mut debug_links = read_debug_links(&resource_dir)?;
+        if let Some(debug_link) = debug_links.remove(&key) {
+            return Ok(Some(debug_link));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Get the key for an autopacked binary in a resource dir's debug links.
+fn debug_link_key(resource_dir: &Path, path: &Path) -> Option<PathBuf> {
+    let root = resource_dir.parent()?;
+    let path = std::path::absolute(path).ok()?;
+    let root = std::path::absolute(root).ok()?;
+    pathdiff::diff_paths(path, root)
+}
+
+/// Debug links recorded while autopacking, by output path.
+#[derive(Debug, Default)]
+pub(crate) struct DebugLinks(Mutex<BTreeMap<PathBuf, DebugLink>>);
+
+impl DebugLinks {
+    pub fn insert(&self, output_path: &Path, debug_link: DebugLink) {
+        let mut debug_links = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        debug_links.insert(output_path.to_owned(), debug_link);
+    }
+
+    /// Take the recorded debug links, by output path.
+    pub fn take(&self) -> BTreeMap<PathBuf, DebugLink> {
+        let mut debug_links = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        std::mem::take(&mut *debug_links)
+    }
+
+    /// Add the recorded debug links to the resource dir's debug link file,
+    /// keeping links already recorded for other binaries.
+    pub fn write(&self, resource_dir: &Path) -> eyre::Result<()> {
+        let new_debug_links = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        if new_debug_links.is_empty() {
+            return Ok(());
+        }
+
+        let mut debug_links = read_debug_links(resource_dir)?;
+        for (output_path, debug_link) in &*new_debug_links {
+            let key = debug_link_key(resource_dir, output_path)
+                .ok_or_eyre("failed to get debug link path")?;
+            debug_links.insert(key, debug_link.clone());
+        }
+
+        let path = resource_dir.join(DEBUG_LINKS_FILE);
+        let contents = serde_json::to_vec_pretty(&debug_links)?;
+        let mut writer = brioche_resources::AtomicWriter::new(&path, 0o666)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        writer
+            .write_all(&contents)
+            .and_then(|()| writer.commit())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! Detecting how a file should be autopacked.
+
+use std::{
+    io::Read as _,
+    path::{Path, PathBuf},
+};
+
+use eyre::Context as _;
+
+use crate::AutopackContext;
+
+/// A file being autopacked. The file is read once through a single open
+/// handle, and the same contents are used both to decide how to autopack
+/// it and to autopack it, so the file being replaced between those steps
+/// can't lead to mismatched results.
+pub(crate) struct SourceFile {
+    pub(crate) path: PathBuf,
+    pub(crate) contents: Vec<u8>,
+    pub(crate) is_executable: bool,
+}
+
+impl SourceFile {
+    pub(crate) fn read(path: &Path) -> eyre::Result<Self> {
+        use std::os::unix::prelude::PermissionsExt as _;
+
+        let mut file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let metadata = file.metadata()?;
+        let is_executable = metadata.permissions().mode() & 0o111 != 0;
+
+        let mut contents = vec![];
+        file.read_to_end(&mut contents)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            contents,
+            is_executable,
+        })
+    }
+}
+
+#[cfg_attr(not(feature = "elf"), allow(unused_variables))]
+pub(crate) fn autopack_kind(ctx: &AutopackContext, contents: &[u8]) -> Option<AutopackKind> {
+    let contents_cursor = std::io::Cursor::new(contents);
+    let pack = brioche_resources::extract_padded_pack(contents_cursor);
+
+    // Files built for other targets are only autopacked with the
+    // `Autopack` policy, otherwise they're skipped
+    #[cfg(feature = "elf")]
+    let target = match ctx.config.foreign_arch {
+        crate::ForeignArchPolicy::Autopack => None,
+        _ => ctx.config.target.as_ref(),
+    };
+
+    if let Ok(extracted) = pack {
+        // Shared libraries, static-PIE programs, and dynamic binaries that
+        // were autopacked with a `Static` pack before get their pack
+        // updated in place, keeping the library dirs it already had
+        #[cfg(feature = "elf")]
+        if matches!(extracted.pack, brioche_pack::Pack::Static { .. }) {
+            let unpacked_contents = &contents[..extracted.unpacked_len];
+            let kind = crate::elf::autopack_kind(unpacked_contents, target);
+            let updated_in_place = match kind {
+                Some(AutopackKind::SharedLibrary) => ctx
+                    .config
+                    .shared_library
+                    .as_ref()
+                    .is_some_and(|config| config.enabled),
+                Some(AutopackKind::StaticPie) => {
+                    ctx.config.dynamic_binary.as_ref().is_some_and(|config| {
+                        config.enabled && config.static_pie == Some(crate::StaticPiePack::Static)
+                    })
+                }
+                Some(AutopackKind::DynamicBinary) => {
+                    ctx.config.dynamic_binary.as_ref().is_some_and(|config| {
+                        config.enabled
+                            && matches!(
+                                config.mode,
+                                crate::DynamicBinaryMode::PatchRunpath
+                                    | crate::DynamicBinaryMode::PatchInterpreter
+                            )
+                    })
+                }
+                _ => false,
+            };
+            if updated_in_place {
+                return kind;
+            }
+        }
+
+        return Some(AutopackKind::Repack);
+    }
+
+    #[cfg(feature = "script")]
+    if contents.starts_with(b"#!") {
+        return Some(AutopackKind::Script);
+    }
+
+    #[cfg(feature = "elf")]
+    if let Some(kind) = crate::elf::autopack_kind(contents, target) {
+        return Some(kind);
+    }
+
+    None
+}
+
+/// How a file gets autopacked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum AutopackKind {
+    #[cfg(feature = "elf")]
+    DynamicBinary,
+    #[cfg(feature = "elf")]
+    SharedLibrary,
+    /// A statically-linked program that loads libraries with `dlopen`.
+    /// Other static programs don't need to be autopacked.
+    #[cfg(feature = "elf")]
+    StaticBinary,
+    /// A static-PIE program, which is a position-independent program with
+    /// an entry point but no interpreter.
+    #[cfg(feature = "elf")]
+    StaticPie,
+    #[cfg(feature = "script")]
+    Script,
+    /// A file that already has a pack, which gets autopacked again from
+    /// its source.
+    Repack,
+}
+
+impl AutopackKind {
+    /// Check if files autopacked this way get run as programs, which is
+    /// everything but shared libraries.
+    pub(crate) fn is_program(self) -> bool {
+        #[cfg(feature = "elf")]
+        if self == Self::SharedLibrary {
+            return false;
+        }
+
+        true
+    }
+}
@@ -0,0 +1,371 @@
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("could not find command {command_name:?} for script {}", path.display())]
+#[diagnostic(
+    code(brioche_autopack::script::command_not_found),
+    help("add a link dependency that provides `{command_name}` under `bin/` or `brioche-env.d/env/PATH`")
+)]
+pub struct ScriptCommandNotFound {
+    pub path: PathBuf,
+    pub command_name: String,
+    #[source_code]
+    pub shebang: miette::NamedSource<String>,
+    #[label("not found in any link dependency")]
+    pub span: miette::SourceSpan,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("expected a command after `env` in shebang for script {}", path.display())]
+#[diagnostic(
+    code(brioche_autopack::script::missing_env_command),
+    help("use a shebang like `#!/usr/bin/env bash`")
+)]
+pub struct ScriptMissingEnvCommand {
+    pub path: PathBuf,
+    #[source_code]
+    pub shebang: miette::NamedSource<String>,
+    #[label("expected a command name after this")]
+    pub span: miette::SourceSpan,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("unsupported `env` shebang for script {}: {reason}", path.display())]
+#[diagnostic(
+    code(brioche_autopack::script::invalid_env_shebang),
+    help("`env` shebangs can set env vars with `NAME=value`, unset them with `-u NAME`, clear them with `-i`, and split args with `-S`")
+)]
+pub struct ScriptInvalidEnvShebang {
+    pub path: PathBuf,
+    pub reason: String,
+    #[source_code]
+    pub shebang: miette::NamedSource<String>,
+    #[label("{reason}")]
+    pub span: miette::SourceSpan,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("invalid glob pattern {pattern:?}")]
+#[diagnostic(
+    code(brioche_autopack::inputs::invalid_glob),
+    help("glob patterns are matched against paths relative to the recipe")
+)]
+pub struct InvalidGlobPattern {
+    pub pattern: String,
+    #[source]
+    pub error: globset::Error,
+    #[source_code]
+    pub source_code: String,
+    #[label("{error}")]
+    pub span: miette::SourceSpan,
+}
+
+impl InvalidGlobPattern {
+    pub fn new(pattern: &str, error: globset::Error) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            error,
+            source_code: pattern.to_string(),
+            span: (0, pattern.len()).into(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("failed to deserialize runnable metadata")]
+#[diagnostic(
+    code(brioche_autopack::pack::invalid_runnable_metadata),
+    help("the pack may have been created by an incompatible version of brioche-packer")
+)]
+pub struct InvalidRunnableMetadata {
+    #[source]
+    pub error: serde_json::Error,
+    #[source_code]
+    pub metadata: miette::NamedSource<String>,
+    #[label("{error}")]
+    pub span: miette::SourceSpan,
+}
+
+impl InvalidRunnableMetadata {
+    pub fn new(metadata: &[u8], error: serde_json::Error) -> Self {
+        let metadata = String::from_utf8_lossy(metadata).into_owned();
+        let offset = line_column_offset(&metadata, error.line(), error.column());
+        Self {
+            error,
+            metadata: miette::NamedSource::new("runnable metadata", metadata),
+            span: offset.into(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error(
+    "no libc provider among link dependencies: could not find {name:?} (searched: {})",
+    display_paths(searched_dependencies)
+)]
+#[diagnostic(
+    code(brioche_autopack::elf::libc_not_found),
+    help(
+        "did you forget to add glibc? add it as a link dependency or set a default libc dependency"
+    )
+)]
+pub struct LibcNotFound {
+    /// The interpreter or libc library that couldn't be found.
+    pub name: String,
+    pub searched_dependencies: Vec<PathBuf>,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error(
+    "library {name:?} is provided by more than one link dependency: {}",
+    display_paths(paths)
+)]
+#[diagnostic(
+    code(brioche_autopack::elf::conflicting_library),
+    help("reorder the link dependencies, or remove the ones that shouldn't provide this library")
+)]
+pub struct ConflictingLibrary {
+    pub name: String,
+    /// The conflicting library files, in link dependency order.
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error(
+    "architecture mismatch: {} is {expected}, but {} is {found}",
+    path.display(),
+    other_path.display()
+)]
+#[diagnostic(
+    code(brioche_autopack::elf::architecture_mismatch),
+    help("check that the link dependencies were built for the same architecture as the binary")
+)]
+pub struct ArchitectureMismatch {
+    /// The file being autopacked.
+    pub path: PathBuf,
+    pub expected: String,
+    /// The interpreter or library that was built for another architecture.
+    pub other_path: PathBuf,
+    pub found: String,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error(
+    "closure of {} is too large: {reason}\n{}",
+    path.display(),
+    display_closure(libraries)
+)]
+#[diagnostic(
+    code(brioche_autopack::elf::closure_too_large),
+    help("check which libraries pulled in unexpected dependencies, or raise the closure limits")
+)]
+pub struct ClosureTooLarge {
+    pub path: PathBuf,
+    pub reason: String,
+    /// The libraries in the closure, in the order they were found.
+    pub libraries: Vec<crate::ClosureLibrary>,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{} was built for another target: {mismatch}", path.display())]
+#[diagnostic(
+    code(brioche_autopack::foreign_arch),
+    help("exclude the file from autopacking, or change the foreign arch policy to skip or autopack it")
+)]
+pub struct ForeignArch {
+    pub path: PathBuf,
+    pub mismatch: String,
+}
+
+#[cfg(any(feature = "elf", feature = "script"))]
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{role} resource {} was denied: {reason}", path.display())]
+#[diagnostic(
+    code(brioche_autopack::resources::denied),
+    help("the resource filter denied adding this file as a resource")
+)]
+pub struct ResourceDenied {
+    pub path: PathBuf,
+    pub role: crate::resources::ResourceRole,
+    pub reason: String,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{warning}")]
+#[diagnostic(
+    code(brioche_autopack::warnings::denied),
+    help("warnings of kind `{}` are denied by the config", warning.kind())
+)]
+pub struct DeniedWarning {
+    pub warning: crate::AutopackWarning,
+
+    /// The part of the config the warning is about, such as an unmatched
+    /// glob pattern. Most warnings are about a file instead.
+    #[source_code]
+    pub source_code: Option<String>,
+    #[label]
+    pub span: Option<miette::SourceSpan>,
+}
+
+impl DeniedWarning {
+    pub fn new(warning: crate::AutopackWarning) -> Self {
+        let source_code = match &warning {
+            crate::AutopackWarning::UnmatchedGlobPattern { pattern, .. } => Some(pattern.clone()),
+            _ => None,
+        };
+        let span = source_code
+            .as_ref()
+            .map(|source_code| (0, source_code.len()).into());
+        Self {
+            warning,
+            source_code,
+            span,
+        }
+    }
+}
+
+/// A problem with an autopack config, found by
+/// [`AutopackConfigBuilder::build`](crate::AutopackConfigBuilder::build)
+/// before autopacking starts.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[non_exhaustive]
+pub enum InvalidAutopackConfig {
+    #[error("packed executable {path:?} could not be read")]
+    #[diagnostic(
+        code(brioche_autopack::config::packed_executable_not_readable),
+        help("build the packed executable first, or use the embedded one")
+    )]
+    PackedExecutableNotReadable {
+        path: PathBuf,
+        #[source]
+        error: std::io::Error,
+    },
+
+    #[error("packed executable {packed_executable} was not built for the target: {mismatch}")]
+    #[diagnostic(
+        code(brioche_autopack::config::packed_executable_target_mismatch),
+        help("use a packed executable built for the target")
+    )]
+    PackedExecutableTargetMismatch {
+        packed_executable: String,
+        mismatch: String,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    InvalidGlobPattern(#[from] InvalidGlobPattern),
+
+    #[error("invalid glibc-hwcaps subdirectory {name:?}")]
+    #[diagnostic(
+        code(brioche_autopack::config::invalid_glibc_hwcap),
+        help("use a subdirectory name such as \"x86-64-v3\"")
+    )]
+    InvalidGlibcHwcap { name: String },
+
+    #[error("invalid resource namespace {name:?}")]
+    #[diagnostic(
+        code(brioche_autopack::config::invalid_resource_namespace),
+        help("use a single directory name, such as a hash of the recipe")
+    )]
+    InvalidResourceNamespace { name: String },
+
+    #[error("recipe path {path:?} is not a directory")]
+    #[diagnostic(code(brioche_autopack::config::recipe_not_dir))]
+    RecipeNotDir { path: PathBuf },
+
+    #[error("invalid env template for ${name}: {reason}")]
+    #[diagnostic(code(brioche_autopack::config::invalid_env_template))]
+    InvalidEnvTemplate { name: String, reason: String },
+
+    #[error("patching the interpreter needs an absolute runtime resource dir")]
+    #[diagnostic(
+        code(brioche_autopack::config::invalid_runtime_resource_dir),
+        help("set the absolute path the resource dir is installed at, or use the launcher mode")
+    )]
+    InvalidRuntimeResourceDir { path: Option<PathBuf> },
+
+    #[error("stat cache dir {path:?} is within the resource dir")]
+    #[diagnostic(
+        code(brioche_autopack::config::invalid_stat_cache_dir),
+        help("use a dir outside of the output, such as in the build's cache dir")
+    )]
+    InvalidStatCacheDir { path: PathBuf },
+}
+
+fn display_closure(libraries: &[crate::ClosureLibrary]) -> String {
+    libraries
+        .iter()
+        .map(|library| format!("  {} ({} bytes)", library.chain(), library.size))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    if paths.is_empty() {
+        return "none".to_string();
+    }
+
+    paths
+        .iter()
+        .map(|path| format!("{path:?}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Find the first error in an error chain that can be rendered as a
+/// diagnostic with source context.
+pub fn find_diagnostic(error: &eyre::Report) -> Option<&dyn miette::Diagnostic> {
+    error
+        .chain()
+        .find_map(|error| -> Option<&dyn miette::Diagnostic> {
+            #[cfg(any(feature = "elf", feature = "script"))]
+            if let Some(diagnostic) = error.downcast_ref::<ResourceDenied>() {
+                return Some(diagnostic);
+            }
+
+            if let Some(diagnostic) = error.downcast_ref::<ScriptCommandNotFound>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<ScriptMissingEnvCommand>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<ScriptInvalidEnvShebang>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<InvalidGlobPattern>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<InvalidRunnableMetadata>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<LibcNotFound>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<ConflictingLibrary>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<ArchitectureMismatch>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<ClosureTooLarge>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<ForeignArch>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<DeniedWarning>() {
+                Some(diagnostic)
+            } else if let Some(diagnostic) = error.downcast_ref::<InvalidAutopackConfig>() {
+                Some(diagnostic)
+            } else {
+                None
+            }
+        })
+}
+
+/// Get the span of `inner` within `outer`, where `inner` is a subslice
+/// of `outer`.
+#[cfg(feature = "script")]
+pub(crate) fn subslice_span(outer: &str, inner: &str) -> miette::SourceSpan {
+    let offset = (inner.as_ptr() as usize).saturating_sub(outer.as_ptr() as usize);
+    (offset, inner.len()).into()
+}
+
+fn line_column_offset(source: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = source
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len())
+        .sum();
+    (line_start + column.saturating_sub(1)).min(source.len())
+}
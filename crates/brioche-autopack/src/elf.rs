@@ -0,0 +1,3580 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use bstr::{ByteSlice as _, ByteVec as _};
+use eyre::{Context as _, OptionExt as _};
+
+use crate::{
+    debug_links::DebugLink,
+    detect::{AutopackKind, SourceFile},
+    diagnostics::{ArchitectureMismatch, ClosureTooLarge, InvalidGlobPattern, LibcNotFound},
+    emit::PackBase,
+    jobs::PendingPaths,
+    linker_script::{LinkerScript, LinkerScriptInput},
+    report::{ClosureLibrary, Outcome, SkipReason},
+    resources::{
+        add_alias, add_build_id, add_named_blob_from, add_named_blob_from_source, ResourceRole,
+    },
+    try_autopack_dependency,
+    warnings::DanglingSymlinks,
+    AutopackContext, AutopackWarning, ClosureVerdict, DynamicBinaryConfig, DynamicBinaryMode,
+    DynamicLinkingConfig, ResolvedClosure, ResolvedLibrary, RpathTokens, StaticPiePack,
+    UnwrappedCopy,
+};
+
+/// An object file parsed by [`parse_object`]. Mach-O files are only
+/// recognized with the `macho` feature.
+pub(crate) enum Object<'a> {
+    Elf(goblin::elf::Elf<'a>),
+    #[cfg(feature = "macho")]
+    MachO(goblin::mach::MachO<'a>),
+    Unknown,
+}
+
+/// Parse an object file, falling back to [`parse_elf_lenient`] for ELF
+/// files that goblin can't fully parse. Universal Mach-O files are parsed
+/// as their slice for `target`.
+pub(crate) fn parse_object<'a>(
+    contents: &'a [u8],
+    target: Option<&target_lexicon::Triple>,
+) -> goblin::error::Result<Object<'a>> {
+    if contents.starts_with(goblin::elf::header::ELFMAG) {
+        return parse_elf(contents).map(Object::Elf);
+    }
+
+    #[cfg(feature = "macho")]
+    match goblin::mach::Mach::parse(contents) {
+        Ok(mach) => return crate::macho::single_arch(mach, target).map(Object::MachO),
+        Err(goblin::error::Error::BadMagic(_)) => {}
+        Err(error) => return Err(error),
+    }
+    #[cfg(not(feature = "macho"))]
+    let _ = target;
+
+    Ok(Object::Unknown)
+}
+
+/// Parse an ELF file, falling back to [`parse_elf_lenient`] if goblin
+/// can't fully parse it.
+pub(crate) fn parse_elf(contents: &[u8]) -> goblin::error::Result<goblin::elf::Elf<'_>> {
+    goblin::elf::Elf::parse(contents).or_else(|error| parse_elf_lenient(contents).ok_or(error))
+}
+
+/// Parse an ELF file using only its program headers and dynamic section,
+/// ignoring its section headers. Tools like patchelf can leave section
+/// headers pointing at moved or truncated data, which the dynamic linker
+/// never reads but goblin rejects. Symbols, relocations, and symbol
+/// versions aren't parsed.
+fn parse_elf_lenient(contents: &[u8]) -> Option<goblin::elf::Elf<'_>> {
+    use goblin::elf::{dynamic, program_header};
+
+    let header = goblin::elf::Elf::parse_header(contents).ok()?;
+    let mut elf = goblin::elf::Elf::lazy_parse(header).ok()?;
+    let container = if elf.is_64 {
+        goblin::container::Container::Big
+    } else {
+        goblin::container::Container::Little
+    };
+    let ctx = goblin::container::Ctx::new(
+        container,
+        goblin::container::Endian::from(elf.little_endian),
+    );
+    elf.program_headers = goblin::elf::ProgramHeader::parse(
+        contents,
+        header.e_phoff as usize,
+        header.e_phnum as usize,
+        ctx,
+    )
+    .ok()?;
+
+    elf.interpreter = elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == program_header::PT_INTERP && ph.p_filesz != 0)
+        .and_then(|ph| {
+            let start = ph.p_offset as usize;
+            let end = start.checked_add(ph.p_filesz as usize - 1)?;
+            std::str::from_utf8(contents.get(start..end)?).ok()
+        });
+
+    elf.dynamic = goblin::elf::Dynamic::parse(contents, &elf.program_headers, ctx).ok()?;
+    if let Some(dynamic) = &elf.dynamic {
+        let dyn_info = &dynamic.info;
+        elf.dynstrtab =
+            goblin::strtab::Strtab::parse(contents, dyn_info.strtab, dyn_info.strsz, 0x0).ok()?;
+        if dyn_info.soname != 0 {
+            elf.soname = elf.dynstrtab.get_at(dyn_info.soname);
+        }
+        elf.libraries = dynamic.get_libraries(&elf.dynstrtab);
+        for dyn_ in &dynamic.dyns {
+            let paths = match dyn_.d_tag {
+                dynamic::DT_RPATH => &mut elf.rpaths,
+                dynamic::DT_RUNPATH => &mut elf.runpaths,
+                _ => continue,
+            };
+            paths.extend(elf.dynstrtab.get_at(dyn_.d_val as usize));
+        }
+        if dyn_info.flags_1 & dynamic::DF_1_PIE != 0 {
+            elf.is_lib = false;
+        }
+    }
+
+    Some(elf)
+}
+
+pub(crate) fn autopack_kind(
+    contents: &[u8],
+    target: Option<&target_lexicon::Triple>,
+) -> Option<AutopackKind> {
+    let program_object = parse_object(contents, target);
+
+    let program_object = match program_object {
+        Ok(Object::Elf(program_object)) => program_object,
+        #[cfg(feature = "macho")]
+        Ok(Object::MachO(macho)) => {
+            return crate::macho::autopack_kind(&macho, target);
+        }
+        _ => return None,
+    };
+
+    // Skip ELF files built for other targets
+    if let Some(target) = target {
+        if target_mismatch(&program_object, target).is_some() {
+            return None;
+        }
+    }
+
+    if program_object.interpreter.is_some() {
+        Some(AutopackKind::DynamicBinary)
+    } else if is_static_pie(&program_object) {
+        Some(AutopackKind::StaticPie)
+    } else if program_object.is_lib {
+        Some(AutopackKind::SharedLibrary)
+    } else if is_static_executable(&program_object) && uses_dlopen(&program_object) {
+        Some(AutopackKind::StaticBinary)
+    } else {
+        None
+    }
+}
+
+/// Check if an ELF file is a statically-linked program that isn't
+/// position-independent.
+fn is_static_executable(elf: &goblin::elf::Elf) -> bool {
+    elf.interpreter.is_none() && elf.header.e_type == goblin::elf::header::ET_EXEC
+}
+
+/// Check if an ELF file is a static-PIE program. These are `ET_DYN` files
+/// with no interpreter like shared libraries, so they're told apart by
+/// having an entry point along with either the `DF_1_PIE` flag (which
+/// older linkers don't set) or no soname.
+fn is_static_pie(elf: &goblin::elf::Elf) -> bool {
+    use goblin::elf::{dynamic, header};
+
+    if elf.interpreter.is_some() || elf.header.e_type != header::ET_DYN || elf.header.e_entry == 0 {
+        return false;
+    }
+
+    let has_pie_flag = elf
+        .dynamic
+        .as_ref()
+        .is_some_and(|dynamic| dynamic.info.flags_1 & dynamic::DF_1_PIE != 0);
+    has_pie_flag || elf.soname.is_none()
+}
+
+/// Check if a static program defines `dlopen`, meaning it can load
+/// libraries at runtime. This reads the symbol table, so stripped programs
+/// are never detected. glibc's static libc always includes `dlopen` to load
+/// NSS and iconv modules, so unstripped static glibc programs always are.
+fn uses_dlopen(elf: &goblin::elf::Elf) -> bool {
+    elf.syms.iter().any(|sym| {
+        sym.st_shndx != goblin::elf::section_header::SHN_UNDEF as usize
+            && matches!(elf.strtab.get_at(sym.st_name), Some("dlopen" | "dlmopen"))
+    })
+}
+
+pub(crate) fn autopack_dynamic_binary(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let Some(dynamic_binary_config) = ctx
+        .config
+        .dynamic_binary
+        .as_ref()
+        .filter(|config| config.enabled)
+    else {
+        return Ok(Outcome::Skipped(SkipReason::Disabled {
+            kind: AutopackKind::DynamicBinary,
+        }));
+    };
+
+    let rewritten_source =
+        rewrite_needed_libraries(&dynamic_binary_config.dynamic_linking, source)?;
+    let source = rewritten_source.as_ref().unwrap_or(source);
+
+    for host_path in &dynamic_binary_config.dynamic_linking.host_library_paths {
+        ctx.warn(AutopackWarning::NonHermeticPath {
+            path: source.path.clone(),
+            host_path: host_path.clone(),
+        })?;
+    }
+
+    let logical_output_path = ctx.logical_path(output_path);
+    let output_path_parent = logical_output_path
+        .parent()
+        .ok_or_eyre("could not get parent of output path")?;
+
+    let program_object = parse_object(&source.contents, ctx.config.target.as_ref())?;
+
+    let program_object = match program_object {
+        Object::Elf(program_object) => program_object,
+        #[cfg(feature = "macho")]
+        Object::MachO(macho) => {
+            eyre::ensure!(
+                dynamic_binary_config.mode == DynamicBinaryMode::Launcher,
+                "only ELF dynamic binaries support the {:?} mode: {}",
+                dynamic_binary_config.mode,
+                source.path.display()
+            );
+            return crate::macho::autopack_dynamic_binary(
+                ctx,
+                dynamic_binary_config,
+                source,
+                &macho,
+                output_path,
+                pending_paths,
+            );
+        }
+        _ => {
+            eyre::bail!(
+                "tried to autopack unsupported dynamic binary: {}",
+                source.path.display()
+            );
+        }
+    };
+
+    // Only glibc-style loaders support the `LdLinux` pack, so binaries for
+    // other OSes are run through the system's own loader instead
+    let elf_os = ElfOs::detect(&program_object, &source.contents);
+    check_elf_os_supported(ctx, elf_os, &source.path)?;
+    if dynamic_binary_config.mode != DynamicBinaryMode::Launcher {
+        eyre::ensure!(
+            elf_os == ElfOs::Linux,
+            "only Linux binaries support the {:?} mode: {}",
+            dynamic_binary_config.mode,
+            source.path.display()
+        );
+        return autopack_patched_binary(
+            ctx,
+            dynamic_binary_config,
+            source,
+            output_path,
+            pending_paths,
+        );
+    }
+    if elf_os != ElfOs::Linux {
+        let system_libraries = match elf_os {
+            ElfOs::Android => dynamic_binary_config.android.system_libraries.clone(),
+            _ => elf_os.system_libraries(),
+        };
+        return autopack_system_loader_binary(
+            ctx,
+            dynamic_binary_config,
+            source,
+            &program_object,
+            &system_libraries,
+            output_path,
+            pending_paths,
+        );
+    }
+
+    let interpreter_resource_path = add_interpreter(
+        ctx,
+        dynamic_binary_config,
+        source,
+        &program_object,
+        pending_paths,
+    )?;
+    let program = ProgramRef::new(ctx, dynamic_binary_config, source, output_path)?;
+
+    let needed_libraries: VecDeque<_> = program_object
+        .libraries
+        .iter()
+        .copied()
+        .chain(dynamic_binary_config.dynamic_linking.additional_libraries())
+        .map(|lib| lib.to_string())
+        .collect();
+
+    let rpath_library_paths = rpath_library_paths(
+        &program_object,
+        &source.path,
+        &dynamic_binary_config.dynamic_linking.rpath_tokens,
+        ctx.config.target.as_ref(),
+    )?;
+
+    let collected_libraries = collect_all_library_dirs(
+        ctx,
+        source,
+        &dynamic_binary_config.dynamic_linking,
+        needed_libraries,
+        rpath_library_paths,
+        &HashSet::new(),
+        pending_paths,
+    )?;
+    check_closure(
+        ctx,
+        source,
+        collected_libraries.libraries,
+        collected_libraries.provided,
+    )?;
+    let library_dir_resource_paths = collected_libraries.resource_dirs;
+
+    // `LdLinux` packs can only run a program from the resource dir
+    let program_resource_path = match program {
+        ProgramRef::Resource(program_resource_path) if !dynamic_binary_config.self_exec => {
+            program_resource_path
+        }
+        program => {
+            let pack = self_exec_pack(
+                dynamic_binary_config,
+                &program,
+                interpreter_resource_path,
+                &library_dir_resource_paths,
+                output_path_parent,
+            )?;
+            ctx.emit_pack(
+                output_path,
+                PackBase::PackedExecutable(&dynamic_binary_config.packed_executable),
+                &pack,
+                &dynamic_binary_config.fingerprint(),
+            )?;
+            return Ok(Outcome::Autopacked(AutopackKind::DynamicBinary));
+        }
+    };
+
+    let program = <Vec<u8>>::from_path_buf(program_resource_path)
+        .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
+    let interpreter = <Vec<u8>>::from_path_buf(interpreter_resource_path)
+        .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
+    let library_dirs = library_dir_resource_paths
+        .into_iter()
+        .map(|resource_path| {
+            <Vec<u8>>::from_path_buf(resource_path)
+                .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let runtime_library_dirs = dynamic_binary_config
+        .extra_runtime_library_paths
+        .iter()
+        .map(|path| {
+            let path = pathdiff::diff_paths(path, output_path_parent).ok_or_else(|| eyre::eyre!("failed to get relative path from output path {output_path_parent:?} to runtime library path {path:?}"))?;
+            <Vec<u8>>::from_path_buf(path)
+                .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
+        })
+        .chain(host_library_dirs(&dynamic_binary_config.dynamic_linking))
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let pack = brioche_pack::Pack::LdLinux {
+        program,
+        interpreter,
+        library_dirs,
+        runtime_library_dirs,
+    };
+
+    ctx.emit_pack(
+        output_path,
+        PackBase::PackedExecutable(&dynamic_binary_config.packed_executable),
+        &pack,
+        &dynamic_binary_config.fingerprint(),
+    )?;
+
+    Ok(Outcome::Autopacked(AutopackKind::DynamicBinary))
+}
+
+/// Find a dynamic binary's interpreter (applying the interpreter remaps),
+/// and add it as a resource. Returns its path within the resource dir.
+fn add_interpreter(
+    ctx: &AutopackContext,
+    dynamic_binary_config: &DynamicBinaryConfig,
+    source: &SourceFile,
+    program_object: &goblin::elf::Elf,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<PathBuf> {
+    let Some(interpreter) = program_object.interpreter else {
+        eyre::bail!(
+            "tried to autopack dynamic binary without an interpreter: {}",
+            source.path.display()
+        );
+    };
+
+    // The interpreter comes from libc, so a missing interpreter usually
+    // means libc is missing. A remapped interpreter is used if it can be
+    // found, otherwise the requested interpreter is
+    let mut interpreter_path = None;
+    if let Some(remapped_interpreter) = dynamic_binary_config
+        .interpreter_remaps
+        .get(Path::new(interpreter))
+    {
+        interpreter_path = ctx.resolver.find_interpreter(remapped_interpreter)?;
+    }
+    if interpreter_path.is_none() {
+        interpreter_path = ctx.resolver.find_interpreter(Path::new(interpreter))?;
+    }
+    let interpreter_path = interpreter_path
+        .ok_or_else(|| libc_not_found(ctx, interpreter))
+        .with_context(|| {
+            format!(
+                "could not find interpreter for dynamic binary: {:?}",
+                source.path
+            )
+        })?;
+
+    check_arch(source, ElfArch::of(program_object), &interpreter_path)?;
+    if let Some(target) = dependency_target(ctx, &source.contents) {
+        let interpreter_contents = std::fs::read(&interpreter_path)
+            .with_context(|| format!("failed to read interpreter {interpreter_path:?}"))?;
+        let interpreter_elf = parse_elf(&interpreter_contents)
+            .with_context(|| format!("failed to parse interpreter {interpreter_path:?}"))?;
+        if let Some(mismatch) = target_mismatch(&interpreter_elf, target) {
+            eyre::bail!("interpreter {interpreter_path:?} does not match target: {mismatch}");
+        }
+    }
+
+    // Autopack the interpreter if it's pending
+    let interpreter_path = try_autopack_dependency(ctx, &interpreter_path, pending_paths)?;
+
+    let interpreter_resource_path =
+        add_named_blob_from(ctx, &interpreter_path, None, ResourceRole::Interpreter).with_context(
+            || format!("failed to add resource for interpreter {interpreter_path:?}"),
+        )?;
+    if let Ok(interpreter_contents) = std::fs::read(&interpreter_path) {
+        add_build_id_from_contents(ctx, &interpreter_resource_path, &interpreter_contents)?;
+    }
+
+    Ok(interpreter_resource_path)
+}
+
+/// Autopack a dynamic binary with [`DynamicBinaryMode::PatchRunpath`] or
+/// [`DynamicBinaryMode::PatchInterpreter`]: its runpath is rewritten to
+/// point to its library dirs (and its interpreter to the interpreter
+/// resource), and a `Static` pack records them. Binaries autopacked this
+/// way before already have a pack, which gets replaced like a shared
+/// library's.
+fn autopack_patched_binary(
+    ctx: &AutopackContext,
+    dynamic_binary_config: &DynamicBinaryConfig,
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let Some(WithoutStaticPack {
+        contents,
+        existing_library_dirs,
+    }) = without_static_pack(source)?
+    else {
+        return Ok(Outcome::Skipped(SkipReason::UnsupportedPack));
+    };
+
+    let logical_output_path = ctx.logical_path(output_path);
+    let output_path_parent = logical_output_path
+        .parent()
+        .ok_or_eyre("could not get parent of output path")?;
+
+    let program_object = parse_elf(contents)?;
+
+    let needed_libraries: VecDeque<_> = program_object
+        .libraries
+        .iter()
+        .copied()
+        .chain(dynamic_binary_config.dynamic_linking.additional_libraries())
+        .map(|lib| lib.to_string())
+        .collect();
+    let rpath_library_paths = rpath_library_paths(
+        &program_object,
+        &source.path,
+        &dynamic_binary_config.dynamic_linking.rpath_tokens,
+        ctx.config.target.as_ref(),
+    )?;
+
+    let collected_libraries = collect_all_library_dirs(
+        ctx,
+        source,
+        &dynamic_binary_config.dynamic_linking,
+        needed_libraries,
+        rpath_library_paths,
+        &HashSet::new(),
+        pending_paths,
+    )?;
+    check_closure(
+        ctx,
+        source,
+        collected_libraries.libraries,
+        collected_libraries.provided,
+    )?;
+    let library_dirs = merge_library_dirs(
+        ctx,
+        collected_libraries.resource_dirs,
+        existing_library_dirs,
+    )?;
+
+    // The dynamic linker expands `$ORIGIN` to the binary's directory, so
+    // the resource dir has to stay at the same path relative to it
+    let absolute_output_parent = std::path::absolute(output_path_parent)?;
+    let absolute_resource_dir = std::path::absolute(&ctx.config.resource_dir)?;
+    let mut runpath = vec![];
+    for library_dir in &library_dirs {
+        let library_dir = absolute_resource_dir.join(library_dir.to_path()?);
+        let relative_dir = pathdiff::diff_paths(&library_dir, &absolute_output_parent)
+            .ok_or_else(|| {
+                eyre::eyre!("failed to get relative path from output path {absolute_output_parent:?} to library dir {library_dir:?}")
+            })?;
+        runpath.push(origin_relative(&relative_dir)?);
+    }
+    for path in &dynamic_binary_config.extra_runtime_library_paths {
+        let relative_path = pathdiff::diff_paths(path, &absolute_output_parent).ok_or_else(|| eyre::eyre!("failed to get relative path from output path {absolute_output_parent:?} to runtime library path {path:?}"))?;
+        runpath.push(origin_relative(&relative_path)?);
+    }
+    for path in host_library_dirs(&dynamic_binary_config.dynamic_linking) {
+        runpath.push(path?);
+    }
+    let runpath = bstr::join(":", runpath);
+
+    let mut patched = patch_runpath(&program_object, contents, &runpath)
+        .with_context(|| format!("failed to patch runpath of {}", source.path.display()))?;
+
+    if dynamic_binary_config.mode == DynamicBinaryMode::PatchInterpreter {
+        let runtime_resource_dir = dynamic_binary_config
+            .runtime_resource_dir
+            .as_ref()
+            .filter(|path| path.is_absolute())
+            .ok_or_eyre("patching the interpreter needs an absolute runtime resource dir")?;
+
+        // Binaries patched before already use an interpreter resource, but
+        // still need it in the resource dir
+        let interpreter_resource_path = match program_object.interpreter.and_then(|interpreter| {
+            Path::new(interpreter)
+                .strip_prefix(runtime_resource_dir)
+                .ok()
+        }) {
+            Some(interpreter_resource_path) => interpreter_resource_path.to_owned(),
+            None => add_interpreter(
+                ctx,
+                dynamic_binary_config,
+                source,
+                &program_object,
+                pending_paths,
+            )?,
+        };
+        let interpreter = runtime_resource_dir.join(interpreter_resource_path);
+        patched = patch_interpreter(&program_object, &patched, &interpreter)
+            .with_context(|| format!("failed to patch interpreter of {}", source.path.display()))?;
+    }
+    let pack = brioche_pack::Pack::Static { library_dirs };
+    ctx.emit_pack(
+        output_path,
+        PackBase::Contents(&patched),
+        &pack,
+        &dynamic_binary_config.fingerprint(),
+    )?;
+
+    Ok(Outcome::Autopacked(AutopackKind::DynamicBinary))
+}
+
+/// Get a runpath entry for a path relative to the binary's directory.
+fn origin_relative(path: &Path) -> eyre::Result<Vec<u8>> {
+    let path = <Vec<u8>>::from_path_buf(path.to_owned())
+        .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
+    if path.is_empty() {
+        return Ok(b"$ORIGIN".to_vec());
+    }
+
+    Ok([&b"$ORIGIN/"[..], &path].concat())
+}
+
+/// Overwrite the string of an ELF file's `DT_RUNPATH` (or `DT_RPATH` if
+/// there's no runpath) with `runpath`. Growing the string table would mean
+/// moving sections around, so the new runpath has to fit in the space of
+/// the existing one, with the rest of it filled with NUL bytes. Fails if
+/// another string shares the existing one's tail, see
+/// [`ensure_unshared_dynstr`].
+fn patch_runpath(elf: &goblin::elf::Elf, contents: &[u8], runpath: &[u8]) -> eyre::Result<Vec<u8>> {
+    use goblin::elf::dynamic::{DT_RPATH, DT_RUNPATH};
+
+    let dynamic = elf.dynamic.as_ref().ok_or_eyre("no dynamic section")?;
+    let entry = dynamic
+        .dyns
+        .iter()
+        .find(|entry| entry.d_tag == DT_RUNPATH)
+        .or_else(|| dynamic.dyns.iter().find(|entry| entry.d_tag == DT_RPATH))
+        .ok_or_eyre(
+            "no DT_RUNPATH or DT_RPATH entry to patch, link with a placeholder runpath such as `-Wl,-rpath,$ORIGIN/////...`",
+        )?;
+
+    // goblin already converts the string table's address to a file offset
+    let start = dynamic.info.strtab + usize::try_from(entry.d_val)?;
+    let existing = contents.get(start..).ok_or_eyre("runpath out of bounds")?;
+    let existing_len = existing
+        .find_byte(b'\0')
+        .ok_or_eyre("runpath is not NUL-terminated")?;
+
+    eyre::ensure!(
+        runpath.len() <= existing_len,
+        "new runpath is {} bytes, but the existing one only has room for {existing_len}, link with a longer placeholder runpath",
+        runpath.len(),
+    );
+    ensure_unshared_dynstr(elf, contents, usize::try_from(entry.d_val)?, existing_len)
+        .context("can't overwrite runpath")?;
+
+    let mut contents = contents.to_vec();
+    let slot = &mut contents[start..start + existing_len];
+    slot.fill(0);
+    slot[..runpath.len()].copy_from_slice(runpath);
+    Ok(contents)
+}
+
+/// Check that overwriting the `len` bytes of the string at `offset` in an
+/// ELF file's dynamic string table won't change any other string. Linkers
+/// merge strings that end another string, such as `foo` into `libfoo`, so
+/// another offset could point inside the overwritten string, or the
+/// overwritten string could be the tail of a longer one. Offsets pointing
+/// to the start of the string refer to the same string, so they're fine.
+///
+/// Symbols and symbol versions also point into the string table, so this
+/// fails if they weren't parsed (see [`parse_elf_lenient`]).
+fn ensure_unshared_dynstr(
+    elf: &goblin::elf::Elf,
+    contents: &[u8],
+    offset: usize,
+    len: usize,
+) -> eyre::Result<()> {
+    use goblin::elf::dynamic::{
+        DT_AUDIT, DT_CONFIG, DT_DEPAUDIT, DT_NEEDED, DT_RPATH, DT_RUNPATH, DT_SONAME,
+    };
+
+    let dynamic = elf.dynamic.as_ref().ok_or_eyre("no dynamic section")?;
+    let dyn_info = &dynamic.info;
+    eyre::ensure!(
+        !symbols_unparsed(elf)
+            && (dyn_info.verneed == 0 || elf.verneed.is_some())
+            && (dyn_info.verdef == 0 || elf.verdef.is_some()),
+        "the symbols couldn't be parsed, so strings sharing bytes in the dynamic string table can't be found"
+    );
+
+    let mut offsets = vec![];
+    offsets.extend(
+        dynamic
+            .dyns
+            .iter()
+            .filter(|entry| {
+                matches!(
+                    entry.d_tag,
+                    DT_NEEDED
+                        | DT_SONAME
+                        | DT_RPATH
+                        | DT_RUNPATH
+                        | DT_CONFIG
+                        | DT_DEPAUDIT
+                        | DT_AUDIT
+                )
+            })
+            .map(|entry| entry.d_val as usize),
+    );
+    offsets.extend(elf.dynsyms.iter().map(|symbol| symbol.st_name));
+    if let Some(verneed) = &elf.verneed {
+        for need in verneed.iter() {
+            offsets.push(need.vn_file);
+            offsets.extend(need.iter().map(|aux| aux.vna_name));
+        }
+    }
+    if let Some(verdef) = &elf.verdef {
+        for def in verdef.iter() {
+            offsets.extend(def.iter().map(|aux| aux.vda_name));
+        }
+    }
+
+    // A string starting before the overwritten one shares it if there's no
+    // NUL byte between them, since they'd end at the same NUL byte
+    let strtab = contents
+        .get(dyn_info.strtab..dyn_info.strtab.saturating_add(dyn_info.strsz))
+        .ok_or_eyre("dynamic string table out of bounds")?;
+    let shares_tail = |other: usize| {
+        strtab
+            .get(other..offset)
+            .is_some_and(|between| !between.contains(&b'\0'))
+    };
+
+    let shared_offset = offsets.into_iter().find(|&other| {
+        (other > offset && other < offset + len) || (other < offset && shares_tail(other))
+    });
+    if let Some(shared_offset) = shared_offset {
+        let shared = elf.dynstrtab.get_at(shared_offset).unwrap_or_default();
+        eyre::bail!("the string {shared:?} shares its bytes in the dynamic string table");
+    }
+
+    Ok(())
+}
+
+/// Overwrite an ELF file's interpreter (`PT_INTERP`) with `interpreter`.
+/// Like with [`patch_runpath`], the new path has to fit in the space of
+/// the existing one.
+fn patch_interpreter(
+    elf: &goblin::elf::Elf,
+    contents: &[u8],
+    interpreter: &Path,
+) -> eyre::Result<Vec<u8>> {
+    let interpreter = <Vec<u8>>::from_path_buf(interpreter.to_owned())
+        .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
+    let interp_header = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == goblin::elf::program_header::PT_INTERP)
+        .ok_or_eyre("no PT_INTERP segment")?;
+    let start = usize::try_from(interp_header.p_offset)?;
+    let slot_len = usize::try_from(interp_header.p_filesz)?;
+
+    // The segment includes the NUL terminator
+    eyre::ensure!(
+        interpreter.len() < slot_len,
+        "new interpreter is {} bytes, but the existing one only has room for {}, link with a longer placeholder interpreter",
+        interpreter.len(),
+        slot_len.saturating_sub(1),
+    );
+
+    let mut contents = contents.to_vec();
+    let slot = contents
+        .get_mut(start..start + slot_len)
+        .ok_or_eyre("PT_INTERP segment out of bounds")?;
+    slot.fill(0);
+    slot[..interpreter.len()].copy_from_slice(&interpreter);
+    Ok(contents)
+}
+
+/// The OS an ELF file was built for, which determines how it gets loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ElfOs {
+    Linux,
+    Android,
+    Freebsd,
+    Illumos,
+    Other(u8),
+}
+
+impl ElfOs {
+    /// Detect the OS from the ELF's OS ABI, falling back to its interpreter
+    /// and notes, since most toolchains leave the OS ABI unset.
+    fn detect(elf: &goblin::elf::Elf, contents: &[u8]) -> Self {
+        use goblin::elf::header;
+
+        // Android binaries are tagged with a note added by the NDK
+        let is_bionic = elf
+            .interpreter
+            .is_some_and(|interpreter| interpreter.starts_with("/system/bin/linker"))
+            || elf
+                .iter_note_headers(contents)
+                .into_iter()
+                .flatten()
+                .any(|note| note.is_ok_and(|note| note.name == "Android"));
+        if is_bionic {
+            return Self::Android;
+        }
+
+        match elf.header.e_ident[header::EI_OSABI] {
+            header::ELFOSABI_NONE | header::ELFOSABI_GNU => {}
+            header::ELFOSABI_FREEBSD => return Self::Freebsd,
+            header::ELFOSABI_SOLARIS => return Self::Illumos,
+            os_abi => return Self::Other(os_abi),
+        }
+
+        match elf.interpreter {
+            Some("/libexec/ld-elf.so.1" | "/libexec/ld-elf32.so.1") => Self::Freebsd,
+            Some(interpreter) if interpreter.ends_with("/ld.so.1") => Self::Illumos,
+            _ => Self::Linux,
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Linux => "Linux".to_string(),
+            Self::Android => "Android".to_string(),
+            Self::Freebsd => "FreeBSD".to_string(),
+            Self::Illumos => "illumos".to_string(),
+            Self::Other(os_abi) => format!("OS ABI {os_abi}"),
+        }
+    }
+
+    /// Libraries from the base system, which are always loaded from the
+    /// host at runtime.
+    fn system_libraries(&self) -> HashSet<String> {
+        let libraries = match self {
+            Self::Linux | Self::Android | Self::Other(_) => &[][..],
+            Self::Freebsd => FREEBSD_SYSTEM_LIBRARIES,
+            Self::Illumos => ILLUMOS_SYSTEM_LIBRARIES,
+        };
+        libraries
+            .iter()
+            .map(|library| library.to_string())
+            .collect()
+    }
+
+    fn matches_target(&self, target: &target_lexicon::Triple) -> bool {
+        use target_lexicon::{Environment, OperatingSystem};
+
+        match self {
+            Self::Linux => target.operating_system == OperatingSystem::Linux,
+            Self::Android => {
+                target.operating_system == OperatingSystem::Linux
+                    && matches!(
+                        target.environment,
+                        Environment::Android | Environment::Androideabi
+                    )
+            }
+            Self::Freebsd => target.operating_system == OperatingSystem::Freebsd,
+            Self::Illumos => matches!(
+                target.operating_system,
+                OperatingSystem::Illumos | OperatingSystem::Solaris
+            ),
+            Self::Other(_) => false,
+        }
+    }
+}
+
+const FREEBSD_SYSTEM_LIBRARIES: &[&str] = &[
+    "libc.so.7",
+    "libc++.so.1",
+    "libcrypt.so.5",
+    "libcxxrt.so.1",
+    "libelf.so.2",
+    "libexecinfo.so.1",
+    "libgcc_s.so.1",
+    "libkvm.so.7",
+    "libm.so.5",
+    "libmd.so.6",
+    "libsys.so.7",
+    "libthr.so.3",
+    "libutil.so.9",
+    "libz.so.6",
+];
+
+const ILLUMOS_SYSTEM_LIBRARIES: &[&str] = &[
+    "libc.so.1",
+    "libdl.so.1",
+    "libgen.so.1",
+    "libkstat.so.1",
+    "libm.so.2",
+    "libmd.so.1",
+    "libnsl.so.1",
+    "libnvpair.so.1",
+    "libpthread.so.1",
+    "libresolv.so.2",
+    "librt.so.1",
+    "libsendfile.so.1",
+    "libsocket.so.1",
+    "libumem.so.1",
+    "libxnet.so.1",
+];
+
+/// Linux and Android ELF files can always be autopacked. ELF files for
+/// other OSes are only supported when the target is set to match, since
+/// they're autopacked assuming that OS's loader.
+fn check_elf_os_supported(ctx: &AutopackContext, elf_os: ElfOs, path: &Path) -> eyre::Result<()> {
+    match elf_os {
+        ElfOs::Linux | ElfOs::Android => Ok(()),
+        ElfOs::Freebsd | ElfOs::Illumos => {
+            let target_matches = ctx
+                .config
+                .target
+                .as_ref()
+                .is_some_and(|target| elf_os.matches_target(target));
+            eyre::ensure!(
+                target_matches,
+                "unsupported {} ELF file {path:?}: set the target to a {} triple to autopack it",
+                elf_os.name(),
+                elf_os.name(),
+            );
+            Ok(())
+        }
+        ElfOs::Other(_) => {
+            eyre::bail!(
+                "unsupported ELF file {path:?}: {} is not supported",
+                elf_os.name()
+            );
+        }
+    }
+}
+
+/// Autopack a dynamic binary that gets run directly through the OS's own
+/// loader, with `$LD_LIBRARY_PATH` set to find its libraries.
+fn autopack_system_loader_binary(
+    ctx: &AutopackContext,
+    dynamic_binary_config: &DynamicBinaryConfig,
+    source: &SourceFile,
+    program_object: &goblin::elf::Elf,
+    system_libraries: &HashSet<String>,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let logical_output_path = ctx.logical_path(output_path);
+    let output_path_parent = logical_output_path
+        .parent()
+        .ok_or_eyre("could not get parent of output path")?;
+
+    let program = ProgramRef::new(ctx, dynamic_binary_config, source, output_path)?;
+
+    let needed_libraries: VecDeque<_> = program_object
+        .libraries
+        .iter()
+        .copied()
+        .chain(dynamic_binary_config.dynamic_linking.additional_libraries())
+        .map(|lib| lib.to_string())
+        .collect();
+
+    let rpath_library_paths = rpath_library_paths(
+        program_object,
+        &source.path,
+        &dynamic_binary_config.dynamic_linking.rpath_tokens,
+        ctx.config.target.as_ref(),
+    )?;
+
+    let collected_libraries = collect_all_library_dirs(
+        ctx,
+        source,
+        &dynamic_binary_config.dynamic_linking,
+        needed_libraries,
+        rpath_library_paths,
+        system_libraries,
+        pending_paths,
+    )?;
+    check_closure(
+        ctx,
+        source,
+        collected_libraries.libraries,
+        collected_libraries.provided,
+    )?;
+    let library_dir_resource_paths = collected_libraries.resource_dirs;
+
+    let library_path = library_path_template(
+        dynamic_binary_config,
+        &library_dir_resource_paths,
+        output_path_parent,
+    )?;
+
+    let mut env = vec![];
+    if let Some(library_path) = library_path {
+        env.push((
+            "LD_LIBRARY_PATH".to_string(),
+            runnable_core::EnvValue::Prepend {
+                value: library_path,
+                separator: b":".to_vec(),
+            },
+        ));
+    }
+
+    let resource_paths = program
+        .resource_path()
+        .into_iter()
+        .chain(&library_dir_resource_paths)
+        .map(|path| {
+            <Vec<u8>>::from_path_buf(path.clone()).map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let fingerprint = dynamic_binary_config.fingerprint();
+    let runnable = runnable_core::Runnable {
+        command: program.template()?,
+        args: vec![runnable_core::ArgValue::Rest],
+        env,
+        clear_env: false,
+        library_dirs: vec![],
+        source: None,
+        packer: Some(fingerprint.clone()),
+    };
+    let pack = crate::metadata_pack(resource_paths, &runnable)?;
+
+    ctx.emit_pack(
+        output_path,
+        PackBase::PackedExecutable(&dynamic_binary_config.packed_executable),
+        &pack,
+        &fingerprint,
+    )?;
+
+    Ok(Outcome::Autopacked(AutopackKind::DynamicBinary))
+}
+
+/// Autopack a statically-linked program that loads libraries with
+/// `dlopen`. There's no interpreter to pass library dirs to, so the program
+/// is run directly with `$LD_LIBRARY_PATH` set to the extra libraries'
+/// dirs and the extra runtime library paths.
+pub(crate) fn autopack_static_binary(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let Some(dynamic_binary_config) = ctx
+        .config
+        .dynamic_binary
+        .as_ref()
+        .filter(|config| config.enabled && config.static_dlopen)
+    else {
+        ctx.warn(AutopackWarning::StaticDlopen {
+            path: source.path.clone(),
+        })?;
+        return Ok(Outcome::Skipped(SkipReason::Disabled {
+            kind: AutopackKind::StaticBinary,
+        }));
+    };
+
+    wrap_static_binary(
+        ctx,
+        dynamic_binary_config,
+        source,
+        output_path,
+        pending_paths,
+    )?;
+
+    Ok(Outcome::Autopacked(AutopackKind::StaticBinary))
+}
+
+/// Autopack a static-PIE program with the pack set by
+/// [`DynamicBinaryConfig::static_pie`]. Without one, the program is
+/// autopacked like other static programs.
+pub(crate) fn autopack_static_pie(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let dynamic_binary_config = ctx
+        .config
+        .dynamic_binary
+        .as_ref()
+        .filter(|config| config.enabled);
+    let static_pie = dynamic_binary_config.and_then(|config| config.static_pie);
+    let (Some(dynamic_binary_config), Some(static_pie)) = (dynamic_binary_config, static_pie)
+    else {
+        let Object::Elf(program_object) = parse_object(&source.contents, None)? else {
+            eyre::bail!("expected ELF file: {}", source.path.display());
+        };
+        if !uses_dlopen(&program_object) {
+            return Ok(Outcome::Skipped(SkipReason::Unsupported));
+        }
+
+        return autopack_static_binary(ctx, source, output_path, pending_paths);
+    };
+
+    match static_pie {
+        StaticPiePack::Metadata => {
+            wrap_static_binary(
+                ctx,
+                dynamic_binary_config,
+                source,
+                output_path,
+                pending_paths,
+            )?;
+        }
+        StaticPiePack::Static => {
+            // Programs autopacked this way before already have a pack,
+            // which gets replaced like a shared library's
+            let Some(WithoutStaticPack {
+                contents,
+                existing_library_dirs,
+            }) = without_static_pack(source)?
+            else {
+                return Ok(Outcome::Skipped(SkipReason::UnsupportedPack));
+            };
+            let has_existing_pack = contents.len() != source.contents.len();
+
+            let needed_libraries = dynamic_binary_config
+                .dynamic_linking
+                .additional_libraries()
+                .map(|lib| lib.to_string())
+                .collect();
+            let collected_libraries = collect_all_library_dirs(
+                ctx,
+                source,
+                &dynamic_binary_config.dynamic_linking,
+                needed_libraries,
+                vec![],
+                &HashSet::new(),
+                pending_paths,
+            )?;
+            check_closure(
+                ctx,
+                source,
+                collected_libraries.libraries,
+                collected_libraries.provided,
+            )?;
+
+            let library_dirs = merge_library_dirs(
+                ctx,
+                collected_libraries.resource_dirs,
+                existing_library_dirs,
+            )?;
+            let pack = brioche_pack::Pack::Static { library_dirs };
+            if !pack.should_add_to_executable() && !has_existing_pack {
+                return Ok(Outcome::Skipped(SkipReason::NoLibraryDirs));
+            }
+
+            let base = if has_existing_pack {
+                PackBase::Contents(contents)
+            } else {
+                PackBase::Source {
+                    path: &source.path,
+                    contents: &source.contents,
+                }
+            };
+            ctx.emit_pack(
+                output_path,
+                base,
+                &pack,
+                &dynamic_binary_config.fingerprint(),
+            )?;
+        }
+    }
+
+    Ok(Outcome::Autopacked(AutopackKind::StaticPie))
+}
+
+/// Wrap a static program in a runnable pack that runs it with
+/// `$LD_LIBRARY_PATH` set to the extra libraries' dirs and the extra
+/// runtime library paths.
+fn wrap_static_binary(
+    ctx: &AutopackContext,
+    dynamic_binary_config: &DynamicBinaryConfig,
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<()> {
+    let logical_output_path = ctx.logical_path(output_path);
+    let output_path_parent = logical_output_path
+        .parent()
+        .ok_or_eyre("could not get parent of output path")?;
+
+    let program = ProgramRef::new(ctx, dynamic_binary_config, source, output_path)?;
+
+    // The program has no needed libraries of its own, so only the extra
+    // libraries get collected
+    let needed_libraries = dynamic_binary_config
+        .dynamic_linking
+        .additional_libraries()
+        .map(|lib| lib.to_string())
+        .collect();
+    let collected_libraries = collect_all_library_dirs(
+        ctx,
+        source,
+        &dynamic_binary_config.dynamic_linking,
+        needed_libraries,
+        vec![],
+        &HashSet::new(),
+        pending_paths,
+    )?;
+    check_closure(
+        ctx,
+        source,
+        collected_libraries.libraries,
+        collected_libraries.provided,
+    )?;
+    let library_dir_resource_paths = collected_libraries.resource_dirs;
+
+    let library_path = library_path_template(
+        dynamic_binary_config,
+        &library_dir_resource_paths,
+        output_path_parent,
+    )?;
+
+    let mut env = vec![];
+    if let Some(library_path) = library_path {
+        env.push((
+            "LD_LIBRARY_PATH".to_string(),
+            runnable_core::EnvValue::Prepend {
+                value: library_path,
+                separator: b":".to_vec(),
+            },
+        ));
+    }
+
+    let resource_paths = program
+        .resource_path()
+        .into_iter()
+        .chain(&library_dir_resource_paths)
+        .map(|path| {
+            <Vec<u8>>::from_path_buf(path.clone()).map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let fingerprint = dynamic_binary_config.fingerprint();
+    let runnable = runnable_core::Runnable {
+        command: program.template()?,
+        args: vec![runnable_core::ArgValue::Rest],
+        env,
+        clear_env: false,
+        library_dirs: vec![],
+        source: Some(runnable_core::RunnableSource {
+            path: program.runnable_path()?,
+        }),
+        packer: Some(fingerprint.clone()),
+    };
+    let pack = crate::metadata_pack(resource_paths, &runnable)?;
+
+    ctx.emit_pack(
+        output_path,
+        PackBase::PackedExecutable(&dynamic_binary_config.packed_executable),
+        &pack,
+        &fingerprint,
+    )?;
+
+    Ok(())
+}
+
+/// The program file run by an autopacked dynamic binary.
+pub(crate) enum ProgramRef {
+    /// A copy of the program added as a resource.
+    Resource(PathBuf),
+
+    /// An unwrapped copy of the program, relative to the autopacked
+    /// binary's directory.
+    UnwrappedCopy(Vec<u8>),
+}
+
+impl ProgramRef {
+    /// Add the program as a resource, or write its unwrapped copy if the
+    /// config asks to keep one.
+    pub(crate) fn new(
+        ctx: &AutopackContext,
+        dynamic_binary_config: &DynamicBinaryConfig,
+        source: &SourceFile,
+        output_path: &Path,
+    ) -> eyre::Result<Self> {
+        let Some(unwrapped_copy) = &dynamic_binary_config.unwrapped_copy else {
+            let program_resource_path =
+                add_named_blob_from_source(ctx, source, ResourceRole::Program).with_context(
+                    || format!("failed to add resource for program {:?}", source.path),
+                )?;
+            add_build_id_from_contents(ctx, &program_resource_path, &source.contents)?;
+            if dynamic_binary_config.debug_links {
+                let debug_link = DebugLink {
+                    program: Some(program_resource_path.clone()),
+                    unwrapped_copy: None,
+                    build_id: source_build_id(source),
+                };
+                ctx.report.debug_links.insert(output_path, debug_link);
+            }
+            return Ok(Self::Resource(program_resource_path));
+        };
+
+        let output_dir = output_path
+            .parent()
+            .ok_or_eyre("could not get parent of output path")?;
+        let file_name = output_path
+            .file_name()
+            .ok_or_eyre("could not get filename of output path")?;
+        let unwrapped_path = match unwrapped_copy {
+            UnwrappedCopy::Suffix(suffix) => {
+                let mut unwrapped_name = file_name.to_owned();
+                unwrapped_name.push(suffix);
+                output_dir.join(unwrapped_name)
+            }
+            UnwrappedCopy::Dir(dir) => output_dir.join(dir).join(file_name),
+        };
+        eyre::ensure!(
+            unwrapped_path != output_path,
+            "unwrapped copy of {:?} would overwrite the autopacked binary",
+            source.path
+        );
+
+        let permissions = std::fs::metadata(&source.path)?.permissions();
+        match ctx.plan {
+            Some(plan) => {
+                use std::os::unix::fs::PermissionsExt as _;
+
+                let mode = permissions.mode() & 0o7777;
+                plan.record_copy(&unwrapped_path, source, mode);
+            }
+            None => {
+                use std::io::Write as _;
+
+                if let Some(unwrapped_dir) = unwrapped_path.parent() {
+                    std::fs::create_dir_all(unwrapped_dir)
+                        .with_context(|| format!("failed to create directory {unwrapped_dir:?}"))?;
+                }
+                let mut unwrapped = brioche_resources::AtomicWriter::new(&unwrapped_path, 0o666)
+                    .with_context(|| format!("failed to create file {unwrapped_path:?}"))?;
+                unwrapped.file().set_permissions(permissions)?;
+                unwrapped
+                    .write_all(&source.contents)
+                    .and_then(|()| unwrapped.commit())
+                    .with_context(|| {
+                        format!("failed to write unwrapped copy {unwrapped_path:?}")
+                    })?;
+            }
+        }
+
+        let relative_path = pathdiff::diff_paths(&unwrapped_path, output_dir)
+            .ok_or_eyre("failed to get path to unwrapped copy")?;
+        if dynamic_binary_config.debug_links {
+            let debug_link = DebugLink {
+                program: None,
+                unwrapped_copy: Some(relative_path.clone()),
+                build_id: source_build_id(source),
+            };
+            ctx.report.debug_links.insert(output_path, debug_link);
+        }
+        let relative_path = <Vec<u8>>::from_path_buf(relative_path)
+            .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
+        Ok(Self::UnwrappedCopy(relative_path))
+    }
+
+    pub(crate) fn resource_path(&self) -> Option<&PathBuf> {
+        match self {
+            Self::Resource(resource_path) => Some(resource_path),
+            Self::UnwrappedCopy(_) => None,
+        }
+    }
+
+    pub(crate) fn template(&self) -> eyre::Result<runnable_core::Template> {
+        match self {
+            Self::Resource(resource_path) => Ok(runnable_core::Template::from_resource_path(
+                resource_path.clone(),
+            )?),
+            Self::UnwrappedCopy(path) => Ok(runnable_core::Template {
+                components: vec![runnable_core::TemplateComponent::RelativePath {
+                    path: path.clone(),
+                }],
+            }),
+        }
+    }
+
+    pub(crate) fn runnable_path(&self) -> eyre::Result<runnable_core::RunnablePath> {
+        match self {
+            Self::Resource(resource_path) => Ok(runnable_core::RunnablePath::from_resource_path(
+                resource_path.clone(),
+            )?),
+            Self::UnwrappedCopy(path) => {
+                Ok(runnable_core::RunnablePath::RelativePath { path: path.clone() })
+            }
+        }
+    }
+}
+
+/// Build a `:`-separated library path from the library dirs added as
+/// resources, the runtime library paths, and the host library paths.
+pub(crate) fn library_path_template(
+    dynamic_binary_config: &DynamicBinaryConfig,
+    library_dir_resource_paths: &[PathBuf],
+    output_path_parent: &Path,
+) -> eyre::Result<Option<runnable_core::Template>> {
+    let library_path_components = library_dir_components(
+        dynamic_binary_config,
+        library_dir_resource_paths,
+        output_path_parent,
+    )?;
+    if library_path_components.is_empty() {
+        return Ok(None);
+    }
+
+    let components = intersperse(library_path_components, || {
+        runnable_core::TemplateComponent::Literal {
+            value: b":".to_vec(),
+        }
+    });
+    Ok(Some(runnable_core::Template { components }))
+}
+
+/// Get a template component for each dir of the library path built by
+/// [`library_path_template`].
+pub(crate) fn library_dir_components(
+    dynamic_binary_config: &DynamicBinaryConfig,
+    library_dir_resource_paths: &[PathBuf],
+    output_path_parent: &Path,
+) -> eyre::Result<Vec<runnable_core::TemplateComponent>> {
+    let mut library_path_components = vec![];
+    for resource_path in library_dir_resource_paths {
+        let resource = <Vec<u8>>::from_path_buf(resource_path.clone())
+            .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
+        library_path_components.push(runnable_core::TemplateComponent::Resource { resource });
+    }
+    for path in &dynamic_binary_config.extra_runtime_library_paths {
+        let path = pathdiff::diff_paths(path, output_path_parent).ok_or_else(|| eyre::eyre!("failed to get relative path from output path {output_path_parent:?} to runtime library path {path:?}"))?;
+        let path =
+            <Vec<u8>>::from_path_buf(path).map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
+        library_path_components.push(runnable_core::TemplateComponent::RelativePath { path });
+    }
+    for path in host_library_dirs(&dynamic_binary_config.dynamic_linking) {
+        library_path_components.push(runnable_core::TemplateComponent::Literal { value: path? });
+    }
+
+    Ok(library_path_components)
+}
+
+/// Build a runnable pack that runs the program through its interpreter,
+/// with the packed executable's own path as `argv[0]`. The `LdLinux` pack
+/// passes the original `argv[0]` through, which can be a relative path or
+/// a bare name, so a program re-executing itself from `argv[0]` could
+/// break after changing directories.
+///
+/// `/proc/self/exe` still refers to the interpreter, so programs should
+/// re-execute themselves using `argv[0]`.
+fn self_exec_pack(
+    dynamic_binary_config: &DynamicBinaryConfig,
+    program: &ProgramRef,
+    interpreter_resource_path: PathBuf,
+    library_dir_resource_paths: &[PathBuf],
+    output_path_parent: &Path,
+) -> eyre::Result<brioche_pack::Pack> {
+    let literal_arg = |value: &str| runnable_core::ArgValue::Arg {
+        value: runnable_core::Template::from_literal(value.as_bytes().to_vec()),
+    };
+
+    // Pass the library path as an argument rather than setting
+    // $LD_LIBRARY_PATH, so it doesn't leak into child processes
+    let library_path = library_path_template(
+        dynamic_binary_config,
+        library_dir_resource_paths,
+        output_path_parent,
+    )?;
+    let mut args = vec![];
+    if let Some(library_path) = library_path {
+        args.push(literal_arg("--library-path"));
+        args.push(runnable_core::ArgValue::Arg {
+            value: library_path,
+        });
+    }
+    args.push(literal_arg("--argv0"));
+    args.push(runnable_core::ArgValue::Arg {
+        value: runnable_core::Template {
+            components: vec![runnable_core::TemplateComponent::SelfPath],
+        },
+    });
+    args.push(runnable_core::ArgValue::Arg {
+        value: program.template()?,
+    });
+    args.push(runnable_core::ArgValue::Rest);
+
+    let resource_paths = program
+        .resource_path()
+        .into_iter()
+        .chain([&interpreter_resource_path])
+        .chain(library_dir_resource_paths)
+        .map(|path| {
+            <Vec<u8>>::from_path_buf(path.clone()).map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let runnable = runnable_core::Runnable {
+        command: runnable_core::Template::from_resource_path(interpreter_resource_path)?,
+        args,
+        env: vec![],
+        clear_env: false,
+        library_dirs: vec![],
+        source: Some(runnable_core::RunnableSource {
+            path: program.runnable_path()?,
+        }),
+        packer: Some(dynamic_binary_config.fingerprint()),
+    };
+    Ok(crate::metadata_pack(resource_paths, &runnable)?)
+}
+
+/// Insert a separator between each item.
+fn intersperse<T>(items: Vec<T>, mut separator: impl FnMut() -> T) -> Vec<T> {
+    let mut result = Vec::with_capacity(items.len() * 2);
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            result.push(separator());
+        }
+        result.push(item);
+    }
+    result
+}
+
+pub(crate) fn autopack_shared_library(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let Some(shared_library_config) = ctx
+        .config
+        .shared_library
+        .as_ref()
+        .filter(|config| config.enabled)
+    else {
+        return Ok(Outcome::Skipped(SkipReason::Disabled {
+            kind: AutopackKind::SharedLibrary,
+        }));
+    };
+
+    let rewritten_source =
+        rewrite_needed_libraries(&shared_library_config.dynamic_linking, source)?;
+    let is_rewritten = rewritten_source.is_some();
+    let source = rewritten_source.as_ref().unwrap_or(source);
+
+    // Libraries that were autopacked before already have a pack, which
+    // gets replaced
+    let Some(WithoutStaticPack {
+        contents,
+        existing_library_dirs,
+    }) = without_static_pack(source)?
+    else {
+        return Ok(Outcome::Skipped(SkipReason::UnsupportedPack));
+    };
+    let has_existing_pack = contents.len() != source.contents.len();
+
+    let program_object = parse_object(contents, ctx.config.target.as_ref())?;
+
+    let (library_dir_resource_paths, normalized_contents) = match program_object {
+        Object::Elf(program_object) => {
+            let needed_libraries: VecDeque<_> = program_object
+                .libraries
+                .iter()
+                .copied()
+                .filter(|library| {
+                    !shared_library_config
+                        .dynamic_linking
+                        .skip_libraries
+                        .contains(*library)
+                })
+                .chain(shared_library_config.dynamic_linking.additional_libraries())
+                .map(|lib| lib.to_string())
+                .collect();
+
+            let rpath_library_paths = rpath_library_paths(
+                &program_object,
+                &source.path,
+                &shared_library_config.dynamic_linking.rpath_tokens,
+                ctx.config.target.as_ref(),
+            )?;
+
+            // System libraries are only skipped for the OS the library was built
+            // for, since other OSes could have a library with the same name
+            let elf_os = ElfOs::detect(&program_object, contents);
+            check_elf_os_supported(ctx, elf_os, &source.path)?;
+            let system_libraries = match elf_os {
+                ElfOs::Android => shared_library_config.android.system_libraries.clone(),
+                _ => elf_os.system_libraries(),
+            };
+
+            let collected_libraries = collect_all_library_dirs(
+                ctx,
+                source,
+                &shared_library_config.dynamic_linking,
+                needed_libraries,
+                rpath_library_paths,
+                &system_libraries,
+                pending_paths,
+            )?;
+            check_closure(
+                ctx,
+                source,
+                collected_libraries.libraries,
+                collected_libraries.provided,
+            )?;
+
+            let normalized_contents = if shared_library_config.normalize_soname {
+                normalize_soname(&program_object, contents)
+                    .with_context(|| format!("failed to normalize soname of {:?}", source.path))?
+            } else {
+                None
+            };
+
+            (collected_libraries.resource_dirs, normalized_contents)
+        }
+        #[cfg(feature = "macho")]
+        Object::MachO(macho) => {
+            let library_dirs = crate::macho::shared_library_dirs(
+                ctx,
+                shared_library_config,
+                source,
+                &macho,
+                pending_paths,
+            )?;
+            (library_dirs, None)
+        }
+        _ => {
+            eyre::bail!(
+                "tried to autopack unsupported shared library: {}",
+                source.path.display()
+            );
+        }
+    };
+
+    let library_dirs = merge_library_dirs(ctx, library_dir_resource_paths, existing_library_dirs)?;
+    let pack = brioche_pack::Pack::Static { library_dirs };
+
+    if !pack.should_add_to_executable()
+        && !shared_library_config.allow_empty
+        && normalized_contents.is_none()
+        && !has_existing_pack
+        && !is_rewritten
+    {
+        return Ok(Outcome::Skipped(SkipReason::NoLibraryDirs));
+    }
+
+    let base = match &normalized_contents {
+        Some(contents) => PackBase::Contents(contents),
+        None if has_existing_pack || is_rewritten => PackBase::Contents(contents),
+        None => PackBase::Source {
+            path: &source.path,
+            contents: &source.contents,
+        },
+    };
+    ctx.emit_pack(
+        output_path,
+        base,
+        &pack,
+        &shared_library_config.fingerprint(),
+    )?;
+
+    Ok(Outcome::Autopacked(AutopackKind::SharedLibrary))
+}
+
+/// A file's contents without the `Static` pack it was autopacked with
+/// before, along with its attached packs.
+struct WithoutStaticPack<'a> {
+    contents: &'a [u8],
+
+    /// The library dirs from the pack, if there was one.
+    existing_library_dirs: Vec<Vec<u8>>,
+}
+
+/// Remove the `Static` pack a file was autopacked with before. Files
+/// without a pack are returned as-is. Returns `None` if the file has a
+/// different kind of pack.
+fn without_static_pack(source: &SourceFile) -> eyre::Result<Option<WithoutStaticPack<'_>>> {
+    let existing_pack =
+        brioche_resources::extract_padded_pack(std::io::Cursor::new(&source.contents)).ok();
+    let Some(extracted) = existing_pack else {
+        return Ok(Some(WithoutStaticPack {
+            contents: &source.contents,
+            existing_library_dirs: vec![],
+        }));
+    };
+    let brioche_pack::Pack::Static { library_dirs } = extracted.pack else {
+        return Ok(None);
+    };
+
+    let attached = brioche_resources::extract_attached_packs(
+        std::io::Cursor::new(&source.contents),
+        extracted.unpacked_len,
+    )?;
+    Ok(Some(WithoutStaticPack {
+        contents: &source.contents[..attached.unpacked_len],
+        existing_library_dirs: library_dirs,
+    }))
+}
+
+/// Get the library dirs for a `Static` pack. The library dirs of the pack
+/// being replaced are kept unless repacking, so libraries it found before
+/// can still be found.
+fn merge_library_dirs(
+    ctx: &AutopackContext,
+    library_dir_resource_paths: Vec<PathBuf>,
+    existing_library_dirs: Vec<Vec<u8>>,
+) -> eyre::Result<Vec<Vec<u8>>> {
+    let mut library_dirs = library_dir_resource_paths
+        .into_iter()
+        .map(|resource_path| {
+            <Vec<u8>>::from_path_buf(resource_path)
+                .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    if ctx.config.repack.is_none() {
+        for library_dir in existing_library_dirs {
+            if !library_dirs.contains(&library_dir) {
+                library_dirs.push(library_dir);
+            }
+        }
+    }
+
+    Ok(library_dirs)
+}
+
+/// Absolute paths on the host to search for host libraries at runtime.
+/// These are passed as runtime library dirs, which launchers skip if they
+/// don't exist.
+fn host_library_dirs(
+    dynamic_linking_config: &DynamicLinkingConfig,
+) -> impl Iterator<Item = eyre::Result<Vec<u8>>> + '_ {
+    dynamic_linking_config
+        .host_library_paths
+        .iter()
+        .map(|path| {
+            eyre::ensure!(
+                path.is_absolute(),
+                "expected host library path to be absolute: {path:?}"
+            );
+            <Vec<u8>>::from_path_buf(path.clone()).map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
+        })
+}
+
+impl DynamicLinkingConfig {
+    /// Libraries to add on top of the ones the ELF file needs, including
+    /// optional libraries.
+    pub(crate) fn additional_libraries(&self) -> impl Iterator<Item = &str> {
+        self.extra_libraries
+            .iter()
+            .chain(&self.optional_libraries)
+            .map(|lib| &**lib)
+    }
+
+    /// Check if a library can be skipped without a warning if it can't be
+    /// found, either because it's one of the optional libraries or it
+    /// matches one of the optional library globs.
+    fn is_optional_library(
+        &self,
+        optional_library_globs: &OptionalLibraryGlobs,
+        library_name: &str,
+    ) -> bool {
+        self.optional_libraries
+            .iter()
+            .any(|lib| lib == library_name)
+            || optional_library_globs.is_match(self, library_name)
+    }
+}
+
+/// The optional library globs of each dynamic linking config, compiled
+/// once before autopacking. Each glob set is keyed by its patterns.
+pub(crate) struct OptionalLibraryGlobs(HashMap<Vec<String>, globset::GlobSet>);
+
+impl OptionalLibraryGlobs {
+    pub(crate) fn new(config: &crate::AutopackConfig) -> eyre::Result<Self> {
+        let dynamic_linking_configs = config
+            .dynamic_binary
+            .iter()
+            .map(|dynamic_binary| &dynamic_binary.dynamic_linking)
+            .chain(
+                config
+                    .shared_library
+                    .iter()
+                    .map(|shared_library| &shared_library.dynamic_linking),
+            );
+
+        let mut glob_sets = HashMap::new();
+        for dynamic_linking in dynamic_linking_configs {
+            let patterns = &dynamic_linking.optional_library_globs;
+            if patterns.is_empty() || glob_sets.contains_key(patterns) {
+                continue;
+            }
+
+            let mut globs = globset::GlobSetBuilder::new();
+            for pattern in patterns {
+                let glob = globset::Glob::new(pattern)
+                    .map_err(|error| InvalidGlobPattern::new(pattern, error))?;
+                globs.add(glob);
+            }
+            glob_sets.insert(patterns.clone(), globs.build()?);
+        }
+
+        Ok(Self(glob_sets))
+    }
+
+    /// Check if a library matches one of the optional library globs from
+    /// `dynamic_linking`.
+    fn is_match(&self, dynamic_linking: &DynamicLinkingConfig, library_name: &str) -> bool {
+        self.0
+            .get(dynamic_linking.optional_library_globs.as_slice())
+            .is_some_and(|globs| globs.is_match(library_name))
+    }
+}
+
+/// Get the names of the libraries `source` loads with `dlopen`, from the
+/// dynamic linking config. Libraries given by path are added to
+/// `library_paths` under their file names.
+fn dlopen_libraries(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    dynamic_linking_config: &DynamicLinkingConfig,
+    library_paths: &mut HashMap<String, PathBuf>,
+) -> eyre::Result<Vec<String>> {
+    if dynamic_linking_config.dlopen_libraries.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let libraries = dynamic_linking_config
+        .dlopen_libraries
+        .get(&source.path)
+        .or_else(|| {
+            let relative_path = ctx.recipe_relative_path(&source.path)?;
+            dynamic_linking_config.dlopen_libraries.get(&relative_path)
+        });
+    let Some(libraries) = libraries else {
+        return Ok(vec![]);
+    };
+
+    let mut library_names = vec![];
+    for library in libraries {
+        if !library.contains('/') {
+            library_names.push(library.clone());
+            continue;
+        }
+
+        let library_path = Path::new(library);
+        eyre::ensure!(
+            library_path.is_file(),
+            "dlopen library for {} not found: {library:?}",
+            source.path.display(),
+        );
+        let library_name = library_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_eyre("invalid dlopen library path")?;
+        library_paths.insert(library_name.to_string(), library_path.to_owned());
+        library_names.push(library_name.to_string());
+    }
+
+    Ok(library_names)
+}
+
+pub(crate) fn collect_all_library_dirs(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    dynamic_linking_config: &DynamicLinkingConfig,
+    needed_libraries: VecDeque<String>,
+    rpath_search_paths: Vec<PathBuf>,
+    system_libraries: &HashSet<String>,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<CollectedLibraries> {
+    let mut library_search_paths = vec![];
+    let mut resource_library_dirs = vec![];
+    let mut resolved_libraries = vec![];
+    let mut provided_libraries = vec![];
+    let mut found_libraries = HashSet::new();
+    let mut found_library_dirs = HashSet::new();
+    let mut has_host_libraries = false;
+    let mut has_unparsed_symbols = false;
+    let source_arch = ElfArch::parse(&source.contents);
+    let target = dependency_target(ctx, &source.contents);
+    let is_foreign = target.is_none() && ctx.config.target.is_some();
+
+    // Search paths from the program's own rpath come first, since those
+    // point to the libraries it was built to load
+    add_search_paths(&mut library_search_paths, rpath_search_paths);
+    add_search_paths(
+        &mut library_search_paths,
+        dynamic_linking_config.library_paths.iter().cloned(),
+    );
+
+    // Libraries the file loads with dlopen are queued along with the ones
+    // it needs directly. Ones given by path are used instead of searching
+    let mut dlopen_library_paths = HashMap::new();
+    let dlopen_libraries = dlopen_libraries(
+        ctx,
+        source,
+        dynamic_linking_config,
+        &mut dlopen_library_paths,
+    )?;
+
+    // Each library is queued with the libraries that needed it, which is
+    // empty for the libraries the file needs directly
+    let mut needed_libraries: VecDeque<_> = needed_libraries
+        .into_iter()
+        .chain(dlopen_libraries)
+        .map(|library_name| (library_name, vec![]))
+        .collect();
+
+    while let Some((library_name, needed_by)) = needed_libraries.pop_front() {
+        let depth = needed_by.len() + 1;
+
+        // If we've already found this library, or if it's provided by the
+        // system or host at runtime, then skip it
+        if found_libraries.contains(&library_name) {
+            continue;
+        }
+        if system_libraries.contains(&library_name)
+            || dynamic_linking_config
+                .host_libraries
+                .contains(&library_name)
+        {
+            has_host_libraries = true;
+            continue;
+        }
+
+        // Stop at cut libraries and libraries past the max depth, since
+        // they're provided at runtime
+        let past_max_depth = dynamic_linking_config
+            .max_depth
+            .is_some_and(|max_depth| depth > max_depth.get() as usize);
+        if past_max_depth || dynamic_linking_config.cut_libraries.contains(&library_name) {
+            found_libraries.insert(library_name.clone());
+            provided_libraries.push(library_name);
+            continue;
+        }
+
+        // Find the path to the library, only from the library map if set,
+        // unless it's a dlopen library given by path
+        let library_path = match (
+            dlopen_library_paths.get(&library_name),
+            &ctx.config.library_map,
+        ) {
+            (Some(library_path), _) => Some(library_path.clone()),
+            (None, Some(library_map)) => library_map.get(&library_name).cloned(),
+            (None, None) => {
+                let library_path = find_library(
+                    &library_search_paths,
+                    &library_name,
+                    target,
+                    source_arch,
+                    &ctx.config.glibc_hwcaps,
+                    &ctx.report.dangling_symlinks,
+                )?;
+                match library_path {
+                    Some(library_path) => Some(library_path),
+                    None if is_foreign => ctx
+                        .resolver
+                        .find_foreign_library(&library_name, &source.path)?,
+                    None => ctx.resolver.find_library(&library_name)?,
+                }
+            }
+        };
+        let Some(library_path) = library_path else {
+            if dynamic_linking_config.is_optional_library(ctx.optional_library_globs, &library_name)
+            {
+                continue;
+            } else if dynamic_linking_config.skip_unknown_libraries {
+                ctx.warn(AutopackWarning::SkippedUnknownLibrary {
+                    path: source.path.clone(),
+                    name: library_name,
+                })?;
+                continue;
+            }
+
+            // A library that was only skipped for being built for another
+            // architecture gets reported as a mismatch instead
+            if let Some(source_arch) = source_arch {
+                let other_library_path = find_library(
+                    &library_search_paths,
+                    &library_name,
+                    None,
+                    None,
+                    &ctx.config.glibc_hwcaps,
+                    &ctx.report.dangling_symlinks,
+                )?;
+                if let Some(other_library_path) = other_library_path {
+                    check_arch(source, source_arch, &other_library_path)?;
+                }
+            }
+
+            if is_libc(&library_name) {
+                return Err(libc_not_found(ctx, &library_name).into());
+            } else {
+                eyre::bail!("library not found: {library_name:?}");
+            }
+        };
+
+        // Some libraries, such as `libc.so`, are linker scripts listing the
+        // real libraries, so look for those instead. Inputs are found by
+        // filename rather than by absolute path, since absolute paths in
+        // the script point to where the libraries were originally installed
+        if let Some(linker_script) = LinkerScript::read(&library_path) {
+            found_libraries.insert(library_name);
+
+            let script_dir = library_path
+                .parent()
+                .ok_or_eyre("failed to get linker script parent dir")?;
+            for input in linker_script.inputs {
+                match input {
+                    LinkerScriptInput::Path(input_path) => {
+                        // Static archives are only used when linking
+                        if input_path.extension() == Some("a".as_ref()) {
+                            continue;
+                        }
+
+                        let Some(input_name) = input_path.file_name() else {
+                            continue;
+                        };
+                        let input_name = input_name.to_str().ok_or_else(|| {
+                            eyre::eyre!("invalid library name in linker script: {input_path:?}")
+                        })?;
+                        if input_path.is_relative() {
+                            let input_dir = script_dir.join(&input_path);
+                            if let Some(input_dir) = input_dir.parent() {
+                                add_search_paths(&mut library_search_paths, [input_dir.to_owned()]);
+                            }
+                        }
+                        needed_libraries.push_back((input_name.to_string(), needed_by.clone()));
+                    }
+                    LinkerScriptInput::Library(name) => {
+                        needed_libraries.push_back((format!("lib{name}.so"), needed_by.clone()));
+                    }
+                }
+            }
+            add_search_paths(&mut library_search_paths, [script_dir.to_owned()]);
+
+            continue;
+        }
+
+        // Libraries from the resolver aren't filtered by architecture
+        if let Some(source_arch) = source_arch {
+            check_arch(source, source_arch, &library_path)?;
+        }
+
+        // Get the library's other names before autopacking it, since it
+        // may be read from a different path afterwards
+        let library_link_names = symlink_chain_names(&library_path, ctx.config.symlink_limit);
+
+        // Autopack the library if it's pending
+        let library_path = try_autopack_dependency(ctx, &library_path, pending_paths)?;
+
+        found_libraries.insert(library_name.clone());
+
+        // Read the library to get its own dependencies, and what it provides
+        let library_file = std::fs::read(&library_path).ok();
+        let library_object = library_file
+            .as_deref()
+            .and_then(|contents| parse_object(contents, ctx.config.target.as_ref()).ok());
+        let library_build_id = match (&library_object, &library_file) {
+            (Some(Object::Elf(library_elf)), Some(library_file)) => {
+                gnu_build_id(library_elf, library_file)
+            }
+            _ => None,
+        };
+        if let Some(library_build_id) = &library_build_id {
+            check_build_library(
+                ctx,
+                source,
+                dynamic_linking_config,
+                &library_name,
+                &library_path,
+                library_build_id,
+            )?;
+        }
+
+        // Don't add the library if it's been skipped. We still do everything
+        // else so we can add transitive dependencies even if a library has
+        // been skipped
+        if !dynamic_linking_config
+            .skip_libraries
+            .contains(&*library_name)
+        {
+            // Add the library to the resource directory
+            let library_alias = Path::new(&library_name);
+            let library_resource_path = add_named_blob_from(
+                ctx,
+                &library_path,
+                Some(library_alias),
+                ResourceRole::Library,
+            )
+            .with_context(|| format!("failed to add resource for library {library_path:?}"))?;
+            if let Some(library_build_id) = &library_build_id {
+                add_build_id(ctx, &library_resource_path, library_build_id)?;
+            }
+
+            // Also add the library under the names it was symlinked from
+            // and its soname, so it can be loaded by any of them
+            let library_soname = match &library_object {
+                Some(Object::Elf(library_elf)) => library_elf.soname,
+                _ => None,
+            };
+            let mut other_names = BTreeSet::new();
+            other_names.extend(library_link_names.iter().map(|name| &**name));
+            other_names.extend(library_soname.map(OsStr::new));
+            other_names.remove(OsStr::new(&library_name));
+            for other_name in other_names {
+                // Names with a path are only loaded by path, not from the
+                // library dirs
+                if other_name.as_encoded_bytes().contains(&b'/') {
+                    continue;
+                }
+                add_alias(ctx, &library_resource_path, Path::new(other_name)).with_context(
+                    || format!("failed to add alias {other_name:?} for library {library_path:?}"),
+                )?;
+            }
+
+            // Add the parent dir to the list of library directories. Note
+            // that this directory is guaranteed to only contain just this
+            // library
+            let library_resource_dir = library_resource_path
+                .parent()
+                .ok_or_eyre("failed to get resource parent dir")?
+                .to_owned();
+
+            let is_new_library_path = found_library_dirs.insert(library_resource_dir.clone());
+            if is_new_library_path {
+                resource_library_dirs.push(library_resource_dir.clone());
+            }
+
+            let mut resolved_library = ResolvedLibrary::new(
+                library_name.clone(),
+                library_path.clone(),
+                library_resource_path,
+            )?;
+            match &library_object {
+                Some(Object::Elf(library_elf)) => {
+                    resolved_library.soname = library_elf.soname.map(|soname| soname.to_string());
+                    resolved_library.build_id.clone_from(&library_build_id);
+                    if ctx.config.closure_policy.is_some() || dynamic_linking_config.check_symbols {
+                        resolved_library.symbols = exported_symbols(library_elf);
+                    }
+                    has_unparsed_symbols |= symbols_unparsed(library_elf);
+                }
+                #[cfg(feature = "macho")]
+                Some(Object::MachO(library_macho)) => {
+                    resolved_library.soname = library_macho.name.map(|name| name.to_string());
+                }
+                _ => {}
+            }
+            resolved_library.needed_by.clone_from(&needed_by);
+            resolved_libraries.push(resolved_library);
+        }
+
+        // Add the library's own dependencies, using its rpath to find them
+        let Some(library_file) = &library_file else {
+            continue;
+        };
+        let mut library_needed_by = needed_by;
+        library_needed_by.push(library_name);
+        match &library_object {
+            Some(Object::Elf(library_elf)) => {
+                needed_libraries.extend(
+                    library_elf
+                        .libraries
+                        .iter()
+                        .map(|lib| (lib.to_string(), library_needed_by.clone())),
+                );
+                let library_rpath_paths = rpath_library_paths(
+                    library_elf,
+                    &library_path,
+                    &dynamic_linking_config.rpath_tokens,
+                    ctx.config.target.as_ref(),
+                )?;
+                add_search_paths(&mut library_search_paths, library_rpath_paths);
+            }
+            #[cfg(feature = "macho")]
+            Some(Object::MachO(library_macho)) => {
+                needed_libraries.extend(
+                    crate::macho::needed_libraries(library_macho)
+                        .into_iter()
+                        .map(|lib| (lib, library_needed_by.clone())),
+                );
+                let library_rpath_paths =
+                    crate::macho::rpath_library_paths(library_macho, &library_path)?;
+                add_search_paths(&mut library_search_paths, library_rpath_paths);
+            }
+            _ => continue,
+        }
+
+        // If the library has a Brioche pack, then use the included resources
+        // for additional search directories
+        let library_file_cursor = std::io::Cursor::new(&library_file[..]);
+        if let Ok(extracted_library) = brioche_resources::extract_padded_pack(library_file_cursor) {
+            let library_dirs = match &extracted_library.pack {
+                brioche_pack::Pack::LdLinux { library_dirs, .. } => &library_dirs[..],
+                brioche_pack::Pack::Static { library_dirs } => &library_dirs[..],
+                brioche_pack::Pack::Metadata { .. } => &[],
+            };
+
+            for library_dir in library_dirs {
+                let Ok(library_dir) = library_dir.to_path() else {
+                    continue;
+                };
+                let Some(library_dir_path) = brioche_resources::find_in_resource_dirs(
+                    &ctx.config.all_resource_dirs,
+                    library_dir,
+                ) else {
+                    continue;
+                };
+
+                add_search_paths(&mut library_search_paths, [library_dir_path]);
+            }
+        }
+    }
+
+    check_closure_size(source, dynamic_linking_config, &resolved_libraries)?;
+
+    // Symbols could come from any library provided at runtime, so they
+    // can only be checked if the closure has every library, and every
+    // library's symbols could be parsed
+    if dynamic_linking_config.check_symbols
+        && !has_host_libraries
+        && !has_unparsed_symbols
+        && provided_libraries.is_empty()
+    {
+        check_symbols(ctx, source, &resolved_libraries)?;
+    }
+
+    Ok(CollectedLibraries {
+        resource_dirs: resource_library_dirs,
+        libraries: resolved_libraries,
+        provided: provided_libraries,
+    })
+}
+
+/// Check a closure against the max closure libraries and bytes.
+fn check_closure_size(
+    source: &SourceFile,
+    dynamic_linking_config: &DynamicLinkingConfig,
+    libraries: &[ResolvedLibrary],
+) -> eyre::Result<()> {
+    let bytes = libraries.iter().map(|library| library.size).sum::<u64>();
+    let reason = match (
+        dynamic_linking_config.max_closure_libraries,
+        dynamic_linking_config.max_closure_bytes,
+    ) {
+        (Some(max_libraries), _) if libraries.len() > max_libraries => format!(
+            "{} libraries is more than the limit of {max_libraries}",
+            libraries.len()
+        ),
+        (_, Some(max_bytes)) if bytes > max_bytes => {
+            format!("{bytes} bytes is more than the limit of {max_bytes}")
+        }
+        _ => return Ok(()),
+    };
+
+    Err(ClosureTooLarge {
+        path: source.path.clone(),
+        reason,
+        libraries: libraries.iter().map(ClosureLibrary::from).collect(),
+    }
+    .into())
+}
+
+/// Get the filenames along the chain of symlinks from `path` to the file
+/// it points to, including the file's own name. For example, this returns
+/// `libfoo.so.1` and `libfoo.so.1.2.3` for `libfoo.so.1 -> libfoo.so.1.2.3`.
+fn symlink_chain_names(path: &Path, symlink_limit: u32) -> Vec<OsString> {
+    let mut names = vec![];
+    let mut path = path.to_owned();
+    for _ in 0..=symlink_limit {
+        if let Some(name) = path.file_name() {
+            names.push(name.to_owned());
+        }
+
+        let Ok(target) = std::fs::read_link(&path) else {
+            break;
+        };
+        path = match path.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+    }
+
+    names
+}
+
+/// Get an ELF file's GNU build ID, as a hex string.
+fn gnu_build_id(elf: &goblin::elf::Elf, contents: &[u8]) -> Option<String> {
+    let notes = elf.iter_note_headers(contents)?;
+    for note in notes {
+        let Ok(note) = note else {
+            continue;
+        };
+        if note.n_type == goblin::elf::note::NT_GNU_BUILD_ID && note.name == "GNU" {
+            let build_id = note.desc.iter().map(|byte| format!("{byte:02x}")).collect();
+            return Some(build_id);
+        }
+    }
+
+    None
+}
+
+/// Get the GNU build ID of an ELF source file, if it has one.
+fn source_build_id(source: &SourceFile) -> Option<String> {
+    let elf = parse_elf(&source.contents).ok()?;
+    gnu_build_id(&elf, &source.contents)
+}
+
+/// Index an ELF resource by its GNU build ID, if it has one.
+fn add_build_id_from_contents(
+    ctx: &AutopackContext,
+    resource_path: &Path,
+    contents: &[u8],
+) -> eyre::Result<()> {
+    let Ok(elf) = parse_elf(contents) else {
+        return Ok(());
+    };
+    if let Some(build_id) = gnu_build_id(&elf, contents) {
+        add_build_id(ctx, resource_path, &build_id)?;
+    }
+
+    Ok(())
+}
+
+/// Warn if a resolved library has a different build ID than the library
+/// with the same name that the build linked against. Build libraries
+/// match by their filename, or the name of any symlink they go through.
+fn check_build_library(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    dynamic_linking_config: &DynamicLinkingConfig,
+    library_name: &str,
+    library_path: &Path,
+    library_build_id: &str,
+) -> eyre::Result<()> {
+    for build_path in &dynamic_linking_config.build_libraries {
+        let build_names = symlink_chain_names(build_path, ctx.config.symlink_limit);
+        if !build_names.iter().any(|name| name == library_name) {
+            continue;
+        }
+
+        let Ok(build_contents) = std::fs::read(build_path) else {
+            continue;
+        };
+        let Ok(build_elf) = parse_elf(&build_contents) else {
+            continue;
+        };
+        let Some(build_id) = gnu_build_id(&build_elf, &build_contents) else {
+            continue;
+        };
+
+        if build_id != library_build_id {
+            ctx.warn(AutopackWarning::LibraryMismatch {
+                path: source.path.clone(),
+                library: library_name.to_string(),
+                resolved_path: library_path.to_owned(),
+                build_path: build_path.clone(),
+            })?;
+        }
+        return Ok(());
+    }
+
+    Ok(())
+}
+
+pub(crate) struct CollectedLibraries {
+    /// The resource dirs to add to the library search path.
+    pub resource_dirs: Vec<PathBuf>,
+    pub libraries: Vec<ResolvedLibrary>,
+    /// Libraries that weren't collected, since they're provided at runtime.
+    pub provided: Vec<String>,
+}
+
+/// Warn about undefined dynamic symbols of an ELF file that no library in
+/// its closure defines. Weak symbols are allowed to stay undefined, so
+/// they aren't checked.
+fn check_symbols(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    libraries: &[ResolvedLibrary],
+) -> eyre::Result<()> {
+    let Ok(Object::Elf(elf)) = parse_object(&source.contents, ctx.config.target.as_ref()) else {
+        return Ok(());
+    };
+
+    let defined_symbols: HashSet<&str> = libraries
+        .iter()
+        .flat_map(|library| &library.symbols)
+        .map(|symbol| &**symbol)
+        .collect();
+    let missing_symbols: Vec<_> = undefined_symbols(&elf)
+        .into_iter()
+        .filter(|symbol| !defined_symbols.contains(&**symbol))
+        .collect();
+    if !missing_symbols.is_empty() {
+        ctx.warn(AutopackWarning::MissingSymbols {
+            path: source.path.clone(),
+            symbols: missing_symbols,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Check if an ELF file has dynamic symbols that weren't parsed, which
+/// happens when it was parsed with [`parse_elf_lenient`].
+fn symbols_unparsed(elf: &goblin::elf::Elf) -> bool {
+    elf.dynamic
+        .as_ref()
+        .is_some_and(|dynamic| dynamic.info.symtab != 0 && elf.dynsyms.is_empty())
+}
+
+/// Get the names of the dynamic symbols an ELF file uses but doesn't
+/// define, other than weak symbols.
+fn undefined_symbols(elf: &goblin::elf::Elf) -> Vec<String> {
+    let mut symbols: Vec<_> = elf
+        .dynsyms
+        .iter()
+        .filter(|symbol| {
+            symbol.st_shndx == goblin::elf::section_header::SHN_UNDEF as usize
+                && symbol.st_bind() == goblin::elf::sym::STB_GLOBAL
+        })
+        .filter_map(|symbol| elf.dynstrtab.get_at(symbol.st_name))
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect();
+    symbols.sort();
+    symbols.dedup();
+    symbols
+}
+
+/// Get the names of the dynamic symbols an ELF file defines and exports.
+fn exported_symbols(elf: &goblin::elf::Elf) -> Vec<String> {
+    use goblin::elf::sym;
+
+    let mut symbols: Vec<_> = elf
+        .dynsyms
+        .iter()
+        .filter(|symbol| {
+            symbol.st_shndx != goblin::elf::section_header::SHN_UNDEF as usize
+                && matches!(symbol.st_bind(), sym::STB_GLOBAL | sym::STB_WEAK)
+                && !matches!(symbol.st_visibility(), sym::STV_HIDDEN | sym::STV_INTERNAL)
+                && !matches!(symbol.st_type(), sym::STT_SECTION | sym::STT_FILE)
+                // Version definitions show up as absolute symbols at 0
+                && !(symbol.st_shndx == goblin::elf::section_header::SHN_ABS as usize
+                    && symbol.st_value == 0)
+        })
+        .filter_map(|symbol| elf.dynstrtab.get_at(symbol.st_name))
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect();
+    symbols.sort();
+    symbols.dedup();
+    symbols
+}
+
+/// Normalize a shared library's `DT_SONAME` to just a filename, for
+/// libraries built with a path as their soname (e.g. `lib/libfoo.so.1`).
+/// Files linked against such a library would otherwise need it by that
+/// path. Returns the normalized contents, or `None` if the soname is
+/// already just a filename.
+///
+/// The filename is always the tail of the existing string, so the
+/// `DT_SONAME` entry is pointed to it rather than changing the string
+/// table, which other entries can share.
+fn normalize_soname(elf: &goblin::elf::Elf, contents: &[u8]) -> eyre::Result<Option<Vec<u8>>> {
+    let Some(soname) = elf.soname else {
+        return Ok(None);
+    };
+    let Some((prefix, _)) = soname.rsplit_once('/') else {
+        return Ok(None);
+    };
+
+    let dynamic_header = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == goblin::elf::program_header::PT_DYNAMIC)
+        .ok_or_eyre("no dynamic segment")?;
+    let dynamic = elf.dynamic.as_ref().ok_or_eyre("no dynamic section")?;
+    let soname_index = dynamic
+        .dyns
+        .iter()
+        .position(|entry| entry.d_tag == goblin::elf::dynamic::DT_SONAME)
+        .ok_or_eyre("no DT_SONAME entry")?;
+    let soname_entry = &dynamic.dyns[soname_index];
+    let new_value = soname_entry.d_val + prefix.len() as u64 + 1;
+
+    // Each entry is a tag followed by a value, both the ELF's word size
+    let word_size = if elf.is_64 { 8 } else { 4 };
+    let value_offset = dynamic_header.p_offset as usize + soname_index * word_size * 2 + word_size;
+    let value_bytes = match (elf.is_64, elf.little_endian) {
+        (true, true) => new_value.to_le_bytes().to_vec(),
+        (true, false) => new_value.to_be_bytes().to_vec(),
+        (false, true) => u32::try_from(new_value)?.to_le_bytes().to_vec(),
+        (false, false) => u32::try_from(new_value)?.to_be_bytes().to_vec(),
+    };
+
+    let mut contents = contents.to_vec();
+    contents
+        .get_mut(value_offset..value_offset + word_size)
+        .ok_or_eyre("DT_SONAME entry out of bounds")?
+        .copy_from_slice(&value_bytes);
+    Ok(Some(contents))
+}
+
+/// Apply [`DynamicLinkingConfig::drop_libraries`] and
+/// [`DynamicLinkingConfig::rename_libraries`] to an ELF file, before
+/// anything else reads its needed libraries. Returns `None` if the file
+/// doesn't need to change.
+fn rewrite_needed_libraries(
+    dynamic_linking_config: &DynamicLinkingConfig,
+    source: &SourceFile,
+) -> eyre::Result<Option<SourceFile>> {
+    if dynamic_linking_config.drop_libraries.is_empty()
+        && dynamic_linking_config.rename_libraries.is_empty()
+    {
+        return Ok(None);
+    }
+
+    // Only ELF files have `DT_NEEDED` entries
+    let Object::Elf(elf) = parse_object(&source.contents, None)? else {
+        return Ok(None);
+    };
+    let contents = edit_needed_libraries(
+        &elf,
+        &source.contents,
+        &dynamic_linking_config.drop_libraries,
+        &dynamic_linking_config.rename_libraries,
+    )
+    .with_context(|| format!("failed to edit needed libraries of {:?}", source.path))?;
+
+    Ok(contents.map(|contents| SourceFile {
+        path: source.path.clone(),
+        contents,
+        is_executable: source.is_executable,
+    }))
+}
+
+/// Remove and rename an ELF file's `DT_NEEDED` entries. Returns the new
+/// contents, or `None` if none of the entries match.
+///
+/// New names are written over the old strings, which keeps the version
+/// requirements naming the same library in sync. Renaming fails if another
+/// string shares the old name's tail, see [`ensure_unshared_dynstr`].
+/// Removed entries are dropped from the dynamic section, moving the entries
+/// after them up, and their version requirements are unlinked (see
+/// [`drop_version_requirements`]).
+fn edit_needed_libraries(
+    elf: &goblin::elf::Elf,
+    contents: &[u8],
+    drop_libraries: &HashSet<String>,
+    rename_libraries: &std::collections::HashMap<String, String>,
+) -> eyre::Result<Option<Vec<u8>>> {
+    use goblin::elf::dynamic::{DT_NEEDED, DT_VERNEED, DT_VERNEEDNUM};
+
+    let Some(dynamic) = &elf.dynamic else {
+        return Ok(None);
+    };
+    let needed_name = |entry: &goblin::elf::Dyn| {
+        (entry.d_tag == DT_NEEDED)
+            .then(|| elf.dynstrtab.get_at(entry.d_val as usize))
+            .flatten()
+    };
+    let is_dropped = |entry: &goblin::elf::Dyn| {
+        needed_name(entry).is_some_and(|name| drop_libraries.contains(name))
+    };
+
+    let has_changes = dynamic.dyns.iter().any(|entry| {
+        needed_name(entry).is_some_and(|name| {
+            drop_libraries.contains(name) || rename_libraries.contains_key(name)
+        })
+    });
+    if !has_changes {
+        return Ok(None);
+    }
+
+    let original_contents = contents;
+    let mut contents = contents.to_vec();
+    for entry in &dynamic.dyns {
+        let Some(name) = needed_name(entry) else {
+            continue;
+        };
+        let Some(new_name) = rename_libraries.get(name).filter(|_| !is_dropped(entry)) else {
+            continue;
+        };
+        eyre::ensure!(
+            new_name.len() <= name.len(),
+            "can't rename needed library {name:?} to {new_name:?}, since the new name is longer"
+        );
+        ensure_unshared_dynstr(elf, original_contents, entry.d_val as usize, name.len())
+            .with_context(|| format!("can't rename needed library {name:?} to {new_name:?}"))?;
+
+        let start = dynamic.info.strtab + entry.d_val as usize;
+        let slot = contents
+            .get_mut(start..start + name.len())
+            .ok_or_eyre("DT_NEEDED string out of bounds")?;
+        slot.fill(0);
+        slot[..new_name.len()].copy_from_slice(new_name.as_bytes());
+    }
+
+    let dropped_libraries = dynamic
+        .dyns
+        .iter()
+        .filter(|entry| is_dropped(entry))
+        .filter_map(needed_name)
+        .collect::<HashSet<_>>();
+    let version_requirements = drop_version_requirements(elf, &mut contents, &dropped_libraries)?;
+
+    // Each entry is a tag followed by a value, both the ELF's word size.
+    // The entries freed up at the end are filled with `DT_NULL` entries,
+    // which are all zeros
+    let word_size = if elf.is_64 { 8 } else { 4 };
+    let encode = |value: u64| -> eyre::Result<Vec<u8>> {
+        let bytes = match (elf.is_64, elf.little_endian) {
+            (true, true) => value.to_le_bytes().to_vec(),
+            (true, false) => value.to_be_bytes().to_vec(),
+            (false, true) => u32::try_from(value)?.to_le_bytes().to_vec(),
+            (false, false) => u32::try_from(value)?.to_be_bytes().to_vec(),
+        };
+        Ok(bytes)
+    };
+    let mut entries = vec![];
+    for entry in dynamic.dyns.iter().filter(|entry| !is_dropped(entry)) {
+        let value = match (entry.d_tag, &version_requirements) {
+            (DT_VERNEED, Some(remaining)) => entry.d_val + remaining.moved_by,
+            (DT_VERNEEDNUM, Some(remaining)) => remaining.count,
+            _ => entry.d_val,
+        };
+        entries.extend(encode(entry.d_tag)?);
+        entries.extend(encode(value)?);
+    }
+    entries.resize(dynamic.dyns.len() * word_size * 2, 0);
+
+    let dynamic_header = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == goblin::elf::program_header::PT_DYNAMIC)
+        .ok_or_eyre("no dynamic segment")?;
+    let start = usize::try_from(dynamic_header.p_offset)?;
+    contents
+        .get_mut(start..start + entries.len())
+        .ok_or_eyre("dynamic section out of bounds")?
+        .copy_from_slice(&entries);
+
+    Ok(Some(contents))
+}
+
+/// The version requirements left after unlinking some, see
+/// [`drop_version_requirements`].
+struct RemainingVersionRequirements {
+    /// The number of requirements left, for `DT_VERNEEDNUM`.
+    count: u64,
+
+    /// How many bytes the first requirement moved by, for `DT_VERNEED`.
+    moved_by: u64,
+}
+
+/// Unlink the version requirements (`.gnu.version_r`) naming dropped
+/// libraries, since the dynamic linker fails if it can't find the library
+/// a requirement names. Symbols that required one of their versions become
+/// unversioned, so nothing refers to the removed versions. Returns `None`
+/// if no requirement names a dropped library.
+fn drop_version_requirements(
+    elf: &goblin::elf::Elf,
+    contents: &mut [u8],
+    dropped_libraries: &HashSet<&str>,
+) -> eyre::Result<Option<RemainingVersionRequirements>> {
+    use goblin::elf::{
+        section_header::SHT_GNU_VERNEED,
+        symver::{VERSYM_VERSION, VER_NDX_GLOBAL},
+    };
+
+    /// A requirement's offset in the file, and the version indices of its
+    /// auxiliary entries.
+    struct Requirement {
+        offset: usize,
+        is_dropped: bool,
+        versions: Vec<u16>,
+    }
+
+    let Some(dynamic) = &elf.dynamic else {
+        return Ok(None);
+    };
+    let verneed = usize::try_from(dynamic.info.verneed)?;
+    if verneed == 0 {
+        return Ok(None);
+    }
+
+    // Each requirement and each of its auxiliary entries is 16 bytes, with
+    // the offsets to the next one relative to itself
+    let bytes = ElfBytes::new(elf);
+    let mut requirements = vec![];
+    let mut offset = verneed;
+    for _ in 0..dynamic.info.verneednum {
+        let aux_count = bytes.read_u16(contents, offset + 2)?;
+        let file = bytes.read_u32(contents, offset + 4)?;
+        let aux = bytes.read_u32(contents, offset + 8)?;
+        let next = bytes.read_u32(contents, offset + 12)?;
+
+        let mut versions = vec![];
+        let mut aux_offset = offset + aux as usize;
+        for _ in 0..aux_count {
+            versions.push(bytes.read_u16(contents, aux_offset + 6)?);
+            aux_offset += bytes.read_u32(contents, aux_offset + 12)? as usize;
+        }
+
+        let name = elf.dynstrtab.get_at(file as usize).unwrap_or_default();
+        requirements.push(Requirement {
+            offset,
+            is_dropped: dropped_libraries.contains(name),
+            versions,
+        });
+
+        if next == 0 {
+            break;
+        }
+        offset += next as usize;
+    }
+
+    let (dropped, kept): (Vec<_>, Vec<_>) = requirements
+        .into_iter()
+        .partition(|requirement| requirement.is_dropped);
+    if dropped.is_empty() {
+        return Ok(None);
+    }
+    let first_kept = kept
+        .first()
+        .ok_or_eyre("can't drop every library with version requirements")?;
+
+    for (index, requirement) in kept.iter().enumerate() {
+        let next = kept
+            .get(index + 1)
+            .map_or(0, |next| next.offset - requirement.offset);
+        bytes.write_u32(contents, requirement.offset + 12, u32::try_from(next)?)?;
+    }
+
+    let dropped_versions = dropped
+        .iter()
+        .flat_map(|requirement| &requirement.versions)
+        .collect::<HashSet<_>>();
+    let versym = usize::try_from(dynamic.info.versym)?;
+    if versym != 0 {
+        for index in 0..elf.dynsyms.len() {
+            let offset = versym + index * 2;
+            let version = bytes.read_u16(contents, offset)?;
+            if dropped_versions.contains(&(version & VERSYM_VERSION)) {
+                bytes.write_u16(contents, offset, VER_NDX_GLOBAL)?;
+            }
+        }
+    }
+
+    // Keep the section header in sync for other tools, since the dynamic
+    // linker only reads the dynamic section
+    let moved_by = (first_kept.offset - verneed) as u64;
+    let section = elf
+        .section_headers
+        .iter()
+        .position(|header| header.sh_type == SHT_GNU_VERNEED);
+    if let Some(section) = section {
+        let header = &elf.section_headers[section];
+        let header_offset =
+            usize::try_from(elf.header.e_shoff)? + section * usize::from(elf.header.e_shentsize);
+        let (addr, offset, size, info) = if elf.is_64 {
+            (16, 24, 32, 44)
+        } else {
+            (12, 16, 20, 28)
+        };
+        bytes.write_word(contents, header_offset + addr, header.sh_addr + moved_by)?;
+        bytes.write_word(
+            contents,
+            header_offset + offset,
+            header.sh_offset + moved_by,
+        )?;
+        bytes.write_word(
+            contents,
+            header_offset + size,
+            header.sh_size.saturating_sub(moved_by),
+        )?;
+        bytes.write_u32(contents, header_offset + info, u32::try_from(kept.len())?)?;
+    }
+
+    Ok(Some(RemainingVersionRequirements {
+        count: kept.len() as u64,
+        moved_by,
+    }))
+}
+
+/// Reads and writes integers in an ELF file's byte order.
+struct ElfBytes {
+    is_64: bool,
+    little_endian: bool,
+}
+
+impl ElfBytes {
+    fn new(elf: &goblin::elf::Elf) -> Self {
+        Self {
+            is_64: elf.is_64,
+            little_endian: elf.little_endian,
+        }
+    }
+
+    fn read<const N: usize>(&self, contents: &[u8], offset: usize) -> eyre::Result<[u8; N]> {
+        let bytes = contents
+            .get(offset..offset + N)
+            .ok_or_eyre("offset out of bounds")?;
+        let mut bytes: [u8; N] = bytes.try_into()?;
+        if !self.little_endian {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn write(&self, contents: &mut [u8], offset: usize, mut bytes: Vec<u8>) -> eyre::Result<()> {
+        if !self.little_endian {
+            bytes.reverse();
+        }
+        contents
+            .get_mut(offset..offset + bytes.len())
+            .ok_or_eyre("offset out of bounds")?
+            .copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn read_u16(&self, contents: &[u8], offset: usize) -> eyre::Result<u16> {
+        self.read(contents, offset).map(u16::from_le_bytes)
+    }
+
+    fn read_u32(&self, contents: &[u8], offset: usize) -> eyre::Result<u32> {
+        self.read(contents, offset).map(u32::from_le_bytes)
+    }
+
+    fn write_u16(&self, contents: &mut [u8], offset: usize, value: u16) -> eyre::Result<()> {
+        self.write(contents, offset, value.to_le_bytes().to_vec())
+    }
+
+    fn write_u32(&self, contents: &mut [u8], offset: usize, value: u32) -> eyre::Result<()> {
+        self.write(contents, offset, value.to_le_bytes().to_vec())
+    }
+
+    /// Write a value the size of the ELF's word size.
+    fn write_word(&self, contents: &mut [u8], offset: usize, value: u64) -> eyre::Result<()> {
+        if self.is_64 {
+            self.write(contents, offset, value.to_le_bytes().to_vec())
+        } else {
+            self.write_u32(contents, offset, u32::try_from(value)?)
+        }
+    }
+}
+
+/// Check the resolved libraries against the closure policy, if any. This
+/// happens before the autopacked file gets written, so a denied file is
+/// left untouched.
+pub(crate) fn check_closure(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    libraries: Vec<ResolvedLibrary>,
+    provided: Vec<String>,
+) -> eyre::Result<()> {
+    if let Some(closure_libraries) = ctx.closure_libraries {
+        let mut closure_libraries = closure_libraries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        *closure_libraries = libraries.iter().map(ClosureLibrary::from).collect();
+    }
+
+    let Some(closure_policy) = &ctx.config.closure_policy else {
+        return Ok(());
+    };
+
+    let closure = ResolvedClosure {
+        path: source.path.clone(),
+        libraries,
+        provided,
+    };
+    let verdict = closure_policy.check(&closure)?;
+    match verdict {
+        ClosureVerdict::Allow { annotations } => {
+            for annotation in annotations {
+                ctx.print(
+                    &source.path,
+                    format!("{}: {annotation}", source.path.display()),
+                );
+            }
+
+            Ok(())
+        }
+        ClosureVerdict::Deny { reason } => {
+            eyre::bail!(
+                "closure policy denied autopacking {}: {reason}",
+                source.path.display()
+            );
+        }
+    }
+}
+
+/// Get the library search paths from an ELF file's `DT_RUNPATH` (or
+/// `DT_RPATH` if there's no runpath), relative to `elf_path`.
+///
+/// Only entries starting with `$ORIGIN` are returned, since absolute
+/// entries refer to paths outside of the recipe. `$LIB` and `$PLATFORM`
+/// tokens are expanded using `rpath_tokens`, with `$PLATFORM` defaulting
+/// to the target's architecture.
+fn rpath_library_paths(
+    elf: &goblin::elf::Elf,
+    elf_path: &Path,
+    rpath_tokens: &RpathTokens,
+    target: Option<&target_lexicon::Triple>,
+) -> eyre::Result<Vec<PathBuf>> {
+    let origin = elf_path
+        .parent()
+        .ok_or_eyre("failed to get parent of ELF path")?;
+    let platform = rpath_tokens.platform(target);
+
+    // `DT_RPATH` is ignored by the dynamic linker when `DT_RUNPATH` is set
+    let rpaths = if elf.runpaths.is_empty() {
+        &elf.rpaths
+    } else {
+        &elf.runpaths
+    };
+
+    // Binaries edited with patchelf often list the same entry more than
+    // once, or with stray `.` components and trailing slashes
+    let mut paths = vec![];
+    add_search_paths(
+        &mut paths,
+        rpaths
+            .iter()
+            .flat_map(|rpath| rpath.split(':'))
+            .filter_map(|entry| expand_rpath_entry(entry, origin, &rpath_tokens.lib, &platform)),
+    );
+    Ok(paths)
+}
+
+/// Add library search paths, skipping paths that are already included.
+/// Paths are compared after normalizing away `.` components, repeated
+/// separators, and trailing slashes.
+fn add_search_paths(search_paths: &mut Vec<PathBuf>, paths: impl IntoIterator<Item = PathBuf>) {
+    for path in paths {
+        let path: PathBuf = path.components().collect();
+        if !search_paths.contains(&path) {
+            search_paths.push(path);
+        }
+    }
+}
+
+/// Expand an rpath entry starting with `$ORIGIN`. Like the dynamic linker,
+/// tokens are only recognized when followed by `/` or the end of the
+/// entry, so `$ORIGINAL` or `$LIBEXEC` are left alone.
+fn expand_rpath_entry(entry: &str, origin: &Path, lib: &str, platform: &str) -> Option<PathBuf> {
+    let mut rest = strip_rpath_token(entry, "ORIGIN")?;
+    let mut relative_path = String::new();
+    while let Some(index) = rest.find('$') {
+        relative_path.push_str(&rest[..index]);
+        let token = &rest[index..];
+        let expanded = [("LIB", lib), ("PLATFORM", platform)]
+            .into_iter()
+            .find_map(|(name, value)| Some((value, strip_rpath_token(token, name)?)));
+        match expanded {
+            Some((value, after)) => {
+                relative_path.push_str(value);
+                rest = after;
+            }
+            None => {
+                relative_path.push('$');
+                rest = &token[1..];
+            }
+        }
+    }
+    relative_path.push_str(rest);
+    let relative_path = relative_path.trim_start_matches('/');
+
+    Some(origin.join(relative_path))
+}
+
+/// Strip a `$NAME` or `${NAME}` token from the start of an rpath entry,
+/// if it's followed by `/` or the end of the entry.
+fn strip_rpath_token<'a>(entry: &'a str, name: &str) -> Option<&'a str> {
+    let rest = entry.strip_prefix('$')?;
+    let rest = match rest.strip_prefix('{') {
+        Some(braced) => braced.strip_prefix(name)?.strip_prefix('}')?,
+        None => rest.strip_prefix(name)?,
+    };
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+fn is_libc(library_name: &str) -> bool {
+    library_name == "libc.so"
+        || library_name.starts_with("libc.so.")
+        || library_name.starts_with("libc.musl-")
+}
+
+fn libc_not_found(ctx: &AutopackContext, name: &str) -> LibcNotFound {
+    LibcNotFound {
+        name: name.to_string(),
+        searched_dependencies: ctx
+            .config
+            .link_dependencies
+            .iter()
+            .chain(&ctx.config.default_libc_dependency)
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Find a library by name from the search paths. When a target or an ELF
+/// architecture is given, libraries for other targets are skipped, like the
+/// dynamic linker does for incompatible libraries.
+pub(crate) fn find_library(
+    library_search_paths: &[PathBuf],
+    library_name: &str,
+    target: Option<&target_lexicon::Triple>,
+    arch: Option<ElfArch>,
+    glibc_hwcaps: &[String],
+    dangling_symlinks: &DanglingSymlinks,
+) -> eyre::Result<Option<PathBuf>> {
+    let mut library_search_path_files = vec![];
+
+    // Try to find a direct filename match from the search paths
+    for path in library_search_paths {
+        if path.is_dir() {
+            // Optimized variants in `glibc-hwcaps` subdirectories take
+            // priority, like with glibc's dynamic linker
+            for hwcap in glibc_hwcaps {
+                let lib_path = path.join("glibc-hwcaps").join(hwcap).join(library_name);
+                if lib_path.is_file() && library_matches_target(&lib_path, target, arch) {
+                    return Ok(Some(lib_path));
+                }
+            }
+
+            // Check if the search path is a directory and contains a file
+            // matching the library name
+            let lib_path = path.join(library_name);
+            if lib_path.is_file() && library_matches_target(&lib_path, target, arch) {
+                return Ok(Some(lib_path));
+            }
+            dangling_symlinks.check(&lib_path);
+        } else if path.is_file() {
+            // Check if the search path is a file that matches the library
+            // name directly
+            let path_filename = path
+                .file_name()
+                .ok_or_eyre("failed to get filename from path")?;
+            if path_filename.to_str() == Some(library_name)
+                && library_matches_target(path, target, arch)
+            {
+                return Ok(Some(path.to_owned()));
+            }
+
+            // If the filename doesn't match, queue it for a further check
+            // if we don't find another path-based match
+            library_search_path_files.push(path);
+        } else {
+            dangling_symlinks.check(path);
+        }
+    }
+
+    // Try to find a library file that matches based on its `DT_SONAME` field
+    // as a fallback
+    for &path in &library_search_path_files {
+        let Ok(contents) = std::fs::read(path) else {
+            continue;
+        };
+
+        let Ok(elf) = parse_elf(&contents) else {
+            continue;
+        };
+
+        if elf.soname == Some(library_name) {
+            let matches_target = target
+                .is_none_or(|target| target_mismatch(&elf, target).is_none())
+                && arch.is_none_or(|arch| arch == ElfArch::of(&elf));
+            if matches_target {
+                return Ok(Some(path.to_owned()));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn library_matches_target(
+    path: &Path,
+    target: Option<&target_lexicon::Triple>,
+    arch: Option<ElfArch>,
+) -> bool {
+    if target.is_none() && arch.is_none() {
+        return true;
+    }
+
+    let Ok(contents) = std::fs::read(path) else {
+        return false;
+    };
+    match parse_object(&contents, target) {
+        Ok(Object::Elf(elf)) => {
+            target.is_none_or(|target| target_mismatch(&elf, target).is_none())
+                && arch.is_none_or(|arch| arch == ElfArch::of(&elf))
+        }
+        #[cfg(feature = "macho")]
+        Ok(Object::MachO(macho)) => {
+            arch.is_none()
+                && target.is_none_or(|target| crate::macho::matches_target(&macho, target))
+        }
+        // Linker scripts get checked by reading the libraries they list
+        _ => LinkerScript::parse(&contents).is_some(),
+    }
+}
+
+/// Check if an ELF or Mach-O file was built for a different target than
+/// the one being autopacked for, returning how it doesn't match. Files
+/// with a pack aren't checked, since they get autopacked from their source.
+pub(crate) fn foreign_arch_mismatch(ctx: &AutopackContext, contents: &[u8]) -> Option<String> {
+    let target = ctx.config.target.as_ref()?;
+    if brioche_resources::extract_padded_pack(std::io::Cursor::new(contents)).is_ok() {
+        return None;
+    }
+
+    match parse_object(contents, Some(target)) {
+        Ok(Object::Elf(elf)) => target_mismatch(&elf, target),
+        #[cfg(feature = "macho")]
+        Ok(Object::MachO(macho)) => (!crate::macho::matches_target(&macho, target))
+            .then(|| format!("Mach-O file does not match target {target}")),
+        _ => None,
+    }
+}
+
+/// Get the target that an ELF file's interpreter and libraries need to
+/// match. This is the configured target, unless the file was built for
+/// another target and is autopacked anyway, in which case they only need
+/// to match the file's own architecture.
+fn dependency_target<'a>(
+    ctx: &AutopackContext<'a>,
+    contents: &[u8],
+) -> Option<&'a target_lexicon::Triple> {
+    let target = ctx.config.target.as_ref()?;
+    match parse_object(contents, None) {
+        Ok(Object::Elf(elf)) if target_mismatch(&elf, target).is_some() => None,
+        _ => Some(target),
+    }
+}
+
+/// The class, byte order, and machine of an ELF file. A binary can only
+/// load an interpreter and libraries with the same architecture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ElfArch {
+    is_64: bool,
+    little_endian: bool,
+    machine: u16,
+}
+
+impl ElfArch {
+    pub(crate) fn of(elf: &goblin::elf::Elf) -> Self {
+        Self {
+            is_64: elf.is_64,
+            little_endian: elf.little_endian,
+            machine: elf.header.e_machine,
+        }
+    }
+
+    /// Get the architecture of an ELF file's contents, or `None` if it's
+    /// not an ELF file.
+    pub(crate) fn parse(contents: &[u8]) -> Option<Self> {
+        match parse_object(contents, None) {
+            Ok(Object::Elf(elf)) => Some(Self::of(&elf)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn read(path: &Path) -> Option<Self> {
+        let contents = std::fs::read(path).ok()?;
+        Self::parse(&contents)
+    }
+}
+
+impl std::fmt::Display for ElfArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bits = if self.is_64 { 64 } else { 32 };
+        let endianness = if self.little_endian { "little" } else { "big" };
+        let machine = goblin::elf::header::machine_to_str(self.machine);
+        write!(f, "a {bits}-bit {endianness}-endian {machine} ELF file")
+    }
+}
+
+/// Check that an interpreter or library at `other_path` has the same
+/// architecture as the file being autopacked.
+fn check_arch(source: &SourceFile, arch: ElfArch, other_path: &Path) -> eyre::Result<()> {
+    match ElfArch::read(other_path) {
+        Some(other_arch) if other_arch != arch => Err(ArchitectureMismatch {
+            path: source.path.clone(),
+            expected: arch.to_string(),
+            other_path: other_path.to_owned(),
+            found: other_arch.to_string(),
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Check if an ELF file was built for `target`, returning a description
+/// of the mismatch if it wasn't.
+pub(crate) fn target_mismatch(
+    elf: &goblin::elf::Elf,
+    target: &target_lexicon::Triple,
+) -> Option<String> {
+    use goblin::elf::header;
+
+    if let Some(expected_machine) = elf_machine(target.architecture) {
+        if elf.header.e_machine != expected_machine {
+            return Some(format!(
+                "machine {} does not match target {target}",
+                header::machine_to_str(elf.header.e_machine)
+            ));
+        }
+    }
+
+    if let Ok(pointer_width) = target.architecture.pointer_width() {
+        let expected_is_64 = pointer_width == target_lexicon::PointerWidth::U64;
+        if elf.is_64 != expected_is_64 {
+            let bits = if elf.is_64 { 64 } else { 32 };
+            return Some(format!("{bits}-bit ELF does not match target {target}"));
+        }
+    }
+
+    if let Ok(endianness) = target.architecture.endianness() {
+        let expected_little_endian = endianness == target_lexicon::Endianness::Little;
+        if elf.little_endian != expected_little_endian {
+            let endianness = if elf.little_endian { "little" } else { "big" };
+            return Some(format!(
+                "{endianness}-endian ELF does not match target {target}"
+            ));
+        }
+    }
+
+    // Most toolchains leave the OS ABI unset, so only reject ELF files
+    // that explicitly declare a different OS
+    let os_abi = elf.header.e_ident[header::EI_OSABI];
+    if let Some(expected_os_abi) = elf_os_abi(target.operating_system) {
+        if os_abi != header::ELFOSABI_NONE && os_abi != expected_os_abi {
+            return Some(format!(
+                "ELF OS ABI {os_abi} does not match target {target}"
+            ));
+        }
+    }
+
+    None
+}
+
+fn elf_machine(architecture: target_lexicon::Architecture) -> Option<u16> {
+    use goblin::elf::header;
+    use target_lexicon::Architecture;
+
+    match architecture {
+        Architecture::X86_64 | Architecture::X86_64h => Some(header::EM_X86_64),
+        Architecture::X86_32(_) => Some(header::EM_386),
+        Architecture::Aarch64(_) => Some(header::EM_AARCH64),
+        Architecture::Arm(_) => Some(header::EM_ARM),
+        Architecture::Riscv32(_) | Architecture::Riscv64(_) => Some(header::EM_RISCV),
+        Architecture::Powerpc => Some(header::EM_PPC),
+        Architecture::Powerpc64 | Architecture::Powerpc64le => Some(header::EM_PPC64),
+        Architecture::S390x => Some(header::EM_S390),
+        Architecture::Mips32(_) | Architecture::Mips64(_) => Some(header::EM_MIPS),
+        _ => None,
+    }
+}
+
+fn elf_os_abi(operating_system: target_lexicon::OperatingSystem) -> Option<u8> {
+    use goblin::elf::header;
+    use target_lexicon::OperatingSystem;
+
+    match operating_system {
+        OperatingSystem::Linux => Some(header::ELFOSABI_GNU),
+        OperatingSystem::Freebsd => Some(header::ELFOSABI_FREEBSD),
+        OperatingSystem::Netbsd => Some(header::ELFOSABI_NETBSD),
+        OperatingSystem::Openbsd => Some(header::ELFOSABI_OPENBSD),
+        OperatingSystem::Solaris | OperatingSystem::Illumos => Some(header::ELFOSABI_SOLARIS),
+        _ => None,
+    }
+}
+
+// The fixtures are built with GNU ld flags, and the ones that get run
+// need a Linux dynamic linker
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt as _, path::Path, process::Command};
+
+    use super::{
+        add_search_paths, edit_needed_libraries, ensure_unshared_dynstr, expand_rpath_entry,
+        parse_elf, patch_runpath, rpath_library_paths, symbols_unparsed, RpathTokens,
+    };
+
+    fn compile(dir: &Path, args: &[&str]) {
+        let status = Command::new("cc")
+            .current_dir(dir)
+            .args(args)
+            .status()
+            .expect("failed to run cc");
+        assert!(status.success(), "cc {args:?} failed");
+    }
+
+    /// Build `libv.so`, which defines `f`, and a program `main` from
+    /// `main_source` linked against it in `dir`, returning the program's
+    /// contents. `lib_args` and `main_args` are extra args to `cc` for
+    /// each.
+    fn build_fixture(
+        dir: &Path,
+        lib_args: &[&str],
+        main_source: &str,
+        main_args: &[&str],
+    ) -> Vec<u8> {
+        std::fs::write(dir.join("v.c"), "int f(void) { return 1; }\n").unwrap();
+        std::fs::write(dir.join("main.c"), main_source).unwrap();
+        compile(
+            dir,
+            &[
+                &["-shared", "-fPIC", "-Wl,-soname,libv.so"],
+                lib_args,
+                &["-o", "libv.so", "v.c"],
+            ]
+            .concat(),
+        );
+        compile(
+            dir,
+            &[main_args, &["-o", "main", "main.c", "-L.", "-lv"]].concat(),
+        );
+        std::fs::read(dir.join("main")).unwrap()
+    }
+
+    #[test]
+    fn test_drop_versioned_library() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+
+        // `main` only calls `f` if it's loaded, so it runs without `libv.so`
+        // as long as its version requirement on `libv.so` is gone too
+        std::fs::write(dir.join("v.map"), "V1 { global: f; local: *; };\n").unwrap();
+        let contents = build_fixture(
+            dir,
+            &["-Wl,--version-script=v.map"],
+            "extern int f(void) __attribute__((weak));\n\
+             int main(void) { return f ? 1 : 0; }\n",
+            &["-Wl,--no-as-needed"],
+        );
+
+        let elf = parse_elf(&contents).unwrap();
+        let drop_libraries = ["libv.so".to_string()].into_iter().collect();
+        let edited = edit_needed_libraries(&elf, &contents, &drop_libraries, &Default::default())
+            .unwrap()
+            .expect("expected libv.so to be dropped");
+
+        let edited_path = dir.join("main-edited");
+        std::fs::write(&edited_path, &edited).unwrap();
+        std::fs::set_permissions(&edited_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let output = Command::new(&edited_path).output().unwrap();
+
+        let edited_elf = parse_elf(&edited).unwrap();
+        let needs_libv = edited_elf
+            .verneed
+            .iter()
+            .flat_map(|verneed| verneed.iter())
+            .any(|need| edited_elf.dynstrtab.get_at(need.vn_file) == Some("libv.so"));
+
+        assert!(!needs_libv, "libv.so version requirement wasn't unlinked");
+        assert!(output.status.success(), "edited binary failed: {output:?}");
+    }
+
+    /// Build a program needing `libv.so`, with a runpath listing the same
+    /// dir more than once like patchelf tends to leave it. Its section
+    /// headers are then moved past the end of the file, like patchelf can
+    /// leave them pointing at moved or truncated data.
+    fn patchelf_fixture(dir: &Path) -> Vec<u8> {
+        let mut contents = build_fixture(
+            dir,
+            &[],
+            "extern int f(void);\nint main(void) { return f(); }\n",
+            &["-Wl,--enable-new-dtags,-rpath,$ORIGIN/lib:$ORIGIN/./lib/:$ORIGIN//lib"],
+        );
+
+        assert_eq!(contents[4], 2, "expected a 64-bit ELF file");
+        let past_end = (contents.len() as u64 + 0x1000).to_le_bytes();
+        contents[0x28..0x30].copy_from_slice(&past_end);
+        contents
+    }
+
+    #[test]
+    fn test_parse_patchelf_modified() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let contents = patchelf_fixture(temp_dir.path());
+
+        assert!(goblin::elf::Elf::parse(&contents).is_err());
+        let elf = parse_elf(&contents).unwrap();
+        assert!(elf.libraries.contains(&"libv.so"));
+        assert_eq!(elf.runpaths, ["$ORIGIN/lib:$ORIGIN/./lib/:$ORIGIN//lib"]);
+        assert!(elf.interpreter.is_some());
+
+        // Strings can't be safely overwritten without the symbols
+        assert!(symbols_unparsed(&elf));
+        let runpath_offset = elf
+            .dynamic
+            .as_ref()
+            .unwrap()
+            .dyns
+            .iter()
+            .find(|entry| entry.d_tag == goblin::elf::dynamic::DT_RUNPATH)
+            .unwrap()
+            .d_val as usize;
+        assert!(ensure_unshared_dynstr(&elf, &contents, runpath_offset, 1).is_err());
+    }
+
+    #[test]
+    fn test_patchelf_runpath_dedupe() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let contents = patchelf_fixture(temp_dir.path());
+
+        let elf = parse_elf(&contents).unwrap();
+        let elf_path = Path::new("/fixture/bin/main");
+        let mut search_paths =
+            rpath_library_paths(&elf, elf_path, &Default::default(), None).unwrap();
+        assert_eq!(search_paths, [Path::new("/fixture/bin/lib")]);
+
+        add_search_paths(
+            &mut search_paths,
+            [
+                "/fixture/bin/lib/".into(),
+                "/fixture/bin/./lib".into(),
+                "/fixture/lib".into(),
+            ],
+        );
+        assert_eq!(
+            search_paths,
+            [Path::new("/fixture/bin/lib"), Path::new("/fixture/lib")]
+        );
+    }
+
+    /// Build a program needing `libv.so` from `lib/`, linked with a
+    /// placeholder runpath that doesn't find it.
+    fn placeholder_runpath_fixture(dir: &Path) -> Vec<u8> {
+        let contents = build_fixture(
+            dir,
+            &[],
+            "extern int f(void);\nint main(void) { return f() - 1; }\n",
+            &["-Wl,--enable-new-dtags,-rpath,$ORIGIN/////////////////"],
+        );
+        std::fs::create_dir(dir.join("lib")).unwrap();
+        std::fs::rename(dir.join("libv.so"), dir.join("lib/libv.so")).unwrap();
+        contents
+    }
+
+    /// The offset of the `DT_RUNPATH` string in the dynamic string table.
+    fn runpath_offset(elf: &goblin::elf::Elf) -> usize {
+        elf.dynamic
+            .as_ref()
+            .unwrap()
+            .dyns
+            .iter()
+            .find(|entry| entry.d_tag == goblin::elf::dynamic::DT_RUNPATH)
+            .unwrap()
+            .d_val as usize
+    }
+
+    fn run_fixture(dir: &Path, name: &str, contents: &[u8]) -> std::process::Output {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        Command::new(&path)
+            .env_remove("LD_LIBRARY_PATH")
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_patch_runpath() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let contents = placeholder_runpath_fixture(dir);
+
+        let output = run_fixture(dir, "main", &contents);
+        assert!(!output.status.success(), "expected libv.so not to be found");
+
+        let elf = parse_elf(&contents).unwrap();
+        let patched = patch_runpath(&elf, &contents, b"$ORIGIN/lib").unwrap();
+        let output = run_fixture(dir, "main-patched", &patched);
+        assert!(output.status.success(), "patched binary failed: {output:?}");
+
+        let patched_elf = parse_elf(&patched).unwrap();
+        assert_eq!(patched_elf.runpaths, ["$ORIGIN/lib"]);
+
+        // Only the runpath's bytes changed, so every other string is the
+        // same as before
+        let strtab = elf.dynamic.as_ref().unwrap().info.strtab;
+        let runpath_start = strtab + runpath_offset(&elf);
+        let runpath_end = runpath_start + "$ORIGIN/////////////////".len();
+        assert_eq!(patched.len(), contents.len());
+        assert_eq!(patched[..runpath_start], contents[..runpath_start]);
+        assert_eq!(patched[runpath_end..], contents[runpath_end..]);
+        assert_eq!(patched_elf.libraries, elf.libraries);
+
+        let too_long = [b'x'; 64];
+        assert!(patch_runpath(&elf, &contents, &too_long).is_err());
+    }
+
+    #[test]
+    fn test_patch_runpath_shared_tail() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut contents = placeholder_runpath_fixture(temp_dir.path());
+
+        // Point the runpath at the tail of the `libv.so` string, like a
+        // linker merging a runpath of `v.so` into it would
+        let elf = parse_elf(&contents).unwrap();
+        assert_eq!(contents[4], 2, "expected a 64-bit ELF file");
+        let dynamic = elf.dynamic.as_ref().unwrap();
+        let needed_offset = dynamic
+            .dyns
+            .iter()
+            .find(|entry| {
+                entry.d_tag == goblin::elf::dynamic::DT_NEEDED
+                    && elf.dynstrtab.get_at(entry.d_val as usize) == Some("libv.so")
+            })
+            .unwrap()
+            .d_val;
+        let runpath_index = dynamic
+            .dyns
+            .iter()
+            .position(|entry| entry.d_tag == goblin::elf::dynamic::DT_RUNPATH)
+            .unwrap();
+        let dynamic_header = elf
+            .program_headers
+            .iter()
+            .find(|header| header.p_type == goblin::elf::program_header::PT_DYNAMIC)
+            .unwrap();
+        let value_start = dynamic_header.p_offset as usize + runpath_index * 16 + 8;
+        contents[value_start..value_start + 8].copy_from_slice(&(needed_offset + 3).to_le_bytes());
+
+        let elf = parse_elf(&contents).unwrap();
+        assert_eq!(elf.runpaths, ["v.so"]);
+        let error = patch_runpath(&elf, &contents, b"x").unwrap_err();
+        assert!(
+            format!("{error:#}").contains("\"libv.so\" shares its bytes"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn test_expand_rpath_entry() {
+        let origin = Path::new("/fixture/bin");
+        let expand = |entry| expand_rpath_entry(entry, origin, "lib64", "x86_64");
+
+        assert_eq!(expand("$ORIGIN").unwrap(), Path::new("/fixture/bin"));
+        assert_eq!(
+            expand("$ORIGIN/lib").unwrap(),
+            Path::new("/fixture/bin/lib")
+        );
+        assert_eq!(
+            expand("${ORIGIN}/../$LIB").unwrap(),
+            Path::new("/fixture/bin/../lib64")
+        );
+        assert_eq!(
+            expand("$ORIGIN/${LIB}/$PLATFORM").unwrap(),
+            Path::new("/fixture/bin/lib64/x86_64")
+        );
+        assert_eq!(
+            expand("$ORIGIN/${PLATFORM}").unwrap(),
+            Path::new("/fixture/bin/x86_64")
+        );
+
+        // Tokens are only recognized when followed by `/` or the end
+        assert_eq!(expand("$ORIGINAL/lib"), None);
+        assert_eq!(expand("${ORIGIN}lib"), None);
+        assert_eq!(
+            expand("$ORIGIN/$LIBEXEC").unwrap(),
+            Path::new("/fixture/bin/$LIBEXEC")
+        );
+        assert_eq!(
+            expand("$ORIGIN/${LIB").unwrap(),
+            Path::new("/fixture/bin/${LIB")
+        );
+        assert_eq!(
+            expand("$ORIGIN/${LIB}64/$").unwrap(),
+            Path::new("/fixture/bin/${LIB}64/$")
+        );
+
+        // Only entries relative to `$ORIGIN` are expanded
+        assert_eq!(expand("/usr/lib"), None);
+        assert_eq!(expand("$LIB/foo"), None);
+    }
+
+    #[test]
+    fn test_rpath_platform() {
+        let target: target_lexicon::Triple = "aarch64-unknown-linux-gnu".parse().unwrap();
+        let tokens = RpathTokens::default();
+        assert_eq!(tokens.platform(Some(&target)), "aarch64");
+        assert_eq!(tokens.platform(None), std::env::consts::ARCH);
+
+        let platform = tokens.platform(Some(&target));
+        assert_eq!(
+            expand_rpath_entry(
+                "$ORIGIN/$PLATFORM",
+                Path::new("/fixture"),
+                &tokens.lib,
+                &platform
+            ),
+            Some("/fixture/aarch64".into())
+        );
+
+        let tokens = RpathTokens {
+            platform: Some("haswell".to_string()),
+            ..RpathTokens::default()
+        };
+        assert_eq!(tokens.platform(Some(&target)), "haswell");
+        assert_eq!(tokens.platform(None), "haswell");
+    }
+}
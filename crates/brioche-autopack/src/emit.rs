@@ -0,0 +1,351 @@
+//! Writing autopacked files.
+
+use std::{io::Write as _, path::Path};
+
+use eyre::Context as _;
+
+use crate::PackedExecutable;
+
+/// Writes an autopacked file to its output path.
+pub trait PackEmitter: Sync {
+    /// Write `pack` to `output_path`, injected into `base`.
+    fn emit(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+    ) -> eyre::Result<()>;
+
+    /// Write `pack` like [`emit`](Self::emit), with `attached` metadata
+    /// packs written before it (see
+    /// [`brioche_resources::inject_pack_with_attached`]). By default,
+    /// fingerprint packs are dropped, since they're only informational,
+    /// and any other attached packs aren't supported.
+    fn emit_with_attached(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+        attached: &[brioche_pack::Pack],
+    ) -> eyre::Result<()> {
+        eyre::ensure!(
+            attached.iter().all(is_fingerprint_pack),
+            "pack emitter doesn't support attached packs"
+        );
+        self.emit(output_path, base, pack)
+    }
+}
+
+/// Check if `pack` is an attached fingerprint pack, see
+/// [`crate::read_fingerprint`].
+fn is_fingerprint_pack(pack: &brioche_pack::Pack) -> bool {
+    matches!(
+        pack,
+        brioche_pack::Pack::Metadata { format, .. }
+            if format == <crate::PackerFingerprint as crate::MetadataFormat>::FORMAT
+    )
+}
+
+/// The file a pack gets injected into.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum PackBase<'a> {
+    /// A launcher, used for programs and scripts.
+    PackedExecutable(&'a PackedExecutable),
+
+    /// A launcher that has to be kept unchanged, such as one with a code
+    /// signature that appending a pack would invalidate. Emitters write
+    /// these like [`SharedLauncherPackEmitter`] does, with the pack in a
+    /// sidecar file.
+    SignedPackedExecutable(&'a PackedExecutable),
+
+    /// The file being autopacked, used for shared libraries. `path` is
+    /// where it was read from, which can be the output path when
+    /// autopacking in place.
+    Source { path: &'a Path, contents: &'a [u8] },
+
+    /// New contents for the file being autopacked, such as a shared
+    /// library with a normalized soname.
+    Contents(&'a [u8]),
+}
+
+/// The default [`PackEmitter`], which writes autopacked files to the
+/// filesystem. Outputs are replaced atomically (see
+/// [`brioche_resources::AtomicWriter`]), since they can be the file being
+/// autopacked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilePackEmitter;
+
+impl PackEmitter for FilePackEmitter {
+    fn emit(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+    ) -> eyre::Result<()> {
+        self.emit_with_attached(output_path, base, pack, &[])
+    }
+
+    fn emit_with_attached(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+        attached: &[brioche_pack::Pack],
+    ) -> eyre::Result<()> {
+        let mut output = match base {
+            PackBase::PackedExecutable(packed_executable) => {
+                packed_executable.create(output_path)?
+            }
+            PackBase::SignedPackedExecutable(_) => {
+                return SharedLauncherPackEmitter.emit_with_attached(
+                    output_path,
+                    base,
+                    pack,
+                    attached,
+                );
+            }
+            PackBase::Source { contents, .. } | PackBase::Contents(contents) => {
+                let mut output = create_output(output_path)?;
+                output
+                    .write_all(contents)
+                    .with_context(|| format!("failed to write {output_path:?}"))?;
+                output
+            }
+        };
+
+        brioche_resources::inject_pack_with_attached(&mut output, pack, attached)
+            .with_context(|| format!("failed to inject pack into {output_path:?}"))?;
+        output
+            .commit()
+            .with_context(|| format!("failed to write {output_path:?}"))?;
+
+        Ok(())
+    }
+}
+
+/// A [`PackEmitter`] that shares one copy of each packed executable per
+/// output directory, instead of copying the packed executable into every
+/// autopacked program. Each autopacked program is a hard link to the
+/// shared copy, and its pack is written to a sidecar file next to it (see
+/// [`brioche_resources::sidecar_pack_path`]), which packed executables
+/// read when their own file doesn't have a pack. Other files, such as
+/// shared libraries, are written the same way as [`FilePackEmitter`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SharedLauncherPackEmitter;
+
+impl PackEmitter for SharedLauncherPackEmitter {
+    fn emit(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+    ) -> eyre::Result<()> {
+        self.emit_with_attached(output_path, base, pack, &[])
+    }
+
+    fn emit_with_attached(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+        attached: &[brioche_pack::Pack],
+    ) -> eyre::Result<()> {
+        let (PackBase::PackedExecutable(packed_executable)
+        | PackBase::SignedPackedExecutable(packed_executable)) = base
+        else {
+            return FilePackEmitter.emit_with_attached(output_path, base, pack, attached);
+        };
+
+        let output_dir = output_path
+            .parent()
+            .ok_or_else(|| eyre::eyre!("invalid output path {output_path:?}"))?;
+        let shared_path = shared_packed_executable(packed_executable, output_dir)?;
+
+        brioche_resources::AtomicWriter::replace_with_link(&shared_path, output_path)
+            .with_context(|| format!("failed to link packed executable to {output_path:?}"))?;
+
+        write_sidecar_pack(output_path, pack, attached)
+    }
+}
+
+/// A [`PackEmitter`] for files that can't have a pack appended. The file
+/// at `output_path` keeps its original contents, and its pack is written
+/// to a sidecar file next to it instead. Packs for any other path, such as
+/// autopacked dependencies, are passed through to `inner`.
+pub(crate) struct SidecarPackEmitter<'a> {
+    pub inner: &'a dyn PackEmitter,
+    pub output_path: &'a Path,
+    pub source: &'a crate::detect::SourceFile,
+
+    /// Records the sidecar pack instead of writing it, when planning.
+    pub plan: Option<&'a crate::plan::PlanRecorder>,
+}
+
+impl PackEmitter for SidecarPackEmitter<'_> {
+    fn emit(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+    ) -> eyre::Result<()> {
+        self.emit_with_attached(output_path, base, pack, &[])
+    }
+
+    fn emit_with_attached(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+        attached: &[brioche_pack::Pack],
+    ) -> eyre::Result<()> {
+        if output_path != self.output_path {
+            return self
+                .inner
+                .emit_with_attached(output_path, base, pack, attached);
+        }
+
+        let contents = match base {
+            PackBase::Contents(contents) => Some(contents),
+            PackBase::PackedExecutable(_)
+            | PackBase::SignedPackedExecutable(_)
+            | PackBase::Source { .. } => {
+                if output_path == self.source.path {
+                    None
+                } else {
+                    Some(&self.source.contents[..])
+                }
+            }
+        };
+        if let Some(plan) = self.plan {
+            let contents = contents.unwrap_or(&self.source.contents);
+            plan.record_sidecar(output_path, contents, pack, attached);
+            return Ok(());
+        }
+
+        if let Some(contents) = contents {
+            write_output(output_path, contents)?;
+        }
+
+        write_sidecar_pack(output_path, pack, attached)
+    }
+}
+
+/// Write `pack` and its attached packs to the sidecar pack file for
+/// `output_path`.
+pub(crate) fn write_sidecar_pack(
+    output_path: &Path,
+    pack: &brioche_pack::Pack,
+    attached: &[brioche_pack::Pack],
+) -> eyre::Result<()> {
+    let sidecar_path = brioche_resources::sidecar_pack_path(output_path)
+        .ok_or_else(|| eyre::eyre!("invalid output path {output_path:?}"))?;
+    let mut sidecar = brioche_resources::AtomicWriter::new(&sidecar_path, 0o666)
+        .with_context(|| format!("failed to create file {sidecar_path:?}"))?;
+    brioche_resources::inject_pack_with_attached(&mut sidecar, pack, attached)
+        .with_context(|| format!("failed to inject pack into {sidecar_path:?}"))?;
+    sidecar
+        .commit()
+        .with_context(|| format!("failed to write {sidecar_path:?}"))?;
+
+    Ok(())
+}
+
+/// The permissions of shared packed executables, which every program
+/// linked to one shares.
+pub(crate) const SHARED_PACKED_EXECUTABLE_MODE: u32 = 0o755;
+
+/// Check if `output_path` is a hard link to a shared packed executable
+/// (see [`SharedLauncherPackEmitter`]). Its mode and modification time
+/// are shared with every program linked to the same packed executable, so
+/// they shouldn't be changed for just one of them. Programs that got a
+/// copy of the packed executable instead have their own.
+pub(crate) fn is_shared_launcher(output_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt as _;
+
+    let Ok(metadata) = std::fs::symlink_metadata(output_path) else {
+        return false;
+    };
+    if metadata.nlink() < 2 {
+        return false;
+    }
+
+    let dir = match output_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        brioche_resources::is_shared_packed_executable_path(&entry.path())
+            && entry.metadata().is_ok_and(|shared_metadata| {
+                shared_metadata.dev() == metadata.dev() && shared_metadata.ino() == metadata.ino()
+            })
+    })
+}
+
+/// Get the shared copy of `packed_executable` in `dir`, creating it if it
+/// doesn't exist yet. The copy is named after a hash of its contents, so
+/// different packed executables can share a directory.
+fn shared_packed_executable(
+    packed_executable: &PackedExecutable,
+    dir: &Path,
+) -> eyre::Result<std::path::PathBuf> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let contents = match packed_executable {
+        PackedExecutable::Path(path) => std::borrow::Cow::Owned(
+            std::fs::read(path)
+                .with_context(|| format!("failed to read packed executable {path:?}"))?,
+        ),
+        PackedExecutable::Bytes(contents) => std::borrow::Cow::Borrowed(&contents[..]),
+    };
+    let hash = blake3::hash(&contents);
+    let shared_path = dir.join(format!(
+        "{}{hash}",
+        brioche_resources::SHARED_PACKED_EXECUTABLE_PREFIX
+    ));
+    if shared_path.is_file() {
+        return Ok(shared_path);
+    }
+
+    let mut shared =
+        brioche_resources::AtomicWriter::new(&shared_path, SHARED_PACKED_EXECUTABLE_MODE)
+            .with_context(|| format!("failed to create file {shared_path:?}"))?;
+    shared
+        .write_all(&contents)
+        .with_context(|| format!("failed to write {shared_path:?}"))?;
+    shared
+        .file()
+        .set_permissions(std::fs::Permissions::from_mode(
+            SHARED_PACKED_EXECUTABLE_MODE,
+        ))?;
+    shared
+        .commit()
+        .with_context(|| format!("failed to write {shared_path:?}"))?;
+
+    Ok(shared_path)
+}
+
+/// Start writing an autopacked file to `output_path`, which replaces any
+/// existing file once it's committed. The existing file's permissions are
+/// kept.
+pub(crate) fn create_output(output_path: &Path) -> eyre::Result<brioche_resources::AtomicWriter> {
+    let output = brioche_resources::AtomicWriter::replacing(output_path)
+        .with_context(|| format!("failed to create file {output_path:?}"))?;
+    Ok(output)
+}
+
+/// Write `contents` to `output_path` atomically, see [`create_output`].
+pub(crate) fn write_output(output_path: &Path, contents: &[u8]) -> eyre::Result<()> {
+    let mut output = create_output(output_path)?;
+    output
+        .write_all(contents)
+        .with_context(|| format!("failed to write {output_path:?}"))?;
+    output
+        .commit()
+        .with_context(|| format!("failed to write {output_path:?}"))?;
+
+    Ok(())
+}
@@ -0,0 +1,268 @@
+//! Fingerprints recording the packer and config that created a pack.
+//!
+//! Runnable packs carry their fingerprint in the runnable itself. The
+//! `LdLinux` and `Static` pack formats are defined by `brioche-pack` and
+//! have no room for one, so their fingerprint is attached to the packed
+//! file as a separate metadata pack instead.
+
+#[cfg(feature = "elf")]
+use std::os::unix::ffi::OsStrExt as _;
+use std::path::Path;
+
+pub use runnable_core::PackerFingerprint;
+
+/// The version of the packer recorded in fingerprints.
+pub const PACKER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+impl crate::MetadataFormat for PackerFingerprint {
+    const FORMAT: &'static str = runnable_core::PACKER_FINGERPRINT_FORMAT;
+}
+
+/// Get the fingerprint from a pack, if it's a runnable pack with one. See
+/// [`read_fingerprint`] to also find fingerprints attached to other packs.
+pub fn pack_fingerprint(pack: &brioche_pack::Pack) -> Option<PackerFingerprint> {
+    let runnable = crate::read_metadata::<runnable_core::Runnable>(pack).ok()??;
+    runnable.packer
+}
+
+/// Read the fingerprint of a packed file, either from its runnable pack or
+/// from an attached fingerprint pack. Files packed before fingerprints were
+/// recorded don't have one.
+pub fn read_fingerprint(path: &Path) -> eyre::Result<Option<PackerFingerprint>> {
+    let extracted = crate::read_pack(path)?;
+    if let Some(fingerprint) = pack_fingerprint(&extracted.pack) {
+        return Ok(Some(fingerprint));
+    }
+
+    let fingerprint = crate::read_attached_metadata::<PackerFingerprint>(path)?
+        .into_iter()
+        .next();
+    Ok(fingerprint)
+}
+
+#[cfg(feature = "elf")]
+impl crate::DynamicBinaryConfig {
+    /// The fingerprint recorded in packs for dynamic binaries autopacked
+    /// with this config. The packed executable, debug links, and whether
+    /// the config is enabled aren't part of the hash, since they don't
+    /// change the pack. Extra runtime library paths aren't either, since
+    /// they're absolute paths on the build machine.
+    pub fn fingerprint(&self) -> PackerFingerprint {
+        let Self {
+            enabled: _,
+            packed_executable: _,
+            mode,
+            debug_links: _,
+            extra_runtime_library_paths: _,
+            dynamic_linking,
+            android,
+            interpreter_remaps,
+            self_exec,
+            unwrapped_copy,
+            static_dlopen,
+            static_pie,
+            runtime_resource_dir,
+        } = self;
+
+        let mut hasher = ConfigHasher::new("dynamicBinary");
+        hasher.field("mode", mode);
+        hasher.dynamic_linking_fields(dynamic_linking);
+        hasher.android_fields(android);
+        hasher.field(
+            "interpreterRemaps",
+            &interpreter_remaps
+                .iter()
+                .map(|(from, to)| (from.as_os_str().as_bytes(), to.as_os_str().as_bytes()))
+                .collect::<std::collections::BTreeSet<_>>(),
+        );
+        hasher.field("selfExec", self_exec);
+        hasher.field("unwrappedCopy", unwrapped_copy);
+        hasher.field("staticDlopen", static_dlopen);
+        hasher.field("staticPie", static_pie);
+        hasher.field(
+            "runtimeResourceDir",
+            &runtime_resource_dir
+                .as_ref()
+                .map(|path| path.as_os_str().as_bytes()),
+        );
+        hasher.finish()
+    }
+}
+
+#[cfg(feature = "elf")]
+impl crate::SharedLibraryConfig {
+    /// The fingerprint recorded in packs for shared libraries autopacked
+    /// with this config. Whether the config is enabled isn't part of the
+    /// hash, since it doesn't change the pack.
+    pub fn fingerprint(&self) -> PackerFingerprint {
+        let Self {
+            enabled: _,
+            dynamic_linking,
+            allow_empty,
+            normalize_soname,
+            android,
+        } = self;
+
+        let mut hasher = ConfigHasher::new("sharedLibrary");
+        hasher.dynamic_linking_fields(dynamic_linking);
+        hasher.android_fields(android);
+        hasher.field("allowEmpty", allow_empty);
+        hasher.field("normalizeSoname", normalize_soname);
+        hasher.finish()
+    }
+}
+
+#[cfg(feature = "script")]
+impl crate::ScriptConfig {
+    /// The fingerprint recorded in runnable packs for scripts autopacked
+    /// with this config. The packed executable and whether the config is
+    /// enabled aren't part of the hash, since they don't change the pack.
+    /// The base path isn't either, since it's an absolute path on the build
+    /// machine.
+    pub fn fingerprint(&self) -> PackerFingerprint {
+        let Self {
+            enabled: _,
+            packed_executable: _,
+            base_path: _,
+            env,
+            clear_env,
+            forward_args,
+            interpreter_fallbacks,
+            posix_shell,
+        } = self;
+
+        let mut hasher = ConfigHasher::new("script");
+        hasher.field(
+            "env",
+            &env.iter().collect::<std::collections::BTreeMap<_, _>>(),
+        );
+        hasher.field("clearEnv", clear_env);
+        hasher.field("forwardArgs", forward_args);
+        hasher.field(
+            "interpreterFallbacks",
+            &interpreter_fallbacks
+                .iter()
+                .collect::<std::collections::BTreeMap<_, _>>(),
+        );
+        hasher.field("posixShell", posix_shell);
+        hasher.finish()
+    }
+}
+
+/// Hashes config fields by their JSON representation. Hash sets and maps
+/// need to be collected into sorted collections first, so the hash doesn't
+/// depend on their iteration order. Paths are hashed by their bytes, since
+/// they might not be valid UTF-8.
+#[cfg(any(feature = "elf", feature = "script"))]
+struct ConfigHasher(blake3::Hasher);
+
+#[cfg(any(feature = "elf", feature = "script"))]
+impl ConfigHasher {
+    fn new(kind: &str) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(kind.as_bytes());
+        hasher.update(b"\n");
+        Self(hasher)
+    }
+
+    fn field(&mut self, name: &str, value: &impl serde::Serialize) {
+        // Serializing can only fail for paths that aren't valid UTF-8.
+        // Hashing the error instead still gives a stable hash
+        let value = serde_json::to_vec(value)
+            .unwrap_or_else(|error| format!("error: {error}").into_bytes());
+        self.0.update(name.as_bytes());
+        self.0.update(b"=");
+        self.0.update(&(value.len() as u64).to_le_bytes());
+        self.0.update(&value);
+    }
+
+    /// Hash the dynamic linking config. Library paths, host library paths,
+    /// and build libraries aren't part of the hash, since they're absolute
+    /// paths on the build machine. The closure limits and symbol checks
+    /// aren't either, since they only decide whether packing fails.
+    #[cfg(feature = "elf")]
+    fn dynamic_linking_fields(&mut self, dynamic_linking: &crate::DynamicLinkingConfig) {
+        let crate::DynamicLinkingConfig {
+            library_paths: _,
+            skip_libraries,
+            extra_libraries,
+            optional_libraries,
+            optional_library_globs,
+            skip_unknown_libraries,
+            rpath_tokens,
+            host_libraries,
+            host_library_paths: _,
+            cut_libraries,
+            max_depth,
+            max_closure_libraries: _,
+            max_closure_bytes: _,
+            build_libraries: _,
+            drop_libraries,
+            rename_libraries,
+            dlopen_libraries,
+            check_symbols: _,
+        } = dynamic_linking;
+
+        self.field(
+            "skipLibraries",
+            &skip_libraries
+                .iter()
+                .collect::<std::collections::BTreeSet<_>>(),
+        );
+        self.field("extraLibraries", extra_libraries);
+        self.field("optionalLibraries", optional_libraries);
+        self.field("optionalLibraryGlobs", optional_library_globs);
+        self.field("skipUnknownLibraries", skip_unknown_libraries);
+        self.field("rpathTokens", rpath_tokens);
+        self.field(
+            "hostLibraries",
+            &host_libraries
+                .iter()
+                .collect::<std::collections::BTreeSet<_>>(),
+        );
+        self.field(
+            "cutLibraries",
+            &cut_libraries
+                .iter()
+                .collect::<std::collections::BTreeSet<_>>(),
+        );
+        self.field("maxDepth", max_depth);
+        self.field(
+            "dropLibraries",
+            &drop_libraries
+                .iter()
+                .collect::<std::collections::BTreeSet<_>>(),
+        );
+        self.field(
+            "renameLibraries",
+            &rename_libraries
+                .iter()
+                .collect::<std::collections::BTreeMap<_, _>>(),
+        );
+        self.field(
+            "dlopenLibraries",
+            &dlopen_libraries
+                .iter()
+                .map(|(path, libraries)| (path.as_os_str().as_bytes(), libraries))
+                .collect::<std::collections::BTreeSet<_>>(),
+        );
+    }
+
+    #[cfg(feature = "elf")]
+    fn android_fields(&mut self, android: &crate::AndroidConfig) {
+        let crate::AndroidConfig { system_libraries } = android;
+        self.field(
+            "androidSystemLibraries",
+            &system_libraries
+                .iter()
+                .collect::<std::collections::BTreeSet<_>>(),
+        );
+    }
+
+    fn finish(self) -> PackerFingerprint {
+        PackerFingerprint {
+            version: PACKER_VERSION.to_string(),
+            config_hash: self.0.finalize().to_hex().to_string(),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Autopacking paths across worker threads.
+//!
+//! Workers take paths from a shared [`PendingPaths`] queue. A file that
+//! depends on another pending path (such as a program and a library from
+//! the same recipe) autopacks that path first, or waits for the worker
+//! already autopacking it, so dependencies are always read after they've
+//! been autopacked.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex, MutexGuard},
+    thread::ThreadId,
+};
+
+use crate::AutopackPathConfig;
+
+/// The paths left to autopack, shared between workers.
+pub(crate) struct PendingPaths {
+    state: Mutex<PendingState>,
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct PendingState {
+    pending: BTreeMap<PathBuf, AutopackPathConfig>,
+    /// Paths being autopacked, and the worker autopacking each one.
+    in_progress: BTreeMap<PathBuf, ThreadId>,
+    /// The path each blocked worker is waiting on.
+    waiting: HashMap<ThreadId, PathBuf>,
+    finished: BTreeSet<PathBuf>,
+}
+
+/// The result of taking a path from [`PendingPaths`].
+pub(crate) enum Taken {
+    /// The path was pending, and should now be autopacked by the caller.
+    /// [`PendingPaths::finish`] must be called afterwards.
+    Pending(AutopackPathConfig),
+
+    /// The path was already autopacked, or is being autopacked by this
+    /// worker further up the stack.
+    Done,
+
+    /// The path was never pending.
+    NotPending,
+}
+
+impl PendingPaths {
+    pub fn new(pending: BTreeMap<PathBuf, AutopackPathConfig>) -> Self {
+        Self {
+            state: Mutex::new(PendingState {
+                pending,
+                ..Default::default()
+            }),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Take the next pending path in path order.
+    pub fn pop_first(&self) -> Option<(PathBuf, AutopackPathConfig)> {
+        let mut state = self.lock();
+        let (path, path_config) = state.pending.pop_first()?;
+        state
+            .in_progress
+            .insert(path.clone(), std::thread::current().id());
+        Some((path, path_config))
+    }
+
+    /// Take `path` if it's pending. If another worker is autopacking it,
+    /// this blocks until it's finished.
+    pub fn take(&self, path: &Path) -> Taken {
+        let current = std::thread::current().id();
+        let mut state = self.lock();
+
+        if let Some(path_config) = state.pending.remove(path) {
+            state.in_progress.insert(path.to_owned(), current);
+            return Taken::Pending(path_config);
+        }
+
+        while state.in_progress.contains_key(path) {
+            // Waiting on a path this worker is autopacking, directly or
+            // through a chain of blocked workers, would never finish. This
+            // happens with circular dependencies, which get read as-is
+            if state.waits_on(path, current) {
+                return Taken::Done;
+            }
+
+            state.waiting.insert(current, path.to_owned());
+            state = self
+                .finished
+                .wait(state)
+                .unwrap_or_else(|error| error.into_inner());
+            state.waiting.remove(&current);
+        }
+
+        if state.finished.contains(path) {
+            Taken::Done
+        } else {
+            Taken::NotPending
+        }
+    }
+
+    /// Drop every pending path, so workers stop once their current path
+    /// is done.
+    pub fn cancel(&self) {
+        self.lock().pending.clear();
+    }
+
+    /// Mark a path taken from the queue as autopacked, whether or not it
+    /// succeeded, and wake any workers waiting on it.
+    pub fn finish(&self, path: &Path) {
+        let mut state = self.lock();
+        state.in_progress.remove(path);
+        state.finished.insert(path.to_owned());
+        drop(state);
+
+        self.finished.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PendingState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl PendingState {
+    /// Check if the worker autopacking `path` is `worker`, or is blocked
+    /// waiting on `worker`.
+    fn waits_on(&self, path: &Path, worker: ThreadId) -> bool {
+        let mut path = path;
+        // Each worker waits on at most one path, so the chain can't be
+        // longer than the number of waiting workers
+        for _ in 0..=self.waiting.len() {
+            let Some(&owner) = self.in_progress.get(path) else {
+                return false;
+            };
+            if owner == worker {
+                return true;
+            }
+            let Some(next_path) = self.waiting.get(&owner) else {
+                return false;
+            };
+            path = next_path;
+        }
+
+        false
+    }
+}
+
+/// Where messages about autopacked paths are printed. With a single job,
+/// messages are printed right away. With more, they're buffered by path
+/// and printed in path order after every worker finishes, so the output
+/// doesn't depend on how paths were scheduled.
+pub(crate) enum Output {
+    Immediate,
+    Buffered(Mutex<BTreeMap<PathBuf, Vec<Message>>>),
+}
+
+pub(crate) enum Message {
+    Stdout(String),
+    #[cfg(any(feature = "elf", feature = "script"))]
+    Stderr(String),
+}
+
+impl Output {
+    pub fn for_jobs(jobs: usize) -> Self {
+        if jobs > 1 {
+            Self::Buffered(Mutex::default())
+        } else {
+            Self::Immediate
+        }
+    }
+
+    pub fn print(&self, path: &Path, message: Message) {
+        match self {
+            Self::Immediate => message.print(),
+            Self::Buffered(messages) => {
+                let mut messages = messages.lock().unwrap_or_else(|error| error.into_inner());
+                messages.entry(path.to_owned()).or_default().push(message);
+            }
+        }
+    }
+
+    /// Print any buffered messages.
+    pub fn flush(&self) {
+        if let Self::Buffered(messages) = self {
+            let mut messages = messages.lock().unwrap_or_else(|error| error.into_inner());
+            for message in std::mem::take(&mut *messages).into_values().flatten() {
+                message.print();
+            }
+        }
+    }
+}
+
+impl Message {
+    fn print(&self) {
+        match self {
+            Self::Stdout(message) => println!("{message}"),
+            #[cfg(any(feature = "elf", feature = "script"))]
+            Self::Stderr(message) => eprintln!("{message}"),
+        }
+    }
+}
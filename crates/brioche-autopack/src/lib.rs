@@ -1,11 +1,108 @@
+//! Autopacking wraps programs, shared libraries, and scripts with a pack,
+//! so they can find their dependencies at runtime without relying on the
+//! host.
+//!
+//! Autopacking is split into a few steps, each with an extension point:
+//!
+//! - [`resolve`] finds the interpreters, libraries, and commands needed by
+//!   each file, using a [`resolve::Resolver`].
+//! - [`resources`] adds those dependencies as resources, using a
+//!   [`resources::ResourceSink`]. Resources can be rewritten or denied
+//!   first with a [`resources::ResourceFilter`].
+//! - [`emit`] writes each autopacked file, using a
+//!   [`emit::PackEmitter`].
+//!
+//! [`autopack`] uses the default implementation of each step, and
+//! [`autopack_with`] can be used to replace them. [`plan`] records what
+//! autopacking would do without changing any files, to be applied later.
+
 use std::{
-    collections::{BTreeMap, HashMap, HashSet, VecDeque},
-    io::{BufRead as _, Read as _, Write as _},
+    collections::{BTreeMap, BTreeSet},
+    os::unix::fs::{MetadataExt as _, PermissionsExt as _},
     path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use bstr::ByteSlice as _;
+use eyre::{Context as _, OptionExt as _};
+
+pub use builder::AutopackConfigBuilder;
+pub use classify::{classify, Classification};
+#[cfg(feature = "elf")]
+pub use closure::{
+    ClosureCallback, ClosurePolicy, ClosureVerdict, ResolvedClosure, ResolvedLibrary,
+};
+pub use config::*;
+#[cfg(feature = "elf")]
+pub use debug_links::{find_debug_link, read_debug_links, DebugLink, DEBUG_LINKS_FILE};
+pub use detect::AutopackKind;
+use detect::{autopack_kind, SourceFile};
+pub use fingerprint::{pack_fingerprint, read_fingerprint, PackerFingerprint, PACKER_VERSION};
+use jobs::{Message, Output, PendingPaths, Taken};
+pub use metadata::{metadata_pack, read_attached_metadata, read_metadata, MetadataFormat};
+#[cfg(any(feature = "elf", feature = "script"))]
+pub use plan::{apply, plan, AutopackPlan};
+use privileges::Privileges;
+pub use report::{
+    AutopackReport, ClosureLibrary, PathOutcome, PathReport, SharedTarget, SkipReason,
 };
+use report::{Outcome, ReportCollector};
+pub use simple::Autopack;
+pub use warnings::{AutopackWarning, WarningCallback, WarningKind, WarningPolicy};
+
+mod builder;
+mod classify;
+#[cfg(feature = "elf")]
+mod closure;
+mod config;
+#[cfg(feature = "elf")]
+mod debug_links;
+mod detect;
+pub mod diagnostics;
+#[cfg(feature = "elf")]
+mod elf;
+#[cfg(any(feature = "elf", feature = "script"))]
+pub mod emit;
+mod fingerprint;
+mod jobs;
+#[cfg(feature = "elf")]
+mod linker_script;
+#[cfg(feature = "macho")]
+mod macho;
+mod metadata;
+#[cfg(any(feature = "elf", feature = "script"))]
+pub mod plan;
+mod privileges;
+mod report;
+#[cfg(any(feature = "elf", feature = "script"))]
+pub mod resolve;
+#[cfg(any(feature = "elf", feature = "script"))]
+pub mod resources;
+#[cfg(any(feature = "elf", feature = "script"))]
+mod retry;
+#[cfg(feature = "script")]
+mod script;
+mod simple;
+mod warnings;
+
+/// Read the pack from a packed file. Files sharing a packed executable
+/// don't have their own pack, so their sidecar pack is read instead.
+pub fn read_pack(
+    path: &Path,
+) -> Result<brioche_pack::ExtractedPack, brioche_pack::ExtractPackError> {
+    let (_, extracted) = brioche_resources::extract_pack_or_sidecar(path)?;
+    Ok(extracted)
+}
 
-use bstr::{ByteSlice as _, ByteVec as _};
-use eyre::{Context as _, ContextCompat as _, OptionExt as _};
+/// Read the metadata packs attached to a packed file, such as ones from
+/// [`AutopackConfig::attached_metadata`], in the order they were written.
+pub fn read_attached_packs(
+    path: &Path,
+) -> Result<Vec<brioche_pack::Pack>, brioche_pack::ExtractPackError> {
+    let (file, extracted) = brioche_resources::extract_pack_or_sidecar(path)?;
+    let attached = brioche_resources::extract_attached_packs(file, extracted.unpacked_len)?;
+    Ok(attached.packs)
+}
 
 pub fn pack_source(
     source_path: &Path,
@@ -28,12 +125,10 @@ pub fn pack_source(
             metadata,
             resource_paths: _,
         } => {
-            if format == runnable_core::FORMAT {
-                let metadata: runnable_core::Runnable = serde_json::from_slice(metadata)
-                    .with_context(|| {
-                        format!("failed to deserialize runnable metadata: {metadata:?}")
-                    })?;
-                let Some(runnable_source) = metadata.source else {
+            let runnable = read_metadata::<runnable_core::Runnable>(pack)
+                .map_err(|error| diagnostics::InvalidRunnableMetadata::new(metadata, error))?;
+            if let Some(runnable) = runnable {
+                let Some(runnable_source) = runnable.source else {
                     eyre::bail!("no source path in metadata");
                 };
 
@@ -77,158 +172,142 @@ pub enum PackSource {
     Path(PathBuf),
 }
 
-#[derive(Debug, Clone)]
-pub struct AutopackConfig {
-    pub resource_dir: PathBuf,
-    pub all_resource_dirs: Vec<PathBuf>,
-    pub inputs: AutopackInputs,
-    pub quiet: bool,
-    pub link_dependencies: Vec<PathBuf>,
-    pub dynamic_binary: Option<DynamicBinaryConfig>,
-    pub shared_library: Option<SharedLibraryConfig>,
-    pub script: Option<ScriptConfig>,
-    pub repack: Option<RepackConfig>,
-}
-
-#[derive(Debug, Clone)]
-pub enum AutopackInputs {
-    Paths(Vec<PathBuf>),
-    Globs {
-        base_path: PathBuf,
-        patterns: Vec<String>,
-        exclude_patterns: Vec<String>,
-    },
+#[derive(Clone, Copy)]
+struct AutopackPathConfig {
+    can_skip: bool,
 }
 
-#[derive(Debug, Clone)]
-pub struct DynamicLinkingConfig {
-    pub library_paths: Vec<PathBuf>,
-    pub skip_libraries: HashSet<String>,
-    pub extra_libraries: Vec<String>,
-    pub skip_unknown_libraries: bool,
+/// Replacements for the default steps of autopacking, used with
+/// [`autopack_with`].
+#[cfg(any(feature = "elf", feature = "script"))]
+#[derive(Clone, Copy)]
+pub struct AutopackExtensions<'a> {
+    pub resolver: &'a dyn resolve::Resolver,
+    pub resource_sink: &'a dyn resources::ResourceSink,
+    pub pack_emitter: &'a dyn emit::PackEmitter,
 }
 
-#[derive(Debug, Clone)]
-pub struct DynamicBinaryConfig {
-    pub packed_executable: PathBuf,
-    pub extra_runtime_library_paths: Vec<PathBuf>,
-    pub dynamic_linking: DynamicLinkingConfig,
-}
+pub fn autopack(config: &AutopackConfig) -> eyre::Result<AutopackReport> {
+    #[cfg(any(feature = "elf", feature = "script"))]
+    {
+        let resolver = resolve::LinkDependencyResolver::new(config)?;
+        let resource_sink = match &config.resource_namespace {
+            Some(namespace) => {
+                resources::ResourceDirSink::with_namespace(config.resource_dir.clone(), namespace)
+            }
+            None => resources::ResourceDirSink::new(config.resource_dir.clone()),
+        };
+        let mut resource_sink = resource_sink.with_hasher(config.content_hasher.clone());
+        if let Some(stat_cache_dir) = &config.stat_cache_dir {
+            resource_sink = resource_sink.with_stat_cache_dir(stat_cache_dir.clone());
+        }
+        let pack_emitter: &dyn emit::PackEmitter = if config.shared_launcher {
+            &emit::SharedLauncherPackEmitter
+        } else {
+            &emit::FilePackEmitter
+        };
+        autopack_with(
+            config,
+            AutopackExtensions {
+                resolver: &resolver,
+                resource_sink: &resource_sink,
+                pack_emitter,
+            },
+        )
+    }
 
-#[derive(Debug, Clone)]
-pub struct SharedLibraryConfig {
-    pub dynamic_linking: DynamicLinkingConfig,
-    pub allow_empty: bool,
+    #[cfg(not(any(feature = "elf", feature = "script")))]
+    {
+        validate_output(config)?;
+        let output = Output::for_jobs(config.jobs.get());
+        let report = ReportCollector::default();
+        let ctx = AutopackContext {
+            config,
+            output: &output,
+            report: &report,
+        };
+        autopack_inputs(&ctx)?;
+        Ok(report.finish())
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct ScriptConfig {
-    pub packed_executable: PathBuf,
-    pub base_path: Option<PathBuf>,
-    pub env: HashMap<String, runnable_core::EnvValue>,
-    pub clear_env: bool,
+/// Autopack using the given extensions in place of the default resolver,
+/// resource sink, or pack emitter.
+#[cfg(any(feature = "elf", feature = "script"))]
+pub fn autopack_with(
+    config: &AutopackConfig,
+    extensions: AutopackExtensions<'_>,
+) -> eyre::Result<AutopackReport> {
+    autopack_with_plan(config, extensions, None)
 }
 
-impl ScriptConfig {
-    /// Returns an iterator of environment variables for autopacked scripts.
-    /// Relative paths in the env vars will be adjusted for `output_path`,
-    /// so that the paths stay relative to `base_path`.
-    ///
-    /// For example, if `base_path` is `/output` and `output_path` is
-    /// `/output/bin/hello`, then relative paths will be prepended with
-    /// a `../` so that they stay relative to `/output`.
-    pub fn env_for_output_path<'a>(
-        &'a self,
-        output_path: &'a Path,
-    ) -> impl Iterator<Item = eyre::Result<(String, runnable_core::EnvValue)>> + 'a {
-        self.env.iter().map(|(key, env_value)| {
-            let env_value = match env_value {
-                runnable_core::EnvValue::Clear => env_value.clone(),
-                runnable_core::EnvValue::Inherit => env_value.clone(),
-                runnable_core::EnvValue::Set { value } => {
-                    let value = relative_template(value, self.base_path.as_deref(), output_path)?;
-                    runnable_core::EnvValue::Set { value }
-                }
-                runnable_core::EnvValue::Fallback { value } => {
-                    let value = relative_template(value, self.base_path.as_deref(), output_path)?;
-                    runnable_core::EnvValue::Fallback { value }
-                }
-                runnable_core::EnvValue::Prepend { value, separator } => {
-                    let value = relative_template(value, self.base_path.as_deref(), output_path)?;
-                    runnable_core::EnvValue::Prepend {
-                        value,
-                        separator: separator.clone(),
-                    }
-                }
-                runnable_core::EnvValue::Append { value, separator } => {
-                    let value = relative_template(value, self.base_path.as_deref(), output_path)?;
-                    runnable_core::EnvValue::Append {
-                        value,
-                        separator: separator.clone(),
-                    }
-                }
-            };
-            eyre::Ok((key.clone(), env_value))
-        })
+/// Autopack with the given extensions. When planning, files are only
+/// changed through the extensions, and everything else gets recorded in
+/// the plan instead.
+#[cfg(any(feature = "elf", feature = "script"))]
+fn autopack_with_plan(
+    config: &AutopackConfig,
+    extensions: AutopackExtensions<'_>,
+    plan: Option<&plan::PlanRecorder>,
+) -> eyre::Result<AutopackReport> {
+    validate_output(config)?;
+
+    let mut sidecar_pack_globs = globset::GlobSetBuilder::new();
+    for pattern in &config.sidecar_pack_globs {
+        let glob = globset::Glob::new(pattern)
+            .map_err(|error| diagnostics::InvalidGlobPattern::new(pattern, error))?;
+        sidecar_pack_globs.add(glob);
     }
-}
-
-fn relative_template(
-    value: &runnable_core::Template,
-    base_path: Option<&Path>,
-    output_path: &Path,
-) -> eyre::Result<runnable_core::Template> {
-    let Some(base_path) = base_path else {
-        return Ok(value.clone());
+    let sidecar_pack_globs = sidecar_pack_globs.build()?;
+    #[cfg(feature = "elf")]
+    let optional_library_globs = elf::OptionalLibraryGlobs::new(config)?;
+
+    let AutopackExtensions {
+        resolver,
+        resource_sink,
+        pack_emitter,
+    } = extensions;
+    let output = Output::for_jobs(config.jobs.get());
+    let report = ReportCollector::default();
+    let resource_cache = resources::ResourceCache::default();
+    let ctx = AutopackContext {
+        config,
+        output: &output,
+        report: &report,
+        resolver,
+        resource_sink,
+        resource_cache: &resource_cache,
+        added_resources: None,
+        #[cfg(feature = "elf")]
+        closure_libraries: None,
+        pack_emitter,
+        sidecar_pack_globs: &sidecar_pack_globs,
+        #[cfg(feature = "elf")]
+        optional_library_globs: &optional_library_globs,
+        plan,
     };
-    let output_path = base_path.join(output_path);
-    let output_dir = output_path
-        .parent()
-        .ok_or_else(|| eyre::eyre!("failed to get parent of output path"))?;
-
-    let components = value
-        .components
-        .iter()
-        .map(|component| -> eyre::Result<_> {
-            match component {
-                runnable_core::TemplateComponent::Literal { .. }
-                | runnable_core::TemplateComponent::Resource { .. } => eyre::Ok(component.clone()),
-                runnable_core::TemplateComponent::RelativePath { path } => {
-                    // TODO: Handle path resolution in a cross-platform way.
-                    // This could change based on the host platform
-
-                    let path = path
-                        .to_path()
-                        .with_context(|| format!("failed to parse path {path:?}"))?;
-
-                    let full_path = base_path.join(path);
-                    let new_relative_path = pathdiff::diff_paths(full_path, output_dir)
-                        .context("failed to get path relative to output dir")?;
-                    let new_relative_path = <Vec<u8>>::from_path_buf(new_relative_path).map_err(
-                        |new_relative_path| {
-                            eyre::eyre!("failed to convert path {new_relative_path:?}")
-                        },
-                    )?;
-
-                    eyre::Ok(runnable_core::TemplateComponent::RelativePath {
-                        path: new_relative_path,
-                    })
-                }
-            }
-        })
-        .collect::<eyre::Result<Vec<_>>>()?;
-    Ok(runnable_core::Template { components })
-}
+    autopack_inputs(&ctx)?;
+
+    #[cfg(feature = "elf")]
+    let dangling_symlinks = report.dangling_symlinks.warnings();
+    #[cfg(not(feature = "elf"))]
+    let dangling_symlinks = vec![];
+    for warning in resolver.warnings().into_iter().chain(dangling_symlinks) {
+        ctx.warn(warning)?;
+    }
 
-#[derive(Debug, Clone)]
-pub struct RepackConfig {}
+    #[cfg(feature = "elf")]
+    match plan {
+        Some(plan) => plan.set_debug_links(report.debug_links.take()),
+        None => report.debug_links.write(&config.resource_dir)?,
+    }
+    ctx.output.flush();
 
-struct AutopackPathConfig {
-    can_skip: bool,
+    Ok(report.finish())
 }
 
-pub fn autopack(config: &AutopackConfig) -> eyre::Result<()> {
-    let ctx = autopack_context(config)?;
+fn autopack_inputs(ctx: &AutopackContext) -> eyre::Result<()> {
+    let config = ctx.config;
     let mut pending_paths = BTreeMap::<PathBuf, AutopackPathConfig>::new();
 
     match &config.inputs {
@@ -246,21 +325,81 @@ pub fn autopack(config: &AutopackConfig) -> eyre::Result<()> {
         } => {
             let mut globs = globset::GlobSetBuilder::new();
             for pattern in patterns {
-                globs.add(globset::Glob::new(pattern)?);
+                let glob = globset::Glob::new(pattern)
+                    .map_err(|error| diagnostics::InvalidGlobPattern::new(pattern, error))?;
+                globs.add(glob);
             }
 
             let mut exclude_globs = globset::GlobSetBuilder::new();
             for pattern in exclude_patterns {
-                exclude_globs.add(globset::Glob::new(pattern)?);
+                let glob = globset::Glob::new(pattern)
+                    .map_err(|error| diagnostics::InvalidGlobPattern::new(pattern, error))?;
+                exclude_globs.add(glob);
+            }
+
+            let mut prune_dirs = globset::GlobSetBuilder::new();
+            for pattern in &config.prune_dirs {
+                let glob = globset::Glob::new(pattern)
+                    .map_err(|error| diagnostics::InvalidGlobPattern::new(pattern, error))?;
+                prune_dirs.add(glob);
             }
 
             let globs = globs.build()?;
             let exclude_globs = exclude_globs.build()?;
+            let prune_dirs = prune_dirs.build()?;
+
+            // Never walk into the output resource dir, since its blobs
+            // would otherwise get autopacked in place. It's matched by
+            // inode so it's skipped however the paths are spelled
+            let resource_dir_id = std::fs::metadata(&config.resource_dir)
+                .ok()
+                .map(|metadata| (metadata.dev(), metadata.ino()));
+
+            // Sort entries so traversal is deterministic across filesystems
+            let walkdir = walkdir::WalkDir::new(base_path)
+                .follow_links(config.follow_symlinks)
+                .max_depth(config.max_depth.unwrap_or(usize::MAX))
+                .sort_by_file_name()
+                .into_iter()
+                .filter_entry(|entry| {
+                    if !entry.file_type().is_dir() {
+                        return true;
+                    }
 
-            let walkdir = walkdir::WalkDir::new(base_path);
+                    let is_pruned = entry.depth() > 0
+                        && entry
+                            .path()
+                            .strip_prefix(base_path)
+                            .is_ok_and(|relative_path| prune_dirs.is_match(relative_path));
+                    let is_resource_dir = resource_dir_id.is_some_and(|resource_dir_id| {
+                        entry.metadata().is_ok_and(|metadata| {
+                            (metadata.dev(), metadata.ino()) == resource_dir_id
+                        })
+                    });
+                    !is_pruned && !is_resource_dir
+                });
+            let mut walked_files = BTreeSet::new();
+            let mut matched_patterns = vec![false; patterns.len()];
             for entry in walkdir {
-                let entry = entry?;
-                if !entry.file_type().is_file() {
+                let entry = match entry {
+                    Ok(entry) => entry,
+
+                    // When following symlinks, skip ones that loop back to
+                    // a parent directory or don't point to anything
+                    Err(error)
+                        if config.follow_symlinks && error.path().is_some_and(is_symlink) =>
+                    {
+                        continue;
+                    }
+                    Err(error) => return Err(error.into()),
+                };
+                // Skip files written by a previous autopack run that share
+                // a packed executable: the sidecar packs, and the hidden
+                // packed executable they're linked to
+                if !entry.file_type().is_file()
+                    || brioche_resources::is_sidecar_pack_path(entry.path())
+                    || brioche_resources::is_shared_packed_executable_path(entry.path())
+                {
                     continue;
                 }
 
@@ -273,777 +412,805 @@ pub fn autopack(config: &AutopackConfig) -> eyre::Result<()> {
                         )
                     })?;
 
-                if globs.is_match(&relative_entry_path)
-                    && !exclude_globs.is_match(&relative_entry_path)
-                {
+                let matches = globs.matches(&relative_entry_path);
+                for &index in &matches {
+                    matched_patterns[index] = true;
+                }
+
+                if !matches.is_empty() && !exclude_globs.is_match(&relative_entry_path) {
+                    // Only autopack a file once if it's reached through
+                    // more than one symlinked directory. Symlinked files
+                    // are handled by the symlink policy instead
+                    if config.follow_symlinks && !entry.path_is_symlink() {
+                        let canonical_path =
+                            brioche_resources::canonicalize(entry.path(), config.symlink_limit)?;
+                        if !walked_files.insert(canonical_path) {
+                            continue;
+                        }
+                    }
+
                     pending_paths.insert(
                         entry.path().to_owned(),
                         AutopackPathConfig { can_skip: false },
                     );
                 }
             }
+
+            // A pattern that matched nothing may have a typo, but patterns
+            // can also be optional, so only warn about it. Patterns whose
+            // matches were all excluded still count as matching
+            for (pattern, matched) in patterns.iter().zip(matched_patterns) {
+                if !matched {
+                    ctx.warn(AutopackWarning::UnmatchedGlobPattern {
+                        path: base_path.clone(),
+                        pattern: pattern.clone(),
+                    })?;
+                }
+            }
         }
     }
 
-    while let Some((path, path_config)) = pending_paths.pop_first() {
-        autopack_path(&ctx, &path, &path_config, &mut pending_paths)?;
+    // Symlinked inputs pointing at the same file (e.g. `bin/foo` linking
+    // to `libexec/foo`) share one autopacked target, which is tracked so
+    // it only gets autopacked once
+    let pending_paths = PendingPaths::new(pending_paths);
+    let autopacked_paths = Mutex::new(BTreeSet::new());
+
+    let run_worker = || -> eyre::Result<()> {
+        while let Some((path, path_config)) = pending_paths.pop_first() {
+            let result = autopack_input(ctx, &path, path_config, &pending_paths, &autopacked_paths);
+            pending_paths.finish(&path);
+            if result.is_err() {
+                // Stop other workers from starting new paths
+                pending_paths.cancel();
+            }
+            result?;
+        }
+
+        Ok(())
+    };
+
+    let jobs = config.jobs.get();
+    let result = if jobs > 1 {
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs).map(|_| scope.spawn(run_worker)).collect();
+            workers.into_iter().try_for_each(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+        })
+    } else {
+        run_worker()
+    };
+
+    ctx.output.flush();
+    result?;
+
+    Ok(())
+}
+
+/// Autopack a path taken from the inputs. Symlinks get their target
+/// autopacked instead, depending on the symlink policy.
+fn autopack_input(
+    ctx: &AutopackContext,
+    path: &Path,
+    path_config: AutopackPathConfig,
+    pending_paths: &PendingPaths,
+    autopacked_paths: &Mutex<BTreeSet<PathBuf>>,
+) -> eyre::Result<()> {
+    let config = ctx.config;
+    let symlink_target = match (&config.output, config.symlink_policy) {
+        (AutopackOutput::InPlace, SymlinkPolicy::AutopackTarget) if is_symlink(path) => {
+            Some(brioche_resources::canonicalize(path, config.symlink_limit)?)
+        }
+        _ => None,
+    };
+
+    match symlink_target {
+        Some(target) => {
+            ctx.report.add_shared_link(&target, path);
+            let is_new_target = autopacked_paths
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .insert(target.clone());
+            if is_new_target {
+                match pending_paths.take(&target) {
+                    Taken::Pending(target_config) => {
+                        let result = autopack_path(ctx, &target, &target_config, pending_paths);
+                        pending_paths.finish(&target);
+                        result?;
+                    }
+                    Taken::NotPending => {
+                        autopack_path(ctx, &target, &path_config, pending_paths)?;
+                    }
+                    Taken::Done => {}
+                }
+            }
+
+            ctx.print(
+                path,
+                format!("{} links to {}", path.display(), target.display()),
+            );
+        }
+        None => {
+            if let Ok(canonical_path) = brioche_resources::canonicalize(path, config.symlink_limit)
+            {
+                autopacked_paths
+                    .lock()
+                    .unwrap_or_else(|error| error.into_inner())
+                    .insert(canonical_path);
+            }
+            autopack_path(ctx, path, &path_config, pending_paths)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_symlink(path: &Path) -> bool {
+    path.symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Ensure the output config can't modify any inputs. This is checked
+/// before doing any work, so a misconfigured run fails without touching
+/// the filesystem.
+fn validate_output(config: &AutopackConfig) -> eyre::Result<()> {
+    let AutopackOutput::CopyOnWrite {
+        input_root,
+        output_root,
+    } = &config.output
+    else {
+        return Ok(());
+    };
+
+    eyre::ensure!(
+        !output_root.starts_with(input_root) && !input_root.starts_with(output_root),
+        "output root {} overlaps input root {}, so inputs could be modified in place",
+        output_root.display(),
+        input_root.display(),
+    );
+
+    let input_paths = match &config.inputs {
+        AutopackInputs::Paths(paths) => &paths[..],
+        AutopackInputs::Globs { base_path, .. } => std::slice::from_ref(base_path),
+    };
+    for input_path in input_paths {
+        eyre::ensure!(
+            input_path.starts_with(input_root),
+            "input path {} is not under input root {}, so it would be modified in place",
+            input_path.display(),
+            input_root.display(),
+        );
     }
 
     Ok(())
 }
 
+#[derive(Clone, Copy)]
 struct AutopackContext<'a> {
     config: &'a AutopackConfig,
-    link_dependency_library_paths: Vec<PathBuf>,
-    link_dependency_paths: Vec<PathBuf>,
+    output: &'a Output,
+    report: &'a ReportCollector,
+    #[cfg(any(feature = "elf", feature = "script"))]
+    resolver: &'a dyn resolve::Resolver,
+    #[cfg(any(feature = "elf", feature = "script"))]
+    resource_sink: &'a dyn resources::ResourceSink,
+    /// Resources already added from files during this run.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    resource_cache: &'a resources::ResourceCache,
+    /// Records the resources added for the path being autopacked.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    added_resources: Option<&'a Mutex<Vec<PathBuf>>>,
+    /// Records the closure of the path being autopacked.
+    #[cfg(feature = "elf")]
+    closure_libraries: Option<&'a Mutex<Vec<ClosureLibrary>>>,
+    #[cfg(any(feature = "elf", feature = "script"))]
+    pack_emitter: &'a dyn emit::PackEmitter,
+    #[cfg(any(feature = "elf", feature = "script"))]
+    sidecar_pack_globs: &'a globset::GlobSet,
+    #[cfg(feature = "elf")]
+    optional_library_globs: &'a elf::OptionalLibraryGlobs,
+    /// Records files that would be written directly, rather than through
+    /// the extensions, when planning.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    plan: Option<&'a plan::PlanRecorder>,
 }
 
-fn autopack_context(config: &AutopackConfig) -> eyre::Result<AutopackContext> {
-    let mut link_dependency_library_paths = vec![];
-    let mut link_dependency_paths = vec![];
-    for link_dep in &config.link_dependencies {
-        // Add $LIBRARY_PATH directories from symlinks under
-        // brioche-env.d/env/LIBRARY_PATH
-        let library_path_env_dir = link_dep
-            .join("brioche-env.d")
-            .join("env")
-            .join("LIBRARY_PATH");
-        let library_path_env_dir_entries = match std::fs::read_dir(&library_path_env_dir) {
-            Ok(entries) => entries,
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                continue;
-            }
-            Err(error) => {
-                return Err(error).with_context(|| {
-                    format!("failed to read directory {:?}", library_path_env_dir)
-                });
-            }
+impl AutopackContext<'_> {
+    /// Write an autopacked file with the pack emitter, attaching the
+    /// configured metadata. Runnable packs already record the fingerprint,
+    /// so it only gets attached for other packs.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    fn emit_pack(
+        &self,
+        output_path: &Path,
+        base: emit::PackBase<'_>,
+        pack: &brioche_pack::Pack,
+        fingerprint: &PackerFingerprint,
+    ) -> eyre::Result<()> {
+        let fingerprint_pack = match pack {
+            brioche_pack::Pack::Metadata { .. } => None,
+            _ => Some(metadata_pack(vec![], fingerprint)?),
         };
-        for entry in library_path_env_dir_entries {
-            let entry = entry?;
-            eyre::ensure!(
-                entry.metadata()?.is_symlink(),
-                "expected {:?} to be a symlink",
-                entry.path()
-            );
-
-            let entry_path = entry
-                .path()
-                .canonicalize()
-                .with_context(|| format!("failed to canonicalize path {:?}", entry.path()))?;
-            link_dependency_library_paths.push(entry_path);
-        }
+        let attached = fingerprint_pack
+            .into_iter()
+            .chain(
+                self.config
+                    .attached_metadata
+                    .iter()
+                    .map(|metadata| metadata.to_pack()),
+            )
+            .collect::<Vec<_>>();
+        self.pack_emitter
+            .emit_with_attached(output_path, base, pack, &attached)
     }
 
-    for link_dep in &config.link_dependencies {
-        // Add $PATH directories from symlinks under brioche-env.d/env/PATH
-        let path_env_dir = link_dep.join("brioche-env.d").join("env").join("PATH");
-        let path_env_dir_entries = match std::fs::read_dir(&path_env_dir) {
-            Ok(entries) => entries,
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                continue;
-            }
-            Err(error) => {
-                return Err(error)
-                    .with_context(|| format!("failed to read directory {:?}", path_env_dir));
+    /// Get the path to write the autopacked version of `path` to.
+    fn output_path(&self, path: &Path) -> eyre::Result<PathBuf> {
+        match &self.config.output {
+            AutopackOutput::InPlace => Ok(path.to_owned()),
+            AutopackOutput::CopyOnWrite {
+                input_root,
+                output_root,
+            } => {
+                let relative_path = relative_to_root(path, input_root, self.config.symlink_limit)
+                    .ok_or_else(|| {
+                    eyre::eyre!(
+                        "path {} is not under input root {}",
+                        path.display(),
+                        input_root.display()
+                    )
+                })?;
+                Ok(output_root.join(relative_path))
             }
-        };
-        for entry in path_env_dir_entries {
-            let entry = entry?;
-            eyre::ensure!(
-                entry.metadata()?.is_symlink(),
-                "expected {:?} to be a symlink",
-                entry.path()
-            );
-
-            let entry_path = entry
-                .path()
-                .canonicalize()
-                .with_context(|| format!("failed to canonicalize path {:?}", entry.path()))?;
-            link_dependency_paths.push(entry_path);
         }
     }
 
-    for link_dep in &config.link_dependencies {
-        // Add bin/ to $PATH if it exists
-        let link_dep_bin = link_dep.join("bin");
-        if link_dep_bin.is_dir() {
-            link_dependency_paths.push(link_dep_bin);
+    /// Get the path an output would have in the input tree. Relative paths
+    /// recorded in packs should be based on this path, since the output
+    /// tree gets layered over the input tree.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    fn logical_path(&self, output_path: &Path) -> PathBuf {
+        match &self.config.output {
+            AutopackOutput::InPlace => output_path.to_owned(),
+            AutopackOutput::CopyOnWrite {
+                input_root,
+                output_root,
+            } => match output_path.strip_prefix(output_root) {
+                Ok(relative_path) => input_root.join(relative_path),
+                Err(_) => output_path.to_owned(),
+            },
         }
     }
 
-    Ok(AutopackContext {
-        config,
-        link_dependency_library_paths,
-        link_dependency_paths,
-    })
-}
+    /// Check if autopacking is only being planned, so files shouldn't be
+    /// changed directly.
+    fn is_planning(&self) -> bool {
+        #[cfg(any(feature = "elf", feature = "script"))]
+        return self.plan.is_some();
+        #[cfg(not(any(feature = "elf", feature = "script")))]
+        return false;
+    }
 
-fn autopack_path(
-    ctx: &AutopackContext,
-    path: &Path,
-    path_config: &AutopackPathConfig,
-    pending_paths: &mut BTreeMap<PathBuf, AutopackPathConfig>,
-) -> eyre::Result<()> {
-    let did_pack = try_autopack_path(ctx, path, path, pending_paths)?;
-    if did_pack {
-        if !ctx.config.quiet {
-            println!("autopacked {}", path.display());
-        }
-    } else if !path_config.can_skip {
-        if !ctx.config.quiet {
-            println!("skipped {}", path.display());
+    /// Print a message about `path`, unless autopacking is quiet.
+    fn print(&self, path: &Path, message: String) {
+        if !self.config.quiet {
+            self.output.print(path, Message::Stdout(message));
         }
-    } else {
-        eyre::bail!("failed to autopack path: {path:?}");
     }
 
-    Ok(())
-}
-
-fn try_autopack_path(
-    ctx: &AutopackContext,
-    source_path: &Path,
-    output_path: &Path,
-    pending_paths: &mut BTreeMap<PathBuf, AutopackPathConfig>,
-) -> eyre::Result<bool> {
-    let Some(kind) = autopack_kind(source_path)? else {
-        return Ok(false);
-    };
+    /// Report a warning, or fail if its kind is denied.
+    fn warn(&self, warning: AutopackWarning) -> eyre::Result<()> {
+        let policy = &self.config.warnings;
+        if policy.denied.contains(&warning.kind()) {
+            return Err(diagnostics::DeniedWarning::new(warning).into());
+        }
 
-    match kind {
-        AutopackKind::DynamicBinary => {
-            autopack_dynamic_binary(ctx, source_path, output_path, pending_paths)
+        if !self.report.add_warning(warning.clone()) {
+            return Ok(());
         }
-        AutopackKind::SharedLibrary => {
-            autopack_shared_library(ctx, source_path, output_path, pending_paths)
+        match &policy.callback {
+            Some(callback) => callback(&warning),
+            None if !self.config.quiet => self.output.print(
+                warning.path(),
+                Message::Stderr(format!("warning: {warning}")),
+            ),
+            None => {}
         }
-        AutopackKind::Script => autopack_script(ctx, source_path, output_path, pending_paths),
-        AutopackKind::Repack => autopack_repack(ctx, source_path, output_path, pending_paths),
+        Ok(())
     }
-}
 
-fn autopack_kind(path: &Path) -> eyre::Result<Option<AutopackKind>> {
-    let contents = std::fs::read(path)?;
+    /// Check if the output at `output_path` is a hard link to a shared
+    /// packed executable, see [`emit::is_shared_launcher`].
+    fn is_shared_launcher(&self, output_path: &Path) -> bool {
+        #[cfg(any(feature = "elf", feature = "script"))]
+        return match self.plan {
+            Some(plan) => plan.is_shared_launcher(output_path),
+            None => emit::is_shared_launcher(output_path),
+        };
+        #[cfg(not(any(feature = "elf", feature = "script")))]
+        return false;
+    }
 
-    let contents_cursor = std::io::Cursor::new(&contents[..]);
-    let pack = brioche_pack::extract_pack(contents_cursor);
+    /// Check if the pack for `path` should be written to a sidecar file.
+    /// Paths are matched relative to the recipe when it's known.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    fn uses_sidecar_pack(&self, path: &Path) -> bool {
+        if self.sidecar_pack_globs.is_empty() {
+            return false;
+        }
 
-    if pack.is_ok() {
-        Ok(Some(AutopackKind::Repack))
-    } else if contents.starts_with(b"#!") {
-        Ok(Some(AutopackKind::Script))
-    } else {
-        let program_object = goblin::Object::parse(&contents);
+        let relative_path = self.recipe_relative_path(path);
+        let path = relative_path.as_deref().unwrap_or(path);
+        self.sidecar_pack_globs.is_match(path)
+    }
 
-        let Ok(goblin::Object::Elf(program_object)) = program_object else {
-            return Ok(None);
+    /// Get `path` relative to the recipe, if the recipe is known.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    fn recipe_relative_path(&self, path: &Path) -> Option<PathBuf> {
+        let root = match (&self.config.inputs, &self.config.output) {
+            (_, AutopackOutput::CopyOnWrite { input_root, .. }) => Some(input_root),
+            (AutopackInputs::Globs { base_path, .. }, AutopackOutput::InPlace) => Some(base_path),
+            (AutopackInputs::Paths(_), AutopackOutput::InPlace) => None,
         };
+        root.and_then(|root| relative_to_root(path, root, self.config.symlink_limit))
+    }
 
-        if program_object.interpreter.is_some() {
-            Ok(Some(AutopackKind::DynamicBinary))
-        } else if program_object.is_lib {
-            Ok(Some(AutopackKind::SharedLibrary))
-        } else {
-            Ok(None)
+    /// Get the path to read a dependency from. If the dependency was
+    /// already autopacked to a separate output, then the output is used.
+    #[cfg(any(feature = "elf", feature = "script"))]
+    fn dependency_path(&self, path: &Path) -> PathBuf {
+        match &self.config.output {
+            AutopackOutput::InPlace => path.to_owned(),
+            AutopackOutput::CopyOnWrite { .. } => match self.output_path(path) {
+                Ok(output_path)
+                    if output_path.is_file()
+                        || self.plan.is_some_and(|plan| plan.has_output(&output_path)) =>
+                {
+                    output_path
+                }
+                _ => path.to_owned(),
+            },
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum AutopackKind {
-    DynamicBinary,
-    SharedLibrary,
-    Script,
-    Repack,
-}
-
-fn autopack_dynamic_binary(
-    ctx: &AutopackContext,
-    source_path: &Path,
-    output_path: &Path,
-    pending_paths: &mut BTreeMap<PathBuf, AutopackPathConfig>,
-) -> eyre::Result<bool> {
-    let Some(dynamic_binary_config) = &ctx.config.dynamic_binary else {
-        return Ok(false);
-    };
-
-    let output_path_parent = output_path
-        .parent()
-        .ok_or_eyre("could not get parent of output path")?;
+impl AutopackContext<'_> {
+    /// Set the permissions and modification time of an autopacked file,
+    /// then get its size and mode. Files sharing a packed executable keep
+    /// its permissions and modification time, since changing them would
+    /// change every program sharing it.
+    fn finish_output(
+        &self,
+        output_path: &Path,
+        source_path: &Path,
+        source_mode: u32,
+        source_time: std::time::SystemTime,
+        privileges: &Privileges,
+        kind: AutopackKind,
+    ) -> eyre::Result<(u64, u32)> {
+        let output_permissions = self.config.output_permissions;
+        let mut mode = if kind.is_program() {
+            output_permissions.program_mode(source_mode)
+        } else {
+            output_permissions.mode(source_mode)
+        };
 
-    let contents = std::fs::read(source_path)?;
-    let program_object = goblin::Object::parse(&contents)?;
+        // Signed packed executables always get shared, even without the
+        // shared launcher enabled
+        let shared_launcher = self.is_shared_launcher(output_path);
 
-    let goblin::Object::Elf(program_object) = program_object else {
-        eyre::bail!(
-            "tried to autopack non-ELF dynamic binary: {}",
-            source_path.display()
-        );
-    };
+        let preserve_privileges =
+            !privileges.is_empty() && matches!(self.config.privileges, PrivilegePolicy::Preserve);
+        if preserve_privileges {
+            // Hard links to a shared packed executable share their mode
+            // and capabilities, so privileges would apply to all of them
+            eyre::ensure!(
+                !shared_launcher,
+                "can't preserve the privileges of {} while sharing packed executables, consider excluding it from autopacking",
+                source_path.display()
+            );
+            mode |= privileges.mode;
+        } else if !privileges.is_empty() {
+            mode &= !privileges.mode;
+
+            #[cfg(any(feature = "elf", feature = "script"))]
+            self.warn(AutopackWarning::StrippedPrivileges {
+                path: source_path.to_owned(),
+                setuid: privileges.mode & 0o4000 != 0,
+                setgid: privileges.mode & 0o2000 != 0,
+                capabilities: privileges.capabilities.is_some(),
+            })?;
+        }
 
-    let Some(interpreter) = program_object.interpreter else {
-        eyre::bail!(
-            "tried to autopack dynamic binary without an interpreter: {}",
-            source_path.display()
-        );
-    };
-    let relative_interpreter = interpreter.strip_prefix('/').ok_or_else(|| {
-        eyre::eyre!("expected program interpreter to start with '/': {interpreter:?}")
-    })?;
-
-    let mut interpreter_path = None;
-    for dependency in &ctx.config.link_dependencies {
-        let dependency_path = dependency.join(relative_interpreter);
-        if dependency_path.exists() {
-            interpreter_path = Some(dependency_path);
-            break;
+        #[cfg(any(feature = "elf", feature = "script"))]
+        if let Some(plan) = self.plan {
+            eyre::ensure!(
+                !(preserve_privileges && privileges.capabilities.is_some()),
+                "can't plan preserving the file capabilities of {}",
+                source_path.display()
+            );
+            let output_size = plan.set_mode(output_path, mode)?;
+            if shared_launcher {
+                return Ok((output_size, emit::SHARED_PACKED_EXECUTABLE_MODE));
+            }
+            return Ok((output_size, mode));
         }
-    }
 
-    let interpreter_path = interpreter_path.ok_or_else(|| {
-        eyre::eyre!("could not find interpreter for dynamic binary: {source_path:?}")
-    })?;
-
-    // Autopack the interpreter if it's pending
-    try_autopack_dependency(ctx, &interpreter_path, pending_paths)?;
-
-    let interpreter_resource_path = add_named_blob_from(ctx, &interpreter_path, None)
-        .with_context(|| format!("failed to add resource for interpreter {interpreter_path:?}"))?;
-    let program_resource_path = add_named_blob_from(ctx, source_path, None)
-        .with_context(|| format!("failed to add resource for program {source_path:?}"))?;
-
-    let needed_libraries: VecDeque<_> = program_object
-        .libraries
-        .iter()
-        .copied()
-        .chain(
-            dynamic_binary_config
-                .dynamic_linking
-                .extra_libraries
-                .iter()
-                .map(|lib| &**lib),
-        )
-        .map(|lib| lib.to_string())
-        .collect();
-
-    let library_dir_resource_paths = collect_all_library_dirs(
-        ctx,
-        &dynamic_binary_config.dynamic_linking,
-        needed_libraries,
-        pending_paths,
-    )?;
+        // Outputs written in place already keep the input's permissions,
+        // so they only need to be set for new outputs or a different mode.
+        // Writing can clear setuid and setgid bits, so they're always set
+        // again when preserved
+        if shared_launcher {
+            // Keep the shared packed executable's permissions and time
+        } else if output_path != source_path || mode != source_mode || preserve_privileges {
+            std::fs::set_permissions(output_path, std::fs::Permissions::from_mode(mode))
+                .with_context(|| {
+                    format!("failed to set permissions of {}", output_path.display())
+                })?;
+        }
+        if preserve_privileges {
+            privileges.apply_capabilities(output_path)?;
+        }
+        if let Some(time) = self
+            .config
+            .timestamps
+            .time(source_time)
+            .filter(|_| !shared_launcher)
+        {
+            std::fs::File::open(output_path)
+                .and_then(|file| file.set_modified(time))
+                .with_context(|| {
+                    format!(
+                        "failed to set modification time of {}",
+                        output_path.display()
+                    )
+                })?;
+        }
 
-    let program = <Vec<u8>>::from_path_buf(program_resource_path)
-        .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
-    let interpreter = <Vec<u8>>::from_path_buf(interpreter_resource_path)
-        .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))?;
-    let library_dirs = library_dir_resource_paths
-        .into_iter()
-        .map(|resource_path| {
-            <Vec<u8>>::from_path_buf(resource_path)
-                .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
-        })
-        .collect::<eyre::Result<Vec<_>>>()?;
-    let runtime_library_dirs = dynamic_binary_config
-        .extra_runtime_library_paths
-        .iter()
-        .map(|path| {
-            let path = pathdiff::diff_paths(path, output_path_parent).ok_or_else(|| eyre::eyre!("failed to get relative path from output path {output_path_parent:?} to runtime library path {path:?}"))?;
-            <Vec<u8>>::from_path_buf(path)
-                .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
-        })
-        .collect::<eyre::Result<Vec<_>>>()?;
+        let output_metadata = std::fs::metadata(output_path)
+            .with_context(|| format!("failed to get metadata of {}", output_path.display()))?;
+        let mode = output_metadata.permissions().mode() & 0o7777;
+        Ok((output_metadata.len(), mode))
+    }
+}
 
-    let pack = brioche_pack::Pack::LdLinux {
-        program,
-        interpreter,
-        library_dirs,
-        runtime_library_dirs,
-    };
+fn relative_to_root(path: &Path, root: &Path, symlink_limit: u32) -> Option<PathBuf> {
+    if let Ok(relative_path) = path.strip_prefix(root) {
+        return Some(relative_path.to_owned());
+    }
 
-    let packed_exec_path = &dynamic_binary_config.packed_executable;
-    let mut packed_exec = std::fs::File::open(packed_exec_path)
-        .with_context(|| format!("failed to open packed executable {packed_exec_path:?}"))?;
-    let mut output = std::fs::File::create(output_path)
-        .with_context(|| format!("failed to create file {output_path:?}"))?;
-    std::io::copy(&mut packed_exec, &mut output)
-        .with_context(|| format!("failed to copy packed executable to {output_path:?}"))?;
-    brioche_pack::inject_pack(output, &pack)
-        .with_context(|| format!("failed to inject pack into {output_path:?}"))?;
-
-    Ok(true)
+    // Fall back to comparing canonical paths, e.g. if the path was reached
+    // through a symlink
+    let path = brioche_resources::canonicalize(path, symlink_limit).ok()?;
+    let root = brioche_resources::canonicalize(root, symlink_limit).ok()?;
+    let relative_path = path.strip_prefix(root).ok()?;
+    Some(relative_path.to_owned())
 }
 
-fn autopack_shared_library(
+fn autopack_path(
     ctx: &AutopackContext,
-    source_path: &Path,
-    output_path: &Path,
-    pending_paths: &mut BTreeMap<PathBuf, AutopackPathConfig>,
-) -> eyre::Result<bool> {
-    let Some(shared_library_config) = &ctx.config.shared_library else {
-        return Ok(false);
-    };
-
-    let contents = std::fs::read(source_path)?;
-    let program_object = goblin::Object::parse(&contents)?;
-
-    let goblin::Object::Elf(program_object) = program_object else {
+    path: &Path,
+    path_config: &AutopackPathConfig,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<()> {
+    let output_path = ctx.output_path(path)?;
+
+    // Symlinks are followed, so this is the metadata of the file being
+    // autopacked
+    let source_metadata = std::fs::metadata(path)
+        .with_context(|| format!("failed to get metadata of {}", path.display()))?;
+    let original_size = source_metadata.len();
+    let source_mode = source_metadata.permissions().mode() & 0o7777;
+    let source_time = source_metadata
+        .modified()
+        .with_context(|| format!("failed to get modification time of {}", path.display()))?;
+    let privileges = Privileges::read(path, source_mode)?;
+    if !privileges.is_empty() && matches!(ctx.config.privileges, PrivilegePolicy::Error) {
         eyre::bail!(
-            "tried to autopack non-ELF dynamic binary: {}",
-            source_path.display()
+            "{} has setuid or setgid bits or file capabilities, which aren't allowed by the privilege policy",
+            path.display()
         );
-    };
-
-    let needed_libraries: VecDeque<_> = program_object
-        .libraries
-        .iter()
-        .copied()
-        .filter(|library| {
-            !shared_library_config
-                .dynamic_linking
-                .skip_libraries
-                .contains(*library)
-        })
-        .chain(
-            shared_library_config
-                .dynamic_linking
-                .extra_libraries
-                .iter()
-                .map(|lib| &**lib),
-        )
-        .map(|lib| lib.to_string())
-        .collect();
-
-    let library_dir_resource_paths = collect_all_library_dirs(
-        ctx,
-        &shared_library_config.dynamic_linking,
-        needed_libraries,
-        pending_paths,
-    )?;
-
-    let library_dirs = library_dir_resource_paths
-        .into_iter()
-        .map(|resource_path| {
-            <Vec<u8>>::from_path_buf(resource_path)
-                .map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
-        })
-        .collect::<eyre::Result<Vec<_>>>()?;
-    let pack = brioche_pack::Pack::Static { library_dirs };
+    }
 
-    if !pack.should_add_to_executable() && !shared_library_config.allow_empty {
-        return Ok(false);
+    if output_path != path && !ctx.is_planning() {
+        let output_parent = output_path
+            .parent()
+            .ok_or_eyre("could not get parent of output path")?;
+        std::fs::create_dir_all(output_parent)
+            .with_context(|| format!("failed to create directory {output_parent:?}"))?;
     }
 
-    let file = if source_path == output_path {
-        std::fs::OpenOptions::new().append(true).open(output_path)?
-    } else {
-        let mut new_file = std::fs::File::create(output_path)?;
-        new_file.write_all(&contents)?;
-        new_file
+    // Replace symlinks with an autopacked copy of their target, rather
+    // than writing through them
+    let replaced_link = match ctx.config.symlink_policy {
+        SymlinkPolicy::ReplaceLink
+            if output_path == path && is_symlink(path) && !ctx.is_planning() =>
+        {
+            let link_target = std::fs::read_link(path)
+                .with_context(|| format!("failed to read symlink {}", path.display()))?;
+            Some(link_target)
+        }
+        _ => None,
     };
-    brioche_pack::inject_pack(file, &pack)?;
-
-    Ok(true)
-}
-
-fn autopack_script(
-    ctx: &AutopackContext,
-    source_path: &Path,
-    output_path: &Path,
-    pending_paths: &mut BTreeMap<PathBuf, AutopackPathConfig>,
-) -> eyre::Result<bool> {
-    let Some(script_config) = &ctx.config.script else {
-        return Ok(false);
+    let source_path = match &replaced_link {
+        Some(_) => {
+            let source_path = brioche_resources::canonicalize(path, ctx.config.symlink_limit)?;
+            std::fs::remove_file(path)
+                .with_context(|| format!("failed to remove symlink {}", path.display()))?;
+            source_path
+        }
+        None => path.to_owned(),
     };
 
-    let script_file = std::fs::File::open(source_path)?;
-    let mut script_file = std::io::BufReader::new(script_file);
-    let mut shebang = [0; 2];
-    let Ok(()) = script_file.read_exact(&mut shebang) else {
-        return Ok(false);
+    // Dependencies autopacked along the way record their own resources
+    // and closure
+    #[cfg(any(feature = "elf", feature = "script"))]
+    let added_resources = Mutex::default();
+    #[cfg(feature = "elf")]
+    let closure_libraries = Mutex::default();
+    #[cfg(any(feature = "elf", feature = "script"))]
+    let ctx = &AutopackContext {
+        added_resources: Some(&added_resources),
+        #[cfg(feature = "elf")]
+        closure_libraries: Some(&closure_libraries),
+        ..*ctx
     };
-    if shebang != *b"#!" {
-        return Ok(false);
+
+    let result = try_autopack_path(ctx, &source_path, &output_path, pending_paths);
+    if let Some(link_target) = &replaced_link {
+        if !matches!(result, Ok(Outcome::Autopacked(_))) {
+            // Restore the symlink if it wasn't replaced
+            let _ = std::fs::remove_file(path);
+            std::os::unix::fs::symlink(link_target, path)
+                .with_context(|| format!("failed to restore symlink {}", path.display()))?;
+        }
     }
 
-    let mut shebang_line = String::new();
-    script_file.read_line(&mut shebang_line)?;
+    let kind = match result? {
+        Outcome::Autopacked(kind) => kind,
+        Outcome::Skipped(reason) => {
+            if path_config.can_skip {
+                eyre::bail!("failed to autopack path: {path:?}");
+            }
 
-    let shebang_line = shebang_line.trim();
-    let shebang_parts = shebang_line.split_once(|c: char| c.is_ascii_whitespace());
-    let (command_path, arg) = match shebang_parts {
-        Some((command_path, arg)) => (command_path.trim(), arg.trim()),
-        None => (shebang_line, ""),
+            ctx.print(path, format!("skipped {}", path.display()));
+            ctx.report.add_path(path, PathOutcome::Skipped { reason });
+            return Ok(());
+        }
     };
 
-    let mut arg = Some(arg).filter(|arg| !arg.is_empty());
-    let mut command_name = command_path
-        .split(['/', '\\'])
-        .last()
-        .unwrap_or(command_path);
+    let (output_size, mode) = ctx.finish_output(
+        &output_path,
+        &source_path,
+        source_mode,
+        source_time,
+        &privileges,
+        kind,
+    )?;
 
-    if command_name == "env" {
-        command_name = arg.ok_or_eyre("expected argument for env script")?;
-        arg = None;
-    }
-    let mut command = None;
-    for link_dependency_path in &ctx.link_dependency_paths {
-        if link_dependency_path.join(command_name).is_file() {
-            command = Some(link_dependency_path.join(command_name));
-            break;
+    // The autopacked file's size change, which is usually the size of
+    // the packed executable and pack
+    let overhead = i128::from(output_size) - i128::from(original_size);
+    if let Some(limit) = &ctx.config.size_overhead_limit {
+        if original_size <= limit.max_file_size && overhead > i128::from(limit.max_overhead) {
+            eyre::bail!(
+                "autopacking {} added {overhead} bytes to a {original_size} byte file, more than the limit of {} bytes; consider a smaller packed executable, or excluding the file from autopacking",
+                path.display(),
+                limit.max_overhead,
+            );
         }
     }
 
-    let command = command.ok_or_else(|| eyre::eyre!("could not find command {command_name:?}"))?;
-
-    // Autopack the command if it's pending
-    try_autopack_dependency(ctx, &command, pending_paths)?;
-
-    let command_resource = add_named_blob_from(ctx, &command, None)?;
-    let script_resource = add_named_blob_from(ctx, source_path, None)?;
-
-    let env_resource_paths = script_config
-        .env
-        .values()
-        .filter_map(|value| match value {
-            runnable_core::EnvValue::Clear => None,
-            runnable_core::EnvValue::Inherit => None,
-            runnable_core::EnvValue::Set { value } => Some(value),
-            runnable_core::EnvValue::Fallback { value } => Some(value),
-            runnable_core::EnvValue::Prepend {
-                value,
-                separator: _,
-            } => Some(value),
-            runnable_core::EnvValue::Append {
-                value,
-                separator: _,
-            } => Some(value),
-        })
-        .flat_map(|template| &template.components)
-        .filter_map(|component| match component {
-            runnable_core::TemplateComponent::Literal { .. }
-            | runnable_core::TemplateComponent::RelativePath { .. } => None,
-            runnable_core::TemplateComponent::Resource { resource } => Some(
-                resource
-                    .to_path()
-                    .map_err(|_| eyre::eyre!("invalid resource path")),
-            ),
-        })
-        .collect::<eyre::Result<Vec<_>>>()?;
-
-    let resource_paths = [command_resource.clone(), script_resource.clone()]
-        .into_iter()
-        .chain(env_resource_paths.into_iter().map(|path| path.to_owned()))
-        .map(|path| {
-            Vec::<u8>::from_path_buf(path).map_err(|_| eyre::eyre!("invalid resource path"))
-        })
-        .collect::<eyre::Result<Vec<_>>>()?;
-
-    let command = runnable_core::Template::from_resource_path(command_resource)?;
-
-    let mut args = vec![];
-    if let Some(arg) = arg {
-        args.push(runnable_core::ArgValue::Arg {
-            value: runnable_core::Template::from_literal(arg.into()),
-        });
-    }
-    args.push(runnable_core::ArgValue::Arg {
-        value: runnable_core::Template::from_resource_path(script_resource.clone())?,
-    });
-    args.push(runnable_core::ArgValue::Rest);
-
-    let env = script_config
-        .env_for_output_path(output_path)
-        .collect::<eyre::Result<_>>()?;
-
-    let runnable_pack = runnable_core::Runnable {
-        command,
-        args,
-        env,
-        clear_env: script_config.clear_env,
-        source: Some(runnable_core::RunnableSource {
-            path: runnable_core::RunnablePath::from_resource_path(script_resource)?,
-        }),
-    };
-    let pack = brioche_pack::Pack::Metadata {
-        resource_paths,
-        format: runnable_core::FORMAT.to_string(),
-        metadata: serde_json::to_vec(&runnable_pack)?,
+    let verb = if ctx.is_planning() {
+        "planned"
+    } else {
+        "autopacked"
     };
+    ctx.print(
+        path,
+        format!("{verb} {} ({overhead:+} bytes)", path.display()),
+    );
+
+    #[cfg(any(feature = "elf", feature = "script"))]
+    let resources = std::mem::take(
+        &mut *added_resources
+            .lock()
+            .unwrap_or_else(|error| error.into_inner()),
+    );
+    #[cfg(not(any(feature = "elf", feature = "script")))]
+    let resources = vec![];
+    #[cfg(feature = "elf")]
+    let libraries = std::mem::take(
+        &mut *closure_libraries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner()),
+    );
+    #[cfg(not(feature = "elf"))]
+    let libraries = vec![];
+    ctx.report.add_path(
+        path,
+        PathOutcome::Autopacked {
+            kind,
+            output_path,
+            resources,
+            original_size,
+            output_size,
+            mode,
+            libraries,
+        },
+    );
 
-    let packed_exec_path = &script_config.packed_executable;
-    let mut packed_exec = std::fs::File::open(packed_exec_path)
-        .with_context(|| format!("failed to open packed executable {packed_exec_path:?}"))?;
-
-    let mut output = std::fs::File::create(output_path)
-        .with_context(|| format!("failed to create file {output_path:?}"))?;
-    std::io::copy(&mut packed_exec, &mut output)
-        .with_context(|| format!("failed to copy packed executable to {output_path:?}"))?;
-    brioche_pack::inject_pack(output, &pack)
-        .with_context(|| format!("failed to inject pack into {output_path:?}"))?;
-
-    Ok(true)
+    Ok(())
 }
 
-fn autopack_repack(
+fn try_autopack_path(
     ctx: &AutopackContext,
     source_path: &Path,
     output_path: &Path,
-    pending_paths: &mut BTreeMap<PathBuf, AutopackPathConfig>,
-) -> eyre::Result<bool> {
-    let Some(_) = &ctx.config.repack else {
-        return Ok(false);
-    };
-
-    let contents = std::fs::read(source_path)?;
-    let extracted = brioche_pack::extract_pack(std::io::Cursor::new(&contents))?;
-
-    let repack_source = pack_source(source_path, &extracted.pack, &ctx.config.all_resource_dirs)
-        .with_context(|| format!("failed to repack {}", source_path.display()))?;
-
-    let unpacked_source_path;
-    let unpacked_output_path;
-    match repack_source {
-        PackSource::This => {
-            // Write the unpacked contents to the output path
-            let unpacked_contents = &contents[..extracted.unpacked_len];
-            std::fs::write(output_path, unpacked_contents).with_context(|| {
-                format!(
-                    "failed to write unpacked contents to {}",
-                    output_path.display()
-                )
-            })?;
-
-            // Repack the unpacked contents directly at the output path
-            unpacked_source_path = output_path.to_owned();
-            unpacked_output_path = output_path.to_owned();
-        }
-        PackSource::Path(path) => {
-            // Repack the source path and write to the output path
-            unpacked_source_path = path;
-            unpacked_output_path = output_path.to_owned();
-        }
-    }
-
-    let result = try_autopack_path(
-        ctx,
-        &unpacked_source_path,
-        &unpacked_output_path,
-        pending_paths,
-    )?;
-    Ok(result)
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    #[cfg(any(feature = "elf", feature = "script"))]
+    let source = retry::with_retries(ctx, source_path, || SourceFile::read(source_path))?;
+    #[cfg(not(any(feature = "elf", feature = "script")))]
+    let source = SourceFile::read(source_path)?;
+    try_autopack_source(ctx, &source, output_path, pending_paths)
 }
 
-fn collect_all_library_dirs(
+fn try_autopack_source(
     ctx: &AutopackContext,
-    dynamic_linking_config: &DynamicLinkingConfig,
-    mut needed_libraries: VecDeque<String>,
-    pending_paths: &mut BTreeMap<PathBuf, AutopackPathConfig>,
-) -> eyre::Result<Vec<PathBuf>> {
-    let mut library_search_paths = vec![];
-    let mut resource_library_dirs = vec![];
-    let mut found_libraries = HashSet::new();
-    let mut found_library_dirs = HashSet::new();
-
-    library_search_paths.extend_from_slice(&dynamic_linking_config.library_paths);
-    library_search_paths.extend_from_slice(&ctx.link_dependency_library_paths);
-
-    while let Some(library_name) = needed_libraries.pop_front() {
-        // If we've already found this library, then skip it
-        if found_libraries.contains(&library_name) {
-            continue;
-        }
-
-        // Find the path to the library
-        let library_path = find_library(&library_search_paths, &library_name)?;
-        let Some(library_path) = library_path else {
-            if dynamic_linking_config.skip_unknown_libraries {
-                continue;
-            } else {
-                eyre::bail!("library not found: {library_name:?}");
-            }
-        };
-
-        // Autopack the library if it's pending
-        try_autopack_dependency(ctx, &library_path, pending_paths)?;
-
-        found_libraries.insert(library_name.clone());
-
-        // Don't add the library if it's been skipped. We still do everything
-        // else so we can add transitive dependencies even if a library has
-        // been skipped
-        if !dynamic_linking_config
-            .skip_libraries
-            .contains(&*library_name)
-        {
-            // Add the library to the resource directory
-            let library_alias = Path::new(&library_name);
-            let library_resource_path =
-                add_named_blob_from(ctx, &library_path, Some(library_alias)).with_context(
-                    || format!("failed to add resource for library {library_path:?}"),
-                )?;
-
-            // Add the parent dir to the list of library directories. Note
-            // that this directory is guaranteed to only contain just this
-            // library
-            let library_resource_dir = library_resource_path
-                .parent()
-                .ok_or_eyre("failed to get resource parent dir")?
-                .to_owned();
-
-            let is_new_library_path = found_library_dirs.insert(library_resource_dir.clone());
-            if is_new_library_path {
-                resource_library_dirs.push(library_resource_dir.clone());
-            }
-        }
-
-        // Try to get the dynamic dependencies from the library itself
-        let Ok(library_file) = std::fs::read(&library_path) else {
-            continue;
-        };
-        let Ok(library_object) = goblin::Object::parse(&library_file) else {
-            continue;
-        };
-
-        // TODO: Support other object files
-        let library_elf = match library_object {
-            goblin::Object::Elf(elf) => elf,
-            _ => {
-                continue;
-            }
-        };
-        needed_libraries.extend(library_elf.libraries.iter().map(|lib| lib.to_string()));
-
-        // If the library has a Brioche pack, then use the included resources
-        // for additional search directories
-        let library_file_cursor = std::io::Cursor::new(&library_file[..]);
-        if let Ok(extracted_library) = brioche_pack::extract_pack(library_file_cursor) {
-            let library_dirs = match &extracted_library.pack {
-                brioche_pack::Pack::LdLinux { library_dirs, .. } => &library_dirs[..],
-                brioche_pack::Pack::Static { library_dirs } => &library_dirs[..],
-                brioche_pack::Pack::Metadata { .. } => &[],
-            };
-
-            for library_dir in library_dirs {
-                let Ok(library_dir) = library_dir.to_path() else {
-                    continue;
-                };
-                let Some(library_dir_path) = brioche_resources::find_in_resource_dirs(
-                    &ctx.config.all_resource_dirs,
-                    library_dir,
-                ) else {
-                    continue;
-                };
-
-                library_search_paths.push(library_dir_path);
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    #[cfg(feature = "elf")]
+    if let Some(mismatch) = elf::foreign_arch_mismatch(ctx, &source.contents) {
+        match ctx.config.foreign_arch {
+            ForeignArchPolicy::Skip => return Ok(Outcome::Skipped(SkipReason::ForeignArch)),
+            ForeignArchPolicy::Autopack => {}
+            ForeignArchPolicy::Error => {
+                return Err(diagnostics::ForeignArch {
+                    path: source.path.clone(),
+                    mismatch,
+                }
+                .into());
             }
         }
     }
 
-    Ok(resource_library_dirs)
-}
-
-fn find_library(
-    library_search_paths: &[PathBuf],
-    library_name: &str,
-) -> eyre::Result<Option<PathBuf>> {
-    let mut library_search_path_files = vec![];
-
-    // Try to find a direct filename match from the search paths
-    for path in library_search_paths {
-        if path.is_dir() {
-            // Check if the search path is a directory and contains a file
-            // matching the library name
-            let lib_path = path.join(library_name);
-            if lib_path.is_file() {
-                return Ok(Some(lib_path));
-            }
-        } else if path.is_file() {
-            // Check if the search path is a file that matches the library
-            // name directly
-            let path_filename = path
-                .file_name()
-                .ok_or_eyre("failed to get filename from path")?;
-            if path_filename.to_str() == Some(library_name) {
-                return Ok(Some(path.to_owned()));
-            }
-
-            // If the filename doesn't match, queue it for a further check
-            // if we don't find another path-based match
-            library_search_path_files.push(path);
-        }
-    }
+    let Some(kind) = autopack_kind(ctx, &source.contents) else {
+        return Ok(Outcome::Skipped(SkipReason::Unsupported));
+    };
 
-    // Try to find a library file that matches based on its `DT_SONAME` field
-    // as a fallback
-    for &path in &library_search_path_files {
-        let Ok(contents) = std::fs::read(path) else {
-            continue;
+    // Files using a sidecar pack keep their original contents, so only
+    // the pack for this file goes to the sidecar emitter. Signed dylibs
+    // always use one, since appending a pack would invalidate them
+    #[cfg(feature = "macho")]
+    let is_signed_library = matches!(kind, AutopackKind::SharedLibrary)
+        && macho::requires_code_signature(&source.contents);
+    #[cfg(all(any(feature = "elf", feature = "script"), not(feature = "macho")))]
+    let is_signed_library = false;
+    #[cfg(any(feature = "elf", feature = "script"))]
+    let sidecar_pack_emitter;
+    #[cfg(any(feature = "elf", feature = "script"))]
+    let sidecar_ctx;
+    #[cfg(any(feature = "elf", feature = "script"))]
+    let ctx = if !matches!(kind, AutopackKind::Repack)
+        && (is_signed_library || ctx.uses_sidecar_pack(&source.path))
+    {
+        sidecar_pack_emitter = emit::SidecarPackEmitter {
+            inner: ctx.pack_emitter,
+            output_path,
+            source,
+            plan: ctx.plan,
         };
-
-        let Ok(elf) = goblin::elf::Elf::parse(&contents) else {
-            continue;
+        sidecar_ctx = AutopackContext {
+            pack_emitter: &sidecar_pack_emitter,
+            ..*ctx
         };
+        &sidecar_ctx
+    } else {
+        ctx
+    };
 
-        if elf.soname == Some(library_name) {
-            return Ok(Some(path.to_owned()));
+    match kind {
+        #[cfg(feature = "elf")]
+        AutopackKind::DynamicBinary => {
+            elf::autopack_dynamic_binary(ctx, source, output_path, pending_paths)
         }
+        #[cfg(feature = "elf")]
+        AutopackKind::SharedLibrary => {
+            elf::autopack_shared_library(ctx, source, output_path, pending_paths)
+        }
+        #[cfg(feature = "elf")]
+        AutopackKind::StaticBinary => {
+            elf::autopack_static_binary(ctx, source, output_path, pending_paths)
+        }
+        #[cfg(feature = "elf")]
+        AutopackKind::StaticPie => {
+            elf::autopack_static_pie(ctx, source, output_path, pending_paths)
+        }
+        #[cfg(feature = "script")]
+        AutopackKind::Script => script::autopack_script(ctx, source, output_path, pending_paths),
+        AutopackKind::Repack => autopack_repack(ctx, source, output_path, pending_paths),
     }
-
-    Ok(None)
 }
 
-fn add_named_blob_from(
+fn autopack_repack(
     ctx: &AutopackContext,
-    path: &Path,
-    alias_name: Option<&Path>,
-) -> eyre::Result<PathBuf> {
-    use std::os::unix::prelude::PermissionsExt as _;
-
-    let alias_name = match alias_name {
-        Some(alias_name) => alias_name,
-        None => {
-            let filename = path
-                .file_name()
-                .ok_or_eyre("failed to get filename from path")?;
-            Path::new(filename)
-        }
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let Some(_) = ctx.config.repack.as_ref().filter(|config| config.enabled) else {
+        return Ok(Outcome::Skipped(SkipReason::Disabled {
+            kind: AutopackKind::Repack,
+        }));
     };
 
-    let mut file = std::fs::File::open(path)?;
-    let metadata = file.metadata()?;
+    let extracted = brioche_resources::extract_padded_pack(std::io::Cursor::new(&source.contents))?;
 
-    let permissions = metadata.permissions();
-    let mode = permissions.mode();
-    let is_executable = mode & 0o111 != 0;
+    let repack_source = pack_source(&source.path, &extracted.pack, &ctx.config.all_resource_dirs)
+        .with_context(|| format!("failed to repack {}", source.path.display()))?;
 
-    let mut contents = vec![];
-    file.read_to_end(&mut contents)?;
+    match repack_source {
+        PackSource::This => {
+            // Write the unpacked contents to the output path, without any
+            // attached packs
+            let attached = brioche_resources::extract_attached_packs(
+                std::io::Cursor::new(&source.contents),
+                extracted.unpacked_len,
+            )?;
+            let unpacked_contents = &source.contents[..attached.unpacked_len];
+            if !ctx.is_planning() {
+                use std::io::Write as _;
+
+                let mut output = brioche_resources::AtomicWriter::replacing(output_path)?;
+                output
+                    .write_all(unpacked_contents)
+                    .and_then(|()| output.commit())
+                    .with_context(|| {
+                        format!(
+                            "failed to write unpacked contents to {}",
+                            output_path.display()
+                        )
+                    })?;
+            }
 
-    let resource_path = brioche_resources::add_named_blob(
-        &ctx.config.resource_dir,
-        std::io::Cursor::new(contents),
-        is_executable,
-        alias_name,
-    )?;
-    Ok(resource_path)
+            // Repack the unpacked contents directly at the output path
+            let unpacked_source = SourceFile {
+                path: output_path.to_owned(),
+                contents: unpacked_contents.to_vec(),
+                is_executable: source.is_executable,
+            };
+            try_autopack_source(ctx, &unpacked_source, output_path, pending_paths)
+        }
+        PackSource::Path(path) => {
+            // Repack the source path and write to the output path
+            try_autopack_path(ctx, &path, output_path, pending_paths)
+        }
+    }
 }
 
+#[cfg(any(feature = "elf", feature = "script"))]
+/// Autopack a dependency if it's one of the pending paths, then return
+/// the path the (possibly autopacked) dependency should be read from.
 fn try_autopack_dependency(
     ctx: &AutopackContext,
     path: &Path,
-    pending_paths: &mut BTreeMap<PathBuf, AutopackPathConfig>,
-) -> eyre::Result<()> {
+    pending_paths: &PendingPaths,
+) -> eyre::Result<PathBuf> {
     // Get the canonical path of the dependency
-    let canonical_path = path
-        .canonicalize()
-        .with_context(|| format!("failed to canonicalize path {path:?}"))?;
+    let canonical_path = brioche_resources::canonicalize(path, ctx.config.symlink_limit)?;
 
     // If the path is pending, then autopack it
-    if let Some(path_config) = pending_paths.remove(&canonical_path) {
-        autopack_path(ctx, path, &path_config, pending_paths)?;
+    if let Taken::Pending(path_config) = pending_paths.take(&canonical_path) {
+        let result = autopack_path(ctx, path, &path_config, pending_paths);
+        pending_paths.finish(&canonical_path);
+        result?;
     }
 
-    Ok(())
+    Ok(ctx.dependency_path(path))
 }
@@ -0,0 +1,168 @@
+//! Reading GNU ld linker scripts. Some libraries, such as `libc.so`, are
+//! installed as a linker script listing the real libraries to link against,
+//! like `GROUP ( libc.so.6 libc_nonshared.a AS_NEEDED ( ld-linux.so.2 ) )`.
+
+use std::path::{Path, PathBuf};
+
+pub(crate) struct LinkerScript {
+    /// The files from each `GROUP` and `INPUT` command, including ones
+    /// marked with `AS_NEEDED`.
+    pub inputs: Vec<LinkerScriptInput>,
+}
+
+pub(crate) enum LinkerScriptInput {
+    /// A file path, which may be absolute or just a filename.
+    Path(PathBuf),
+
+    /// A library to search for, from `-l<name>`.
+    Library(String),
+}
+
+impl LinkerScript {
+    /// Read the linker script at `path`. Returns `None` if the file isn't a
+    /// linker script, such as when it's a shared library.
+    pub fn read(path: &Path) -> Option<Self> {
+        let contents = std::fs::read(path).ok()?;
+        Self::parse(&contents)
+    }
+
+    /// Parse a linker script. Only `GROUP` and `INPUT` commands are read,
+    /// and other commands are skipped. Returns `None` if the contents
+    /// aren't text or don't have any input commands.
+    pub fn parse(contents: &[u8]) -> Option<Self> {
+        if contents.starts_with(b"\x7fELF") {
+            return None;
+        }
+        let contents = std::str::from_utf8(contents).ok()?;
+
+        let tokens = tokenize(contents)?;
+        let mut tokens = tokens.into_iter().peekable();
+        let mut inputs = vec![];
+        let mut has_input_command = false;
+        while let Some(token) = tokens.next() {
+            let is_input_command = matches!(token, "GROUP" | "INPUT");
+            if tokens.peek() != Some(&"(") {
+                continue;
+            }
+            tokens.next();
+
+            // Read up to the matching closing paren, keeping inputs only
+            // for input commands. `AS_NEEDED` nests its own list
+            let mut depth = 1;
+            while depth > 0 {
+                match tokens.next()? {
+                    "(" => depth += 1,
+                    ")" => depth -= 1,
+                    "," | "AS_NEEDED" => {}
+                    input if is_input_command => {
+                        let input = match input.strip_prefix("-l") {
+                            Some(name) => LinkerScriptInput::Library(name.to_string()),
+                            None => LinkerScriptInput::Path(PathBuf::from(input)),
+                        };
+                        inputs.push(input);
+                    }
+                    _ => {}
+                }
+            }
+
+            has_input_command |= is_input_command;
+        }
+
+        has_input_command.then_some(Self { inputs })
+    }
+}
+
+/// Split a linker script into words, quoted strings, parens, and commas,
+/// skipping comments. Returns `None` if a comment or string isn't closed.
+fn tokenize(contents: &str) -> Option<Vec<&str>> {
+    let mut tokens = vec![];
+    let mut rest = contents.trim_start();
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment.find("*/")?;
+            rest = &comment[end + 2..];
+        } else if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            tokens.push(&quoted[..end]);
+            rest = &quoted[end + 1..];
+        } else if rest.starts_with(['(', ')', ',']) {
+            tokens.push(&rest[..1]);
+            rest = &rest[1..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | ',' | '"'))
+                .unwrap_or(rest.len());
+            let end = match rest[..end].find("/*") {
+                Some(comment_start) if comment_start > 0 => comment_start,
+                _ => end,
+            };
+            tokens.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Some(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinkerScript, LinkerScriptInput};
+
+    /// Parse a linker script, listing its inputs with libraries as
+    /// `-l<name>`.
+    fn parse_inputs(contents: &[u8]) -> Option<Vec<String>> {
+        let script = LinkerScript::parse(contents)?;
+        let inputs = script
+            .inputs
+            .into_iter()
+            .map(|input| match input {
+                LinkerScriptInput::Path(path) => path.display().to_string(),
+                LinkerScriptInput::Library(name) => format!("-l{name}"),
+            })
+            .collect();
+        Some(inputs)
+    }
+
+    #[test]
+    fn test_parse_glibc_libc_so() {
+        let script = b"/* GNU ld script
+   Use the shared library, but some functions are only in
+   the static library, so try that secondarily.  */
+OUTPUT_FORMAT(elf64-x86-64)
+GROUP ( /lib/x86_64-linux-gnu/libc.so.6 /usr/lib/x86_64-linux-gnu/libc_nonshared.a  AS_NEEDED ( /lib64/ld-linux-x86-64.so.2 ) )
+";
+        assert_eq!(
+            parse_inputs(script).unwrap(),
+            [
+                "/lib/x86_64-linux-gnu/libc.so.6",
+                "/usr/lib/x86_64-linux-gnu/libc_nonshared.a",
+                "/lib64/ld-linux-x86-64.so.2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(
+            parse_inputs(b"INPUT(libncursesw.so.6 -ltinfo)").unwrap(),
+            ["libncursesw.so.6", "-ltinfo"]
+        );
+        assert_eq!(
+            parse_inputs(b"INPUT(-lfoo, \"bar baz.so\",libqux.so/* comment */)\nGROUP(libm.so.6)")
+                .unwrap(),
+            ["-lfoo", "bar baz.so", "libqux.so", "libm.so.6"]
+        );
+    }
+
+    #[test]
+    fn test_parse_not_linker_script() {
+        assert!(parse_inputs(b"\x7fELF\x02\x01\x01").is_none());
+        assert!(parse_inputs(b"GROUP ( \xff )").is_none());
+        assert!(parse_inputs(b"OUTPUT_FORMAT(elf64-x86-64)").is_none());
+        assert!(parse_inputs(b"/* GROUP ( libc.so.6 )").is_none());
+        assert!(parse_inputs(b"GROUP ( libc.so.6").is_none());
+        assert!(parse_inputs(b"GROUP ( \"libc.so.6 )").is_none());
+    }
+}
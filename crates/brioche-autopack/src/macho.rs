@@ -0,0 +1,312 @@
+//! Autopacking Mach-O binaries and dylibs for macOS.
+//!
+//! macOS doesn't have a separate dynamic linker that can be run with a
+//! library path like `ld-linux.so`, so dynamic binaries are packed as
+//! runnables that run the program directly, with their library dirs
+//! recorded as [`runnable_core::Runnable::library_dirs`]. The launcher
+//! passes these to dyld through `$DYLD_LIBRARY_PATH`, which dyld searches
+//! by each dylib's filename before its install name, so this works for
+//! `@rpath/` and absolute install names. System dylibs and frameworks are
+//! always loaded from the OS.
+//!
+//! Universal binaries are read through the slice for the target, or the
+//! host when there's no target, and are packed whole so the OS still picks
+//! the slice to run.
+//!
+//! Appending a pack invalidates a Mach-O file's code signature, which
+//! arm64 macOS requires. Files with an arm64 slice are kept unchanged, with
+//! their pack in a sidecar file: dylibs like files matched by
+//! [`crate::AutopackConfig::sidecar_pack_globs`], and programs through an
+//! unmodified copy of the packed executable (see
+//! [`PackBase::SignedPackedExecutable`]).
+
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteVec as _;
+use eyre::OptionExt as _;
+
+use crate::{
+    detect::{AutopackKind, SourceFile},
+    elf::{check_closure, collect_all_library_dirs, library_dir_components, ProgramRef},
+    emit::PackBase,
+    jobs::PendingPaths,
+    report::Outcome,
+    AutopackContext, DynamicBinaryConfig, SharedLibraryConfig,
+};
+
+/// Install name prefixes for dylibs provided by macOS itself.
+const SYSTEM_DYLIB_PREFIXES: &[&str] = &["/usr/lib/", "/System/Library/"];
+
+pub(crate) fn autopack_kind(
+    macho: &goblin::mach::MachO,
+    target: Option<&target_lexicon::Triple>,
+) -> Option<AutopackKind> {
+    if let Some(target) = target {
+        if !matches_target(macho, target) {
+            return None;
+        }
+    }
+
+    // Programs that only load system dylibs are still packed, so they're
+    // run the same way as other programs
+    match macho.header.filetype {
+        goblin::mach::header::MH_EXECUTE => Some(AutopackKind::DynamicBinary),
+        goblin::mach::header::MH_DYLIB => Some(AutopackKind::SharedLibrary),
+        _ => None,
+    }
+}
+
+/// Check if a Mach-O file was built for `target`.
+pub(crate) fn matches_target(macho: &goblin::mach::MachO, target: &target_lexicon::Triple) -> bool {
+    if target.binary_format != target_lexicon::BinaryFormat::Macho {
+        return false;
+    }
+
+    match cputype(target.architecture) {
+        Some(expected_cputype) => macho.header.cputype == expected_cputype,
+        None => true,
+    }
+}
+
+/// Get the Mach-O CPU type for an architecture, if it's one macOS runs on.
+fn cputype(architecture: target_lexicon::Architecture) -> Option<goblin::mach::cputype::CpuType> {
+    use goblin::mach::cputype;
+
+    match architecture {
+        target_lexicon::Architecture::X86_64 => Some(cputype::CPU_TYPE_X86_64),
+        target_lexicon::Architecture::Aarch64(_) => Some(cputype::CPU_TYPE_ARM64),
+        _ => None,
+    }
+}
+
+/// Get the Mach-O file to read from a thin or universal file. For
+/// universal files, this is the slice for `target`, or the host's
+/// architecture without a target, falling back to the first slice.
+pub(crate) fn single_arch<'a>(
+    mach: goblin::mach::Mach<'a>,
+    target: Option<&target_lexicon::Triple>,
+) -> goblin::error::Result<goblin::mach::MachO<'a>> {
+    let multi_arch = match mach {
+        goblin::mach::Mach::Binary(macho) => return Ok(macho),
+        goblin::mach::Mach::Fat(multi_arch) => multi_arch,
+    };
+
+    let architecture = target.map_or(target_lexicon::HOST.architecture, |target| {
+        target.architecture
+    });
+    let arches = multi_arch.arches()?;
+    let index = cputype(architecture)
+        .and_then(|cputype| arches.iter().position(|arch| arch.cputype == cputype))
+        .unwrap_or_default();
+    match multi_arch.get(index)? {
+        goblin::mach::SingleArch::MachO(macho) => Ok(macho),
+        goblin::mach::SingleArch::Archive(_) => Err(goblin::error::Error::Malformed(
+            "universal binary slice is an archive".to_string(),
+        )),
+    }
+}
+
+/// Check if a file is a Mach-O file with an arm64 slice. arm64 macOS only
+/// runs code with a valid signature, so these files can't have a pack
+/// appended.
+pub(crate) fn requires_code_signature(contents: &[u8]) -> bool {
+    use goblin::mach::cputype::CPU_TYPE_ARM64;
+
+    match goblin::mach::Mach::parse(contents) {
+        Ok(goblin::mach::Mach::Binary(macho)) => macho.header.cputype == CPU_TYPE_ARM64,
+        Ok(goblin::mach::Mach::Fat(multi_arch)) => multi_arch
+            .iter_arches()
+            .any(|arch| arch.is_ok_and(|arch| arch.cputype == CPU_TYPE_ARM64)),
+        Err(_) => false,
+    }
+}
+
+pub(crate) fn autopack_dynamic_binary(
+    ctx: &AutopackContext,
+    dynamic_binary_config: &DynamicBinaryConfig,
+    source: &SourceFile,
+    macho: &goblin::mach::MachO,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let logical_output_path = ctx.logical_path(output_path);
+    let output_path_parent = logical_output_path
+        .parent()
+        .ok_or_eyre("could not get parent of output path")?;
+
+    let program = ProgramRef::new(ctx, dynamic_binary_config, source, output_path)?;
+
+    let needed_libraries: VecDeque<_> = needed_libraries(macho)
+        .into_iter()
+        .chain(
+            dynamic_binary_config
+                .dynamic_linking
+                .additional_libraries()
+                .map(|lib| lib.to_string()),
+        )
+        .collect();
+    let rpath_library_paths = rpath_library_paths(macho, &source.path)?;
+
+    let collected_libraries = collect_all_library_dirs(
+        ctx,
+        source,
+        &dynamic_binary_config.dynamic_linking,
+        needed_libraries,
+        rpath_library_paths,
+        &HashSet::new(),
+        pending_paths,
+    )?;
+    check_closure(
+        ctx,
+        source,
+        collected_libraries.libraries,
+        collected_libraries.provided,
+    )?;
+    let library_dir_resource_paths = collected_libraries.resource_dirs;
+
+    let library_dirs = library_dir_components(
+        dynamic_binary_config,
+        &library_dir_resource_paths,
+        output_path_parent,
+    )?
+    .into_iter()
+    .map(|component| runnable_core::Template {
+        components: vec![component],
+    })
+    .collect();
+
+    let resource_paths = program
+        .resource_path()
+        .into_iter()
+        .chain(&library_dir_resource_paths)
+        .map(|path| {
+            <Vec<u8>>::from_path_buf(path.clone()).map_err(|_| eyre::eyre!("invalid UTF-8 in path"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let fingerprint = dynamic_binary_config.fingerprint();
+    let runnable = runnable_core::Runnable {
+        command: program.template()?,
+        args: vec![runnable_core::ArgValue::Rest],
+        env: vec![],
+        clear_env: false,
+        library_dirs,
+        source: Some(runnable_core::RunnableSource {
+            path: program.runnable_path()?,
+        }),
+        packer: Some(fingerprint.clone()),
+    };
+    let pack = crate::metadata_pack(resource_paths, &runnable)?;
+
+    // The packed executable runs on the same architecture as the program,
+    // so it needs to keep its signature if the program does
+    let packed_executable = &dynamic_binary_config.packed_executable;
+    let base = if macho.header.cputype == goblin::mach::cputype::CPU_TYPE_ARM64 {
+        PackBase::SignedPackedExecutable(packed_executable)
+    } else {
+        PackBase::PackedExecutable(packed_executable)
+    };
+    ctx.emit_pack(output_path, base, &pack, &fingerprint)?;
+
+    Ok(Outcome::Autopacked(AutopackKind::DynamicBinary))
+}
+
+/// Collect the library dirs for a dylib's own dependencies, which get
+/// added to its static pack so binaries linking against it can find them.
+pub(crate) fn shared_library_dirs(
+    ctx: &AutopackContext,
+    shared_library_config: &SharedLibraryConfig,
+    source: &SourceFile,
+    macho: &goblin::mach::MachO,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Vec<PathBuf>> {
+    let needed_libraries: VecDeque<_> = needed_libraries(macho)
+        .into_iter()
+        .filter(|library| {
+            !shared_library_config
+                .dynamic_linking
+                .skip_libraries
+                .contains(library)
+        })
+        .chain(
+            shared_library_config
+                .dynamic_linking
+                .additional_libraries()
+                .map(|lib| lib.to_string()),
+        )
+        .collect();
+    let rpath_library_paths = rpath_library_paths(macho, &source.path)?;
+
+    let collected_libraries = collect_all_library_dirs(
+        ctx,
+        source,
+        &shared_library_config.dynamic_linking,
+        needed_libraries,
+        rpath_library_paths,
+        &HashSet::new(),
+        pending_paths,
+    )?;
+    check_closure(
+        ctx,
+        source,
+        collected_libraries.libraries,
+        collected_libraries.provided,
+    )?;
+
+    Ok(collected_libraries.resource_dirs)
+}
+
+/// Get the filenames of the dylibs a Mach-O file loads, skipping dylibs
+/// and frameworks provided by the OS.
+pub(crate) fn needed_libraries(macho: &goblin::mach::MachO) -> Vec<String> {
+    // The first entry is the file's own install name
+    macho
+        .libs
+        .iter()
+        .skip(1)
+        .filter(|install_name| {
+            !SYSTEM_DYLIB_PREFIXES
+                .iter()
+                .any(|prefix| install_name.starts_with(prefix))
+        })
+        .filter_map(|install_name| Path::new(install_name).file_name()?.to_str())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Get the `LC_RPATH` entries of a Mach-O file as search paths, expanding
+/// `@loader_path` and `@executable_path` relative to the file. Other
+/// relative entries are skipped.
+pub(crate) fn rpath_library_paths(
+    macho: &goblin::mach::MachO,
+    macho_path: &Path,
+) -> eyre::Result<Vec<PathBuf>> {
+    let loader_dir = macho_path
+        .parent()
+        .ok_or_eyre("failed to get parent of Mach-O path")?;
+
+    let paths = macho
+        .rpaths
+        .iter()
+        .filter_map(|rpath| {
+            let relative_path = strip_path_token(rpath, "@loader_path")
+                .or_else(|| strip_path_token(rpath, "@executable_path"));
+            match relative_path {
+                Some(relative_path) => Some(loader_dir.join(relative_path.trim_start_matches('/'))),
+                None if rpath.starts_with('/') => Some(PathBuf::from(rpath)),
+                None => None,
+            }
+        })
+        .collect();
+    Ok(paths)
+}
+
+/// Strip `token` from the start of an rpath entry, only if it's followed
+/// by `/` or the end of the entry.
+fn strip_path_token<'a>(rpath: &'a str, token: &str) -> Option<&'a str> {
+    let rest = rpath.strip_prefix(token)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
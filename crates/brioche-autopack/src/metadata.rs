@@ -0,0 +1,69 @@
+//! Metadata packs, which hold a payload identified by a format string.
+//! Autopacking writes [`runnable_core::Runnable`] payloads, but other tools
+//! can use the same packs with their own payload types.
+
+use std::path::Path;
+
+use crate::AttachedMetadata;
+
+/// A payload type for metadata packs. Payloads are serialized as JSON, and
+/// `FORMAT` is recorded alongside them so they can be found when reading
+/// packs back. The format should be unique to the payload type and change
+/// with incompatible schema changes, like [`runnable_core::FORMAT`].
+pub trait MetadataFormat: serde::Serialize + serde::de::DeserializeOwned {
+    const FORMAT: &'static str;
+}
+
+impl MetadataFormat for runnable_core::Runnable {
+    const FORMAT: &'static str = runnable_core::FORMAT;
+}
+
+/// Build a metadata pack holding `payload`.
+pub fn metadata_pack<T: MetadataFormat>(
+    resource_paths: Vec<Vec<u8>>,
+    payload: &T,
+) -> Result<brioche_pack::Pack, serde_json::Error> {
+    Ok(brioche_pack::Pack::Metadata {
+        resource_paths,
+        format: T::FORMAT.to_string(),
+        metadata: serde_json::to_vec(payload)?,
+    })
+}
+
+/// Read the payload of a metadata pack. Returns `None` if the pack isn't a
+/// metadata pack using `T`'s format.
+pub fn read_metadata<T: MetadataFormat>(
+    pack: &brioche_pack::Pack,
+) -> Result<Option<T>, serde_json::Error> {
+    match pack {
+        brioche_pack::Pack::Metadata {
+            format, metadata, ..
+        } if format == T::FORMAT => serde_json::from_slice(metadata).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Read the payloads of the metadata packs using `T`'s format attached to
+/// a packed file, in the order they were written. See
+/// [`read_attached_packs`](crate::read_attached_packs).
+pub fn read_attached_metadata<T: MetadataFormat>(path: &Path) -> eyre::Result<Vec<T>> {
+    let packs = crate::read_attached_packs(path)?;
+    let mut payloads = vec![];
+    for pack in &packs {
+        if let Some(payload) = read_metadata(pack)? {
+            payloads.push(payload);
+        }
+    }
+
+    Ok(payloads)
+}
+
+impl AttachedMetadata {
+    /// Attach `payload` to autopacked files, using `T`'s format.
+    pub fn from_payload<T: MetadataFormat>(payload: &T) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            format: T::FORMAT.to_string(),
+            metadata: serde_json::to_vec(payload)?,
+        })
+    }
+}
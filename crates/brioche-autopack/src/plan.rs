@@ -0,0 +1,802 @@
+//! Planning autopacking without changing any files, then applying the
+//! plan separately.
+//!
+//! [`plan`] autopacks the same way as [`autopack`](crate::autopack), but
+//! records the resources it would add and the files it would write in an
+//! [`AutopackPlan`] instead. Plans can be serialized, inspected or changed,
+//! and applied later with [`apply`], possibly on another machine with the
+//! same inputs. Files read from the inputs are recorded by their path and
+//! hash, and applying a plan fails if any of them changed since it was
+//! planned.
+//!
+//! The serialized format is versioned by [`PLAN_FORMAT_VERSION`], so plans
+//! can be computed centrally and applied by workers running a different
+//! build. Large plans can be divided between workers with
+//! [`AutopackPlan::split`], and workers can check they have the right
+//! inputs with [`AutopackPlan::verify_inputs`] before applying anything.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    os::unix::fs::PermissionsExt as _,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use eyre::Context as _;
+
+use crate::{
+    emit::{
+        write_output, write_sidecar_pack, FilePackEmitter, PackBase, PackEmitter,
+        SharedLauncherPackEmitter,
+    },
+    resources::ResourceSink,
+    AutopackConfig, AutopackReport, PackedExecutable,
+};
+
+/// The version of the serialized plan format. Plans with a different
+/// version can't be applied.
+pub const PLAN_FORMAT_VERSION: u32 = 2;
+
+/// The changes autopacking would make, returned by [`plan`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutopackPlan {
+    /// The format version the plan was written with, see
+    /// [`PLAN_FORMAT_VERSION`].
+    pub format_version: u32,
+
+    /// The resource dir to add resources to.
+    pub resource_dir: PathBuf,
+
+    /// Resources to add, by their path within the resource dir.
+    pub resources: Vec<PlannedResource>,
+
+    /// Other names to add for resources, see
+    /// [`ResourceSink::add_alias`].
+    pub aliases: Vec<PlannedAlias>,
+
+    /// Resources to index by build ID, see
+    /// [`ResourceSink::add_build_id`].
+    pub build_ids: Vec<PlannedBuildId>,
+
+    /// Files to write, in path order.
+    pub files: Vec<PlannedFile>,
+
+    /// Debug links to record in the resource dir, by output path.
+    #[cfg(feature = "elf")]
+    pub debug_links: BTreeMap<PathBuf, crate::DebugLink>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedResource {
+    /// The resource's path within the resource dir.
+    pub path: PathBuf,
+    pub name: PathBuf,
+    pub executable: bool,
+    pub contents: PlannedContents,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedAlias {
+    pub resource_path: PathBuf,
+    pub name: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedBuildId {
+    pub resource_path: PathBuf,
+    pub build_id: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedFile {
+    pub path: PathBuf,
+
+    /// The file's contents, before its pack.
+    pub contents: PlannedContents,
+
+    /// The pack to inject into the file. Files without a pack, such as
+    /// unwrapped copies of programs, are written as-is.
+    pub pack: Option<brioche_pack::Pack>,
+
+    /// Metadata packs attached before the pack, see
+    /// [`AutopackConfig::attached_metadata`].
+    pub attached: Vec<brioche_pack::Pack>,
+
+    /// Write the pack to a sidecar file instead of injecting it, see
+    /// [`AutopackConfig::sidecar_pack_globs`].
+    pub sidecar_pack: bool,
+
+    /// The contents are a packed executable, which the file is a hard link
+    /// to a shared copy of, with the pack written to a sidecar file. See
+    /// [`AutopackConfig::shared_launcher`].
+    pub shared_launcher: bool,
+
+    /// The permission bits to set on the file.
+    pub mode: Option<u32>,
+}
+
+/// Where a planned file or resource gets its contents from.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[non_exhaustive]
+pub enum PlannedContents {
+    /// A packed executable read from a file.
+    PackedExecutable { path: PathBuf, hash: String },
+
+    /// A file that's expected to have the given BLAKE3 hash when the plan
+    /// is applied.
+    File { path: PathBuf, hash: String },
+
+    /// Contents stored in the plan, such as ones built while autopacking.
+    Inline { contents: Vec<u8> },
+}
+
+impl PlannedContents {
+    /// Read the contents, checking that files still match their hash.
+    pub fn read(&self) -> eyre::Result<std::borrow::Cow<'_, [u8]>> {
+        match self {
+            Self::PackedExecutable { path, hash } | Self::File { path, hash } => {
+                let contents =
+                    std::fs::read(path).with_context(|| format!("failed to read {path:?}"))?;
+                let actual_hash = blake3::hash(&contents).to_hex();
+                eyre::ensure!(
+                    actual_hash.as_str() == hash,
+                    "{path:?} changed since the plan was made (expected hash {hash}, got {actual_hash})"
+                );
+                Ok(std::borrow::Cow::Owned(contents))
+            }
+            Self::Inline { contents } => Ok(std::borrow::Cow::Borrowed(contents)),
+        }
+    }
+
+    /// Use the file at `path` if it has the same contents, or store the
+    /// contents in the plan otherwise.
+    fn from_contents(path: Option<&Path>, contents: &[u8]) -> Self {
+        let hash = blake3::hash(contents);
+        let file_hash = path.and_then(|path| {
+            let file_contents = std::fs::read(path).ok()?;
+            Some(blake3::hash(&file_contents))
+        });
+        match path {
+            Some(path) if file_hash == Some(hash) => Self::File {
+                path: path.to_owned(),
+                hash: hash.to_hex().to_string(),
+            },
+            _ => Self::Inline {
+                contents: contents.to_vec(),
+            },
+        }
+    }
+}
+
+/// Plan autopacking with the default steps, without changing any files.
+/// Returns the plan along with the report autopacking would return.
+pub fn plan(config: &AutopackConfig) -> eyre::Result<(AutopackPlan, AutopackReport)> {
+    eyre::ensure!(
+        config.resource_namespace.is_none(),
+        "can't plan autopacking with a resource namespace"
+    );
+    eyre::ensure!(
+        config.content_hasher.name() == "blake3",
+        "can't plan autopacking with content hasher {:?}",
+        config.content_hasher.name(),
+    );
+
+    let resolver = crate::resolve::LinkDependencyResolver::new(config)?;
+    let recorder = PlanRecorder {
+        shared_launcher: config.shared_launcher,
+        ..PlanRecorder::default()
+    };
+    let report = crate::autopack_with_plan(
+        config,
+        crate::AutopackExtensions {
+            resolver: &resolver,
+            resource_sink: &recorder,
+            pack_emitter: &recorder,
+        },
+        Some(&recorder),
+    )?;
+
+    let plan = recorder.finish(config);
+    Ok((plan, report))
+}
+
+impl AutopackPlan {
+    /// Check that every input the plan reads from still has the contents
+    /// it was planned with, without writing anything.
+    pub fn verify_inputs(&self) -> eyre::Result<()> {
+        check_format_version(self)?;
+
+        let resource_contents = self.resources.iter().map(|resource| &resource.contents);
+        let file_contents = self.files.iter().map(|file| &file.contents);
+        for contents in resource_contents.chain(file_contents) {
+            contents.read()?;
+        }
+
+        Ok(())
+    }
+
+    /// Split the plan into at most `parts` plans, which can be applied
+    /// independently, such as by different workers. Applying every part
+    /// has the same result as applying the whole plan. Aliases and build
+    /// IDs stay with the part adding their resource, and debug links are
+    /// all kept in the first part, since they're written to a single file.
+    /// Resources can be read from files that get autopacked in place, so
+    /// parts adding resources should be applied before parts writing
+    /// files when the two could overlap.
+    pub fn split(self, parts: std::num::NonZeroUsize) -> Vec<Self> {
+        let Self {
+            format_version,
+            resource_dir,
+            resources,
+            aliases,
+            build_ids,
+            files,
+            #[cfg(feature = "elf")]
+            mut debug_links,
+        } = self;
+
+        let parts = parts.get().min(resources.len().max(files.len()).max(1));
+        let mut plans: Vec<_> = (0..parts)
+            .map(|_| Self {
+                format_version,
+                resource_dir: resource_dir.clone(),
+                resources: vec![],
+                aliases: vec![],
+                build_ids: vec![],
+                files: vec![],
+                #[cfg(feature = "elf")]
+                debug_links: std::mem::take(&mut debug_links),
+            })
+            .collect();
+
+        let mut resource_parts = HashMap::new();
+        for (index, resource) in resources.into_iter().enumerate() {
+            resource_parts.insert(resource.path.clone(), index % parts);
+            plans[index % parts].resources.push(resource);
+        }
+        for alias in aliases {
+            let part = resource_parts.get(&alias.resource_path).copied();
+            plans[part.unwrap_or_default()].aliases.push(alias);
+        }
+        for build_id in build_ids {
+            let part = resource_parts.get(&build_id.resource_path).copied();
+            plans[part.unwrap_or_default()].build_ids.push(build_id);
+        }
+        for (index, file) in files.into_iter().enumerate() {
+            plans[index % parts].files.push(file);
+        }
+
+        plans
+    }
+}
+
+fn check_format_version(plan: &AutopackPlan) -> eyre::Result<()> {
+    eyre::ensure!(
+        plan.format_version == PLAN_FORMAT_VERSION,
+        "unsupported plan format version {} (expected {PLAN_FORMAT_VERSION})",
+        plan.format_version
+    );
+    Ok(())
+}
+
+/// Apply a plan from [`plan`], adding its resources and writing its files.
+/// Every input is checked with [`AutopackPlan::verify_inputs`] first, so
+/// this fails without writing anything if any input it reads from changed
+/// since the plan was made, including files autopacked in place by an
+/// earlier apply.
+pub fn apply(plan: &AutopackPlan) -> eyre::Result<()> {
+    plan.verify_inputs()?;
+    let resource_dir = &plan.resource_dir;
+
+    for resource in &plan.resources {
+        let contents = resource.contents.read()?;
+        let resource_path = brioche_resources::add_named_blob(
+            resource_dir,
+            std::io::Cursor::new(&contents[..]),
+            resource.executable,
+            &resource.name,
+        )?;
+        eyre::ensure!(
+            resource_path == resource.path,
+            "resource {:?} was added as {resource_path:?}",
+            resource.path
+        );
+    }
+    for alias in &plan.aliases {
+        brioche_resources::add_blob_alias(resource_dir, &alias.resource_path, &alias.name)?;
+    }
+    for build_id in &plan.build_ids {
+        brioche_resources::add_build_id(resource_dir, &build_id.resource_path, &build_id.build_id)?;
+    }
+
+    for file in &plan.files {
+        apply_file(file).with_context(|| format!("failed to write {:?}", file.path))?;
+    }
+
+    #[cfg(feature = "elf")]
+    {
+        let debug_links = crate::debug_links::DebugLinks::default();
+        for (output_path, debug_link) in &plan.debug_links {
+            debug_links.insert(output_path, debug_link.clone());
+        }
+        debug_links.write(resource_dir)?;
+    }
+
+    Ok(())
+}
+
+fn apply_file(file: &PlannedFile) -> eyre::Result<()> {
+    let path = &file.path;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {parent:?}"))?;
+    }
+
+    let contents = file.contents.read()?;
+    match &file.pack {
+        None => {
+            write_output(path, &contents)?;
+        }
+        Some(pack) if file.sidecar_pack => {
+            write_output(path, &contents)?;
+            write_sidecar_pack(path, pack, &file.attached)?;
+        }
+        Some(pack) if file.shared_launcher => {
+            let packed_executable =
+                PackedExecutable::Bytes(std::borrow::Cow::Owned(contents.to_vec()));
+            SharedLauncherPackEmitter.emit_with_attached(
+                path,
+                PackBase::PackedExecutable(&packed_executable),
+                pack,
+                &file.attached,
+            )?;
+        }
+        Some(pack) => {
+            FilePackEmitter.emit_with_attached(
+                path,
+                PackBase::Contents(&contents),
+                pack,
+                &file.attached,
+            )?;
+        }
+    }
+
+    if let Some(mode) = file.mode.filter(|_| !file.shared_launcher) {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("failed to set permissions of {path:?}"))?;
+    }
+
+    Ok(())
+}
+
+/// Records what autopacking would do, used as both the resource sink and
+/// pack emitter while planning.
+#[derive(Default)]
+pub(crate) struct PlanRecorder {
+    /// Record programs as sharing their packed executable, see
+    /// [`AutopackConfig::shared_launcher`].
+    shared_launcher: bool,
+
+    resources: Mutex<BTreeMap<PathBuf, PlannedResource>>,
+    aliases: Mutex<BTreeSet<PlannedAlias>>,
+    build_ids: Mutex<BTreeSet<PlannedBuildId>>,
+    files: Mutex<BTreeMap<PathBuf, PlannedFile>>,
+
+    /// The full contents of each planned file, so autopacking can read
+    /// dependencies as they would be after being autopacked.
+    outputs: Mutex<HashMap<PathBuf, Vec<u8>>>,
+
+    #[cfg(feature = "elf")]
+    debug_links: Mutex<BTreeMap<PathBuf, crate::DebugLink>>,
+}
+
+impl PlanRecorder {
+    /// Get the contents of a planned file, if `path` would be written.
+    pub fn output_contents(&self, path: &Path) -> Option<Vec<u8>> {
+        let outputs = self
+            .outputs
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        outputs.get(&output_key(path)).cloned()
+    }
+
+    pub fn has_output(&self, path: &Path) -> bool {
+        let outputs = self
+            .outputs
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        outputs.contains_key(&output_key(path))
+    }
+
+    /// Record that a resource's contents come from the file at `path`, so
+    /// the plan refers to the file instead of including its contents. The
+    /// contents are kept if the file doesn't match them, such as when the
+    /// resource was read from an autopacked file's planned contents.
+    pub fn set_resource_file(&self, resource_path: &Path, path: &Path) {
+        let mut resources = self
+            .resources
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let Some(resource) = resources.get_mut(resource_path) else {
+            return;
+        };
+        if let PlannedContents::Inline { contents } = &resource.contents {
+            resource.contents = PlannedContents::from_contents(Some(path), contents);
+        }
+    }
+
+    /// Record a file written without a pack, such as an unwrapped copy.
+    #[cfg(feature = "elf")]
+    pub fn record_copy(&self, path: &Path, source: &crate::detect::SourceFile, mode: u32) {
+        self.record_file(
+            PlannedFile {
+                path: path.to_owned(),
+                contents: PlannedContents::from_contents(Some(&source.path), &source.contents),
+                pack: None,
+                attached: vec![],
+                sidecar_pack: false,
+                shared_launcher: false,
+                mode: Some(mode),
+            },
+            source.contents.clone(),
+        );
+    }
+
+    /// Record a file that keeps `contents` and has its pack written to a
+    /// sidecar file.
+    pub fn record_sidecar(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        pack: &brioche_pack::Pack,
+        attached: &[brioche_pack::Pack],
+    ) {
+        self.record_file(
+            PlannedFile {
+                path: path.to_owned(),
+                contents: PlannedContents::from_contents(Some(path), contents),
+                pack: Some(pack.clone()),
+                attached: attached.to_vec(),
+                sidecar_pack: true,
+                shared_launcher: false,
+                mode: None,
+            },
+            contents.to_vec(),
+        );
+    }
+
+    /// Check if a planned file is a hard link to a shared packed
+    /// executable, see [`crate::emit::is_shared_launcher`].
+    pub fn is_shared_launcher(&self, path: &Path) -> bool {
+        let files = self.files.lock().unwrap_or_else(|error| error.into_inner());
+        files.get(path).is_some_and(|file| file.shared_launcher)
+    }
+
+    /// Set the permissions of a planned file, returning its size. Files
+    /// sharing a packed executable keep its permissions.
+    pub fn set_mode(&self, path: &Path, mode: u32) -> eyre::Result<u64> {
+        let mut files = self.files.lock().unwrap_or_else(|error| error.into_inner());
+        let file = files
+            .get_mut(path)
+            .ok_or_else(|| eyre::eyre!("no file planned for {path:?}"))?;
+        if !file.shared_launcher {
+            file.mode = Some(mode);
+        }
+
+        let outputs = self
+            .outputs
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let size = outputs
+            .get(&output_key(path))
+            .map_or(0, |contents| contents.len());
+        Ok(size as u64)
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn set_debug_links(&self, debug_links: BTreeMap<PathBuf, crate::DebugLink>) {
+        *self
+            .debug_links
+            .lock()
+            .unwrap_or_else(|error| error.into_inner()) = debug_links;
+    }
+
+    fn record_file(&self, file: PlannedFile, output_contents: Vec<u8>) {
+        let mut outputs = self
+            .outputs
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        outputs.insert(output_key(&file.path), output_contents);
+
+        let mut files = self.files.lock().unwrap_or_else(|error| error.into_inner());
+        files.insert(file.path.clone(), file);
+    }
+
+    fn finish(self, config: &AutopackConfig) -> AutopackPlan {
+        AutopackPlan {
+            format_version: PLAN_FORMAT_VERSION,
+            resource_dir: config.resource_dir.clone(),
+            resources: into_inner(self.resources).into_values().collect(),
+            aliases: into_inner(self.aliases).into_iter().collect(),
+            build_ids: into_inner(self.build_ids).into_iter().collect(),
+            files: into_inner(self.files).into_values().collect(),
+            #[cfg(feature = "elf")]
+            debug_links: into_inner(self.debug_links),
+        }
+    }
+}
+
+impl ResourceSink for PlanRecorder {
+    fn add_named_blob(
+        &self,
+        contents: &[u8],
+        executable: bool,
+        name: &Path,
+    ) -> eyre::Result<PathBuf> {
+        let resource_path = brioche_resources::named_blob_path(contents, executable, name);
+
+        let mut resources = self
+            .resources
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        resources
+            .entry(resource_path.clone())
+            .or_insert_with(|| PlannedResource {
+                path: resource_path.clone(),
+                name: name.to_owned(),
+                executable,
+                contents: PlannedContents::Inline {
+                    contents: contents.to_vec(),
+                },
+            });
+
+        Ok(resource_path)
+    }
+
+    fn add_alias(&self, resource_path: &Path, name: &Path) -> eyre::Result<()> {
+        let mut aliases = self
+            .aliases
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        aliases.insert(PlannedAlias {
+            resource_path: resource_path.to_owned(),
+            name: name.to_owned(),
+        });
+        Ok(())
+    }
+
+    fn add_build_id(&self, resource_path: &Path, build_id: &str) -> eyre::Result<()> {
+        let mut build_ids = self
+            .build_ids
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        build_ids.insert(PlannedBuildId {
+            resource_path: resource_path.to_owned(),
+            build_id: build_id.to_string(),
+        });
+        Ok(())
+    }
+}
+
+impl PackEmitter for PlanRecorder {
+    fn emit(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+    ) -> eyre::Result<()> {
+        self.emit_with_attached(output_path, base, pack, &[])
+    }
+
+    fn emit_with_attached(
+        &self,
+        output_path: &Path,
+        base: PackBase<'_>,
+        pack: &brioche_pack::Pack,
+        attached: &[brioche_pack::Pack],
+    ) -> eyre::Result<()> {
+        let shared_launcher = match base {
+            PackBase::PackedExecutable(_) => self.shared_launcher,
+            PackBase::SignedPackedExecutable(_) => true,
+            PackBase::Source { .. } | PackBase::Contents(_) => false,
+        };
+        let (contents, mut output_contents) = match base {
+            PackBase::PackedExecutable(PackedExecutable::Path(path))
+            | PackBase::SignedPackedExecutable(PackedExecutable::Path(path)) => {
+                let contents = std::fs::read(path)
+                    .with_context(|| format!("failed to read packed executable {path:?}"))?;
+                let planned_contents = PlannedContents::PackedExecutable {
+                    path: path.clone(),
+                    hash: blake3::hash(&contents).to_hex().to_string(),
+                };
+                (planned_contents, contents)
+            }
+            PackBase::PackedExecutable(PackedExecutable::Bytes(contents))
+            | PackBase::SignedPackedExecutable(PackedExecutable::Bytes(contents)) => (
+                PlannedContents::Inline {
+                    contents: contents.to_vec(),
+                },
+                contents.to_vec(),
+            ),
+            PackBase::Source { path, contents } => (
+                PlannedContents::from_contents(Some(path), contents),
+                contents.to_vec(),
+            ),
+            PackBase::Contents(contents) => (
+                PlannedContents::from_contents(None, contents),
+                contents.to_vec(),
+            ),
+        };
+
+        // Shared packed executables are written unchanged, with the pack
+        // in a sidecar file
+        if !shared_launcher {
+            brioche_resources::inject_pack_with_attached(&mut output_contents, pack, attached)?;
+        }
+
+        self.record_file(
+            PlannedFile {
+                path: output_path.to_owned(),
+                contents,
+                pack: Some(pack.clone()),
+                attached: attached.to_vec(),
+                sidecar_pack: false,
+                shared_launcher,
+                mode: None,
+            },
+            output_contents,
+        );
+        Ok(())
+    }
+}
+
+/// Planned files are looked up by their canonical path when they exist,
+/// since dependencies can be reached through symlinks.
+fn output_key(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+fn into_inner<T>(mutex: Mutex<T>) -> T {
+    mutex
+        .into_inner()
+        .unwrap_or_else(|error| error.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt as _, path::Path};
+
+    use super::{
+        apply, AutopackPlan, PlannedContents, PlannedFile, PlannedResource, PLAN_FORMAT_VERSION,
+    };
+
+    /// A plan adding a resource and autopacking `input/program` in `dir`
+    /// to `output/program`, as read back after being serialized.
+    fn test_plan(dir: &Path) -> AutopackPlan {
+        let input_path = dir.join("input/program");
+        std::fs::create_dir_all(input_path.parent().unwrap()).unwrap();
+        std::fs::write(&input_path, b"program").unwrap();
+
+        let resource_dir = dir.join("brioche-resources.d");
+        std::fs::create_dir_all(&resource_dir).unwrap();
+        let resource_hash = blake3::hash(b"resource").to_hex();
+        let resource_path = brioche_resources::named_blob_path_with_hash(
+            &resource_hash,
+            false,
+            Path::new("libfoo.so"),
+        );
+
+        let plan = AutopackPlan {
+            format_version: PLAN_FORMAT_VERSION,
+            resource_dir,
+            resources: vec![PlannedResource {
+                path: resource_path,
+                name: "libfoo.so".into(),
+                executable: false,
+                contents: PlannedContents::Inline {
+                    contents: b"resource".to_vec(),
+                },
+            }],
+            aliases: vec![],
+            build_ids: vec![],
+            files: vec![PlannedFile {
+                path: dir.join("output/program"),
+                contents: PlannedContents::File {
+                    hash: blake3::hash(b"program").to_hex().to_string(),
+                    path: input_path,
+                },
+                pack: Some(brioche_pack::Pack::Metadata {
+                    resource_paths: vec![],
+                    format: "test".to_string(),
+                    metadata: b"metadata".to_vec(),
+                }),
+                attached: vec![],
+                sidecar_pack: false,
+                shared_launcher: false,
+                mode: Some(0o755),
+            }],
+            #[cfg(feature = "elf")]
+            debug_links: Default::default(),
+        };
+
+        let serialized = serde_json::to_string(&plan).unwrap();
+        serde_json::from_str(&serialized).unwrap()
+    }
+
+    /// Check that applying `plan` failed without writing anything.
+    fn assert_not_applied(plan: &AutopackPlan, dir: &Path) {
+        assert!(!dir.join("output").exists());
+        let resources = std::fs::read_dir(&plan.resource_dir).unwrap();
+        assert_eq!(resources.count(), 0);
+    }
+
+    #[test]
+    fn test_apply_round_trip() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let plan = test_plan(dir);
+        apply(&plan).unwrap();
+
+        let resource = plan.resource_dir.join(&plan.resources[0].path);
+        assert_eq!(std::fs::read(resource).unwrap(), b"resource");
+
+        let output_path = dir.join("output/program");
+        let extracted = crate::read_pack(&output_path).unwrap();
+        assert!(matches!(
+            extracted.pack,
+            brioche_pack::Pack::Metadata { format, metadata, .. }
+                if format == "test" && metadata == b"metadata"
+        ));
+        let output = std::fs::read(&output_path).unwrap();
+        assert_eq!(&output[..extracted.unpacked_len], b"program");
+        let mode = std::fs::metadata(&output_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    #[test]
+    fn test_apply_other_format_version() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let mut plan = test_plan(dir);
+        plan.format_version = PLAN_FORMAT_VERSION + 1;
+
+        let error = apply(&plan).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("unsupported plan format version"),
+            "{error:#}"
+        );
+        assert_not_applied(&plan, dir);
+    }
+
+    #[test]
+    fn test_apply_changed_input() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let plan = test_plan(dir);
+        std::fs::write(dir.join("input/program"), b"changed").unwrap();
+
+        let error = apply(&plan).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("changed since the plan was made"),
+            "{error:#}"
+        );
+        assert_not_applied(&plan, dir);
+    }
+}
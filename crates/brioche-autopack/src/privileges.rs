@@ -0,0 +1,83 @@
+//! Setuid and setgid bits and file capabilities of files being autopacked.
+
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use eyre::Context as _;
+
+/// The name of the xattr holding a file's capabilities.
+#[cfg(target_os = "linux")]
+const CAPABILITY_XATTR: &str = "security.capability";
+
+/// The privileges of a file being autopacked, see
+/// [`PrivilegePolicy`](crate::PrivilegePolicy).
+#[derive(Debug, Default)]
+pub(crate) struct Privileges {
+    /// The setuid and setgid bits of the file's mode.
+    pub mode: u32,
+
+    /// The raw value of the file's capability xattr, if it has one.
+    pub capabilities: Option<Vec<u8>>,
+}
+
+impl Privileges {
+    /// Read the privileges of the file at `path`, which has the given
+    /// mode.
+    pub fn read(path: &Path, mode: u32) -> eyre::Result<Self> {
+        Ok(Self {
+            mode: mode & 0o6000,
+            capabilities: read_capabilities(path)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mode == 0 && self.capabilities.is_none()
+    }
+
+    /// Set the file capabilities on an autopacked file. This needs to
+    /// happen after the file is written, since writing to a file removes
+    /// its capabilities.
+    pub fn apply_capabilities(&self, path: &Path) -> eyre::Result<()> {
+        let Some(capabilities) = &self.capabilities else {
+            return Ok(());
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            rustix::fs::setxattr(
+                path,
+                CAPABILITY_XATTR,
+                capabilities,
+                rustix::fs::XattrFlags::empty(),
+            )
+            .with_context(|| format!("failed to set file capabilities of {}", path.display()))?;
+            Ok(())
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = capabilities;
+            eyre::bail!(
+                "file capabilities of {} are only supported on Linux",
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_capabilities(path: &Path) -> eyre::Result<Option<Vec<u8>>> {
+    // Capability xattrs are at most 24 bytes
+    let mut capabilities = [0; 64];
+    match rustix::fs::getxattr(path, CAPABILITY_XATTR, &mut capabilities[..]) {
+        Ok(len) => Ok(Some(capabilities[..len].to_vec())),
+        Err(rustix::io::Errno::NODATA | rustix::io::Errno::NOTSUP) => Ok(None),
+        Err(error) => Err(error)
+            .with_context(|| format!("failed to read file capabilities of {}", path.display())),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_capabilities(_path: &Path) -> eyre::Result<Option<Vec<u8>>> {
+    Ok(None)
+}
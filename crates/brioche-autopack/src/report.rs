@@ -0,0 +1,250 @@
+//! Reporting what autopacking did.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{AutopackKind, AutopackWarning};
+
+/// What autopacking did, returned by [`autopack`](crate::autopack). Paths
+/// are listed in path order, so the report doesn't depend on how many
+/// jobs were used.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutopackReport {
+    /// Each path that was autopacked or skipped, including dependencies
+    /// that were autopacked before the files depending on them.
+    pub paths: Vec<PathReport>,
+
+    /// Warnings reported while autopacking, including ones handled by a
+    /// warning callback.
+    pub warnings: Vec<AutopackWarning>,
+
+    /// Targets autopacked once for the symlinked inputs pointing at them
+    /// (see [`SymlinkPolicy::AutopackTarget`](crate::SymlinkPolicy::AutopackTarget)).
+    pub shared_targets: Vec<SharedTarget>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathReport {
+    pub path: PathBuf,
+    pub outcome: PathOutcome,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[non_exhaustive]
+pub enum PathOutcome {
+    Autopacked {
+        /// How the file was autopacked. Repacked files report how their
+        /// unpacked contents were autopacked.
+        kind: AutopackKind,
+        output_path: PathBuf,
+
+        /// Resources added for this file, as paths within the resource
+        /// dir. Resources for dependencies autopacked along the way are
+        /// reported with the dependency.
+        resources: Vec<PathBuf>,
+
+        /// The size of the file before autopacking.
+        original_size: u64,
+
+        /// The size of the autopacked file, which is the number of bytes
+        /// written to the output path.
+        output_size: u64,
+
+        /// The permission bits of the autopacked file, such as `0o755`.
+        mode: u32,
+
+        /// The libraries in the file's closure, in the order they were
+        /// found. Empty for files without libraries, such as scripts.
+        libraries: Vec<ClosureLibrary>,
+    },
+    Skipped {
+        reason: SkipReason,
+    },
+}
+
+/// A file autopacked in place of the symlinked inputs pointing at it.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedTarget {
+    pub target: PathBuf,
+
+    /// The symlinks that were inputs, in path order.
+    pub links: Vec<PathBuf>,
+}
+
+/// A library in an autopacked file's closure, along with why it's there.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClosureLibrary {
+    /// The name the library was needed by, usually its soname.
+    pub name: String,
+
+    /// The library's path within the resource dir.
+    pub resource_path: PathBuf,
+
+    /// The size of the library file in bytes.
+    pub size: u64,
+
+    /// The libraries that pulled this library into the closure. See
+    /// [`ResolvedLibrary::needed_by`](crate::ResolvedLibrary::needed_by).
+    pub needed_by: Vec<String>,
+}
+
+#[cfg(feature = "elf")]
+impl From<&crate::ResolvedLibrary> for ClosureLibrary {
+    fn from(library: &crate::ResolvedLibrary) -> Self {
+        Self {
+            name: library.name.clone(),
+            resource_path: library.resource_path.clone(),
+            size: library.size,
+            needed_by: library.needed_by.clone(),
+        }
+    }
+}
+
+impl ClosureLibrary {
+    /// The chain of libraries from one the autopacked file needs directly
+    /// to this library, such as `libfoo.so.1 -> libzstd.so.1`.
+    pub fn chain(&self) -> String {
+        self.needed_by
+            .iter()
+            .chain([&self.name])
+            .map(|name| &**name)
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+}
+
+/// Why a path wasn't autopacked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// The file isn't a kind of file that can be autopacked.
+    Unsupported,
+
+    /// Autopacking this kind of file isn't enabled by the config.
+    Disabled { kind: AutopackKind },
+
+    /// A shared library without any library dirs to add to its pack (see
+    /// [`SharedLibraryConfig::allow_empty`](crate::SharedLibraryConfig::allow_empty)).
+    NoLibraryDirs,
+
+    /// The file already has a pack that can't be updated.
+    UnsupportedPack,
+
+    /// The file was built for a different target (see
+    /// [`ForeignArchPolicy::Skip`](crate::ForeignArchPolicy::Skip)).
+    ForeignArch,
+}
+
+/// The result of trying to autopack a single file.
+pub(crate) enum Outcome {
+    #[cfg_attr(not(any(feature = "elf", feature = "script")), allow(dead_code))]
+    Autopacked(AutopackKind),
+    Skipped(SkipReason),
+}
+
+/// Collects the report while autopacking, possibly from more than one
+/// worker.
+#[derive(Default)]
+pub(crate) struct ReportCollector {
+    paths: Mutex<BTreeMap<PathBuf, PathOutcome>>,
+    warnings: Mutex<Vec<AutopackWarning>>,
+    shared_targets: Mutex<BTreeMap<PathBuf, Vec<PathBuf>>>,
+
+    /// Dangling symlinks found while searching for libraries, which get
+    /// reported once autopacking finishes.
+    #[cfg(feature = "elf")]
+    pub dangling_symlinks: crate::warnings::DanglingSymlinks,
+
+    /// Debug links for autopacked binaries, which get written to the
+    /// resource dir once autopacking finishes.
+    #[cfg(feature = "elf")]
+    pub debug_links: crate::debug_links::DebugLinks,
+}
+
+impl ReportCollector {
+    pub fn add_path(&self, path: &Path, outcome: PathOutcome) {
+        let mut paths = self.paths.lock().unwrap_or_else(|error| error.into_inner());
+        paths.insert(path.to_owned(), outcome);
+    }
+
+    /// Add a warning to the report. Returns false if the warning should
+    /// only be reported once and already was.
+    pub fn add_warning(&self, warning: AutopackWarning) -> bool {
+        let mut warnings = self
+            .warnings
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if let AutopackWarning::DanglingSymlink { path, .. } = &warning {
+            let already_reported = warnings.iter().any(|other| {
+                matches!(other, AutopackWarning::DanglingSymlink { path: other_path, .. } if other_path == path)
+            });
+            if already_reported {
+                return false;
+            }
+        }
+
+        warnings.push(warning);
+        true
+    }
+
+    /// Record that the input `link` is a symlink to `target`, which gets
+    /// autopacked instead.
+    pub fn add_shared_link(&self, target: &Path, link: &Path) {
+        let mut shared_targets = self
+            .shared_targets
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        shared_targets
+            .entry(target.to_owned())
+            .or_default()
+            .push(link.to_owned());
+    }
+
+    pub fn finish(self) -> AutopackReport {
+        let paths = self
+            .paths
+            .into_inner()
+            .unwrap_or_else(|error| error.into_inner());
+        let mut warnings = self
+            .warnings
+            .into_inner()
+            .unwrap_or_else(|error| error.into_inner());
+        warnings.sort_by(|a, b| a.path().cmp(b.path()));
+        let shared_targets = self
+            .shared_targets
+            .into_inner()
+            .unwrap_or_else(|error| error.into_inner());
+
+        AutopackReport {
+            paths: paths
+                .into_iter()
+                .map(|(path, outcome)| PathReport { path, outcome })
+                .collect(),
+            warnings,
+            shared_targets: shared_targets
+                .into_iter()
+                .map(|(target, mut links)| {
+                    links.sort();
+                    SharedTarget { target, links }
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,699 @@
+//! Finding the dependencies of autopacked files.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use eyre::Context as _;
+
+#[cfg(feature = "elf")]
+use crate::LibraryConflicts;
+use crate::{warnings::DanglingSymlinks, AutopackConfig, AutopackWarning};
+
+/// Finds interpreters, libraries, and commands needed by autopacked files.
+/// Each method returns `Ok(None)` if the dependency isn't found, so an
+/// implementation only needs to override the kinds of dependencies it
+/// provides.
+pub trait Resolver: Sync {
+    /// Find the interpreter requested by a dynamic binary, such as
+    /// `/lib64/ld-linux-x86-64.so.2`.
+    fn find_interpreter(&self, interpreter: &Path) -> eyre::Result<Option<PathBuf>> {
+        let _ = interpreter;
+        Ok(None)
+    }
+
+    /// Find a library by name, such as `libssl.so.3`. This is used after
+    /// searching the ELF file's own rpath and the configured library paths.
+    fn find_library(&self, name: &str) -> eyre::Result<Option<PathBuf>> {
+        let _ = name;
+        Ok(None)
+    }
+
+    /// Find a library by name for the file at `path`, which was built for
+    /// a different target and is autopacked anyway (see
+    /// [`ForeignArchPolicy::Autopack`](crate::ForeignArchPolicy::Autopack)).
+    /// The library needs to match the file's architecture rather than the
+    /// target.
+    fn find_foreign_library(&self, name: &str, path: &Path) -> eyre::Result<Option<PathBuf>> {
+        let _ = (name, path);
+        Ok(None)
+    }
+
+    /// Find the command named by a script's shebang, such as `bash`.
+    fn find_command(&self, name: &str) -> eyre::Result<Option<PathBuf>> {
+        let _ = name;
+        Ok(None)
+    }
+
+    /// Warnings about problems found while searching for dependencies,
+    /// such as dangling symlinks in search paths. These are reported once
+    /// autopacking finishes.
+    fn warnings(&self) -> Vec<AutopackWarning> {
+        vec![]
+    }
+}
+
+/// What kind of dependency a [`CandidateChooser`] is choosing.
+#[cfg(feature = "elf")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CandidateKind {
+    Library,
+    Interpreter,
+}
+
+#[cfg(feature = "elf")]
+impl std::fmt::Display for CandidateKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::Library => "library",
+            Self::Interpreter => "interpreter",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Called with the kind of dependency, its name (such as `libz.so.1` or
+/// `/lib64/ld-linux-x86-64.so.2`), and the candidate files in search
+/// order. Returns the candidate to use, or `None` to use the first one.
+#[cfg(feature = "elf")]
+pub type CandidateChooserCallback =
+    dyn Fn(CandidateKind, &str, &[PathBuf]) -> eyre::Result<Option<PathBuf>> + Send + Sync;
+
+/// Chooses between link dependencies that provide different files for the
+/// same library or interpreter, such as by asking the user or reading
+/// earlier choices from a file. Identical copies aren't a choice, so only
+/// candidates with different contents are passed.
+#[cfg(feature = "elf")]
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum CandidateChooser {
+    Callback(Arc<CandidateChooserCallback>),
+}
+
+#[cfg(feature = "elf")]
+impl std::fmt::Debug for CandidateChooser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Callback(_) => write!(f, "Callback(<fn>)"),
+        }
+    }
+}
+
+#[cfg(feature = "elf")]
+impl CandidateChooser {
+    /// Choose one of `candidates`, which should have more than one entry.
+    pub fn choose(
+        &self,
+        kind: CandidateKind,
+        name: &str,
+        candidates: &[PathBuf],
+    ) -> eyre::Result<PathBuf> {
+        let choice = match self {
+            Self::Callback(callback) => callback(kind, name, candidates)?,
+        };
+        let Some(choice) = choice else {
+            let first = candidates
+                .first()
+                .ok_or_else(|| eyre::eyre!("no candidates for {kind} {name:?}"))?;
+            return Ok(first.clone());
+        };
+
+        eyre::ensure!(
+            candidates.contains(&choice),
+            "chose {choice:?} for {kind} {name:?}, which isn't one of the candidates"
+        );
+        Ok(choice)
+    }
+}
+
+/// The default [`Resolver`], which finds dependencies from the config's
+/// link dependencies. Libraries are found from each dependency's
+/// `brioche-env.d/env/LIBRARY_PATH` entries and `etc/ld.so.conf` files,
+/// and commands are found from
+/// its `brioche-env.d/env/PATH` entries and `bin/` directory. The default
+/// libc dependency is used as a fallback for interpreters and libraries.
+/// Dangling symlinks in search paths are skipped with a warning. When
+/// link dependencies provide different files for a library or
+/// interpreter, the config's candidate chooser picks one.
+#[derive(Debug, Clone)]
+pub struct LinkDependencyResolver {
+    #[cfg(feature = "elf")]
+    link_dependencies: Vec<PathBuf>,
+    #[cfg(feature = "elf")]
+    library_paths: Vec<PathBuf>,
+    #[cfg(feature = "elf")]
+    default_libc_library_paths: Vec<PathBuf>,
+    #[cfg(feature = "elf")]
+    target: Option<target_lexicon::Triple>,
+    #[cfg(feature = "elf")]
+    library_conflicts: LibraryConflicts,
+    #[cfg(feature = "elf")]
+    glibc_hwcaps: Vec<String>,
+    #[cfg(feature = "elf")]
+    candidate_chooser: Option<CandidateChooser>,
+    #[cfg(feature = "script")]
+    command_paths: Vec<PathBuf>,
+    dangling_symlinks: Arc<DanglingSymlinks>,
+}
+
+impl LinkDependencyResolver {
+    pub fn new(config: &AutopackConfig) -> eyre::Result<Self> {
+        let dangling_symlinks = Arc::new(DanglingSymlinks::default());
+
+        #[cfg(feature = "elf")]
+        let mut library_paths = vec![];
+        #[cfg(feature = "elf")]
+        for link_dep in &config.link_dependencies {
+            add_library_path_env_entries(
+                link_dep,
+                config.symlink_limit,
+                &dangling_symlinks,
+                &mut library_paths,
+            )?;
+            add_ld_so_conf_entries(link_dep, &mut library_paths)?;
+        }
+
+        #[cfg(feature = "elf")]
+        let mut default_libc_library_paths = vec![];
+        #[cfg(feature = "elf")]
+        if let Some(default_libc_dependency) = &config.default_libc_dependency {
+            add_library_path_env_entries(
+                default_libc_dependency,
+                config.symlink_limit,
+                &dangling_symlinks,
+                &mut default_libc_library_paths,
+            )?;
+            add_ld_so_conf_entries(default_libc_dependency, &mut default_libc_library_paths)?;
+        }
+
+        #[cfg(feature = "script")]
+        let command_paths = command_paths(config, &dangling_symlinks)?;
+
+        Ok(Self {
+            #[cfg(feature = "elf")]
+            link_dependencies: config
+                .link_dependencies
+                .iter()
+                .chain(&config.default_libc_dependency)
+                .cloned()
+                .collect(),
+            #[cfg(feature = "elf")]
+            library_paths,
+            #[cfg(feature = "elf")]
+            default_libc_library_paths,
+            #[cfg(feature = "elf")]
+            target: config.target.clone(),
+            #[cfg(feature = "elf")]
+            library_conflicts: config.library_conflicts,
+            #[cfg(feature = "elf")]
+            glibc_hwcaps: config.glibc_hwcaps.clone(),
+            #[cfg(feature = "elf")]
+            candidate_chooser: config.candidate_chooser.clone(),
+            #[cfg(feature = "script")]
+            command_paths,
+            dangling_symlinks,
+        })
+    }
+
+    /// The dirs searched for libraries, from the link dependencies'
+    /// `brioche-env.d/env/LIBRARY_PATH` entries and `etc/ld.so.conf`
+    /// files, in search order.
+    #[cfg(feature = "elf")]
+    pub fn library_paths(&self) -> &[PathBuf] {
+        &self.library_paths
+    }
+
+    /// The dirs searched for libraries from the default libc dependency,
+    /// which are searched after [`library_paths`](Self::library_paths).
+    #[cfg(feature = "elf")]
+    pub fn default_libc_library_paths(&self) -> &[PathBuf] {
+        &self.default_libc_library_paths
+    }
+
+    /// The dirs searched for script commands, in search order.
+    #[cfg(feature = "script")]
+    pub fn command_paths(&self) -> &[PathBuf] {
+        &self.command_paths
+    }
+}
+
+impl Resolver for LinkDependencyResolver {
+    #[cfg(feature = "elf")]
+    fn find_interpreter(&self, interpreter: &Path) -> eyre::Result<Option<PathBuf>> {
+        let relative_interpreter = interpreter.strip_prefix("/").with_context(|| {
+            format!("expected program interpreter to start with '/': {interpreter:?}")
+        })?;
+
+        // The interpreter comes from libc, so the default libc dependency
+        // is checked last
+        let mut candidates = self
+            .link_dependencies
+            .iter()
+            .map(|dependency| dependency.join(relative_interpreter))
+            .filter(|dependency_path| dependency_path.exists());
+        let Some(interpreter_path) = candidates.next() else {
+            return Ok(None);
+        };
+
+        if let Some(candidate_chooser) = &self.candidate_chooser {
+            let candidates = distinct_candidates(&interpreter_path, candidates)?;
+            if candidates.len() > 1 {
+                let interpreter_path = candidate_chooser.choose(
+                    CandidateKind::Interpreter,
+                    &interpreter.to_string_lossy(),
+                    &candidates,
+                )?;
+                return Ok(Some(interpreter_path));
+            }
+        }
+
+        Ok(Some(interpreter_path))
+    }
+
+    #[cfg(feature = "elf")]
+    fn find_library(&self, name: &str) -> eyre::Result<Option<PathBuf>> {
+        let library_path = crate::elf::find_library(
+            &self.library_paths,
+            name,
+            self.target.as_ref(),
+            None,
+            &self.glibc_hwcaps,
+            &self.dangling_symlinks,
+        )?;
+        if let Some(library_path) = library_path {
+            if let Some(candidate_chooser) = &self.candidate_chooser {
+                let candidates = self.library_candidates(name, &library_path)?;
+                if candidates.len() > 1 {
+                    let library_path =
+                        candidate_chooser.choose(CandidateKind::Library, name, &candidates)?;
+                    return Ok(Some(library_path));
+                }
+            } else if self.library_conflicts == LibraryConflicts::Error {
+                let candidates = self.library_candidates(name, &library_path)?;
+                if candidates.len() > 1 {
+                    return Err(crate::diagnostics::ConflictingLibrary {
+                        name: name.to_string(),
+                        paths: candidates,
+                    }
+                    .into());
+                }
+            }
+            return Ok(Some(library_path));
+        }
+
+        crate::elf::find_library(
+            &self.default_libc_library_paths,
+            name,
+            self.target.as_ref(),
+            None,
+            &self.glibc_hwcaps,
+            &self.dangling_symlinks,
+        )
+    }
+
+    #[cfg(feature = "elf")]
+    fn find_foreign_library(&self, name: &str, path: &Path) -> eyre::Result<Option<PathBuf>> {
+        let Some(arch) = crate::elf::ElfArch::read(path) else {
+            return Ok(None);
+        };
+
+        let library_paths = self
+            .library_paths
+            .iter()
+            .chain(&self.default_libc_library_paths)
+            .cloned()
+            .collect::<Vec<_>>();
+        crate::elf::find_library(
+            &library_paths,
+            name,
+            None,
+            Some(arch),
+            &self.glibc_hwcaps,
+            &self.dangling_symlinks,
+        )
+    }
+
+    #[cfg(feature = "script")]
+    fn find_command(&self, name: &str) -> eyre::Result<Option<PathBuf>> {
+        let command = self
+            .command_paths
+            .iter()
+            .map(|command_path| command_path.join(name))
+            .find(|command| command.is_file());
+        Ok(command)
+    }
+
+    fn warnings(&self) -> Vec<AutopackWarning> {
+        self.dangling_symlinks.warnings()
+    }
+}
+
+#[cfg(feature = "elf")]
+impl LinkDependencyResolver {
+    /// Find the files providing `name` in each library path, starting with
+    /// `library_path`, the one that was found first. Files with the same
+    /// contents as an earlier one are skipped.
+    fn library_candidates(&self, name: &str, library_path: &Path) -> eyre::Result<Vec<PathBuf>> {
+        let mut other_paths = vec![];
+        for search_path in &self.library_paths {
+            let other_path = crate::elf::find_library(
+                std::slice::from_ref(search_path),
+                name,
+                self.target.as_ref(),
+                None,
+                &self.glibc_hwcaps,
+                &self.dangling_symlinks,
+            )?;
+            other_paths.extend(other_path);
+        }
+
+        distinct_candidates(library_path, other_paths)
+    }
+}
+
+/// Get `first` followed by each of `others` with different contents than
+/// the candidates before it.
+#[cfg(feature = "elf")]
+fn distinct_candidates(
+    first: &Path,
+    others: impl IntoIterator<Item = PathBuf>,
+) -> eyre::Result<Vec<PathBuf>> {
+    let read = |path: &Path| {
+        std::fs::read(path).with_context(|| format!("failed to read candidate {path:?}"))
+    };
+
+    let mut candidates = vec![first.to_owned()];
+    let mut candidate_contents = vec![read(first)?];
+    for other in others {
+        if candidates.contains(&other) {
+            continue;
+        }
+
+        let contents = read(&other)?;
+        if !candidate_contents.contains(&contents) {
+            candidates.push(other);
+            candidate_contents.push(contents);
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Add $LIBRARY_PATH directories from symlinks under
+/// brioche-env.d/env/LIBRARY_PATH
+#[cfg(feature = "elf")]
+fn add_library_path_env_entries(
+    link_dep: &Path,
+    symlink_limit: u32,
+    dangling_symlinks: &DanglingSymlinks,
+    library_paths: &mut Vec<PathBuf>,
+) -> eyre::Result<()> {
+    let library_path_env_dir = link_dep
+        .join("brioche-env.d")
+        .join("env")
+        .join("LIBRARY_PATH");
+    let library_path_env_dir_entries = match std::fs::read_dir(&library_path_env_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(());
+        }
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("failed to read directory {:?}", library_path_env_dir));
+        }
+    };
+    for entry in library_path_env_dir_entries {
+        let entry = entry?;
+        eyre::ensure!(
+            entry.metadata()?.is_symlink(),
+            "expected {:?} to be a symlink",
+            entry.path()
+        );
+
+        if dangling_symlinks.check(&entry.path()) {
+            continue;
+        }
+
+        let entry_path = brioche_resources::canonicalize(&entry.path(), symlink_limit)?;
+        library_paths.push(entry_path);
+    }
+
+    Ok(())
+}
+
+/// Add library directories listed in the link dependency's
+/// `etc/ld.so.conf` and `etc/ld.so.conf.d/*.conf` files, for dependencies
+/// that don't have `brioche-env.d/env/LIBRARY_PATH` entries. Absolute
+/// paths and `include` patterns are resolved within the link dependency,
+/// and directories that don't exist are skipped.
+#[cfg(feature = "elf")]
+fn add_ld_so_conf_entries(link_dep: &Path, library_paths: &mut Vec<PathBuf>) -> eyre::Result<()> {
+    let mut visited = std::collections::HashSet::new();
+    let etc_dir = link_dep.join("etc");
+    add_ld_so_conf_file(
+        link_dep,
+        &etc_dir.join("ld.so.conf"),
+        &mut visited,
+        library_paths,
+    )?;
+    for conf_path in glob_ld_so_conf_files(&etc_dir.join("ld.so.conf.d"), "*.conf")? {
+        add_ld_so_conf_file(link_dep, &conf_path, &mut visited, library_paths)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "elf")]
+fn add_ld_so_conf_file(
+    link_dep: &Path,
+    conf_path: &Path,
+    visited: &mut std::collections::HashSet<PathBuf>,
+    library_paths: &mut Vec<PathBuf>,
+) -> eyre::Result<()> {
+    // Files can be included more than once, including by themselves
+    if !visited.insert(conf_path.to_owned()) {
+        return Ok(());
+    }
+
+    let contents = match std::fs::read_to_string(conf_path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(());
+        }
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {conf_path:?}"));
+        }
+    };
+    let conf_dir = conf_path.parent().unwrap_or(link_dep);
+
+    for line in contents.lines() {
+        let line = line.split_once('#').map_or(line, |(line, _)| line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(pattern) = line.strip_prefix("include") {
+            let Some(pattern) = pattern.strip_prefix(char::is_whitespace) else {
+                continue;
+            };
+            for pattern in pattern.split_whitespace() {
+                let pattern = resolve_ld_so_conf_path(link_dep, conf_dir, Path::new(pattern));
+                let (Some(dir), Some(file_pattern)) = (pattern.parent(), pattern.file_name())
+                else {
+                    continue;
+                };
+                let file_pattern = file_pattern
+                    .to_str()
+                    .ok_or_else(|| eyre::eyre!("invalid include pattern in {conf_path:?}"))?;
+                for included_path in glob_ld_so_conf_files(dir, file_pattern)? {
+                    add_ld_so_conf_file(link_dep, &included_path, visited, library_paths)?;
+                }
+            }
+            continue;
+        }
+
+        // `hwcap` lines are for the old hardware capability directories,
+        // which aren't searched
+        if line.starts_with("hwcap") {
+            continue;
+        }
+
+        // Entries are usually one per line, but ldconfig also accepts
+        // lists separated by spaces, commas, or colons
+        for entry in line.split(|c: char| c.is_whitespace() || c == ',' || c == ':') {
+            if entry.is_empty() {
+                continue;
+            }
+            let library_path = resolve_ld_so_conf_path(link_dep, conf_dir, Path::new(entry));
+            if library_path.is_dir() && !library_paths.contains(&library_path) {
+                library_paths.push(library_path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve a path from an ld.so.conf file. Absolute paths are relative to
+/// the link dependency, and relative paths are relative to the conf file.
+#[cfg(feature = "elf")]
+fn resolve_ld_so_conf_path(link_dep: &Path, conf_dir: &Path, path: &Path) -> PathBuf {
+    match path.strip_prefix("/") {
+        Ok(path) => link_dep.join(path),
+        Err(_) => conf_dir.join(path),
+    }
+}
+
+/// List the files in `dir` matching a glob pattern, sorted by name like
+/// ldconfig does.
+#[cfg(feature = "elf")]
+fn glob_ld_so_conf_files(dir: &Path, pattern: &str) -> eyre::Result<Vec<PathBuf>> {
+    let matcher = globset::Glob::new(pattern)
+        .with_context(|| format!("invalid ld.so.conf include pattern {pattern:?}"))?
+        .compile_matcher();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(vec![]);
+        }
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read directory {dir:?}"));
+        }
+    };
+
+    let mut paths = vec![];
+    for entry in entries {
+        let entry = entry?;
+        if matcher.is_match(entry.file_name()) && entry.path().is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+#[cfg(feature = "script")]
+fn command_paths(
+    config: &AutopackConfig,
+    dangling_symlinks: &DanglingSymlinks,
+) -> eyre::Result<Vec<PathBuf>> {
+    let mut command_paths = vec![];
+
+    for link_dep in &config.link_dependencies {
+        // Add $PATH directories from symlinks under brioche-env.d/env/PATH
+        let path_env_dir = link_dep.join("brioche-env.d").join("env").join("PATH");
+        let path_env_dir_entries = match std::fs::read_dir(&path_env_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                continue;
+            }
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("failed to read directory {:?}", path_env_dir));
+            }
+        };
+        for entry in path_env_dir_entries {
+            let entry = entry?;
+            eyre::ensure!(
+                entry.metadata()?.is_symlink(),
+                "expected {:?} to be a symlink",
+                entry.path()
+            );
+
+            if dangling_symlinks.check(&entry.path()) {
+                continue;
+            }
+
+            let entry_path = brioche_resources::canonicalize(&entry.path(), config.symlink_limit)?;
+            command_paths.push(entry_path);
+        }
+    }
+
+    for link_dep in &config.link_dependencies {
+        // Add bin/ to $PATH if it exists
+        let link_dep_bin = link_dep.join("bin");
+        if link_dep_bin.is_dir() {
+            command_paths.push(link_dep_bin);
+        }
+    }
+
+    Ok(command_paths)
+}
+
+#[cfg(all(test, feature = "elf"))]
+mod tests {
+    use std::path::Path;
+
+    use super::add_ld_so_conf_entries;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_add_ld_so_conf_entries() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let link_dep = temp_dir.path();
+        for dir in [
+            "usr/lib64",
+            "opt/a/lib",
+            "opt/b/lib",
+            "opt/c/lib",
+            "opt/x/lib",
+            "opt/ignored/lib",
+            "etc/ld.so.conf.d/relative",
+        ] {
+            std::fs::create_dir_all(link_dep.join(dir)).unwrap();
+        }
+
+        write(
+            &link_dep.join("etc/ld.so.conf"),
+            "# Library paths\n\
+             include ld.so.conf.d/*.conf\n\
+             include /etc/ld.so.conf\n\
+             /usr/lib64 # comment\n\
+             hwcap 1 nosegneg\n",
+        );
+        write(
+            &link_dep.join("etc/ld.so.conf.d/a.conf"),
+            "/opt/a/lib, /opt/b/lib:/opt/c/lib\n/opt/missing/lib\nrelative\n",
+        );
+        write(
+            &link_dep.join("etc/ld.so.conf.d/b.conf"),
+            "include /etc/extra/*.conf\n/usr/lib64\n",
+        );
+        write(
+            &link_dep.join("etc/ld.so.conf.d/ignored.txt"),
+            "/opt/ignored/lib\n",
+        );
+        write(&link_dep.join("etc/extra/x.conf"), "/opt/x/lib\n");
+
+        let mut library_paths = vec![];
+        add_ld_so_conf_entries(link_dep, &mut library_paths).unwrap();
+        assert_eq!(
+            library_paths,
+            [
+                link_dep.join("opt/a/lib"),
+                link_dep.join("opt/b/lib"),
+                link_dep.join("opt/c/lib"),
+                link_dep.join("etc/ld.so.conf.d/relative"),
+                link_dep.join("opt/x/lib"),
+                link_dep.join("usr/lib64"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_ld_so_conf_entries_without_conf() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("usr/lib")).unwrap();
+
+        let mut library_paths = vec![];
+        add_ld_so_conf_entries(temp_dir.path(), &mut library_paths).unwrap();
+        assert!(library_paths.is_empty());
+    }
+}
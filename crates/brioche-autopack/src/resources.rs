@@ -0,0 +1,597 @@
+//! Adding the files autopacked files depend on as resources.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use eyre::{Context as _, OptionExt as _};
+
+use crate::{detect::SourceFile, retry::with_retries, AutopackContext};
+
+/// Stores the resources that autopacked files depend on, such as
+/// programs, interpreters, and libraries.
+pub trait ResourceSink: Sync {
+    /// Add a resource named `name` with the given contents, and return its
+    /// path within the resource dir. The returned path is recorded in
+    /// packs, so launchers need to be able to find it from their resource
+    /// dirs.
+    fn add_named_blob(
+        &self,
+        contents: &[u8],
+        executable: bool,
+        name: &Path,
+    ) -> eyre::Result<PathBuf>;
+
+    /// Add another name for a resource returned by
+    /// [`add_named_blob`](Self::add_named_blob), in the same directory, so
+    /// a library can be loaded by any of its names. By default, other names
+    /// aren't added, so libraries can only be loaded by the name they were
+    /// found by.
+    fn add_alias(&self, resource_path: &Path, name: &Path) -> eyre::Result<()> {
+        let _ = (resource_path, name);
+        Ok(())
+    }
+
+    /// Index a resource returned by [`add_named_blob`](Self::add_named_blob)
+    /// by its GNU build ID, so debuggers and other tools can find it
+    /// without reading every resource. By default, resources aren't
+    /// indexed.
+    fn add_build_id(&self, resource_path: &Path, build_id: &str) -> eyre::Result<()> {
+        let _ = (resource_path, build_id);
+        Ok(())
+    }
+
+    /// Set the modification time of a resource returned by
+    /// [`add_named_blob`](Self::add_named_blob), see
+    /// [`AutopackConfig::timestamps`](crate::AutopackConfig::timestamps).
+    /// By default, the time isn't changed.
+    fn set_modified(&self, resource_path: &Path, time: std::time::SystemTime) -> eyre::Result<()> {
+        let _ = (resource_path, time);
+        Ok(())
+    }
+
+    /// Find a resource that an earlier run added from a file, so the file
+    /// doesn't need to be read again. `stat_key` identifies the file by its
+    /// metadata, and changes whenever the file's contents could have. By
+    /// default, nothing is found.
+    fn find_cached_blob(&self, stat_key: &str) -> eyre::Result<Option<PathBuf>> {
+        let _ = stat_key;
+        Ok(None)
+    }
+
+    /// Record that a resource was added from the file identified by
+    /// `stat_key`, for [`find_cached_blob`](Self::find_cached_blob). By
+    /// default, nothing is recorded.
+    fn cache_blob(&self, stat_key: &str, resource_path: &Path) -> eyre::Result<()> {
+        let _ = (stat_key, resource_path);
+        Ok(())
+    }
+}
+
+/// What a resource is used for, passed to a [`ResourceFilter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum ResourceRole {
+    /// The original program of a dynamic binary.
+    Program,
+    /// The interpreter (dynamic linker) of a dynamic binary.
+    Interpreter,
+    /// A library needed by a dynamic binary or shared library.
+    Library,
+    /// The contents of an autopacked script.
+    Script,
+    /// The command a script runs with, from its shebang.
+    ScriptCommand,
+}
+
+impl std::fmt::Display for ResourceRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let role = match self {
+            Self::Program => "program",
+            Self::Interpreter => "interpreter",
+            Self::Library => "library",
+            Self::Script => "script",
+            Self::ScriptCommand => "script command",
+        };
+        f.write_str(role)
+    }
+}
+
+/// What to do with a resource, returned by a [`ResourceFilter`].
+#[derive(Debug, Clone)]
+pub enum ResourceVerdict {
+    /// Add the resource with its original contents.
+    Keep,
+
+    /// Add the resource with these contents instead, such as with
+    /// timestamps stripped.
+    Rewrite { contents: Vec<u8> },
+
+    /// Fail autopacking the file that needs this resource.
+    Deny { reason: String },
+}
+
+pub type ResourceFilterCallback =
+    dyn Fn(&Path, &[u8], ResourceRole) -> eyre::Result<ResourceVerdict> + Send + Sync;
+
+/// Checks each resource before it's added, with the path it was read from,
+/// its contents, and its role. This can rewrite the contents, such as to
+/// normalize them, or deny adding the resource.
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum ResourceFilter {
+    Callback(Arc<ResourceFilterCallback>),
+}
+
+impl std::fmt::Debug for ResourceFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Callback(_) => write!(f, "Callback(<fn>)"),
+        }
+    }
+}
+
+impl ResourceFilter {
+    pub fn check(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        role: ResourceRole,
+    ) -> eyre::Result<ResourceVerdict> {
+        match self {
+            Self::Callback(callback) => callback(path, contents, role),
+        }
+    }
+}
+
+/// The default [`ResourceSink`], which adds resources to a resource dir.
+#[derive(Debug, Clone)]
+pub struct ResourceDirSink {
+    resource_dir: PathBuf,
+    namespace: Option<PathBuf>,
+    hasher: Arc<dyn brioche_resources::ContentHasher>,
+    stat_cache_dir: Option<PathBuf>,
+}
+
+impl ResourceDirSink {
+    pub fn new(resource_dir: PathBuf) -> Self {
+        Self {
+            resource_dir,
+            namespace: None,
+            hasher: Arc::new(brioche_resources::Blake3ContentHasher::default()),
+            stat_cache_dir: None,
+        }
+    }
+
+    /// Add new resources under `namespaces/<namespace>` within the
+    /// resource dir, which is laid out like a resource dir of its own.
+    /// Resources are recorded with the namespace in their paths, so
+    /// launchers find them from the same resource dirs. Resources that
+    /// are already in the resource dir outside of the namespace are reused
+    /// instead, so removing the namespace only removes resources added to
+    /// it.
+    pub fn with_namespace(resource_dir: PathBuf, namespace: &str) -> Self {
+        Self {
+            resource_dir,
+            namespace: Some(Path::new("namespaces").join(namespace)),
+            hasher: Arc::new(brioche_resources::Blake3ContentHasher::default()),
+            stat_cache_dir: None,
+        }
+    }
+
+    /// Name new blobs using `hasher` instead of BLAKE3.
+    pub fn with_hasher(mut self, hasher: Arc<dyn brioche_resources::ContentHasher>) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Record which files resources were added from in `stat_cache_dir`,
+    /// so later runs can reuse them without reading the files again. The
+    /// dir needs to be outside of the resource dir, since the cache
+    /// depends on the build machine. Without one, files are only reused
+    /// within a run.
+    pub fn with_stat_cache_dir(mut self, stat_cache_dir: PathBuf) -> Self {
+        self.stat_cache_dir = Some(stat_cache_dir);
+        self
+    }
+
+    /// Get the path of the stat cache entry for `stat_key`, for resources
+    /// added to `namespace` or outside of any namespace. Entries are kept
+    /// per hash function, since each names blobs differently.
+    fn stat_cache_path(&self, namespace: Option<&Path>, stat_key: &str) -> Option<PathBuf> {
+        let stat_cache_dir = self.stat_cache_dir.as_ref()?;
+        let cache_dir = match namespace {
+            Some(namespace) => stat_cache_dir.join(namespace),
+            None => stat_cache_dir.clone(),
+        };
+        Some(cache_dir.join(self.hasher.name()).join(stat_key))
+    }
+
+    /// Get the dir a resource was added to, and its path within that dir.
+    fn split_resource_path<'a>(&self, resource_path: &'a Path) -> (PathBuf, &'a Path) {
+        if let Some(namespace) = &self.namespace {
+            if let Ok(namespaced_path) = resource_path.strip_prefix(namespace) {
+                return (self.resource_dir.join(namespace), namespaced_path);
+            }
+        }
+
+        (self.resource_dir.clone(), resource_path)
+    }
+}
+
+impl ResourceSink for ResourceDirSink {
+    fn add_named_blob(
+        &self,
+        contents: &[u8],
+        executable: bool,
+        name: &Path,
+    ) -> eyre::Result<PathBuf> {
+        let Some(namespace) = &self.namespace else {
+            let resource_path = brioche_resources::add_named_blob_with_hasher(
+                &*self.hasher,
+                &self.resource_dir,
+                std::io::Cursor::new(contents),
+                executable,
+                name,
+            )?;
+            return Ok(resource_path);
+        };
+
+        let hash = self.hasher.hash(&mut &contents[..])?;
+        let existing_path = brioche_resources::named_blob_path_with_hash(&hash, executable, name);
+        if self.resource_dir.join(&existing_path).is_file() {
+            return Ok(existing_path);
+        }
+
+        let namespace_dir = self.resource_dir.join(namespace);
+        std::fs::create_dir_all(&namespace_dir)
+            .with_context(|| format!("failed to create dir {}", namespace_dir.display()))?;
+        let resource_path = brioche_resources::add_named_blob_with_hash(
+            &namespace_dir,
+            contents,
+            &hash,
+            executable,
+            name,
+        )?;
+        Ok(namespace.join(resource_path))
+    }
+
+    fn add_alias(&self, resource_path: &Path, name: &Path) -> eyre::Result<()> {
+        let (resource_dir, resource_path) = self.split_resource_path(resource_path);
+        brioche_resources::add_blob_alias(&resource_dir, resource_path, name)?;
+        Ok(())
+    }
+
+    fn add_build_id(&self, resource_path: &Path, build_id: &str) -> eyre::Result<()> {
+        let (resource_dir, resource_path) = self.split_resource_path(resource_path);
+        brioche_resources::add_build_id(&resource_dir, resource_path, build_id)?;
+        Ok(())
+    }
+
+    fn set_modified(&self, resource_path: &Path, time: std::time::SystemTime) -> eyre::Result<()> {
+        let path = self.resource_dir.join(resource_path);
+        std::fs::File::open(&path)
+            .and_then(|file| file.set_modified(time))
+            .with_context(|| format!("failed to set modification time of {path:?}"))?;
+        Ok(())
+    }
+
+    fn find_cached_blob(&self, stat_key: &str) -> eyre::Result<Option<PathBuf>> {
+        use std::os::unix::ffi::OsStringExt as _;
+
+        // Resources outside of the namespace can be reused, but ones from
+        // other namespaces can't, since those can be removed
+        let namespaces = self
+            .namespace
+            .as_deref()
+            .map(Some)
+            .into_iter()
+            .chain([None]);
+        for namespace in namespaces {
+            let Some(cache_path) = self.stat_cache_path(namespace, stat_key) else {
+                return Ok(None);
+            };
+            let resource_path = match std::fs::read(&cache_path) {
+                Ok(resource_path) => PathBuf::from(std::ffi::OsString::from_vec(resource_path)),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(error) => {
+                    return Err(error)
+                        .with_context(|| format!("failed to read {}", cache_path.display()));
+                }
+            };
+
+            // The resource could have been removed since it was cached
+            if self.resource_dir.join(&resource_path).is_file() {
+                return Ok(Some(resource_path));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn cache_blob(&self, stat_key: &str, resource_path: &Path) -> eyre::Result<()> {
+        use std::{io::Write as _, os::unix::ffi::OsStrExt as _};
+
+        let Some(cache_path) = self.stat_cache_path(self.namespace.as_deref(), stat_key) else {
+            return Ok(());
+        };
+        let cache_parent = cache_path
+            .parent()
+            .ok_or_eyre("failed to get stat cache dir")?;
+        std::fs::create_dir_all(cache_parent)
+            .with_context(|| format!("failed to create dir {}", cache_parent.display()))?;
+
+        let mut writer = brioche_resources::AtomicWriter::new(&cache_path, 0o666)
+            .with_context(|| format!("failed to create {}", cache_path.display()))?;
+        writer.write_all(resource_path.as_os_str().as_bytes())?;
+        writer.commit()?;
+        Ok(())
+    }
+}
+
+pub(crate) fn add_named_blob_from(
+    ctx: &AutopackContext,
+    path: &Path,
+    alias_name: Option<&Path>,
+    role: ResourceRole,
+) -> eyre::Result<PathBuf> {
+    use std::os::unix::prelude::PermissionsExt as _;
+
+    let alias_name = match alias_name {
+        Some(alias_name) => alias_name,
+        None => {
+            let filename = path
+                .file_name()
+                .ok_or_eyre("failed to get filename from path")?;
+            Path::new(filename)
+        }
+    };
+
+    let metadata = with_retries(ctx, path, || Ok(std::fs::metadata(path)?))?;
+    let permissions = metadata.permissions();
+    let mode = permissions.mode();
+    let is_executable = mode & 0o111 != 0;
+
+    // When planning, files that would have been autopacked already are
+    // added with their planned contents
+    if let Some(contents) = ctx.plan.and_then(|plan| plan.output_contents(path)) {
+        return add_named_blob(ctx, path, &contents, is_executable, alias_name, role);
+    }
+
+    // Reuse the resource if this file was already added during this run
+    let cache_key = ResourceCacheKey::new(path, alias_name, role, &metadata);
+    if let Some(resource_path) = ctx.resource_cache.get(&cache_key) {
+        record_added_resource(ctx, &resource_path);
+        return Ok(resource_path);
+    }
+
+    // Reuse the resource if an earlier run added it from the same file,
+    // without reading it. Files can't be reused without reading them when
+    // planning or when the resource filter needs their contents
+    let stat_key = cache_key.stat_key();
+    let can_reuse = ctx.plan.is_none() && ctx.config.resource_filter.is_none();
+    if can_reuse {
+        let cached_resource_path =
+            with_retries(ctx, path, || ctx.resource_sink.find_cached_blob(&stat_key))?;
+        if let Some(resource_path) = cached_resource_path {
+            record_added_resource(ctx, &resource_path);
+            set_resource_modified(ctx, path, &resource_path)?;
+            ctx.resource_cache.insert(cache_key, resource_path.clone());
+            return Ok(resource_path);
+        }
+    }
+
+    let contents = with_retries(ctx, path, || Ok(std::fs::read(path)?))?;
+    let resource_path = add_named_blob(ctx, path, &contents, is_executable, alias_name, role)?;
+    if can_reuse {
+        with_retries(ctx, path, || {
+            ctx.resource_sink.cache_blob(&stat_key, &resource_path)
+        })?;
+    }
+    ctx.resource_cache.insert(cache_key, resource_path.clone());
+    if let Some(plan) = ctx.plan {
+        plan.set_resource_file(&resource_path, path);
+    }
+    Ok(resource_path)
+}
+
+pub(crate) fn add_named_blob_from_source(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    role: ResourceRole,
+) -> eyre::Result<PathBuf> {
+    let filename = source
+        .path
+        .file_name()
+        .ok_or_eyre("failed to get filename from path")?;
+
+    let resource_path = add_named_blob(
+        ctx,
+        &source.path,
+        &source.contents,
+        source.is_executable,
+        Path::new(filename),
+        role,
+    )?;
+    if let Some(plan) = ctx.plan {
+        plan.set_resource_file(&resource_path, &source.path);
+    }
+    Ok(resource_path)
+}
+
+/// Add a resource read from `source_path`, which is the path reported if
+/// adding it needs to be retried. The resource filter is checked first.
+fn add_named_blob(
+    ctx: &AutopackContext,
+    source_path: &Path,
+    contents: &[u8],
+    executable: bool,
+    name: &Path,
+    role: ResourceRole,
+) -> eyre::Result<PathBuf> {
+    let verdict = match &ctx.config.resource_filter {
+        Some(filter) => filter
+            .check(source_path, contents, role)
+            .with_context(|| format!("failed to filter {role} resource {source_path:?}"))?,
+        None => ResourceVerdict::Keep,
+    };
+    let contents = match &verdict {
+        ResourceVerdict::Keep => contents,
+        ResourceVerdict::Rewrite { contents } => contents,
+        ResourceVerdict::Deny { reason } => {
+            return Err(crate::diagnostics::ResourceDenied {
+                path: source_path.to_owned(),
+                role,
+                reason: reason.clone(),
+            }
+            .into());
+        }
+    };
+
+    let resource_path = with_retries(ctx, source_path, || {
+        ctx.resource_sink.add_named_blob(contents, executable, name)
+    })?;
+    record_added_resource(ctx, &resource_path);
+    set_resource_modified(ctx, source_path, &resource_path)?;
+
+    Ok(resource_path)
+}
+
+/// Set the modification time of a resource added from `source_path`, if
+/// the config sets the times of resources.
+fn set_resource_modified(
+    ctx: &AutopackContext,
+    source_path: &Path,
+    resource_path: &Path,
+) -> eyre::Result<()> {
+    if ctx.config.timestamps == crate::OutputTimestamps::Current || ctx.plan.is_some() {
+        return Ok(());
+    }
+
+    let source_time = with_retries(ctx, source_path, || {
+        Ok(std::fs::metadata(source_path)?.modified()?)
+    })?;
+    if let Some(time) = ctx.config.timestamps.time(source_time) {
+        with_retries(ctx, resource_path, || {
+            ctx.resource_sink.set_modified(resource_path, time)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Add another name for a resource, see [`ResourceSink::add_alias`].
+#[cfg(feature = "elf")]
+pub(crate) fn add_alias(
+    ctx: &AutopackContext,
+    resource_path: &Path,
+    name: &Path,
+) -> eyre::Result<()> {
+    with_retries(ctx, resource_path, || {
+        ctx.resource_sink.add_alias(resource_path, name)
+    })
+}
+
+/// Index a resource by its GNU build ID, see [`ResourceSink::add_build_id`].
+#[cfg(feature = "elf")]
+pub(crate) fn add_build_id(
+    ctx: &AutopackContext,
+    resource_path: &Path,
+    build_id: &str,
+) -> eyre::Result<()> {
+    with_retries(ctx, resource_path, || {
+        ctx.resource_sink.add_build_id(resource_path, build_id)
+    })
+}
+
+fn record_added_resource(ctx: &AutopackContext, resource_path: &Path) {
+    if let Some(added_resources) = ctx.added_resources {
+        let mut added_resources = added_resources
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if !added_resources.iter().any(|added| added == resource_path) {
+            added_resources.push(resource_path.to_owned());
+        }
+    }
+}
+
+/// Resources added from files during one run, so files shared by many
+/// autopacked files (such as libc) are only read and hashed once. Files
+/// are identified by their path and metadata, so a file that changes
+/// during the run, such as by being autopacked, gets added again.
+#[derive(Default)]
+pub(crate) struct ResourceCache(Mutex<HashMap<ResourceCacheKey, PathBuf>>);
+
+impl ResourceCache {
+    fn get(&self, key: &ResourceCacheKey) -> Option<PathBuf> {
+        let resources = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        resources.get(key).cloned()
+    }
+
+    fn insert(&self, key: ResourceCacheKey, resource_path: PathBuf) {
+        let mut resources = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        resources.insert(key, resource_path);
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct ResourceCacheKey {
+    path: PathBuf,
+    alias_name: PathBuf,
+    role: ResourceRole,
+    dev: u64,
+    ino: u64,
+    len: u64,
+    mode: u32,
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+}
+
+impl ResourceCacheKey {
+    fn new(
+        path: &Path,
+        alias_name: &Path,
+        role: ResourceRole,
+        metadata: &std::fs::Metadata,
+    ) -> Self {
+        use std::os::unix::fs::MetadataExt as _;
+
+        Self {
+            path: path.to_owned(),
+            alias_name: alias_name.to_owned(),
+            role,
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            len: metadata.len(),
+            mode: metadata.mode(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+        }
+    }
+
+    /// A key identifying the file and its metadata across runs, for
+    /// [`ResourceSink::find_cached_blob`]. The path isn't part of the key,
+    /// so hard links to the same file share it.
+    fn stat_key(&self) -> String {
+        let Self {
+            path: _,
+            alias_name,
+            role: _,
+            dev,
+            ino,
+            len,
+            mode,
+            mtime,
+            ctime,
+        } = self;
+
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(alias_name.as_os_str().as_encoded_bytes());
+        hasher.update(b"\0");
+        hasher.update(format!("{dev}:{ino}:{len}:{mode}:{mtime:?}:{ctime:?}").as_bytes());
+        hasher.finalize().to_hex().to_string()
+    }
+}
@@ -0,0 +1,57 @@
+//! Retrying filesystem operations that fail with transient errors.
+
+use std::path::Path;
+
+use crate::{AutopackContext, AutopackWarning};
+
+/// Run `operation`, retrying it with backoff while it fails with a
+/// transient error, if the config has a [`RetryPolicy`](crate::RetryPolicy).
+/// Each retry is reported as a warning for `path`.
+pub(crate) fn with_retries<T>(
+    ctx: &AutopackContext,
+    path: &Path,
+    mut operation: impl FnMut() -> eyre::Result<T>,
+) -> eyre::Result<T> {
+    let Some(policy) = &ctx.config.retry else {
+        return operation();
+    };
+
+    let mut backoff = policy.initial_backoff;
+    let mut retry = 0;
+    loop {
+        match operation() {
+            Ok(value) => return Ok(value),
+            Err(error) if retry < policy.max_retries && is_transient(&error) => {
+                retry += 1;
+                ctx.warn(AutopackWarning::RetriedIoError {
+                    path: path.to_owned(),
+                    error: format!("{error:#}"),
+                    retry,
+                })?;
+
+                std::thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2).min(policy.max_backoff);
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Check if an error was caused by an I/O error that might not happen
+/// again, such as a stale file handle or an I/O error from a network
+/// filesystem.
+fn is_transient(error: &eyre::Report) -> bool {
+    use rustix::io::Errno;
+
+    error.chain().any(|cause| {
+        // Resource errors wrap I/O errors transparently, so they don't
+        // show up in the chain on their own
+        let io_error = match cause.downcast_ref::<brioche_resources::AddBlobError>() {
+            Some(brioche_resources::AddBlobError::IoError(io_error)) => Some(io_error),
+            None => cause.downcast_ref::<std::io::Error>(),
+        };
+        io_error
+            .and_then(Errno::from_io_error)
+            .is_some_and(|errno| matches!(errno, Errno::STALE | Errno::IO | Errno::TIMEDOUT))
+    })
+}
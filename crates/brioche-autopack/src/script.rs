@@ -0,0 +1,841 @@
+use std::path::Path;
+
+use bstr::{ByteSlice as _, ByteVec as _};
+use eyre::{Context as _, ContextCompat as _};
+
+use crate::{
+    detect::{AutopackKind, SourceFile},
+    diagnostics,
+    emit::PackBase,
+    jobs::PendingPaths,
+    report::{Outcome, SkipReason},
+    resources::{add_named_blob_from, add_named_blob_from_source, ResourceRole},
+    try_autopack_dependency, AutopackContext, AutopackWarning, ForwardArgs, ScriptConfig,
+};
+
+impl ScriptConfig {
+    /// Returns an iterator of environment variables for autopacked scripts.
+    /// Relative paths in the env vars will be adjusted for `output_path`,
+    /// so that the paths stay relative to `base_path`.
+    ///
+    /// For example, if `base_path` is `/output` and `output_path` is
+    /// `/output/bin/hello`, then relative paths will be prepended with
+    /// a `../` so that they stay relative to `/output`.
+    pub fn env_for_output_path<'a>(
+        &'a self,
+        output_path: &'a Path,
+    ) -> impl Iterator<Item = eyre::Result<(String, runnable_core::EnvValue)>> + 'a {
+        self.env.iter().map(|(key, env_value)| {
+            let env_value = match env_value {
+                runnable_core::EnvValue::Clear => env_value.clone(),
+                runnable_core::EnvValue::Inherit => env_value.clone(),
+                runnable_core::EnvValue::Set { value } => {
+                    let value = relative_template(value, self.base_path.as_deref(), output_path)?;
+                    runnable_core::EnvValue::Set { value }
+                }
+                runnable_core::EnvValue::Fallback { value } => {
+                    let value = relative_template(value, self.base_path.as_deref(), output_path)?;
+                    runnable_core::EnvValue::Fallback { value }
+                }
+                runnable_core::EnvValue::Prepend { value, separator } => {
+                    let value = relative_template(value, self.base_path.as_deref(), output_path)?;
+                    runnable_core::EnvValue::Prepend {
+                        value,
+                        separator: separator.clone(),
+                    }
+                }
+                runnable_core::EnvValue::Append { value, separator } => {
+                    let value = relative_template(value, self.base_path.as_deref(), output_path)?;
+                    runnable_core::EnvValue::Append {
+                        value,
+                        separator: separator.clone(),
+                    }
+                }
+            };
+            eyre::Ok((key.clone(), env_value))
+        })
+    }
+}
+
+fn relative_template(
+    value: &runnable_core::Template,
+    base_path: Option<&Path>,
+    output_path: &Path,
+) -> eyre::Result<runnable_core::Template> {
+    let Some(base_path) = base_path else {
+        return Ok(value.clone());
+    };
+    let output_path = base_path.join(output_path);
+    let output_dir = output_path
+        .parent()
+        .ok_or_else(|| eyre::eyre!("failed to get parent of output path"))?;
+
+    let components = value
+        .components
+        .iter()
+        .map(|component| -> eyre::Result<_> {
+            match component {
+                runnable_core::TemplateComponent::Literal { .. }
+                | runnable_core::TemplateComponent::Resource { .. }
+                | runnable_core::TemplateComponent::SelfPath => eyre::Ok(component.clone()),
+                runnable_core::TemplateComponent::RelativePath { path } => {
+                    // TODO: Handle path resolution in a cross-platform way.
+                    // This could change based on the host platform
+
+                    let path = path
+                        .to_path()
+                        .with_context(|| format!("failed to parse path {path:?}"))?;
+
+                    let full_path = base_path.join(path);
+                    let new_relative_path = pathdiff::diff_paths(full_path, output_dir)
+                        .context("failed to get path relative to output dir")?;
+                    let new_relative_path = <Vec<u8>>::from_path_buf(new_relative_path).map_err(
+                        |new_relative_path| {
+                            eyre::eyre!("failed to convert path {new_relative_path:?}")
+                        },
+                    )?;
+
+                    eyre::Ok(runnable_core::TemplateComponent::RelativePath {
+                        path: new_relative_path,
+                    })
+                }
+            }
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    Ok(runnable_core::Template { components })
+}
+
+pub(crate) fn autopack_script(
+    ctx: &AutopackContext,
+    source: &SourceFile,
+    output_path: &Path,
+    pending_paths: &PendingPaths,
+) -> eyre::Result<Outcome> {
+    let Some(script_config) = ctx.config.script.as_ref().filter(|config| config.enabled) else {
+        return Ok(Outcome::Skipped(SkipReason::Disabled {
+            kind: AutopackKind::Script,
+        }));
+    };
+
+    let Some(shebang) = Shebang::parse(&source.path, &source.contents)? else {
+        return Ok(Outcome::Skipped(SkipReason::Unsupported));
+    };
+    // `sh` scripts run with the configured POSIX shell, if there is one
+    let posix_shell = script_config
+        .posix_shell
+        .as_ref()
+        .filter(|_| shebang.command_name == "sh");
+    let command_name = match posix_shell {
+        Some(posix_shell) => &*posix_shell.command,
+        None => &*shebang.command_name,
+    };
+
+    let mut command = ctx.resolver.find_command(command_name)?;
+    if command.is_none() {
+        let fallbacks = script_config
+            .interpreter_fallbacks
+            .get(command_name)
+            .into_iter()
+            .flatten();
+        for fallback in fallbacks {
+            command = ctx.resolver.find_command(fallback)?;
+            if command.is_some() {
+                ctx.warn(AutopackWarning::SubstitutedInterpreter {
+                    path: source.path.clone(),
+                    requested: command_name.to_string(),
+                    used: fallback.clone(),
+                })?;
+                break;
+            }
+        }
+    }
+    let command = command.ok_or_else(|| diagnostics::ScriptCommandNotFound {
+        path: source.path.clone(),
+        command_name: command_name.to_string(),
+        shebang: shebang.named_source(&source.path),
+        span: shebang.span(shebang.command_name_raw),
+    })?;
+
+    // Autopack the command if it's pending
+    let command = try_autopack_dependency(ctx, &command, pending_paths)?;
+
+    let command_resource = add_named_blob_from(ctx, &command, None, ResourceRole::ScriptCommand)?;
+    let script_resource = add_named_blob_from_source(ctx, source, ResourceRole::Script)?;
+
+    let env_resource_paths = script_config
+        .env
+        .values()
+        .filter_map(|value| match value {
+            runnable_core::EnvValue::Clear => None,
+            runnable_core::EnvValue::Inherit => None,
+            runnable_core::EnvValue::Set { value } => Some(value),
+            runnable_core::EnvValue::Fallback { value } => Some(value),
+            runnable_core::EnvValue::Prepend {
+                value,
+                separator: _,
+            } => Some(value),
+            runnable_core::EnvValue::Append {
+                value,
+                separator: _,
+            } => Some(value),
+        })
+        .flat_map(|template| &template.components)
+        .filter_map(|component| match component {
+            runnable_core::TemplateComponent::Literal { .. }
+            | runnable_core::TemplateComponent::RelativePath { .. }
+            | runnable_core::TemplateComponent::SelfPath => None,
+            runnable_core::TemplateComponent::Resource { resource } => Some(
+                resource
+                    .to_path()
+                    .map_err(|_| eyre::eyre!("invalid resource path")),
+            ),
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let resource_paths = [command_resource.clone(), script_resource.clone()]
+        .into_iter()
+        .chain(env_resource_paths.into_iter().map(|path| path.to_owned()))
+        .map(|path| {
+            Vec::<u8>::from_path_buf(path).map_err(|_| eyre::eyre!("invalid resource path"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let command = runnable_core::Template::from_resource_path(command_resource)?;
+
+    let mut args = vec![];
+    let posix_shell_args = posix_shell.into_iter().flat_map(|shell| &shell.args);
+    for arg in posix_shell_args.cloned().chain(shebang.args) {
+        args.push(runnable_core::ArgValue::Arg {
+            value: runnable_core::Template::from_literal(arg.into()),
+        });
+    }
+    args.push(runnable_core::ArgValue::Arg {
+        value: runnable_core::Template::from_resource_path(script_resource.clone())?,
+    });
+    match &script_config.forward_args {
+        ForwardArgs::Rest => {
+            args.push(runnable_core::ArgValue::Rest);
+        }
+        ForwardArgs::RestAfterSeparator(separator) => {
+            args.push(runnable_core::ArgValue::RestAfterSeparator {
+                separator: separator.clone().into_bytes(),
+            });
+        }
+        ForwardArgs::None => {}
+    }
+
+    // Env vars from the shebang are set first, so the configured env can
+    // still override them
+    let env = shebang
+        .env
+        .into_iter()
+        .map(Ok)
+        .chain(script_config.env_for_output_path(&ctx.logical_path(output_path)))
+        .collect::<eyre::Result<_>>()?;
+
+    let fingerprint = script_config.fingerprint();
+    let runnable_pack = runnable_core::Runnable {
+        command,
+        args,
+        env,
+        clear_env: script_config.clear_env || shebang.clear_env,
+        library_dirs: vec![],
+        source: Some(runnable_core::RunnableSource {
+            path: runnable_core::RunnablePath::from_resource_path(script_resource)?,
+        }),
+        packer: Some(fingerprint.clone()),
+    };
+    let pack = crate::metadata_pack(resource_paths, &runnable_pack)?;
+
+    ctx.emit_pack(
+        output_path,
+        PackBase::PackedExecutable(&script_config.packed_executable),
+        &pack,
+        &fingerprint,
+    )?;
+
+    Ok(Outcome::Autopacked(AutopackKind::Script))
+}
+
+/// A script's shebang line, split into the command to run and the args to
+/// pass to it before the script.
+pub(crate) struct Shebang<'a> {
+    /// The shebang line without the `#!` prefix.
+    line: &'a str,
+
+    /// The command's name, without its path. For `env` shebangs, this is
+    /// the command run by `env`.
+    pub(crate) command_name: String,
+
+    /// The command name as written in the shebang, which can differ from
+    /// the command name if it was quoted in an `env -S` shebang.
+    command_name_raw: &'a str,
+
+    pub(crate) args: Vec<String>,
+    env: Vec<(String, runnable_core::EnvValue)>,
+    clear_env: bool,
+}
+
+impl<'a> Shebang<'a> {
+    /// Parse the shebang from a script's contents, or return `None` if it
+    /// doesn't start with one. `path` is only used for diagnostics.
+    pub(crate) fn parse(path: &Path, contents: &'a [u8]) -> eyre::Result<Option<Self>> {
+        let Some(script) = contents.strip_prefix(b"#!") else {
+            return Ok(None);
+        };
+        let line_end = script.find_byte(b'\n').unwrap_or(script.len());
+        let line = std::str::from_utf8(&script[..line_end])
+            .with_context(|| format!("invalid UTF-8 in shebang for script {}", path.display()))?
+            .trim();
+
+        let shebang_parts = line.split_once(|c: char| c.is_ascii_whitespace());
+        let (command_path, arg) = match shebang_parts {
+            Some((command_path, arg)) => (command_path.trim(), arg.trim()),
+            None => (line, ""),
+        };
+        let command_name = command_path
+            .split(['/', '\\'])
+            .next_back()
+            .unwrap_or(command_path);
+
+        let mut shebang = Self {
+            line,
+            command_name: command_name.to_string(),
+            command_name_raw: command_name,
+            args: vec![],
+            env: vec![],
+            clear_env: false,
+        };
+
+        if command_name != "env" {
+            if !arg.is_empty() {
+                shebang.args.push(arg.to_string());
+            }
+            return Ok(Some(shebang));
+        }
+
+        let env_shebang = parse_env_shebang(arg).map_err(|(reason, raw)| {
+            diagnostics::ScriptInvalidEnvShebang {
+                path: path.to_owned(),
+                reason,
+                shebang: shebang.named_source(path),
+                span: shebang.span(raw),
+            }
+        })?;
+        let env_command =
+            env_shebang
+                .command_name
+                .ok_or_else(|| diagnostics::ScriptMissingEnvCommand {
+                    path: path.to_owned(),
+                    shebang: shebang.named_source(path),
+                    span: shebang.span(command_name),
+                })?;
+        shebang.command_name = env_command.value;
+        shebang.command_name_raw = env_command.raw;
+        shebang.args = env_shebang.args.into_iter().map(|arg| arg.value).collect();
+        shebang.env = env_shebang.env;
+        shebang.clear_env = env_shebang.clear_env;
+
+        Ok(Some(shebang))
+    }
+
+    fn named_source(&self, path: &Path) -> miette::NamedSource<String> {
+        miette::NamedSource::new(path.display().to_string(), format!("#!{}", self.line))
+    }
+
+    /// Get the span of `part` of the shebang line, shifted by 2 to account
+    /// for the `#!` prefix.
+    fn span(&self, part: &str) -> miette::SourceSpan {
+        let span = diagnostics::subslice_span(self.line, part);
+        miette::SourceSpan::from((span.offset() + 2, span.len()))
+    }
+}
+
+/// The command and env vars from an `env` shebang, such as
+/// `#!/usr/bin/env -S PYTHONUNBUFFERED=1 python3 -E`.
+#[derive(Debug, Default)]
+struct EnvShebang<'a> {
+    command_name: Option<ShebangWord<'a>>,
+    args: Vec<ShebangWord<'a>>,
+    env: Vec<(String, runnable_core::EnvValue)>,
+    clear_env: bool,
+}
+
+#[derive(Debug)]
+struct ShebangWord<'a> {
+    value: String,
+
+    /// The word as written in the shebang, used for diagnostics.
+    raw: &'a str,
+}
+
+/// Parse the args passed to `env` in a shebang. On error, returns the
+/// reason along with the part of `arg` that caused it.
+fn parse_env_shebang(arg: &str) -> Result<EnvShebang<'_>, (String, &str)> {
+    let mut shebang = EnvShebang::default();
+
+    // Linux passes everything after `env` as a single arg, but other
+    // platforms split it on whitespace, so split it here either way
+    let mut words: Vec<_> = arg
+        .split_ascii_whitespace()
+        .map(|word| ShebangWord {
+            value: word.to_string(),
+            raw: word,
+        })
+        .collect();
+    let mut is_split_string = false;
+
+    let mut index = 0;
+    while let Some(word) = words.get(index) {
+        let raw_offset = diagnostics::subslice_span(arg, word.raw).offset();
+        let split_string_offset = match word.value.as_str() {
+            "-S" | "--split-string" => Some(raw_offset + word.raw.len()),
+            value if value.starts_with("--split-string=") => {
+                Some(raw_offset + "--split-string=".len())
+            }
+            value if value.starts_with("-S") => Some(raw_offset + "-S".len()),
+            _ => None,
+        };
+        if let Some(split_string_offset) = split_string_offset {
+            if is_split_string {
+                return Err(("`-S` can only be used once".to_string(), word.raw));
+            }
+
+            words = split_string(&arg[split_string_offset..])?;
+            is_split_string = true;
+            index = 0;
+            continue;
+        }
+
+        match word.value.as_str() {
+            "-i" | "-" | "--ignore-environment" => {
+                shebang.clear_env = true;
+            }
+            "-u" | "--unset" => {
+                index += 1;
+                let name = words
+                    .get(index)
+                    .ok_or_else(|| ("expected a name after this".to_string(), word.raw))?;
+                shebang
+                    .env
+                    .push((name.value.clone(), runnable_core::EnvValue::Clear));
+            }
+            value if value.starts_with("--unset=") || value.starts_with("-u") => {
+                let name = value
+                    .strip_prefix("--unset=")
+                    .or_else(|| value.strip_prefix("-u"))
+                    .unwrap_or_default();
+                shebang
+                    .env
+                    .push((name.to_string(), runnable_core::EnvValue::Clear));
+            }
+            "--" => {
+                index += 1;
+                break;
+            }
+            value if value.starts_with('-') => {
+                return Err(("unsupported `env` option".to_string(), word.raw));
+            }
+            _ => {
+                break;
+            }
+        }
+
+        index += 1;
+    }
+
+    let mut words = words.into_iter().skip(index).peekable();
+
+    while let Some(word) = words
+        .next_if(|word| matches!(word.value.split_once('='), Some((name, _)) if !name.is_empty()))
+    {
+        let (name, value) = word.value.split_once('=').unwrap_or_default();
+        shebang.env.push((
+            name.to_string(),
+            runnable_core::EnvValue::Set {
+                value: runnable_core::Template::from_literal(value.into()),
+            },
+        ));
+    }
+
+    shebang.command_name = words.next();
+    shebang.args = words.collect();
+
+    Ok(shebang)
+}
+
+/// Split `text` into words the same way as `env -S`. Words are separated
+/// by whitespace or `\_`, and can be quoted with single or double quotes
+/// or use backslash escapes. Within double quotes, `\_` is a space instead.
+/// A `#` at the start of a word starts a comment, and `\c` outside of quotes
+/// ends the string.
+fn split_string(text: &str) -> Result<Vec<ShebangWord<'_>>, (String, &str)> {
+    let mut words = vec![];
+    let mut chars = text.char_indices().peekable();
+
+    loop {
+        loop {
+            if chars.next_if(|(_, c)| c.is_ascii_whitespace()).is_some() {
+                continue;
+            }
+            match chars.peek() {
+                Some(&(index, '\\')) if text[index..].starts_with("\\_") => {
+                    chars.next();
+                    chars.next();
+                }
+                _ => break,
+            }
+        }
+
+        let Some(&(start, c)) = chars.peek() else {
+            break;
+        };
+        if c == '#' {
+            break;
+        }
+
+        let mut value = String::new();
+        let mut is_end = false;
+        let mut separator_index = None;
+        while let Some((index, c)) = chars.next_if(|(_, c)| !c.is_ascii_whitespace()) {
+            match c {
+                '\\' if chars.next_if(|(_, c)| *c == 'c').is_some() => {
+                    is_end = true;
+                    break;
+                }
+                '\\' if chars.next_if(|(_, c)| *c == '_').is_some() => {
+                    separator_index = Some(index);
+                    break;
+                }
+                '\\' => {
+                    let (_, escaped) = chars
+                        .next()
+                        .ok_or_else(|| ("unterminated escape".to_string(), &text[index..]))?;
+                    let escaped = unescape(escaped).ok_or_else(|| {
+                        let end = index + 1 + escaped.len_utf8();
+                        ("unsupported escape".to_string(), &text[index..end])
+                    })?;
+                    value.push(escaped);
+                }
+                '\'' => loop {
+                    let (_, c) = chars
+                        .next()
+                        .ok_or_else(|| ("unterminated single quote".to_string(), &text[index..]))?;
+                    match c {
+                        '\'' => break,
+                        '\\' => {
+                            // Only backslashes and single quotes can be
+                            // escaped within single quotes
+                            let escaped = chars.next_if(|(_, c)| matches!(c, '\\' | '\''));
+                            value.push(escaped.map_or('\\', |(_, escaped)| escaped));
+                        }
+                        c => value.push(c),
+                    }
+                },
+                '"' => loop {
+                    let (escape_index, c) = chars
+                        .next()
+                        .ok_or_else(|| ("unterminated double quote".to_string(), &text[index..]))?;
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            let (_, escaped) = chars.next().ok_or_else(|| {
+                                ("unterminated double quote".to_string(), &text[index..])
+                            })?;
+                            let unescaped = match escaped {
+                                '_' => Some(' '),
+                                escaped => unescape(escaped),
+                            };
+                            let escaped = unescaped.ok_or_else(|| {
+                                let end = escape_index + 1 + escaped.len_utf8();
+                                ("unsupported escape".to_string(), &text[escape_index..end])
+                            })?;
+                            value.push(escaped);
+                        }
+                        '$' => {
+                            return Err((
+                                "variable expansion is not supported".to_string(),
+                                &text[escape_index..escape_index + 1],
+                            ));
+                        }
+                        c => value.push(c),
+                    }
+                },
+                '$' => {
+                    return Err((
+                        "variable expansion is not supported".to_string(),
+                        &text[index..index + 1],
+                    ));
+                }
+                c => value.push(c),
+            }
+        }
+
+        let end = separator_index
+            .or_else(|| chars.peek().map(|(index, _)| *index))
+            .unwrap_or(text.len());
+        let raw = &text[start..end];
+        if is_end {
+            // The rest of the string is ignored, including anything after
+            // `\c` in the same word
+            if raw != "\\c" {
+                words.push(ShebangWord { value, raw });
+            }
+            break;
+        }
+
+        words.push(ShebangWord { value, raw });
+    }
+
+    Ok(words)
+}
+
+/// Get the character for an escape sequence supported by `env -S`.
+fn unescape(c: char) -> Option<char> {
+    match c {
+        '\\' | '\'' | '"' | '#' | '$' => Some(c),
+        'f' => Some('\x0c'),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        'v' => Some('\x0b'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse_env_shebang, split_string, Shebang};
+
+    fn split(text: &str) -> Result<Vec<String>, String> {
+        let words = split_string(text).map_err(|(message, _)| message)?;
+        Ok(words.into_iter().map(|word| word.value).collect())
+    }
+
+    /// Format env vars from a shebang as `NAME=value`, or `-NAME` for
+    /// unset vars.
+    fn env_strings(env: &[(String, runnable_core::EnvValue)]) -> Vec<String> {
+        env.iter()
+            .map(|(name, value)| match value {
+                runnable_core::EnvValue::Clear => format!("-{name}"),
+                runnable_core::EnvValue::Set { value } => {
+                    let value: Vec<u8> = value
+                        .components
+                        .iter()
+                        .flat_map(|component| match component {
+                            runnable_core::TemplateComponent::Literal { value } => value.clone(),
+                            component => panic!("unexpected component {component:?}"),
+                        })
+                        .collect();
+                    format!("{name}={}", String::from_utf8_lossy(&value))
+                }
+                value => panic!("unexpected env value {value:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_string() {
+        let cases: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("  python3  -E ", &["python3", "-E"]),
+            (r"a\_b", &["a", "b"]),
+            (r"\_\_a\_\_b\_", &["a", "b"]),
+            (r#""a\_b""#, &["a b"]),
+            (r"'a\_b'", &[r"a\_b"]),
+            (r#"'it\'s' "say \"hi\"""#, &["it's", r#"say "hi""#]),
+            (r"a\tb", &["a\tb"]),
+            (r#"a "" b"#, &["a", "", "b"]),
+            ("a #comment", &["a"]),
+            ("a#b", &["a#b"]),
+            (r"a \c b", &["a"]),
+            (r"ab\c d", &["ab"]),
+        ];
+
+        for (text, expected) in cases {
+            let words = split(text).unwrap_or_else(|error| panic!("{text:?}: {error}"));
+            assert_eq!(words, *expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn test_split_string_errors() {
+        let cases = [
+            (r"a\", "unterminated escape"),
+            ("'a", "unterminated single quote"),
+            (r#""a"#, "unterminated double quote"),
+            (r"\q", "unsupported escape"),
+            ("$HOME", "variable expansion is not supported"),
+            (r#""${HOME}""#, "variable expansion is not supported"),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(split(text), Err(expected.to_string()), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_env_shebang() {
+        // The shebang arg, then the expected env, command name, and args
+        type Case = (
+            &'static str,
+            &'static [&'static str],
+            Option<&'static str>,
+            &'static [&'static str],
+        );
+        let cases: &[Case] = &[
+            ("python3", &[], Some("python3"), &[]),
+            ("python3 -E", &[], Some("python3"), &["-E"]),
+            ("-S python3 -E", &[], Some("python3"), &["-E"]),
+            (r"-Spython3\_-E", &[], Some("python3"), &["-E"]),
+            ("--split-string=python3 -E", &[], Some("python3"), &["-E"]),
+            (
+                "-S A=1 B= python3 C=2",
+                &["A=1", "B="],
+                Some("python3"),
+                &["C=2"],
+            ),
+            (
+                "-u A -uB --unset=C sh",
+                &["-A", "-B", "-C"],
+                Some("sh"),
+                &[],
+            ),
+            ("-i A=1 sh", &["A=1"], Some("sh"), &[]),
+            ("-- -sh", &[], Some("-sh"), &[]),
+            ("=x sh", &[], Some("=x"), &["sh"]),
+            ("A=1", &["A=1"], None, &[]),
+        ];
+
+        for (arg, expected_env, expected_command, expected_args) in cases {
+            let shebang =
+                parse_env_shebang(arg).unwrap_or_else(|(message, _)| panic!("{arg:?}: {message}"));
+            let env = env_strings(&shebang.env);
+            let command = shebang.command_name.as_ref().map(|word| &*word.value);
+            let args: Vec<_> = shebang.args.iter().map(|word| &*word.value).collect();
+
+            assert_eq!(env, *expected_env, "{arg:?}");
+            assert_eq!(command, *expected_command, "{arg:?}");
+            assert_eq!(args, *expected_args, "{arg:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_env_shebang_errors() {
+        let cases = [
+            ("-S -S a", "`-S` can only be used once", "-S"),
+            ("-u", "expected a name after this", "-u"),
+            ("--chdir=/ sh", "unsupported `env` option", "--chdir=/"),
+        ];
+
+        for (arg, expected_message, expected_raw) in cases {
+            let (message, raw) = parse_env_shebang(arg).expect_err(arg);
+            assert_eq!(message, expected_message, "{arg:?}");
+            assert_eq!(raw, expected_raw, "{arg:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_shebang_corpus() {
+        // Shebang lines from real scripts, then the expected env, command
+        // name, and args
+        type Case = (
+            &'static str,
+            &'static [&'static str],
+            &'static str,
+            &'static [&'static str],
+        );
+        let cases: &[Case] = &[
+            // Nix and Guix store paths
+            (
+                "#!/nix/store/4bj2kxdm1462fzcc2i2s4dn33g2angcc-bash-5.2p32/bin/bash",
+                &[],
+                "bash",
+                &[],
+            ),
+            (
+                "#!/nix/store/4bj2kxdm1462fzcc2i2s4dn33g2angcc-bash-5.2p32/bin/bash -e",
+                &[],
+                "bash",
+                &["-e"],
+            ),
+            (
+                "#!/nix/store/qp5zys77biz7imbk6yy85q5pdv7qk84j-python3-3.11.9/bin/python3.11",
+                &[],
+                "python3.11",
+                &[],
+            ),
+            (
+                "#!/gnu/store/2lczkxbdbzh4gk7wh91bzrqrk7h5g1dl-bash-minimal-5.1.16/bin/sh",
+                &[],
+                "sh",
+                &[],
+            ),
+            (
+                "#!/gnu/store/8wv5a1hmjzjkrnsyh4rfx2ql9fa1rmz4-guile-3.0.9/bin/guile --no-auto-compile",
+                &[],
+                "guile",
+                &["--no-auto-compile"],
+            ),
+            ("#!/usr/bin/env nix-shell", &[], "nix-shell", &[]),
+            // Interpreters with args, which are passed as a single arg
+            ("#!/usr/bin/python3 -u", &[], "python3", &["-u"]),
+            ("#!/usr/bin/python3 -Es", &[], "python3", &["-Es"]),
+            ("#!/usr/bin/perl -w", &[], "perl", &["-w"]),
+            ("#!/usr/bin/perl -wT", &[], "perl", &["-wT"]),
+            ("#!/usr/bin/perl -w -T", &[], "perl", &["-w -T"]),
+            ("#!/usr/bin/awk -f", &[], "awk", &["-f"]),
+            ("#!/bin/sh -", &[], "sh", &["-"]),
+            ("#! /bin/sh", &[], "sh", &[]),
+            ("#!/bin/bash\r\necho hi\r\n", &[], "bash", &[]),
+            // `env` forms
+            ("#!/usr/bin/env python3", &[], "python3", &[]),
+            ("#!/usr/bin/env -S python3 -u", &[], "python3", &["-u"]),
+            ("#!/usr/bin/env -S perl -w", &[], "perl", &["-w"]),
+            (
+                "#!/usr/bin/env -S bash -euo pipefail",
+                &[],
+                "bash",
+                &["-euo", "pipefail"],
+            ),
+            ("#!/usr/bin/env -S uv run --script", &[], "uv", &["run", "--script"]),
+            (
+                "#!/usr/bin/env -S deno run --allow-net --allow-read",
+                &[],
+                "deno",
+                &["run", "--allow-net", "--allow-read"],
+            ),
+            (
+                "#!/usr/bin/env -S NODE_OPTIONS=--enable-source-maps node",
+                &["NODE_OPTIONS=--enable-source-maps"],
+                "node",
+                &[],
+            ),
+            (
+                "#!/usr/bin/env -S -i PATH=/usr/bin:/bin sh",
+                &["PATH=/usr/bin:/bin"],
+                "sh",
+                &[],
+            ),
+            (
+                "#!/nix/store/1n7w2s8xpq2mmd4b1p0g1hmgxpyfmxg8-coreutils-9.5/bin/env -S python3 -u",
+                &[],
+                "python3",
+                &["-u"],
+            ),
+        ];
+
+        for (line, expected_env, expected_command, expected_args) in cases {
+            let shebang = Shebang::parse(Path::new("script"), line.as_bytes())
+                .unwrap_or_else(|error| panic!("{line:?}: {error}"))
+                .unwrap_or_else(|| panic!("{line:?}: no shebang"));
+            let args: Vec<_> = shebang.args.iter().map(|arg| &**arg).collect();
+
+            assert_eq!(env_strings(&shebang.env), *expected_env, "{line:?}");
+            assert_eq!(shebang.command_name, *expected_command, "{line:?}");
+            assert_eq!(args, *expected_args, "{line:?}");
+        }
+    }
+}
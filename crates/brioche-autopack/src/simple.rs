@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+
+use eyre::Context as _;
+
+#[cfg(any(feature = "elf", feature = "script"))]
+use crate::PackedExecutable;
+#[cfg(feature = "script")]
+use crate::ScriptConfig;
+use crate::{AutopackConfig, AutopackInputs, AutopackReport};
+#[cfg(feature = "elf")]
+use crate::{DynamicBinaryConfig, SharedLibraryConfig};
+
+/// A shorthand for the common case of autopacking files in a recipe in
+/// place, using the default config for each kind of file. For example,
+/// `Autopack::new(recipe).glob("bin/**").dependency(dep).dynamic(packed_exec).run()`
+/// autopacks the dynamic binaries under `bin/` using libraries from `dep`.
+/// The resource dir is found from the recipe. Use
+/// [`AutopackConfig::builder`] for anything else.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct Autopack {
+    recipe: PathBuf,
+    globs: Vec<String>,
+    exclude_globs: Vec<String>,
+    dependencies: Vec<PathBuf>,
+    quiet: bool,
+    #[cfg(feature = "elf")]
+    dynamic: Option<PackedExecutable>,
+    #[cfg(feature = "elf")]
+    shared_libraries: bool,
+    #[cfg(feature = "script")]
+    script: Option<PackedExecutable>,
+}
+
+impl Autopack {
+    pub fn new(recipe: impl Into<PathBuf>) -> Self {
+        Self {
+            recipe: recipe.into(),
+            globs: vec![],
+            exclude_globs: vec![],
+            dependencies: vec![],
+            quiet: false,
+            #[cfg(feature = "elf")]
+            dynamic: None,
+            #[cfg(feature = "elf")]
+            shared_libraries: false,
+            #[cfg(feature = "script")]
+            script: None,
+        }
+    }
+
+    /// Autopack files matching `pattern`, relative to the recipe. If no
+    /// globs are added, every file in the recipe is autopacked.
+    pub fn glob(mut self, pattern: impl Into<String>) -> Self {
+        self.globs.push(pattern.into());
+        self
+    }
+
+    /// Skip files matching `pattern`, relative to the recipe, even if they
+    /// match a glob.
+    pub fn exclude_glob(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude_globs.push(pattern.into());
+        self
+    }
+
+    /// Add a link dependency, which dependencies are found from.
+    pub fn dependency(mut self, dependency: impl Into<PathBuf>) -> Self {
+        self.dependencies.push(dependency.into());
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Autopack dynamic binaries, using `packed_executable` as the launcher.
+    #[cfg(feature = "elf")]
+    pub fn dynamic(mut self, packed_executable: PackedExecutable) -> Self {
+        self.dynamic = Some(packed_executable);
+        self
+    }
+
+    #[cfg(feature = "elf")]
+    pub fn shared_libraries(mut self) -> Self {
+        self.shared_libraries = true;
+        self
+    }
+
+    /// Autopack scripts, using `packed_executable` as the launcher.
+    #[cfg(feature = "script")]
+    pub fn script(mut self, packed_executable: PackedExecutable) -> Self {
+        self.script = Some(packed_executable);
+        self
+    }
+
+    /// Get the full config, e.g. to change options that aren't covered
+    /// here before autopacking.
+    pub fn config(self) -> eyre::Result<AutopackConfig> {
+        let Self {
+            recipe,
+            mut globs,
+            exclude_globs,
+            dependencies,
+            quiet,
+            #[cfg(feature = "elf")]
+            dynamic,
+            #[cfg(feature = "elf")]
+            shared_libraries,
+            #[cfg(feature = "script")]
+            script,
+        } = self;
+
+        if globs.is_empty() {
+            globs.push("**".to_string());
+        }
+
+        // Finding a resource dir takes a program path, but only uses its
+        // parent directory
+        let program = recipe.join("program");
+        let resource_dir = brioche_resources::find_output_resource_dir(&program)
+            .with_context(|| format!("failed to find resource dir for {recipe:?}"))?;
+        let all_resource_dirs = brioche_resources::find_resource_dirs(&program, true)?;
+
+        let inputs = AutopackInputs::Globs {
+            base_path: recipe,
+            patterns: globs,
+            exclude_patterns: exclude_globs,
+        };
+        let builder = AutopackConfig::builder(resource_dir, inputs)
+            .all_resource_dirs(all_resource_dirs)
+            .quiet(quiet)
+            .link_dependencies(dependencies);
+
+        #[cfg(feature = "elf")]
+        let builder = match dynamic {
+            Some(packed_executable) => {
+                builder.dynamic_binary(DynamicBinaryConfig::new(packed_executable))
+            }
+            None => builder,
+        };
+
+        #[cfg(feature = "elf")]
+        let builder = if shared_libraries {
+            builder.shared_library(SharedLibraryConfig::default())
+        } else {
+            builder
+        };
+
+        #[cfg(feature = "script")]
+        let builder = match script {
+            Some(packed_executable) => builder.script(ScriptConfig::new(packed_executable)),
+            None => builder,
+        };
+
+        let config = builder.build()?;
+        Ok(config)
+    }
+
+    pub fn run(self) -> eyre::Result<AutopackReport> {
+        let config = self.config()?;
+        crate::autopack(&config)
+    }
+}
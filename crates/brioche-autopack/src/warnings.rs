@@ -0,0 +1,309 @@
+//! Non-fatal problems found while autopacking.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A non-fatal problem found while autopacking a file, such as a library
+/// that was skipped. Warnings are reported through a [`WarningPolicy`],
+/// which can also turn them into errors.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[non_exhaustive]
+pub enum AutopackWarning {
+    /// A library couldn't be found, so it was left out because unknown
+    /// libraries are skipped.
+    SkippedUnknownLibrary { path: PathBuf, name: String },
+
+    /// A script's command couldn't be found, so one of its interpreter
+    /// fallbacks was used instead.
+    SubstitutedInterpreter {
+        path: PathBuf,
+        requested: String,
+        used: String,
+    },
+
+    /// A dynamic binary searches a path on the host at runtime, so it
+    /// depends on more than its link dependencies.
+    NonHermeticPath { path: PathBuf, host_path: PathBuf },
+
+    /// A static binary loads libraries with `dlopen`, so it may not find
+    /// them once relocated. See
+    /// [`DynamicBinaryConfig::static_dlopen`](crate::DynamicBinaryConfig::static_dlopen).
+    StaticDlopen { path: PathBuf },
+
+    /// A symlink in a library or command search path points to a file
+    /// that doesn't exist, so it was skipped. Each one is reported once,
+    /// after autopacking finishes.
+    DanglingSymlink { path: PathBuf, target: PathBuf },
+
+    /// A library resolved to a different build than the one the build
+    /// linked against, by comparing their GNU build IDs. See
+    /// [`DynamicLinkingConfig::build_libraries`](crate::DynamicLinkingConfig::build_libraries).
+    LibraryMismatch {
+        path: PathBuf,
+        library: String,
+        resolved_path: PathBuf,
+        build_path: PathBuf,
+    },
+
+    /// A file had setuid or setgid bits or file capabilities, which were
+    /// removed from the autopacked file. See
+    /// [`PrivilegePolicy::Strip`](crate::PrivilegePolicy::Strip).
+    StrippedPrivileges {
+        path: PathBuf,
+        setuid: bool,
+        setgid: bool,
+        capabilities: bool,
+    },
+
+    /// Reading a file or writing a resource failed with a transient error,
+    /// so it was retried. See [`AutopackConfig::retry`](crate::AutopackConfig::retry).
+    RetriedIoError {
+        path: PathBuf,
+        error: String,
+        retry: u32,
+    },
+
+    /// An ELF file uses dynamic symbols that no library in its closure
+    /// defines, so it may fail to load. See
+    /// [`DynamicLinkingConfig::check_symbols`](crate::DynamicLinkingConfig::check_symbols).
+    MissingSymbols { path: PathBuf, symbols: Vec<String> },
+
+    /// An input glob pattern didn't match any files, so it may have a typo.
+    /// The path is the base path the pattern is matched relative to. See
+    /// [`AutopackInputs::Globs`](crate::AutopackInputs::Globs).
+    UnmatchedGlobPattern { path: PathBuf, pattern: String },
+}
+
+impl AutopackWarning {
+    pub fn kind(&self) -> WarningKind {
+        match self {
+            Self::SkippedUnknownLibrary { .. } => WarningKind::SkippedUnknownLibrary,
+            Self::SubstitutedInterpreter { .. } => WarningKind::SubstitutedInterpreter,
+            Self::NonHermeticPath { .. } => WarningKind::NonHermeticPath,
+            Self::StaticDlopen { .. } => WarningKind::StaticDlopen,
+            Self::DanglingSymlink { .. } => WarningKind::DanglingSymlink,
+            Self::LibraryMismatch { .. } => WarningKind::LibraryMismatch,
+            Self::StrippedPrivileges { .. } => WarningKind::StrippedPrivileges,
+            Self::RetriedIoError { .. } => WarningKind::RetriedIoError,
+            Self::MissingSymbols { .. } => WarningKind::MissingSymbols,
+            Self::UnmatchedGlobPattern { .. } => WarningKind::UnmatchedGlobPattern,
+        }
+    }
+
+    /// The path of the file being autopacked, of the dangling symlink, of
+    /// the file or resource that was retried, or that an unmatched glob
+    /// pattern is relative to.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::SkippedUnknownLibrary { path, .. }
+            | Self::SubstitutedInterpreter { path, .. }
+            | Self::NonHermeticPath { path, .. }
+            | Self::StaticDlopen { path }
+            | Self::DanglingSymlink { path, .. }
+            | Self::LibraryMismatch { path, .. }
+            | Self::StrippedPrivileges { path, .. }
+            | Self::RetriedIoError { path, .. }
+            | Self::MissingSymbols { path, .. }
+            | Self::UnmatchedGlobPattern { path, .. } => path,
+        }
+    }
+}
+
+impl std::fmt::Display for AutopackWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SkippedUnknownLibrary { path, name } => {
+                write!(f, "{}: skipped unknown library {name:?}", path.display())
+            }
+            Self::SubstitutedInterpreter {
+                path,
+                requested,
+                used,
+            } => write!(
+                f,
+                "{}: using {used} in place of {requested}",
+                path.display()
+            ),
+            Self::NonHermeticPath { path, host_path } => write!(
+                f,
+                "{}: searches host path {} at runtime",
+                path.display(),
+                host_path.display()
+            ),
+            Self::StaticDlopen { path } => write!(
+                f,
+                "{}: static binary loads libraries with dlopen, so they may not be found",
+                path.display()
+            ),
+            Self::DanglingSymlink { path, target } => write!(
+                f,
+                "{}: skipped dangling symlink to {}",
+                path.display(),
+                target.display()
+            ),
+            Self::LibraryMismatch {
+                path,
+                library,
+                resolved_path,
+                build_path,
+            } => write!(
+                f,
+                "{}: {library} resolved to {}, which has a different build ID than {} from the build",
+                path.display(),
+                resolved_path.display(),
+                build_path.display()
+            ),
+            Self::StrippedPrivileges {
+                path,
+                setuid,
+                setgid,
+                capabilities,
+            } => {
+                let privileges = [
+                    (setuid, "setuid bit"),
+                    (setgid, "setgid bit"),
+                    (capabilities, "file capabilities"),
+                ]
+                .into_iter()
+                .filter_map(|(removed, name)| removed.then_some(name))
+                .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "{}: removed {} from the autopacked file",
+                    path.display(),
+                    privileges.join(", ")
+                )
+            }
+            Self::RetriedIoError { path, error, retry } => write!(
+                f,
+                "{}: retrying after transient error (retry {retry}): {error}",
+                path.display()
+            ),
+            Self::MissingSymbols { path, symbols } => write!(
+                f,
+                "{}: no library in the closure defines {}",
+                path.display(),
+                symbols.join(", ")
+            ),
+            Self::UnmatchedGlobPattern { path, pattern } => write!(
+                f,
+                "{}: glob pattern {pattern:?} didn't match any files",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// The kind of an [`AutopackWarning`], used to deny warnings selectively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum WarningKind {
+    SkippedUnknownLibrary,
+    SubstitutedInterpreter,
+    NonHermeticPath,
+    StaticDlopen,
+    DanglingSymlink,
+    LibraryMismatch,
+    StrippedPrivileges,
+    RetriedIoError,
+    MissingSymbols,
+    UnmatchedGlobPattern,
+}
+
+impl WarningKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SkippedUnknownLibrary => "skippedUnknownLibrary",
+            Self::SubstitutedInterpreter => "substitutedInterpreter",
+            Self::NonHermeticPath => "nonHermeticPath",
+            Self::StaticDlopen => "staticDlopen",
+            Self::DanglingSymlink => "danglingSymlink",
+            Self::LibraryMismatch => "libraryMismatch",
+            Self::StrippedPrivileges => "strippedPrivileges",
+            Self::RetriedIoError => "retriedIoError",
+            Self::MissingSymbols => "missingSymbols",
+            Self::UnmatchedGlobPattern => "unmatchedGlobPattern",
+        }
+    }
+}
+
+impl std::fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub type WarningCallback = dyn Fn(&AutopackWarning) + Send + Sync;
+
+/// How warnings are reported. By default, warnings are printed unless
+/// autopacking is quiet.
+#[derive(Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WarningPolicy {
+    /// Called with each warning instead of printing it.
+    #[serde(skip)]
+    pub callback: Option<Arc<WarningCallback>>,
+
+    /// Kinds of warnings that fail autopacking instead.
+    #[serde(serialize_with = "crate::config::serialize_sorted_set")]
+    pub denied: HashSet<WarningKind>,
+}
+
+impl std::fmt::Debug for WarningPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WarningPolicy")
+            .field("callback", &self.callback.as_ref().map(|_| "<fn>"))
+            .field("denied", &self.denied)
+            .finish()
+    }
+}
+
+/// Dangling symlinks found in search paths, shared between workers. Paths
+/// already found to be dangling aren't checked again, and each one is only
+/// reported once.
+#[cfg(any(feature = "elf", feature = "script"))]
+#[derive(Debug, Default)]
+pub(crate) struct DanglingSymlinks(std::sync::Mutex<std::collections::BTreeMap<PathBuf, PathBuf>>);
+
+#[cfg(any(feature = "elf", feature = "script"))]
+impl DanglingSymlinks {
+    /// Check if `path` is a dangling symlink, recording it if it is.
+    pub fn check(&self, path: &Path) -> bool {
+        if self.lock().contains_key(path) {
+            return true;
+        }
+
+        let is_symlink = std::fs::symlink_metadata(path).is_ok_and(|meta| meta.is_symlink());
+        if !is_symlink || path.exists() {
+            return false;
+        }
+
+        let target = std::fs::read_link(path).unwrap_or_default();
+        self.lock().insert(path.to_owned(), target);
+        true
+    }
+
+    /// Get a warning for each dangling symlink found so far.
+    pub fn warnings(&self) -> Vec<AutopackWarning> {
+        self.lock()
+            .iter()
+            .map(|(path, target)| AutopackWarning::DanglingSymlink {
+                path: path.clone(),
+                target: target.clone(),
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, std::collections::BTreeMap<PathBuf, PathBuf>> {
+        self.0.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
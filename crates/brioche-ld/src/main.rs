@@ -1,4 +1,4 @@
-use std::{collections::HashSet, path::PathBuf, process::ExitCode};
+use std::{path::PathBuf, process::ExitCode};
 
 use bstr::ByteSlice as _;
 use eyre::{Context as _, OptionExt as _};
@@ -126,30 +126,28 @@ fn run() -> eyre::Result<ExitCode> {
             resource_dir,
             all_resource_dirs,
         } => {
-            let dynamic_linking_config = brioche_autopack::DynamicLinkingConfig {
-                library_paths: library_search_paths,
-                skip_libraries: HashSet::new(),
-                extra_libraries: vec![],
-                skip_unknown_libraries: skip_unknown_libs,
-            };
-            brioche_autopack::autopack(&brioche_autopack::AutopackConfig {
+            let mut dynamic_linking_config = brioche_autopack::DynamicLinkingConfig::default();
+            dynamic_linking_config.library_paths = library_search_paths;
+            dynamic_linking_config.skip_unknown_libraries = skip_unknown_libs;
+
+            let mut dynamic_binary_config = brioche_autopack::DynamicBinaryConfig::new(packed_path);
+            dynamic_binary_config.dynamic_linking = dynamic_linking_config.clone();
+
+            let mut shared_library_config = brioche_autopack::SharedLibraryConfig::default();
+            shared_library_config.dynamic_linking = dynamic_linking_config;
+            shared_library_config.allow_empty = true;
+
+            let autopack_config = brioche_autopack::AutopackConfig::builder(
                 resource_dir,
-                all_resource_dirs,
-                inputs: brioche_autopack::AutopackInputs::Paths(vec![output_path]),
-                quiet: true,
-                link_dependencies: vec![ld_resource_dir],
-                dynamic_binary: Some(brioche_autopack::DynamicBinaryConfig {
-                    packed_executable: packed_path,
-                    extra_runtime_library_paths: vec![],
-                    dynamic_linking: dynamic_linking_config.clone(),
-                }),
-                shared_library: Some(brioche_autopack::SharedLibraryConfig {
-                    dynamic_linking: dynamic_linking_config,
-                    allow_empty: true,
-                }),
-                repack: None,
-                script: None,
-            })?;
+                brioche_autopack::AutopackInputs::Paths(vec![output_path]),
+            )
+            .all_resource_dirs(all_resource_dirs)
+            .quiet(true)
+            .link_dependencies(vec![ld_resource_dir])
+            .dynamic_binary(dynamic_binary_config)
+            .shared_library(shared_library_config)
+            .build()?;
+            brioche_autopack::autopack(&autopack_config)?;
         }
         Mode::AutopackDisabled => {
             // We already wrote the binary, so nothing to do
@@ -0,0 +1,141 @@
+//! Read packs from a file's contents without touching the filesystem, so
+//! pack contents can be inspected anywhere, including from wasm.
+
+#[cfg(feature = "wasm")]
+mod wasm;
+
+/// A pack extracted from a file, along with its decoded metadata.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectedPack {
+    pub pack: brioche_pack::Pack,
+
+    /// The length of the file's contents before the pack was injected.
+    pub unpacked_len: usize,
+
+    /// The runnable, if the pack is a metadata pack using the runnable
+    /// format.
+    pub runnable: Option<runnable_core::Runnable>,
+
+    /// The metadata packs attached before the pack, in the order they were
+    /// written.
+    pub attached_packs: Vec<brioche_pack::Pack>,
+
+    /// The fingerprint from an attached fingerprint pack, which packs
+    /// other than runnable packs use to record their packer.
+    pub attached_packer: Option<runnable_core::PackerFingerprint>,
+}
+
+impl InspectedPack {
+    /// The packer version and config the pack was created with, either
+    /// from its runnable or from an attached fingerprint pack. Files packed
+    /// before fingerprints were recorded don't have one.
+    pub fn packer(&self) -> Option<&runnable_core::PackerFingerprint> {
+        self.runnable
+            .as_ref()
+            .and_then(|runnable| runnable.packer.as_ref())
+            .or(self.attached_packer.as_ref())
+    }
+}
+
+pub fn inspect_pack(contents: &[u8]) -> Result<InspectedPack, InspectPackError> {
+    let extracted = extract_padded_pack(contents)?;
+
+    let runnable = read_metadata(&extracted.pack, runnable_core::FORMAT)?;
+
+    let attached_packs = extract_attached_packs(contents, extracted.unpacked_len)?;
+    let mut attached_packer = None;
+    for attached_pack in &attached_packs {
+        attached_packer = read_metadata(attached_pack, runnable_core::PACKER_FINGERPRINT_FORMAT)?;
+        if attached_packer.is_some() {
+            break;
+        }
+    }
+
+    Ok(InspectedPack {
+        pack: extracted.pack,
+        unpacked_len: extracted.unpacked_len,
+        runnable,
+        attached_packs,
+        attached_packer,
+    })
+}
+
+/// The most trailing padding skipped when looking for a pack, matching
+/// `brioche_resources`.
+const MAX_PACK_PADDING: usize = 64 * 1024;
+
+/// Extract the pack from `contents`, skipping trailing zero bytes after
+/// the pack, like `brioche_resources::extract_padded_pack`. That crate
+/// works with files, so it can't be used from wasm.
+fn extract_padded_pack(
+    contents: &[u8],
+) -> Result<brioche_pack::ExtractedPack, brioche_pack::ExtractPackError> {
+    match brioche_pack::extract_pack(std::io::Cursor::new(contents)) {
+        Err(brioche_pack::ExtractPackError::MarkerNotFound) => {}
+        result => return result,
+    }
+
+    let window = &contents[contents.len().saturating_sub(MAX_PACK_PADDING)..];
+    let padding_len = window.iter().rev().take_while(|&&byte| byte == 0).count();
+    if padding_len == 0 || padding_len == window.len() {
+        return Err(brioche_pack::ExtractPackError::MarkerNotFound);
+    }
+
+    let unpadded = &contents[..contents.len() - padding_len];
+    brioche_pack::extract_pack(std::io::Cursor::new(unpadded))
+}
+
+/// Deserialize the metadata of `pack`, if it's a metadata pack using
+/// `format`.
+fn read_metadata<T: serde::de::DeserializeOwned>(
+    pack: &brioche_pack::Pack,
+    format: &str,
+) -> Result<Option<T>, serde_json::Error> {
+    match pack {
+        brioche_pack::Pack::Metadata {
+            format: pack_format,
+            metadata,
+            ..
+        } if pack_format == format => serde_json::from_slice(metadata).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Read the metadata packs attached before a pack ending at
+/// `unpacked_len`, like `brioche_resources::extract_attached_packs`.
+/// Attached packs end at the first thing before them that isn't a metadata
+/// pack.
+fn extract_attached_packs(
+    contents: &[u8],
+    unpacked_len: usize,
+) -> Result<Vec<brioche_pack::Pack>, brioche_pack::ExtractPackError> {
+    let mut packs = vec![];
+    let mut unpacked_len = unpacked_len;
+    loop {
+        let truncated = std::io::Cursor::new(&contents[..unpacked_len]);
+        match brioche_pack::extract_pack(truncated) {
+            Ok(extracted) if matches!(extracted.pack, brioche_pack::Pack::Metadata { .. }) => {
+                packs.push(extracted.pack);
+                unpacked_len = extracted.unpacked_len;
+            }
+            Ok(_)
+            | Err(
+                brioche_pack::ExtractPackError::MarkerNotFound
+                | brioche_pack::ExtractPackError::MalformedMarker,
+            ) => break,
+            Err(error) => return Err(error),
+        }
+    }
+
+    packs.reverse();
+    Ok(packs)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InspectPackError {
+    #[error(transparent)]
+    ExtractPackError(#[from] brioche_pack::ExtractPackError),
+    #[error("failed to deserialize pack metadata: {0}")]
+    InvalidRunnableMetadata(#[from] serde_json::Error),
+}
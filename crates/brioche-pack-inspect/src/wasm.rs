@@ -0,0 +1,9 @@
+use wasm_bindgen::prelude::*;
+
+/// Inspect the pack in a file's contents, returning it as a JSON string.
+#[wasm_bindgen(js_name = inspectPack)]
+pub fn inspect_pack(contents: &[u8]) -> Result<String, JsError> {
+    let inspected = crate::inspect_pack(contents)?;
+    let inspected_json = serde_json::to_string(&inspected)?;
+    Ok(inspected_json)
+}
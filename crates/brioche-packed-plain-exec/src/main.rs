@@ -1,4 +1,9 @@
-use std::{ffi::OsString, os::unix::process::CommandExt as _, path::PathBuf, process::ExitCode};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::process::CommandExt as _,
+    path::PathBuf,
+    process::ExitCode,
+};
 
 use bstr::ByteSlice as _;
 
@@ -16,6 +21,9 @@ pub fn main() -> ExitCode {
 }
 
 fn run() -> Result<(), PackedError> {
+    let mut env: runnable_core::launch::Env = std::env::vars_os().collect();
+    runnable_core::launch::restore_library_path(&mut env);
+
     let program_path = std::env::current_exe()?;
     let program_parent_path = program_path
         .parent()
@@ -23,8 +31,7 @@ fn run() -> Result<(), PackedError> {
             path: program_path.clone(),
         })?;
     let resource_dirs = brioche_resources::find_resource_dirs(&program_path, true)?;
-    let mut program = std::fs::File::open(&program_path)?;
-    let extracted = brioche_pack::extract_pack(&mut program)?;
+    let (_, extracted) = brioche_resources::extract_pack_or_sidecar(&program_path)?;
 
     match extracted.pack {
         brioche_pack::Pack::LdLinux {
@@ -56,7 +63,12 @@ fn run() -> Result<(), PackedError> {
                             path: library_dir.clone().into(),
                         })?;
                 let resolved_library_dir = program_parent_path.join(library_dir);
-                resolved_library_dirs.push(resolved_library_dir);
+
+                // Runtime library dirs are optional, so skip any that
+                // don't exist
+                if resolved_library_dir.is_dir() {
+                    resolved_library_dirs.push(resolved_library_dir);
+                }
             }
 
             for library_dir in &library_dirs {
@@ -88,9 +100,9 @@ fn run() -> Result<(), PackedError> {
                     ld_library_path.extend(path);
                 }
 
-                if let Some(env_library_path) = std::env::var_os("LD_LIBRARY_PATH") {
+                if let Some(env_library_path) = env.get(OsStr::new("LD_LIBRARY_PATH")) {
                     let env_library_path =
-                        <[u8]>::from_os_str(&env_library_path).ok_or_else(|| {
+                        <[u8]>::from_os_str(env_library_path).ok_or_else(|| {
                             PackedError::InvalidPathOsString {
                                 path: env_library_path.clone(),
                             }
@@ -126,10 +138,15 @@ fn run() -> Result<(), PackedError> {
                 .ok_or_else(|| PackedError::ResourceNotFound {
                     resource: program.to_owned(),
                 })?;
-            let program = program.canonicalize()?;
+            let program = brioche_resources::canonicalize(
+                &program,
+                brioche_resources::DEFAULT_SYMLINK_LIMIT,
+            )?;
             command.arg(program);
 
             command.args(args);
+            command.env_clear();
+            command.envs(&env);
 
             let error = command.exec();
             Err(PackedError::IoError(error))
@@ -143,103 +160,19 @@ fn run() -> Result<(), PackedError> {
             metadata,
         } => match &*format {
             runnable_core::FORMAT => {
-                let runnable: runnable_core::Runnable = serde_json::from_slice(&metadata)?;
-
-                let program = runnable
-                    .command
-                    .to_os_string(&program_path, &resource_dirs)?;
-
-                let mut command = std::process::Command::new(program);
-                let mut original_args = Some(std::env::args_os().skip(1));
-
-                for arg in &runnable.args {
-                    match arg {
-                        runnable_core::ArgValue::Arg { value } => {
-                            let value = value.to_os_string(&program_path, &resource_dirs)?;
-                            command.arg(value);
-                        }
-                        runnable_core::ArgValue::Rest => {
-                            let original_args =
-                                original_args.take().ok_or(PackedError::RepeatedArgs)?;
-                            command.args(original_args);
-                        }
-                    }
-                }
-
-                if runnable.clear_env {
-                    command.env_clear();
-                }
-
-                for (env_name, env_value) in &runnable.env {
-                    match env_value {
-                        runnable_core::EnvValue::Clear => {
-                            command.env_remove(env_name);
-                        }
-                        runnable_core::EnvValue::Inherit => {
-                            let value = std::env::var_os(env_name);
-                            if let Some(value) = value {
-                                command.env(env_name, value);
-                            }
-                        }
-                        runnable_core::EnvValue::Set { value } => {
-                            let value = value.to_os_string(&program_path, &resource_dirs)?;
-                            command.env(env_name, value);
-                        }
-                        runnable_core::EnvValue::Fallback { value } => {
-                            let current_value = std::env::var_os(env_name);
-                            let current_value = current_value.filter(|value| !value.is_empty());
-                            let value = match current_value {
-                                Some(current_value) => current_value,
-                                None => value.to_os_string(&program_path, &resource_dirs)?,
-                            };
-                            command.env(env_name, value);
-                        }
-                        runnable_core::EnvValue::Prepend { value, separator } => {
-                            let mut value = value.to_os_string(&program_path, &resource_dirs)?;
-                            let separator =
-                                separator
-                                    .to_os_str()
-                                    .map_err(|_| PackedError::InvalidUtf8 {
-                                        bytes: separator.clone().into(),
-                                    })?;
-
-                            let current_value = std::env::var_os(env_name);
-                            let new_value = match current_value {
-                                Some(current_value) if !current_value.is_empty() => {
-                                    value.push(separator);
-                                    value.push(current_value);
-
-                                    value
-                                }
-                                _ => value,
-                            };
-                            command.env(env_name, new_value);
-                        }
-                        runnable_core::EnvValue::Append { value, separator } => {
-                            let value = value.to_os_string(&program_path, &resource_dirs)?;
-                            let separator =
-                                separator
-                                    .to_os_str()
-                                    .map_err(|_| PackedError::InvalidUtf8 {
-                                        bytes: separator.clone().into(),
-                                    })?;
-
-                            let current_value = std::env::var_os(env_name);
-                            let new_value = match current_value {
-                                Some(mut current_value) if !current_value.is_empty() => {
-                                    current_value.push(separator);
-                                    current_value.push(value);
-
-                                    current_value
-                                }
-                                _ => value,
-                            };
-                            command.env(env_name, new_value);
-                        }
-                    }
-                }
-
-                let error = command.exec();
+                let runnable = runnable_core::Runnable::from_metadata(&metadata)?;
+                let launch = runnable.to_launch_command(
+                    &program_path,
+                    &resource_dirs,
+                    std::env::args_os().skip(1),
+                    env,
+                )?;
+
+                let error = std::process::Command::new(launch.program)
+                    .args(launch.args)
+                    .env_clear()
+                    .envs(launch.env)
+                    .exec();
                 Err(PackedError::IoError(error))
             }
             _ => {
@@ -260,13 +193,11 @@ enum PackedError {
     #[error(transparent)]
     PackResourceDirError(#[from] brioche_resources::PackResourceDirError),
     #[error(transparent)]
+    CanonicalizeError(#[from] brioche_resources::CanonicalizeError),
+    #[error(transparent)]
     RunnableTemplateError(#[from] runnable_core::RunnableTemplateError),
-    #[error("tried to pass remaining arguments more than once")]
-    RepeatedArgs,
     #[error("resource not found: {resource}")]
     ResourceNotFound { resource: PathBuf },
-    #[error("invalid UTF-8: {bytes:?}")]
-    InvalidUtf8 { bytes: bstr::BString },
     #[error("invalid path: {path:?}")]
     InvalidPathBytes { path: bstr::BString },
     #[error("invalid path: {path:?}")]
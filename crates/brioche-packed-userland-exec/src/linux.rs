@@ -1,6 +1,10 @@
 #![cfg(target_os = "linux")]
 
-use std::ffi::{CStr, CString};
+use std::{
+    ffi::{CStr, CString, OsStr},
+    os::unix::ffi::{OsStrExt as _, OsStringExt as _},
+    path::Path,
+};
 
 use bstr::ByteSlice as _;
 
@@ -50,11 +54,22 @@ pub unsafe fn entrypoint(argc: libc::c_int, argv: *const *const libc::c_char) ->
 }
 
 fn run(args: &[&CStr], env_vars: &[&CStr]) -> Result<(), PackedError> {
+    let mut env: runnable_core::launch::Env = env_vars
+        .iter()
+        .filter_map(|var| {
+            let (name, value) = var.to_bytes().split_once_str(b"=")?;
+            Some((
+                OsStr::from_bytes(name).to_owned(),
+                OsStr::from_bytes(value).to_owned(),
+            ))
+        })
+        .collect();
+    runnable_core::launch::restore_library_path(&mut env);
+
     let path = std::env::current_exe()?;
     let parent_path = path.parent().ok_or(PackedError::InvalidPath)?;
     let resource_dirs = brioche_resources::find_resource_dirs(&path, true)?;
-    let mut program = std::fs::File::open(&path)?;
-    let extracted = brioche_pack::extract_pack(&mut program)?;
+    let (_, extracted) = brioche_resources::extract_pack_or_sidecar(&path)?;
 
     match extracted.pack {
         brioche_pack::Pack::LdLinux {
@@ -72,7 +87,10 @@ fn run(args: &[&CStr], env_vars: &[&CStr]) -> Result<(), PackedError> {
             let program = program.to_path().map_err(|_| PackedError::InvalidPath)?;
             let program = brioche_resources::find_in_resource_dirs(&resource_dirs, program)
                 .ok_or(PackedError::ResourceNotFound)?;
-            let program = program.canonicalize()?;
+            let program = brioche_resources::canonicalize(
+                &program,
+                brioche_resources::DEFAULT_SYMLINK_LIMIT,
+            )?;
             let mut exec = userland_execve::ExecOptions::new(&interpreter);
 
             let interpreter = <[u8]>::from_path(&interpreter).ok_or(PackedError::InvalidPath)?;
@@ -85,7 +103,12 @@ fn run(args: &[&CStr], env_vars: &[&CStr]) -> Result<(), PackedError> {
                     .to_path()
                     .map_err(|_| PackedError::InvalidPath)?;
                 let resolved_library_dir = parent_path.join(library_dir);
-                resolved_library_dirs.push(resolved_library_dir);
+
+                // Runtime library dirs are optional, so skip any that
+                // don't exist
+                if resolved_library_dir.is_dir() {
+                    resolved_library_dirs.push(resolved_library_dir);
+                }
             }
 
             for library_dir in &library_dirs {
@@ -112,9 +135,9 @@ fn run(args: &[&CStr], env_vars: &[&CStr]) -> Result<(), PackedError> {
                     ld_library_path.extend(path);
                 }
 
-                if let Some(env_library_path) = std::env::var_os("LD_LIBRARY_PATH") {
+                if let Some(env_library_path) = env.get(OsStr::new("LD_LIBRARY_PATH")) {
                     let env_library_path =
-                        <[u8]>::from_os_str(&env_library_path).ok_or(PackedError::InvalidPath)?;
+                        <[u8]>::from_os_str(env_library_path).ok_or(PackedError::InvalidPath)?;
                     if !env_library_path.is_empty() {
                         ld_library_path.push(b':');
                         ld_library_path.extend(env_library_path);
@@ -140,24 +163,73 @@ fn run(args: &[&CStr], env_vars: &[&CStr]) -> Result<(), PackedError> {
 
             exec.args(args);
 
-            exec.env_pairs(env_vars);
+            let env_pairs = env_pairs(env)?;
+            let env_pairs: Vec<&CStr> = env_pairs.iter().map(|pair| pair.as_c_str()).collect();
+            exec.env_pairs(&env_pairs);
 
             userland_execve::exec_with_options(exec);
         }
         brioche_pack::Pack::Static { .. } => {
             unimplemented!("execution of a static executable");
         }
+        brioche_pack::Pack::Metadata {
+            resource_paths: _,
+            format,
+            metadata,
+        } if format == runnable_core::FORMAT => {
+            let runnable = runnable_core::Runnable::from_metadata(&metadata)
+                .map_err(|_| PackedError::InvalidRunnable)?;
+
+            let args = args
+                .iter()
+                .skip(1)
+                .map(|arg| OsStr::from_bytes(arg.to_bytes()).to_owned());
+            let launch = runnable.to_launch_command(&path, &resource_dirs, args, env)?;
+
+            let mut exec = userland_execve::ExecOptions::new(Path::new(&launch.program));
+
+            // Add argv0
+            let program =
+                CString::new(launch.program.into_vec()).map_err(|_| PackedError::InvalidPath)?;
+            exec.arg(program);
+
+            for arg in launch.args {
+                let arg = CString::new(arg.into_vec()).map_err(|_| PackedError::InvalidPath)?;
+                exec.arg(arg);
+            }
+
+            let env_pairs = env_pairs(launch.env)?;
+            let env_pairs: Vec<&CStr> = env_pairs.iter().map(|pair| pair.as_c_str()).collect();
+            exec.env_pairs(&env_pairs);
+
+            userland_execve::exec_with_options(exec);
+        }
         brioche_pack::Pack::Metadata { .. } => {
-            unimplemented!("execution of a metadata pack");
+            unimplemented!("execution of a metadata pack with an unknown format");
         }
     }
 }
 
+/// Convert `env` to the `NAME=value` pairs passed to the new program.
+fn env_pairs(env: runnable_core::launch::Env) -> Result<Vec<CString>, PackedError> {
+    env.into_iter()
+        .map(|(name, value)| {
+            let mut pair = name.into_vec();
+            pair.push(b'=');
+            pair.extend(value.into_vec());
+            CString::new(pair).map_err(|_| PackedError::InvalidPath)
+        })
+        .collect()
+}
+
 #[derive(Debug, thiserror::Error)]
 enum PackedError {
     IoError(#[from] std::io::Error),
     ExtractPackError(#[from] brioche_pack::ExtractPackError),
     PackResourceDirError(#[from] brioche_resources::PackResourceDirError),
+    CanonicalizeError(#[from] brioche_resources::CanonicalizeError),
+    RunnableTemplateError(#[from] runnable_core::RunnableTemplateError),
+    InvalidRunnable,
     InvalidPath,
     ResourceNotFound,
 }
@@ -195,6 +267,37 @@ fn error_summary(error: &PackedError) -> &'static str {
                 "error while searching for brioche pack resource dir: io error"
             }
         },
+        PackedError::CanonicalizeError(error) => match error {
+            brioche_resources::CanonicalizeError::IoError { .. } => {
+                "failed to resolve program path: io error"
+            }
+            brioche_resources::CanonicalizeError::SymlinkLoop { .. } => {
+                "symlink loop while resolving program path"
+            }
+            brioche_resources::CanonicalizeError::SymlinkLimitReached { .. } => {
+                "followed too many symlinks while resolving program path"
+            }
+        },
+        PackedError::RunnableTemplateError(error) => match error {
+            runnable_core::RunnableTemplateError::Utf8Error(_) => {
+                "invalid UTF-8 in runnable template"
+            }
+            runnable_core::RunnableTemplateError::PathError => "invalid path in runnable template",
+            runnable_core::RunnableTemplateError::InvalidProgramPath => "invalid program path",
+            runnable_core::RunnableTemplateError::PackResourceDirError(_) => {
+                "error while searching for brioche pack resource dir"
+            }
+            runnable_core::RunnableTemplateError::ResourceNotFound { .. } => {
+                "runnable resource not found"
+            }
+            runnable_core::RunnableTemplateError::PrependAndAppend => {
+                "tried prepending and appending to env var"
+            }
+            runnable_core::RunnableTemplateError::RepeatedArgs => {
+                "tried to pass remaining arguments more than once"
+            }
+        },
+        PackedError::InvalidRunnable => "failed to parse runnable metadata",
         PackedError::InvalidPath => "invalid path",
         PackedError::ResourceNotFound => "resource not found",
     }
@@ -0,0 +1,98 @@
+//! The public API of `brioche-packer`, collected in one place.
+//!
+//! Everything exported here follows semver, so breaking changes only
+//! happen in major releases. Config structs and enums that are expected to
+//! grow new fields or variants are marked `#[non_exhaustive]`, so adding
+//! one isn't a breaking change. Build config structs from their `new` or
+//! `Default` constructors (or [`AutopackConfig::builder`]) and set their
+//! fields from there, and include a wildcard arm when matching on enums.
+//!
+//! Pack types from `brioche-pack` are used in some signatures but aren't
+//! re-exported here, so depend on `brioche-pack` directly to use them.
+
+// Autopack configuration, either built directly or from a JSON template.
+pub use brioche_autopack::{
+    autopack, AndroidConfig, AttachedMetadata, Autopack, AutopackConfig, AutopackConfigBuilder,
+    AutopackInputs, AutopackOutput, ClosureCallback, ClosurePolicy, ClosureVerdict,
+    DynamicBinaryConfig, DynamicBinaryMode, DynamicLinkingConfig, ForeignArchPolicy, ForwardArgs,
+    LibraryConflicts, OutputPermissions, OutputTimestamps, PackedExecutable, PosixShell,
+    PrivilegePolicy, RepackConfig, ResolvedClosure, ResolvedLibrary, RetryPolicy, RpathTokens,
+    ScriptConfig, SharedLibraryConfig, SizeOverheadLimit, StaticPiePack, SymlinkPolicy,
+    UnwrappedCopy,
+};
+
+// Planning autopacking without changing any files, and applying plans.
+pub use brioche_autopack::plan::{
+    apply, plan, AutopackPlan, PlannedAlias, PlannedBuildId, PlannedContents, PlannedFile,
+    PlannedResource, PLAN_FORMAT_VERSION,
+};
+
+// What autopacking did, returned by `autopack`.
+pub use brioche_autopack::{
+    AutopackKind, AutopackReport, ClosureLibrary, PathOutcome, PathReport, SharedTarget, SkipReason,
+};
+
+// Classifying files the way autopacking would, without autopacking them.
+pub use brioche_autopack::{classify, Classification};
+
+// Warnings for non-fatal problems found while autopacking.
+pub use brioche_autopack::{AutopackWarning, WarningCallback, WarningKind, WarningPolicy};
+
+// Extension points for replacing steps of autopacking.
+pub use brioche_autopack::{
+    autopack_with,
+    emit::{FilePackEmitter, PackBase, PackEmitter, SharedLauncherPackEmitter},
+    resolve::{
+        CandidateChooser, CandidateChooserCallback, CandidateKind, LinkDependencyResolver, Resolver,
+    },
+    resources::{
+        ResourceDirSink, ResourceFilter, ResourceFilterCallback, ResourceRole, ResourceSink,
+        ResourceVerdict,
+    },
+    AutopackExtensions,
+};
+
+pub use crate::autopack_template::{
+    AutopackConfigTemplate, AutopackConfigTemplateContext, TemplateVariableValue,
+};
+
+// Errors, including diagnostics that can be rendered with source context.
+pub use brioche_autopack::diagnostics::{
+    find_diagnostic, ArchitectureMismatch, ConflictingLibrary, DeniedWarning,
+    InvalidAutopackConfig, InvalidGlobPattern, InvalidRunnableMetadata, LibcNotFound,
+    ScriptCommandNotFound, ScriptInvalidEnvShebang, ScriptMissingEnvCommand,
+};
+pub use brioche_resources::{
+    AddBlobError, CanonicalizeError, InjectMetadataPackError, InvalidResourceDirError,
+    PackResourceDirError,
+};
+
+// Reading and writing packs, and inspecting their runnable metadata.
+pub use brioche_autopack::{metadata_pack, read_attached_metadata, read_metadata, MetadataFormat};
+pub use brioche_autopack::{pack_fingerprint, read_fingerprint, PackerFingerprint, PACKER_VERSION};
+pub use brioche_autopack::{pack_source, read_attached_packs, read_pack, PackSource};
+pub use brioche_pack_inspect::{inspect_pack, InspectPackError, InspectedPack};
+pub use runnable_core::{
+    ArgValue, EnvValue, Runnable, RunnablePath, RunnableSource, Template, TemplateComponent,
+    FORMAT as RUNNABLE_FORMAT,
+};
+
+// Finding the original programs of autopacked binaries, for debuggers.
+pub use brioche_autopack::{find_debug_link, read_debug_links, DebugLink, DEBUG_LINKS_FILE};
+pub use brioche_resources::find_by_build_id;
+
+// Finding packed files in a tree, exporting their dependency closure, and
+// looking up which packed files depend on a library or resource.
+pub use crate::graph::{
+    DependencyGraph, GraphEdge, GraphEdgeKind, GraphNode, GraphNodeKind, UsageQuery,
+};
+pub use crate::wrapped::{find_wrapped, WrappedFile};
+
+// Listing the resources referenced by a single packed file.
+pub use crate::resources::{
+    referenced_resources, ReferencedResource, ResourceContents, ResourceFile,
+};
+
+// Checking autopacked programs for other architectures with qemu-user.
+#[cfg(feature = "qemu")]
+pub use crate::qemu::{check_with_qemu, QemuCheckOptions};
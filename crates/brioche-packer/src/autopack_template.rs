@@ -4,10 +4,13 @@ use std::{
 };
 
 use bstr::ByteVec as _;
+use eyre::{Context as _, OptionExt as _};
 use runnable_core::encoding::TickEncoded;
 
 pub struct AutopackConfigTemplateContext {
     pub variables: HashMap<String, TemplateVariableValue>,
+    /// The resource dir that autopacking adds resources to, such as the
+    /// resources for template variables.
     pub resource_dir: PathBuf,
 }
 
@@ -28,18 +31,58 @@ pub struct AutopackConfigTemplate {
     #[serde(default)]
     globs: Vec<String>,
 
+    /// Files to skip even if they match `globs`, such as `bin/*.debug`.
+    /// Can't be used without `globs`.
     #[serde(default)]
     exclude_globs: Vec<String>,
 
+    output_root: Option<PathBuf>,
+
     #[serde(default)]
     quiet: bool,
 
+    symlink_limit: Option<u32>,
+
+    /// Add resources under `namespaces/<name>` in the resource dir, so
+    /// concurrent runs sharing a resource dir don't write to the same
+    /// paths.
+    resource_namespace: Option<String>,
+
+    /// The hash function new resources are named by.
+    #[serde(default)]
+    content_hash: ContentHashTemplate,
+
+    /// Where to record which files resources were added from, so later
+    /// runs reuse them without reading unchanged files. Must be outside of
+    /// the resource dir.
+    stat_cache_dir: Option<PathBuf>,
+
+    target: Option<String>,
+
+    /// What to do with files built for a different target.
     #[serde(default)]
-    link_dependencies: Vec<TemplatePath>,
+    foreign_arch: ForeignArchPolicyTemplate,
+
+    #[serde(default)]
+    link_dependencies: Vec<LinkDependencyTemplate>,
+
+    #[serde(default)]
+    library_conflicts: LibraryConflictsTemplate,
+
+    /// `glibc-hwcaps` subdirectories to search first, such as `x86-64-v3`.
+    #[serde(default)]
+    glibc_hwcaps: Vec<String>,
 
     #[serde(default)]
     self_dependency: bool,
 
+    default_libc_dependency: Option<TemplatePath>,
+
+    /// Library files to use by name, instead of searching for libraries.
+    library_map: Option<HashMap<String, TemplatePath>>,
+
+    closure_policy: Option<ClosurePolicyTemplate>,
+
     dynamic_binary: Option<DynamicBinaryConfigTemplate>,
 
     shared_library: Option<SharedLibraryConfigTemplate>,
@@ -47,9 +90,79 @@ pub struct AutopackConfigTemplate {
     script: Option<ScriptConfigTemplate>,
 
     repack: Option<RepackConfigTemplate>,
+
+    #[serde(default)]
+    symlink_policy: SymlinkPolicyTemplate,
+
+    /// The permissions autopacked files are written with.
+    #[serde(default)]
+    output_permissions: OutputPermissionsTemplate,
+
+    /// The modification times autopacked files and resources are written
+    /// with.
+    #[serde(default)]
+    timestamps: OutputTimestampsTemplate,
+
+    /// What to do with setuid and setgid bits and file capabilities.
+    #[serde(default)]
+    privileges: PrivilegePolicyTemplate,
+
+    /// Follow symlinked files and directories when walking globs.
+    #[serde(default)]
+    follow_symlinks: bool,
+
+    /// How deep to walk into the recipe when matching globs.
+    max_depth: Option<usize>,
+
+    /// Directories to skip when matching globs, such as `share/doc`.
+    #[serde(default)]
+    prune_dirs: Vec<String>,
+
+    size_overhead_limit: Option<SizeOverheadLimitTemplate>,
+
+    #[serde(default)]
+    shared_launcher: bool,
+
+    #[serde(default)]
+    sidecar_pack_globs: Vec<String>,
+
+    /// Metadata attached to every autopacked file, such as build
+    /// provenance.
+    #[serde(default)]
+    attached_metadata: Vec<AttachedMetadataTemplate>,
+
+    /// Retry reading files and writing resources after transient errors,
+    /// such as on network filesystems.
+    retry: Option<RetryPolicyTemplate>,
+
+    /// Kinds of warnings that fail autopacking instead of being printed.
+    #[serde(default)]
+    denied_warnings: Vec<WarningKindTemplate>,
+
+    jobs: Option<std::num::NonZeroUsize>,
 }
 
 impl AutopackConfigTemplate {
+    /// Read a config template from a file, so the config can be checked
+    /// in alongside a recipe. Files ending in `.toml` are read as TOML, and
+    /// other files are read as JSON.
+    pub fn from_file(path: &Path) -> eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let config_template = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&contents)?
+        } else {
+            serde_json::from_str(&contents)?
+        };
+        Ok(config_template)
+    }
+
+    /// The directory to write autopacked files to, if it's separate from
+    /// the recipe.
+    pub fn output_root(&self) -> Option<&Path> {
+        self.output_root.as_deref()
+    }
+
     pub fn build(
         self,
         ctx: &AutopackConfigTemplateContext,
@@ -59,29 +172,83 @@ impl AutopackConfigTemplate {
             paths,
             globs,
             exclude_globs,
+            output_root,
             quiet,
+            symlink_limit,
+            resource_namespace,
+            content_hash,
+            stat_cache_dir,
+            target,
+            foreign_arch,
             link_dependencies,
+            library_conflicts,
+            glibc_hwcaps,
             self_dependency,
+            default_libc_dependency,
+            library_map,
+            closure_policy,
             dynamic_binary,
             shared_library,
             script,
             repack,
+            symlink_policy,
+            output_permissions,
+            timestamps,
+            privileges,
+            follow_symlinks,
+            max_depth,
+            prune_dirs,
+            size_overhead_limit,
+            shared_launcher,
+            sidecar_pack_globs,
+            attached_metadata,
+            retry,
+            denied_warnings,
+            jobs,
         } = self;
 
         let paths = paths
             .into_iter()
             .map(|path| path.build(ctx))
             .collect::<eyre::Result<Vec<_>>>()?;
+        // Link dependencies are searched in order, so sort by priority
+        // while keeping the order of dependencies with the same priority
         let mut link_dependencies = link_dependencies
             .into_iter()
-            .map(|path| path.build(ctx))
+            .map(|link_dependency| link_dependency.build(ctx))
             .collect::<eyre::Result<Vec<_>>>()?;
+        link_dependencies.sort_by_key(|(_, priority)| std::cmp::Reverse(*priority));
+        let mut link_dependencies: Vec<_> = link_dependencies
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        let default_libc_dependency = default_libc_dependency
+            .map(|path| path.build(ctx))
+            .transpose()?;
+        let library_map = library_map
+            .map(|library_map| {
+                library_map
+                    .into_iter()
+                    .map(|(name, path)| eyre::Ok((name, path.build(ctx)?)))
+                    .collect::<eyre::Result<HashMap<_, _>>>()
+            })
+            .transpose()?;
+        let target = target
+            .map(|target| {
+                target
+                    .parse::<target_lexicon::Triple>()
+                    .map_err(|error| eyre::eyre!("invalid target {target:?}: {error}"))
+            })
+            .transpose()?;
+        let closure_policy = closure_policy.map(|policy| policy.build(ctx)).transpose()?;
         let dynamic_binary = dynamic_binary
+            .map(|opts| opts.build(ctx, &recipe_path, target.as_ref()))
+            .transpose()?;
+        let shared_library = shared_library
             .map(|opts| opts.build(ctx, &recipe_path))
             .transpose()?;
-        let shared_library = shared_library.map(|opts| opts.build(ctx)).transpose()?;
         let script = script
-            .map(|opts| opts.build(ctx, &recipe_path))
+            .map(|opts| opts.build(ctx, &recipe_path, target.as_ref()))
             .transpose()?;
         let repack = repack.map(|opts| opts.build());
 
@@ -110,28 +277,389 @@ impl AutopackConfigTemplate {
 
         // HACK: Workaround because finding a resource dir takes a program
         // path rather than a directory path, but then gets the parent path
-        let program = recipe_path.join("program");
+        let program = output_root.as_ref().unwrap_or(&recipe_path).join("program");
 
-        let resource_dir = brioche_resources::find_output_resource_dir(&program)?;
-        let all_resource_dirs = brioche_resources::find_resource_dirs(&program, true)?;
+        // The output resource dir was already found for the context, and
+        // may have been set explicitly rather than found from the recipe
+        let resource_dir = ctx.resource_dir.clone();
+        let found_resource_dirs = match brioche_resources::find_resource_dirs(&program, true) {
+            Ok(resource_dirs) => resource_dirs,
+            Err(brioche_resources::PackResourceDirError::NotFound) => vec![],
+            Err(error) => return Err(error.into()),
+        };
+        let mut all_resource_dirs = vec![resource_dir.clone()];
+        for found_resource_dir in found_resource_dirs {
+            if !all_resource_dirs.contains(&found_resource_dir) {
+                all_resource_dirs.push(found_resource_dir);
+            }
+        }
 
-        Ok(brioche_autopack::AutopackConfig {
-            resource_dir,
-            all_resource_dirs,
-            inputs,
-            quiet,
-            link_dependencies,
-            dynamic_binary,
-            shared_library,
-            script,
-            repack,
-        })
+        let output = match output_root {
+            Some(output_root) => brioche_autopack::AutopackOutput::CopyOnWrite {
+                input_root: recipe_path.clone(),
+                output_root,
+            },
+            None => brioche_autopack::AutopackOutput::InPlace,
+        };
+
+        let mut builder = brioche_autopack::AutopackConfig::builder(resource_dir, inputs)
+            .all_resource_dirs(all_resource_dirs)
+            .output(output)
+            .quiet(quiet)
+            .symlink_limit(symlink_limit.unwrap_or(brioche_resources::DEFAULT_SYMLINK_LIMIT))
+            .foreign_arch(foreign_arch.build())
+            .content_hasher(content_hash.build())
+            .link_dependencies(link_dependencies)
+            .library_conflicts(library_conflicts.build())
+            .glibc_hwcaps(glibc_hwcaps)
+            .symlink_policy(symlink_policy.build())
+            .output_permissions(output_permissions.build()?)
+            .timestamps(timestamps.build()?)
+            .privileges(privileges.build())
+            .follow_symlinks(follow_symlinks)
+            .prune_dirs(prune_dirs)
+            .shared_launcher(shared_launcher)
+            .sidecar_pack_globs(sidecar_pack_globs);
+        for metadata in attached_metadata {
+            let (format, metadata) = metadata.build(ctx)?;
+            builder = builder.attach_metadata(format, metadata);
+        }
+        for kind in denied_warnings {
+            builder = builder.deny_warning(kind.build());
+        }
+        if let Some(jobs) = jobs {
+            builder = builder.jobs(jobs);
+        }
+        if let Some(max_depth) = max_depth {
+            builder = builder.max_depth(max_depth);
+        }
+        if let Some(target) = target {
+            builder = builder.target(target);
+        }
+        if let Some(resource_namespace) = resource_namespace {
+            builder = builder.resource_namespace(resource_namespace);
+        }
+        if let Some(stat_cache_dir) = stat_cache_dir {
+            builder = builder.stat_cache_dir(stat_cache_dir);
+        }
+        if let Some(default_libc_dependency) = default_libc_dependency {
+            builder = builder.default_libc_dependency(default_libc_dependency);
+        }
+        if let Some(library_map) = library_map {
+            builder = builder.library_map(library_map);
+        }
+        if let Some(closure_policy) = closure_policy {
+            builder = builder.closure_policy(closure_policy);
+        }
+        if let Some(dynamic_binary) = dynamic_binary {
+            builder = builder.dynamic_binary(dynamic_binary);
+        }
+        if let Some(shared_library) = shared_library {
+            builder = builder.shared_library(shared_library);
+        }
+        if let Some(script) = script {
+            builder = builder.script(script);
+        }
+        if let Some(repack) = repack {
+            builder = builder.repack(repack);
+        }
+        if let Some(size_overhead_limit) = size_overhead_limit {
+            builder = builder.size_overhead_limit(size_overhead_limit.build());
+        }
+        if let Some(retry) = retry {
+            builder = builder.retry(retry.build());
+        }
+
+        let config = builder.build()?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct RetryPolicyTemplate {
+    max_retries: Option<u32>,
+    initial_backoff_ms: Option<u64>,
+    max_backoff_ms: Option<u64>,
+}
+
+impl RetryPolicyTemplate {
+    fn build(self) -> brioche_autopack::RetryPolicy {
+        let Self {
+            max_retries,
+            initial_backoff_ms,
+            max_backoff_ms,
+        } = self;
+        let mut retry = brioche_autopack::RetryPolicy::default();
+        retry.max_retries = max_retries.unwrap_or(retry.max_retries);
+        retry.initial_backoff =
+            initial_backoff_ms.map_or(retry.initial_backoff, std::time::Duration::from_millis);
+        retry.max_backoff =
+            max_backoff_ms.map_or(retry.max_backoff, std::time::Duration::from_millis);
+        retry
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct SizeOverheadLimitTemplate {
+    max_file_size: u64,
+    max_overhead: u64,
+}
+
+impl SizeOverheadLimitTemplate {
+    fn build(self) -> brioche_autopack::SizeOverheadLimit {
+        let Self {
+            max_file_size,
+            max_overhead,
+        } = self;
+        brioche_autopack::SizeOverheadLimit::new(max_file_size, max_overhead)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+enum LinkDependencyTemplate {
+    Path(TemplatePath),
+    #[serde(rename_all = "camelCase")]
+    WithPriority {
+        path: TemplatePath,
+
+        /// Dependencies with a higher priority are searched first.
+        /// Defaults to 0.
+        priority: i32,
+    },
+}
+
+impl LinkDependencyTemplate {
+    fn build(self, ctx: &AutopackConfigTemplateContext) -> eyre::Result<(PathBuf, i32)> {
+        match self {
+            Self::Path(path) => Ok((path.build(ctx)?, 0)),
+            Self::WithPriority { path, priority } => Ok((path.build(ctx)?, priority)),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+enum LibraryConflictsTemplate {
+    #[default]
+    FirstMatch,
+    Error,
+}
+
+impl LibraryConflictsTemplate {
+    fn build(self) -> brioche_autopack::LibraryConflicts {
+        match self {
+            Self::FirstMatch => brioche_autopack::LibraryConflicts::FirstMatch,
+            Self::Error => brioche_autopack::LibraryConflicts::Error,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+enum SymlinkPolicyTemplate {
+    #[default]
+    AutopackTarget,
+    ReplaceLink,
+}
+
+impl SymlinkPolicyTemplate {
+    fn build(self) -> brioche_autopack::SymlinkPolicy {
+        match self {
+            Self::AutopackTarget => brioche_autopack::SymlinkPolicy::AutopackTarget,
+            Self::ReplaceLink => brioche_autopack::SymlinkPolicy::ReplaceLink,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+enum ForeignArchPolicyTemplate {
+    #[default]
+    Skip,
+    Autopack,
+    Error,
+}
+
+impl ForeignArchPolicyTemplate {
+    fn build(self) -> brioche_autopack::ForeignArchPolicy {
+        match self {
+            Self::Skip => brioche_autopack::ForeignArchPolicy::Skip,
+            Self::Autopack => brioche_autopack::ForeignArchPolicy::Autopack,
+            Self::Error => brioche_autopack::ForeignArchPolicy::Error,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+enum ContentHashTemplate {
+    #[default]
+    Blake3,
+    /// BLAKE3, hashing large files with multiple threads.
+    Blake3Multithreaded,
+    /// SHA-256, which uses the CPU's SHA extensions when available.
+    Sha256,
+}
+
+impl ContentHashTemplate {
+    fn build(self) -> std::sync::Arc<dyn brioche_resources::ContentHasher> {
+        match self {
+            Self::Blake3 => std::sync::Arc::new(brioche_resources::Blake3ContentHasher {
+                multithreaded: false,
+            }),
+            Self::Blake3Multithreaded => {
+                std::sync::Arc::new(brioche_resources::Blake3ContentHasher {
+                    multithreaded: true,
+                })
+            }
+            Self::Sha256 => std::sync::Arc::new(brioche_resources::Sha256ContentHasher),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+enum PrivilegePolicyTemplate {
+    Preserve,
+    #[default]
+    Strip,
+    Error,
+}
+
+impl PrivilegePolicyTemplate {
+    fn build(self) -> brioche_autopack::PrivilegePolicy {
+        match self {
+            Self::Preserve => brioche_autopack::PrivilegePolicy::Preserve,
+            Self::Strip => brioche_autopack::PrivilegePolicy::Strip,
+            Self::Error => brioche_autopack::PrivilegePolicy::Error,
+        }
+    }
+}
+
+/// Output permissions, with modes written as octal strings like `"755"`.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum OutputPermissionsTemplate {
+    #[default]
+    Source,
+    #[serde(rename_all = "camelCase")]
+    Fixed { mode: String },
+    #[serde(rename_all = "camelCase")]
+    Masked { mask: String },
+}
+
+impl OutputPermissionsTemplate {
+    fn build(self) -> eyre::Result<brioche_autopack::OutputPermissions> {
+        let parse_mode = |mode: &str| {
+            let digits = mode.strip_prefix("0o").unwrap_or(mode);
+            u32::from_str_radix(digits, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .ok_or_else(|| eyre::eyre!("invalid mode {mode:?}"))
+        };
+
+        match self {
+            Self::Source => Ok(brioche_autopack::OutputPermissions::Source),
+            Self::Fixed { mode } => Ok(brioche_autopack::OutputPermissions::Fixed(parse_mode(
+                &mode,
+            )?)),
+            Self::Masked { mask } => Ok(brioche_autopack::OutputPermissions::Masked(parse_mode(
+                &mask,
+            )?)),
+        }
+    }
+}
+
+/// Output timestamps, with fixed times written as seconds since the Unix
+/// epoch. `source_date_epoch` reads the time from `$SOURCE_DATE_EPOCH`.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum OutputTimestampsTemplate {
+    #[default]
+    Current,
+    Source,
+    #[serde(rename_all = "camelCase")]
+    Fixed {
+        unix_time: u64,
+    },
+    SourceDateEpoch,
+}
+
+impl OutputTimestampsTemplate {
+    fn build(self) -> eyre::Result<brioche_autopack::OutputTimestamps> {
+        let fixed = |unix_time: u64| {
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(unix_time);
+            brioche_autopack::OutputTimestamps::Fixed(time)
+        };
+
+        match self {
+            Self::Current => Ok(brioche_autopack::OutputTimestamps::Current),
+            Self::Source => Ok(brioche_autopack::OutputTimestamps::Source),
+            Self::Fixed { unix_time } => Ok(fixed(unix_time)),
+            Self::SourceDateEpoch => {
+                let source_date_epoch = std::env::var("SOURCE_DATE_EPOCH")
+                    .context("failed to read $SOURCE_DATE_EPOCH")?;
+                let unix_time = source_date_epoch.trim().parse().with_context(|| {
+                    format!("invalid $SOURCE_DATE_EPOCH: {source_date_epoch:?}")
+                })?;
+                Ok(fixed(unix_time))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+enum WarningKindTemplate {
+    SkippedUnknownLibrary,
+    SubstitutedInterpreter,
+    NonHermeticPath,
+    StaticDlopen,
+    DanglingSymlink,
+    LibraryMismatch,
+    StrippedPrivileges,
+    RetriedIoError,
+    MissingSymbols,
+    UnmatchedGlobPattern,
+}
+
+impl WarningKindTemplate {
+    fn build(self) -> brioche_autopack::WarningKind {
+        match self {
+            Self::SkippedUnknownLibrary => brioche_autopack::WarningKind::SkippedUnknownLibrary,
+            Self::SubstitutedInterpreter => brioche_autopack::WarningKind::SubstitutedInterpreter,
+            Self::NonHermeticPath => brioche_autopack::WarningKind::NonHermeticPath,
+            Self::StaticDlopen => brioche_autopack::WarningKind::StaticDlopen,
+            Self::DanglingSymlink => brioche_autopack::WarningKind::DanglingSymlink,
+            Self::LibraryMismatch => brioche_autopack::WarningKind::LibraryMismatch,
+            Self::StrippedPrivileges => brioche_autopack::WarningKind::StrippedPrivileges,
+            Self::RetriedIoError => brioche_autopack::WarningKind::RetriedIoError,
+            Self::MissingSymbols => brioche_autopack::WarningKind::MissingSymbols,
+            Self::UnmatchedGlobPattern => brioche_autopack::WarningKind::UnmatchedGlobPattern,
+        }
     }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct DynamicLinkingConfigTemplate {
+    /// Extra directories to search for libraries, such as a vendored `lib/`
+    /// dir. Relative paths are relative to the recipe.
     #[serde(default)]
     library_paths: Vec<TemplatePath>,
 
@@ -141,46 +669,364 @@ struct DynamicLinkingConfigTemplate {
     #[serde(default)]
     extra_libraries: Vec<String>,
 
+    #[serde(default)]
+    optional_libraries: Vec<String>,
+
+    #[serde(default)]
+    optional_library_globs: Vec<String>,
+
     #[serde(default)]
     skip_unknown_libraries: bool,
+
+    #[serde(default)]
+    rpath_tokens: RpathTokensTemplate,
+
+    #[serde(default)]
+    host_libraries: HashSet<String>,
+
+    #[serde(default)]
+    host_library_paths: Vec<PathBuf>,
+
+    /// Libraries to stop collecting the closure at, which are provided at
+    /// runtime along with their dependencies.
+    #[serde(default)]
+    cut_libraries: HashSet<String>,
+
+    /// How many levels of libraries to collect.
+    max_depth: Option<std::num::NonZeroU32>,
+
+    /// The most libraries a file's closure can have.
+    max_closure_libraries: Option<usize>,
+
+    /// The most bytes a file's closure can have in total.
+    max_closure_bytes: Option<u64>,
+
+    /// Libraries the build linked against, to warn about resolved
+    /// libraries with a different build ID.
+    #[serde(default)]
+    build_libraries: Vec<TemplatePath>,
+
+    /// A GNU ld map file from the build (from `-Map`), which the libraries
+    /// the build linked against are read from, like `build_libraries`.
+    build_link_map: Option<TemplatePath>,
+
+    /// Treat GPU drivers as host-provided libraries.
+    #[serde(default)]
+    gpu_drivers: bool,
+
+    /// `DT_NEEDED` entries to remove from ELF files.
+    #[serde(default)]
+    drop_libraries: HashSet<String>,
+
+    /// `DT_NEEDED` entries to rename in ELF files, from old name to new
+    /// name. New names can't be longer than old ones.
+    #[serde(default)]
+    rename_libraries: HashMap<String, String>,
+
+    /// Libraries that specific files load with `dlopen`, keyed by the
+    /// file's path. Each library is a name to search for, or a path to a
+    /// library file if it contains a `/`. Relative paths are relative to
+    /// the recipe.
+    #[serde(default)]
+    dlopen_libraries: HashMap<PathBuf, Vec<String>>,
+
+    /// Warn about undefined symbols that no library in the closure
+    /// defines.
+    #[serde(default)]
+    check_symbols: bool,
 }
 
 impl DynamicLinkingConfigTemplate {
     fn build(
         self,
         ctx: &AutopackConfigTemplateContext,
+        recipe_path: &Path,
     ) -> eyre::Result<brioche_autopack::DynamicLinkingConfig> {
         let Self {
             library_paths,
             skip_libraries,
             extra_libraries,
+            optional_libraries,
+            optional_library_globs,
             skip_unknown_libraries,
+            rpath_tokens,
+            host_libraries,
+            host_library_paths,
+            cut_libraries,
+            max_depth,
+            max_closure_libraries,
+            max_closure_bytes,
+            build_libraries,
+            build_link_map,
+            gpu_drivers,
+            drop_libraries,
+            rename_libraries,
+            dlopen_libraries,
+            check_symbols,
         } = self;
 
         let library_paths = library_paths
+            .into_iter()
+            .map(|path| Ok(recipe_path.join(path.build(ctx)?)))
+            .collect::<eyre::Result<_>>()?;
+        let mut build_libraries = build_libraries
             .into_iter()
             .map(|path| path.build(ctx))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        if let Some(build_link_map) = build_link_map {
+            let build_link_map = build_link_map.build(ctx)?;
+            build_libraries.extend(read_link_map_libraries(&build_link_map)?);
+        }
+        let dlopen_libraries = dlopen_libraries
+            .into_iter()
+            .map(|(path, libraries)| {
+                let libraries = libraries
+                    .into_iter()
+                    .map(|library| {
+                        if library.contains('/') {
+                            recipe_path
+                                .join(library)
+                                .into_os_string()
+                                .into_string()
+                                .map_err(|_| eyre::eyre!("invalid UTF-8 in dlopen library path"))
+                        } else {
+                            Ok(library)
+                        }
+                    })
+                    .collect::<eyre::Result<_>>()?;
+                Ok((recipe_path.join(path), libraries))
+            })
             .collect::<eyre::Result<_>>()?;
 
-        Ok(brioche_autopack::DynamicLinkingConfig {
-            library_paths,
-            skip_libraries,
-            extra_libraries,
-            skip_unknown_libraries,
+        let mut dynamic_linking = brioche_autopack::DynamicLinkingConfig::default();
+        dynamic_linking.library_paths = library_paths;
+        dynamic_linking.skip_libraries = skip_libraries;
+        dynamic_linking.extra_libraries = extra_libraries;
+        dynamic_linking.optional_libraries = optional_libraries;
+        dynamic_linking.optional_library_globs = optional_library_globs;
+        dynamic_linking.skip_unknown_libraries = skip_unknown_libraries;
+        dynamic_linking.rpath_tokens = rpath_tokens.build();
+        dynamic_linking.host_libraries = host_libraries;
+        dynamic_linking.host_library_paths = host_library_paths;
+        dynamic_linking.cut_libraries = cut_libraries;
+        dynamic_linking.max_depth = max_depth;
+        dynamic_linking.max_closure_libraries = max_closure_libraries;
+        dynamic_linking.max_closure_bytes = max_closure_bytes;
+        dynamic_linking.build_libraries = build_libraries;
+        dynamic_linking.drop_libraries = drop_libraries;
+        dynamic_linking.rename_libraries = rename_libraries;
+        dynamic_linking.dlopen_libraries = dlopen_libraries;
+        dynamic_linking.check_symbols = check_symbols;
+        if gpu_drivers {
+            dynamic_linking.add_gpu_drivers();
+        }
+
+        Ok(dynamic_linking)
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct RpathTokensTemplate {
+    lib: Option<String>,
+    platform: Option<String>,
+}
+
+impl RpathTokensTemplate {
+    fn build(self) -> brioche_autopack::RpathTokens {
+        let Self { lib, platform } = self;
+        let mut rpath_tokens = brioche_autopack::RpathTokens::default();
+        if let Some(lib) = lib {
+            rpath_tokens.lib = lib;
+        }
+        rpath_tokens.platform = platform;
+        rpath_tokens
+    }
+}
+
+/// Either a single packed executable, or a list of packed executables to
+/// pick from by the target, such as one for each architecture.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+enum PackedExecutableTemplate {
+    Path(TemplatePath),
+    Candidates(Vec<TemplatePath>),
+}
+
+/// Use the configured packed executable, falling back to the embedded one
+/// when brioche-packer was built with it. With a list of candidates, the
+/// first one built for the target is used, or the first one if there's no
+/// target.
+fn build_packed_executable(
+    ctx: &AutopackConfigTemplateContext,
+    packed_executable: Option<PackedExecutableTemplate>,
+    target: Option<&target_lexicon::Triple>,
+) -> eyre::Result<brioche_autopack::PackedExecutable> {
+    match packed_executable {
+        Some(PackedExecutableTemplate::Path(packed_executable)) => {
+            Ok(packed_executable.build(ctx)?.into())
+        }
+        Some(PackedExecutableTemplate::Candidates(candidates)) => {
+            let mut candidates = candidates.into_iter().map(|candidate| {
+                candidate
+                    .build(ctx)
+                    .map(brioche_autopack::PackedExecutable::from)
+            });
+            let Some(target) = target else {
+                return candidates
+                    .next()
+                    .ok_or_eyre("packedExecutable must include at least one candidate")?;
+            };
+
+            let mut mismatches = vec![];
+            for candidate in candidates {
+                let candidate = candidate?;
+                let mismatch = candidate
+                    .target_mismatch(target)
+                    .with_context(|| format!("failed to read packed executable {candidate:?}"))?;
+                match mismatch {
+                    Some(mismatch) => mismatches.push(format!("{candidate:?}: {mismatch}")),
+                    None => return Ok(candidate),
+                }
+            }
+
+            eyre::bail!(
+                "no packedExecutable candidate was built for target {target}: {}",
+                mismatches.join(", ")
+            );
+        }
+        #[cfg(feature = "embedded-packed-executable")]
+        None => Ok(brioche_autopack::PackedExecutable::embedded()),
+        #[cfg(not(feature = "embedded-packed-executable"))]
+        None => eyre::bail!(
+            "packedExecutable is required, since brioche-packer was built without an embedded packed executable"
+        ),
+    }
+}
+
+/// Metadata to attach to autopacked files, either as JSON or read from a
+/// file.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum AttachedMetadataTemplate {
+    #[serde(rename_all = "camelCase")]
+    Json {
+        format: String,
+        value: serde_json::Value,
+    },
+    #[serde(rename_all = "camelCase")]
+    File { format: String, path: TemplatePath },
+}
+
+impl AttachedMetadataTemplate {
+    fn build(self, ctx: &AutopackConfigTemplateContext) -> eyre::Result<(String, Vec<u8>)> {
+        match self {
+            Self::Json { format, value } => {
+                let metadata = serde_json::to_vec(&value)?;
+                Ok((format, metadata))
+            }
+            Self::File { format, path } => {
+                let path = path.build(ctx)?;
+                let metadata = std::fs::read(&path)
+                    .with_context(|| format!("failed to read metadata from {}", path.display()))?;
+                Ok((format, metadata))
+            }
+        }
+    }
+}
+
+/// A command that checks the resolved libraries of each ELF file, see
+/// [`brioche_autopack::ClosurePolicy::Command`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct ClosurePolicyTemplate {
+    command: TemplatePath,
+
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+impl ClosurePolicyTemplate {
+    fn build(
+        self,
+        ctx: &AutopackConfigTemplateContext,
+    ) -> eyre::Result<brioche_autopack::ClosurePolicy> {
+        let Self { command, args } = self;
+
+        Ok(brioche_autopack::ClosurePolicy::Command {
+            program: command.build(ctx)?,
+            args,
         })
     }
 }
 
+/// Read the shared libraries loaded by the linker from a GNU ld map file,
+/// which are listed as `LOAD <path>` lines.
+fn read_link_map_libraries(path: &Path) -> eyre::Result<Vec<PathBuf>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read link map {}", path.display()))?;
+    let libraries = contents
+        .lines()
+        .filter_map(|line| line.strip_prefix("LOAD "))
+        .map(|library| PathBuf::from(library.trim()))
+        .filter(|library| {
+            library
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(".so") || name.contains(".so."))
+        })
+        .collect();
+    Ok(libraries)
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DynamicBinaryConfigTemplate {
-    packed_executable: TemplatePath,
+    /// Defaults to `true`.
+    enabled: Option<bool>,
+
+    packed_executable: Option<PackedExecutableTemplate>,
 
+    /// Whether to wrap binaries with the packed executable or patch their
+    /// runpath (and interpreter) in place.
+    #[serde(default)]
+    mode: DynamicBinaryModeTemplate,
+
+    /// Directories within the recipe to search at runtime, for libraries
+    /// loaded with `dlopen`. Relative paths are relative to the recipe.
     #[serde(default)]
     extra_runtime_library_paths: Vec<PathBuf>,
 
     #[serde(flatten)]
     dynamic_linking: DynamicLinkingConfigTemplate,
+
+    #[serde(default)]
+    android: AndroidConfigTemplate,
+
+    /// Interpreters to use in place of the one a program requests.
+    #[serde(default)]
+    interpreter_remaps: HashMap<PathBuf, PathBuf>,
+
+    #[serde(default)]
+    self_exec: bool,
+
+    unwrapped_copy: Option<UnwrappedCopyTemplate>,
+
+    /// Autopack static binaries that load libraries with `dlopen`.
+    #[serde(default)]
+    static_dlopen: bool,
+
+    /// Autopack static-PIE programs with this kind of pack.
+    static_pie: Option<StaticPiePackTemplate>,
+
+    /// Record where to find each binary's original program, for debuggers.
+    #[serde(default)]
+    debug_links: bool,
+
+    /// The absolute path the resource dir is installed at, which the
+    /// `patch_interpreter` mode needs.
+    runtime_resource_dir: Option<PathBuf>,
 }
 
 impl DynamicBinaryConfigTemplate {
@@ -188,15 +1034,26 @@ impl DynamicBinaryConfigTemplate {
         self,
         ctx: &AutopackConfigTemplateContext,
         recipe_path: &Path,
+        target: Option<&target_lexicon::Triple>,
     ) -> eyre::Result<brioche_autopack::DynamicBinaryConfig> {
         let Self {
+            enabled,
             packed_executable,
+            mode,
             extra_runtime_library_paths,
             dynamic_linking,
+            android,
+            interpreter_remaps,
+            self_exec,
+            unwrapped_copy,
+            static_dlopen,
+            static_pie,
+            debug_links,
+            runtime_resource_dir,
         } = self;
 
-        let packed_executable = packed_executable.build(ctx)?;
-        let dynamic_linking = dynamic_linking.build(ctx)?;
+        let packed_executable = build_packed_executable(ctx, packed_executable, target)?;
+        let dynamic_linking = dynamic_linking.build(ctx, recipe_path)?;
 
         let extra_runtime_library_paths = extra_runtime_library_paths
             .into_iter()
@@ -210,53 +1067,177 @@ impl DynamicBinaryConfigTemplate {
             })
             .collect::<eyre::Result<_>>()?;
 
-        Ok(brioche_autopack::DynamicBinaryConfig {
-            packed_executable,
-            extra_runtime_library_paths,
-            dynamic_linking,
-        })
+        let mut config = brioche_autopack::DynamicBinaryConfig::new(packed_executable);
+        config.enabled = enabled.unwrap_or(true);
+        config.mode = mode.build();
+        config.extra_runtime_library_paths = extra_runtime_library_paths;
+        config.dynamic_linking = dynamic_linking;
+        config.android = android.build();
+        config.interpreter_remaps = interpreter_remaps;
+        config.self_exec = self_exec;
+        config.unwrapped_copy = unwrapped_copy.map(|unwrapped_copy| unwrapped_copy.build());
+        config.static_dlopen = static_dlopen;
+        config.static_pie = static_pie.map(|static_pie| static_pie.build());
+        config.debug_links = debug_links;
+        config.runtime_resource_dir = runtime_resource_dir;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum UnwrappedCopyTemplate {
+    #[serde(rename_all = "camelCase")]
+    Suffix { suffix: String },
+    #[serde(rename_all = "camelCase")]
+    Dir { dir: PathBuf },
+}
+
+impl UnwrappedCopyTemplate {
+    fn build(self) -> brioche_autopack::UnwrappedCopy {
+        match self {
+            Self::Suffix { suffix } => brioche_autopack::UnwrappedCopy::Suffix(suffix),
+            Self::Dir { dir } => brioche_autopack::UnwrappedCopy::Dir(dir),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+enum DynamicBinaryModeTemplate {
+    #[default]
+    Launcher,
+    PatchRunpath,
+    PatchInterpreter,
+}
+
+impl DynamicBinaryModeTemplate {
+    fn build(self) -> brioche_autopack::DynamicBinaryMode {
+        match self {
+            Self::Launcher => brioche_autopack::DynamicBinaryMode::Launcher,
+            Self::PatchRunpath => brioche_autopack::DynamicBinaryMode::PatchRunpath,
+            Self::PatchInterpreter => brioche_autopack::DynamicBinaryMode::PatchInterpreter,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum StaticPiePackTemplate {
+    Metadata,
+    Static,
+}
+
+impl StaticPiePackTemplate {
+    fn build(self) -> brioche_autopack::StaticPiePack {
+        match self {
+            Self::Metadata => brioche_autopack::StaticPiePack::Metadata,
+            Self::Static => brioche_autopack::StaticPiePack::Static,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct AndroidConfigTemplate {
+    system_libraries: Option<HashSet<String>>,
+}
+
+impl AndroidConfigTemplate {
+    fn build(self) -> brioche_autopack::AndroidConfig {
+        let Self { system_libraries } = self;
+        let mut android = brioche_autopack::AndroidConfig::default();
+        if let Some(system_libraries) = system_libraries {
+            android.system_libraries = system_libraries;
+        }
+        android
     }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SharedLibraryConfigTemplate {
+    /// Defaults to `true`.
+    enabled: Option<bool>,
+
     #[serde(flatten)]
     dynamic_linking: DynamicLinkingConfigTemplate,
 
     #[serde(default)]
     allow_empty: bool,
+
+    #[serde(default)]
+    normalize_soname: bool,
+
+    #[serde(default)]
+    android: AndroidConfigTemplate,
 }
 
 impl SharedLibraryConfigTemplate {
     fn build(
         self,
         ctx: &AutopackConfigTemplateContext,
+        recipe_path: &Path,
     ) -> eyre::Result<brioche_autopack::SharedLibraryConfig> {
         let Self {
+            enabled,
             dynamic_linking,
             allow_empty,
+            normalize_soname,
+            android,
         } = self;
 
-        let dynamic_linking = dynamic_linking.build(ctx)?;
+        let dynamic_linking = dynamic_linking.build(ctx, recipe_path)?;
 
-        Ok(brioche_autopack::SharedLibraryConfig {
-            dynamic_linking,
-            allow_empty,
-        })
+        let mut config = brioche_autopack::SharedLibraryConfig::default();
+        config.enabled = enabled.unwrap_or(true);
+        config.dynamic_linking = dynamic_linking;
+        config.allow_empty = allow_empty;
+        config.normalize_soname = normalize_soname;
+        config.android = android.build();
+        Ok(config)
     }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ScriptConfigTemplate {
-    packed_executable: TemplatePath,
+    /// Defaults to `true`.
+    enabled: Option<bool>,
+
+    packed_executable: Option<PackedExecutableTemplate>,
 
     #[serde(default)]
     env: HashMap<String, EnvValueTemplate>,
 
+    /// A file to load env vars from, relative to the recipe. Files ending
+    /// in `.json` use the same format as `env`, and other files use the
+    /// dotenv format. Vars from `env` take precedence.
+    env_file: Option<TemplatePath>,
+
     #[serde(default)]
     clear_env: bool,
+
+    #[serde(default)]
+    forward_args: ForwardArgsTemplate,
+
+    #[serde(default)]
+    interpreter_fallbacks: HashMap<String, Vec<String>>,
+
+    /// The shell to run `sh` scripts with, such as `bash` with `--posix`.
+    posix_shell: Option<PosixShellTemplate>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct PosixShellTemplate {
+    command: String,
+
+    #[serde(default)]
+    args: Vec<String>,
 }
 
 impl ScriptConfigTemplate {
@@ -264,44 +1245,95 @@ impl ScriptConfigTemplate {
         self,
         ctx: &AutopackConfigTemplateContext,
         recipe_path: &Path,
+        target: Option<&target_lexicon::Triple>,
     ) -> eyre::Result<brioche_autopack::ScriptConfig> {
         let Self {
+            enabled,
             packed_executable,
             env,
+            env_file,
             clear_env,
+            forward_args,
+            interpreter_fallbacks,
+            posix_shell,
         } = self;
 
-        let packed_executable = packed_executable.build(ctx)?;
-        let env = env
+        let packed_executable = build_packed_executable(ctx, packed_executable, target)?;
+        let env_file = env_file
+            .map(|path| {
+                let path = recipe_path.join(path.build(ctx)?);
+                load_env_file(&path).with_context(|| format!("failed to load env file {path:?}"))
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let env = env_file
             .into_iter()
+            .chain(env)
             .map(|(env_var, value)| {
                 let value = value.build(ctx, &env_var)?;
                 eyre::Ok((env_var, value))
             })
             .collect::<eyre::Result<_>>()?;
 
-        Ok(brioche_autopack::ScriptConfig {
-            packed_executable,
-            base_path: Some(recipe_path.into()),
-            env,
-            clear_env,
-        })
+        let mut config = brioche_autopack::ScriptConfig::new(packed_executable);
+        config.enabled = enabled.unwrap_or(true);
+        config.base_path = Some(recipe_path.into());
+        config.env = env;
+        config.clear_env = clear_env;
+        config.forward_args = forward_args.build();
+        config.interpreter_fallbacks = interpreter_fallbacks;
+        config.posix_shell = posix_shell.map(|PosixShellTemplate { command, args }| {
+            brioche_autopack::PosixShell::new(command, args)
+        });
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum ForwardArgsTemplate {
+    #[default]
+    Rest,
+    #[serde(rename_all = "camelCase")]
+    RestAfterSeparator {
+        separator: String,
+    },
+    None,
+}
+
+impl ForwardArgsTemplate {
+    fn build(self) -> brioche_autopack::ForwardArgs {
+        match self {
+            Self::Rest => brioche_autopack::ForwardArgs::Rest,
+            Self::RestAfterSeparator { separator } => {
+                brioche_autopack::ForwardArgs::RestAfterSeparator(separator)
+            }
+            Self::None => brioche_autopack::ForwardArgs::None,
+        }
     }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct RepackConfigTemplate {}
+pub struct RepackConfigTemplate {
+    /// Defaults to `true`.
+    enabled: Option<bool>,
+}
 
 impl RepackConfigTemplate {
     fn build(self) -> brioche_autopack::RepackConfig {
-        let Self {} = self;
-        brioche_autopack::RepackConfig {}
+        let Self { enabled } = self;
+        let mut repack = brioche_autopack::RepackConfig::default();
+        repack.enabled = enabled.unwrap_or(true);
+        repack
     }
 }
 
 #[serde_with::serde_as]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 enum EnvValueTemplate {
@@ -358,13 +1390,179 @@ impl EnvValueTemplate {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+/// Load env vars from a JSON or dotenv file. In dotenv files, each line
+/// sets a var with `NAME=value`, and comments start with a `#` at the
+/// start of a line or after whitespace in an unquoted value. Values can
+/// include `${relative:PATH}`, `${resource:PATH}`, and `${variable:NAME}`
+/// templates, and `$$` for a literal `$`. Relative paths are relative to
+/// the recipe, the same as in `env`. Templates aren't expanded in
+/// single-quoted values.
+fn load_env_file(path: &Path) -> eyre::Result<Vec<(String, EnvValueTemplate)>> {
+    let contents = std::fs::read_to_string(path)?;
+
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        let env: HashMap<String, EnvValueTemplate> = serde_json::from_str(&contents)?;
+        return Ok(env.into_iter().collect());
+    }
+
+    let mut env = vec![];
+    for (line_index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (env_var, value) = parse_dotenv_line(line)
+            .with_context(|| format!("invalid line {}: {line:?}", line_index + 1))?;
+        env.push((env_var, EnvValueTemplate::Set { value }));
+    }
+
+    Ok(env)
+}
+
+fn parse_dotenv_line(line: &str) -> eyre::Result<(String, EnvValueTemplateValue)> {
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (env_var, raw_value) = line
+        .split_once('=')
+        .ok_or_else(|| eyre::eyre!("expected `NAME=value`"))?;
+    let env_var = env_var.trim();
+    eyre::ensure!(
+        !env_var.is_empty() && !env_var.contains(|c: char| c.is_ascii_whitespace()),
+        "invalid env var name {env_var:?}"
+    );
+
+    let value = raw_value.trim_start();
+    let value = if let Some(value) = value.strip_prefix('\'') {
+        let (value, rest) = value
+            .split_once('\'')
+            .ok_or_else(|| eyre::eyre!("unterminated single quote"))?;
+        ensure_dotenv_line_end(rest)?;
+        EnvValueTemplateValue::from_literal(value)
+    } else if let Some(value) = value.strip_prefix('"') {
+        let mut unescaped = String::new();
+        let mut chars = value.chars();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => unescaped.push('\n'),
+                    Some('t') => unescaped.push('\t'),
+                    Some(c @ ('"' | '\\' | '$')) => unescaped.push(c),
+                    Some(c) => eyre::bail!("unsupported escape \\{c}"),
+                    None => eyre::bail!("unterminated double quote"),
+                },
+                Some(c) => unescaped.push(c),
+                None => eyre::bail!("unterminated double quote"),
+            }
+        }
+        ensure_dotenv_line_end(chars.as_str())?;
+        parse_dotenv_template(&unescaped)?
+    } else {
+        // Unquoted values end at a comment, which starts with a `#` after
+        // any whitespace
+        let comment_start = raw_value
+            .match_indices('#')
+            .find(|&(index, _)| raw_value[..index].ends_with(|c: char| c.is_whitespace()));
+        let value = match comment_start {
+            Some((comment_start, _)) => &raw_value[..comment_start],
+            None => raw_value,
+        };
+        parse_dotenv_template(value.trim())?
+    };
+
+    Ok((env_var.to_string(), value))
+}
+
+fn ensure_dotenv_line_end(rest: &str) -> eyre::Result<()> {
+    let rest = rest.trim_start();
+    eyre::ensure!(
+        rest.is_empty() || rest.starts_with('#'),
+        "unexpected text after quoted value: {rest:?}"
+    );
+    Ok(())
+}
+
+fn parse_dotenv_template(value: &str) -> eyre::Result<EnvValueTemplateValue> {
+    let mut components = vec![];
+    let mut literal = String::new();
+    let mut rest = value;
+
+    while let Some(dollar_index) = rest.find('$') {
+        literal.push_str(&rest[..dollar_index]);
+        rest = &rest[dollar_index + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            literal.push('$');
+            rest = after;
+            continue;
+        }
+
+        let Some(after) = rest.strip_prefix('{') else {
+            literal.push('$');
+            continue;
+        };
+        let (template, after) = after
+            .split_once('}')
+            .ok_or_else(|| eyre::eyre!("unterminated template in {value:?}"))?;
+        let component = match template.split_once(':') {
+            Some(("relative", path)) => EnvValueTemplateValueComponent::RelativePath {
+                path: path.as_bytes().to_vec(),
+            },
+            Some(("resource", resource)) => EnvValueTemplateValueComponent::Resource {
+                resource: resource.as_bytes().to_vec(),
+            },
+            Some(("variable", variable)) => {
+                EnvValueTemplateValueComponent::Variable(TemplateVariable {
+                    variable: variable.to_string(),
+                })
+            }
+            _ => eyre::bail!(
+                "unsupported template ${{{template}}}, expected `${{relative:PATH}}`, `${{resource:PATH}}`, or `${{variable:NAME}}`"
+            ),
+        };
+
+        if !literal.is_empty() {
+            components.push(EnvValueTemplateValueComponent::Literal {
+                value: std::mem::take(&mut literal).into_bytes(),
+            });
+        }
+        components.push(component);
+        rest = after;
+    }
+
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        components.push(EnvValueTemplateValueComponent::Literal {
+            value: literal.into_bytes(),
+        });
+    }
+
+    Ok(EnvValueTemplateValue { components })
+}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 #[serde(rename_all = "camelCase")]
 struct EnvValueTemplateValue {
     components: Vec<EnvValueTemplateValueComponent>,
 }
 
 impl EnvValueTemplateValue {
+    fn from_literal(value: &str) -> Self {
+        let components = if value.is_empty() {
+            vec![]
+        } else {
+            vec![EnvValueTemplateValueComponent::Literal {
+                value: value.as_bytes().to_vec(),
+            }]
+        };
+        Self { components }
+    }
+
     fn build(
         self,
         ctx: &AutopackConfigTemplateContext,
@@ -381,7 +1579,9 @@ impl EnvValueTemplateValue {
 }
 
 #[serde_with::serde_as]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 enum EnvValueTemplateValueComponent {
@@ -457,13 +1657,180 @@ impl TemplatePath {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 #[serde(rename_all = "camelCase")]
 pub struct TemplateVariable {
     variable: String,
 }
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum TemplateVariableValue {
     Path(PathBuf),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        load_env_file, parse_dotenv_line, parse_dotenv_template, EnvValueTemplate,
+        EnvValueTemplateValue, EnvValueTemplateValueComponent, TemplateVariable,
+    };
+
+    fn literal(value: &str) -> EnvValueTemplateValueComponent {
+        EnvValueTemplateValueComponent::Literal {
+            value: value.as_bytes().to_vec(),
+        }
+    }
+
+    fn value(components: Vec<EnvValueTemplateValueComponent>) -> EnvValueTemplateValue {
+        EnvValueTemplateValue { components }
+    }
+
+    fn assert_line(line: &str, env_var: &str, expected: EnvValueTemplateValue) {
+        let (parsed_env_var, parsed) = parse_dotenv_line(line).unwrap();
+        assert_eq!(parsed_env_var, env_var, "{line:?}");
+        assert_eq!(parsed, expected, "{line:?}");
+    }
+
+    fn assert_line_error(line: &str, message: &str) {
+        let error = parse_dotenv_line(line).unwrap_err();
+        assert!(
+            error.to_string().contains(message),
+            "{line:?}: expected error containing {message:?}, got {error:?}"
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv_line() {
+        assert_line("FOO=bar", "FOO", value(vec![literal("bar")]));
+        assert_line("export FOO=bar", "FOO", value(vec![literal("bar")]));
+        assert_line("FOO = bar ", "FOO", value(vec![literal("bar")]));
+        assert_line("FOO=", "FOO", value(vec![]));
+        assert_line("FOO=a=b", "FOO", value(vec![literal("a=b")]));
+
+        // Unquoted values end at a `#` after whitespace
+        assert_line("FOO=bar # comment", "FOO", value(vec![literal("bar")]));
+        assert_line("FOO=bar\t# comment", "FOO", value(vec![literal("bar")]));
+        assert_line("FOO= # comment", "FOO", value(vec![]));
+        assert_line("FOO=bar#baz", "FOO", value(vec![literal("bar#baz")]));
+        assert_line("FOO=#bar", "FOO", value(vec![literal("#bar")]));
+
+        // Single-quoted values are taken as-is
+        assert_line(
+            "FOO='a # b ${relative:x} \\n'",
+            "FOO",
+            value(vec![literal("a # b ${relative:x} \\n")]),
+        );
+        assert_line("FOO=''", "FOO", value(vec![]));
+        assert_line("FOO='bar' # comment", "FOO", value(vec![literal("bar")]));
+
+        // Double-quoted values support escapes and templates
+        assert_line(
+            r#"FOO="a # b\n\t\"\\\$""#,
+            "FOO",
+            value(vec![literal("a # b\n\t\"\\$")]),
+        );
+        assert_line(
+            r#"FOO="${variable:x}" # comment"#,
+            "FOO",
+            value(vec![EnvValueTemplateValueComponent::Variable(
+                TemplateVariable {
+                    variable: "x".to_string(),
+                },
+            )]),
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv_line_errors() {
+        assert_line_error("FOO", "expected `NAME=value`");
+        assert_line_error("=bar", "invalid env var name");
+        assert_line_error("FOO BAR=baz", "invalid env var name");
+        assert_line_error("FOO='bar", "unterminated single quote");
+        assert_line_error("FOO='bar' baz", "unexpected text after quoted value");
+        assert_line_error(r#"FOO="bar"#, "unterminated double quote");
+        assert_line_error(r#"FOO="bar\"#, "unterminated double quote");
+        assert_line_error(r#"FOO="bar\x""#, "unsupported escape \\x");
+        assert_line_error(r#"FOO="bar" baz"#, "unexpected text after quoted value");
+        assert_line_error("FOO=${relative:x", "unterminated template");
+    }
+
+    #[test]
+    fn test_parse_dotenv_template() {
+        assert_eq!(parse_dotenv_template("").unwrap(), value(vec![]));
+        assert_eq!(
+            parse_dotenv_template("a$$b$c$").unwrap(),
+            value(vec![literal("a$b$c$")])
+        );
+        assert_eq!(
+            parse_dotenv_template("${relative:lib}:${resource:share}:${variable:x}").unwrap(),
+            value(vec![
+                EnvValueTemplateValueComponent::RelativePath {
+                    path: b"lib".to_vec(),
+                },
+                literal(":"),
+                EnvValueTemplateValueComponent::Resource {
+                    resource: b"share".to_vec(),
+                },
+                literal(":"),
+                EnvValueTemplateValueComponent::Variable(TemplateVariable {
+                    variable: "x".to_string(),
+                }),
+            ])
+        );
+
+        let error = parse_dotenv_template("${env:HOME}").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("unsupported template ${env:HOME}"));
+        let error = parse_dotenv_template("${relative}").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("unsupported template ${relative}"));
+    }
+
+    #[test]
+    fn test_load_env_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let dotenv_path = dir.path().join(".env");
+        std::fs::write(
+            &dotenv_path,
+            "# comment\n\n  FOO=foo\n    # indented comment\nexport BAR='bar'\n",
+        )
+        .unwrap();
+        let env = load_env_file(&dotenv_path).unwrap();
+        assert_eq!(
+            env,
+            [
+                (
+                    "FOO".to_string(),
+                    EnvValueTemplate::Set {
+                        value: value(vec![literal("foo")]),
+                    },
+                ),
+                (
+                    "BAR".to_string(),
+                    EnvValueTemplate::Set {
+                        value: value(vec![literal("bar")]),
+                    },
+                ),
+            ]
+        );
+
+        let invalid_path = dir.path().join("invalid.env");
+        std::fs::write(&invalid_path, "FOO=foo\nBAR\n").unwrap();
+        let error = load_env_file(&invalid_path).unwrap_err();
+        assert_eq!(error.to_string(), r#"invalid line 2: "BAR""#);
+
+        let json_path = dir.path().join("env.json");
+        std::fs::write(&json_path, r#"{ "FOO": { "type": "clear" } }"#).unwrap();
+        let env = load_env_file(&json_path).unwrap();
+        assert_eq!(env, [("FOO".to_string(), EnvValueTemplate::Clear)]);
+
+        let missing_path = dir.path().join("missing.env");
+        assert!(load_env_file(&missing_path).is_err());
+    }
+}
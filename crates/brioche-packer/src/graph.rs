@@ -0,0 +1,322 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice as _;
+use eyre::Context as _;
+
+use crate::wrapped::find_wrapped;
+
+/// The dependency closure of the packed files in a tree. Binaries need
+/// their interpreter, resources, and libraries, and each library is
+/// provided by a resource.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphNode {
+    pub id: String,
+    pub kind: GraphNodeKind,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum GraphNodeKind {
+    /// A packed file in the tree.
+    Binary,
+    /// A library, by name.
+    Library,
+    /// An interpreter resource.
+    Interpreter,
+    /// Any other resource, such as a program or library directory.
+    Resource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: GraphEdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum GraphEdgeKind {
+    Needs,
+    Provides,
+}
+
+impl DependencyGraph {
+    /// Build the graph for all packed files under `root`.
+    pub fn from_tree(root: &Path) -> eyre::Result<Self> {
+        let mut builder = GraphBuilder::default();
+
+        for wrapped in find_wrapped(root)? {
+            let all_resource_dirs = brioche_resources::find_resource_dirs(&wrapped.path, true)
+                .with_context(|| format!("failed to find resource dirs for {:?}", wrapped.path))?;
+
+            let relative_path = wrapped.path.strip_prefix(root).unwrap_or(&wrapped.path);
+            let relative_path = if relative_path.as_os_str().is_empty() {
+                wrapped.path.file_name().map(Path::new).unwrap_or(root)
+            } else {
+                relative_path
+            };
+            let binary = builder.node(GraphNodeKind::Binary, relative_path.display().to_string());
+
+            builder
+                .add_pack(&binary, &wrapped.pack, &all_resource_dirs)
+                .with_context(|| format!("failed to add {:?} to graph", wrapped.path))?;
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Find the binaries that need a library or resource matching the
+    /// query, either directly or through another resource.
+    pub fn dependents(&self, query: &UsageQuery) -> Vec<&GraphNode> {
+        let mut dependents_of: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for edge in &self.edges {
+            if edge.kind == GraphEdgeKind::Needs {
+                dependents_of.entry(&edge.to).or_default().push(&edge.from);
+            }
+        }
+
+        let mut queue: Vec<&str> = self
+            .nodes
+            .iter()
+            .filter(|node| query.matches(node))
+            .map(|node| &*node.id)
+            .collect();
+        let mut visited: BTreeSet<&str> = queue.iter().copied().collect();
+        while let Some(node) = queue.pop() {
+            for &dependent in dependents_of.get(node).into_iter().flatten() {
+                if visited.insert(dependent) {
+                    queue.push(dependent);
+                }
+            }
+        }
+
+        self.nodes
+            .iter()
+            .filter(|node| node.kind == GraphNodeKind::Binary && visited.contains(&*node.id))
+            .collect()
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph dependencies {\n");
+        for node in &self.nodes {
+            let shape = match node.kind {
+                GraphNodeKind::Binary => "box",
+                GraphNodeKind::Library => "ellipse",
+                GraphNodeKind::Interpreter => "diamond",
+                GraphNodeKind::Resource => "note",
+            };
+            dot.push_str(&format!(
+                "    {} [label={}, shape={shape}];\n",
+                dot_string(&node.id),
+                dot_string(&node.label),
+            ));
+        }
+        for edge in &self.edges {
+            let label = match edge.kind {
+                GraphEdgeKind::Needs => "needs",
+                GraphEdgeKind::Provides => "provides",
+            };
+            dot.push_str(&format!(
+                "    {} -> {} [label={label}];\n",
+                dot_string(&edge.from),
+                dot_string(&edge.to),
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// A library or resource to look up the dependents of.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum UsageQuery {
+    /// A library by name, such as `libssl.so.3`.
+    Library(String),
+    /// A resource, either by its full path within the resource dir or by
+    /// its hash.
+    Resource(String),
+}
+
+impl UsageQuery {
+    fn matches(&self, node: &GraphNode) -> bool {
+        match self {
+            Self::Library(name) => node.kind == GraphNodeKind::Library && node.label == *name,
+            Self::Resource(resource) => {
+                if !matches!(
+                    node.kind,
+                    GraphNodeKind::Resource | GraphNodeKind::Interpreter
+                ) {
+                    return false;
+                }
+
+                // Named resources end in a `<hash>.x` component for
+                // executables, or just `<hash>` otherwise
+                node.label == *resource
+                    || Path::new(&node.label).components().any(|component| {
+                        let component = component.as_os_str().to_string_lossy();
+                        component.strip_suffix(".x").unwrap_or(&component) == resource
+                    })
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct GraphBuilder {
+    nodes: BTreeMap<String, GraphNode>,
+    edges: BTreeSet<GraphEdge>,
+    visited_library_dirs: BTreeSet<PathBuf>,
+}
+
+impl GraphBuilder {
+    fn node(&mut self, kind: GraphNodeKind, label: String) -> String {
+        let prefix = match kind {
+            GraphNodeKind::Binary => "binary",
+            GraphNodeKind::Library => "library",
+            GraphNodeKind::Interpreter => "interpreter",
+            GraphNodeKind::Resource => "resource",
+        };
+        let id = format!("{prefix}:{label}");
+        self.nodes.entry(id.clone()).or_insert_with(|| GraphNode {
+            id: id.clone(),
+            kind,
+            label,
+        });
+        id
+    }
+
+    fn edge(&mut self, from: &str, to: &str, kind: GraphEdgeKind) {
+        self.edges.insert(GraphEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            kind,
+        });
+    }
+
+    fn add_pack(
+        &mut self,
+        from: &str,
+        pack: &brioche_pack::Pack,
+        all_resource_dirs: &[PathBuf],
+    ) -> eyre::Result<()> {
+        match pack {
+            brioche_pack::Pack::LdLinux {
+                program,
+                interpreter,
+                library_dirs,
+                runtime_library_dirs: _,
+            } => {
+                let interpreter = self.node(
+                    GraphNodeKind::Interpreter,
+                    resource_path(interpreter)?.display().to_string(),
+                );
+                self.edge(from, &interpreter, GraphEdgeKind::Needs);
+
+                let program = self.node(
+                    GraphNodeKind::Resource,
+                    resource_path(program)?.display().to_string(),
+                );
+                self.edge(from, &program, GraphEdgeKind::Needs);
+
+                self.add_library_dirs(from, library_dirs, all_resource_dirs)?;
+            }
+            brioche_pack::Pack::Static { library_dirs } => {
+                self.add_library_dirs(from, library_dirs, all_resource_dirs)?;
+            }
+            brioche_pack::Pack::Metadata { resource_paths, .. } => {
+                for path in resource_paths {
+                    let resource = self.node(
+                        GraphNodeKind::Resource,
+                        resource_path(path)?.display().to_string(),
+                    );
+                    self.edge(from, &resource, GraphEdgeKind::Needs);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the libraries from each library dir. Autopacking puts each
+    /// library in its own directory, so each library dir resource provides
+    /// the libraries within it.
+    fn add_library_dirs(
+        &mut self,
+        from: &str,
+        library_dirs: &[Vec<u8>],
+        all_resource_dirs: &[PathBuf],
+    ) -> eyre::Result<()> {
+        for library_dir in library_dirs {
+            let library_dir = resource_path(library_dir)?;
+            let resource = self.node(GraphNodeKind::Resource, library_dir.display().to_string());
+            self.edge(from, &resource, GraphEdgeKind::Needs);
+
+            let library_dir_path =
+                brioche_resources::find_in_resource_dirs(all_resource_dirs, library_dir)
+                    .ok_or_else(|| eyre::eyre!("resource not found: {}", library_dir.display()))?;
+            let is_new_library_dir = self.visited_library_dirs.insert(library_dir_path.clone());
+
+            let mut entries = std::fs::read_dir(&library_dir_path)
+                .with_context(|| format!("failed to read directory {library_dir_path:?}"))?
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+
+            for entry in entries {
+                let library_name = entry.file_name().to_string_lossy().into_owned();
+                let library = self.node(GraphNodeKind::Library, library_name);
+                self.edge(from, &library, GraphEdgeKind::Needs);
+                self.edge(&resource, &library, GraphEdgeKind::Provides);
+
+                // Libraries can have their own packs, so follow them to
+                // include transitive dependencies. These are attached to the
+                // resource rather than the library name, since different
+                // resources can provide the same library
+                if !is_new_library_dir {
+                    continue;
+                }
+                let Ok(mut library_file) = std::fs::File::open(entry.path()) else {
+                    continue;
+                };
+                if let Ok(extracted) = brioche_resources::extract_padded_pack(&mut library_file) {
+                    self.add_pack(&resource, &extracted.pack, all_resource_dirs)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build(self) -> DependencyGraph {
+        DependencyGraph {
+            nodes: self.nodes.into_values().collect(),
+            edges: self.edges.into_iter().collect(),
+        }
+    }
+}
+
+fn resource_path(path: &[u8]) -> eyre::Result<&Path> {
+    path.to_path()
+        .map_err(|_| eyre::eyre!("invalid resource path: {}", bstr::BStr::new(path)))
+}
+
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,13 @@
+//! Library interface for `brioche-packer`.
+//!
+//! Only the items exported from [`api`] are covered by semver guarantees.
+//! Everything else is an implementation detail of the `brioche-packer`
+//! binary and may change in any release.
+
+pub mod api;
+mod autopack_template;
+mod graph;
+#[cfg(feature = "qemu")]
+mod qemu;
+mod resources;
+mod wrapped;
@@ -1,16 +1,17 @@
 use std::{
-    io::Seek as _,
+    collections::BTreeMap,
+    io::{Read as _, Write as _},
     os::unix::fs::OpenOptionsExt as _,
     path::{Path, PathBuf},
     process::ExitCode,
+    sync::{Arc, Mutex},
 };
 
+use brioche_packer::api;
 use bstr::{ByteSlice as _, ByteVec as _};
 use clap::Parser;
 use eyre::{Context as _, OptionExt as _};
 
-mod autopack_template;
-
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Parser)]
 enum Args {
@@ -23,6 +24,17 @@ enum Args {
         pack: String,
     },
     Autopack(AutopackArgs),
+    /// Print the config `autopack` would use after applying defaults,
+    /// variables, and env files, along with the paths searched for
+    /// dependencies
+    ExplainConfig(ExplainConfigArgs),
+    ApplyPlan {
+        plan: PathBuf,
+        /// Only check that the plan's inputs haven't changed, without
+        /// applying it
+        #[arg(long)]
+        check: bool,
+    },
     Read {
         program: PathBuf,
     },
@@ -30,6 +42,21 @@ enum Args {
         program: PathBuf,
     },
     UpdateSource(UpdateSourceArgs),
+    Graph {
+        path: PathBuf,
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    Query(QueryArgs),
+    Resources(ResourcesArgs),
+    #[cfg(feature = "qemu")]
+    QemuCheck(QemuCheckArgs),
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
 }
 
 impl std::str::FromStr for AutopackTemplateValue {
@@ -46,7 +73,7 @@ impl std::str::FromStr for AutopackTemplateValue {
         let value = match ty {
             "path" => {
                 let value = PathBuf::from(value);
-                autopack_template::TemplateVariableValue::Path(value)
+                api::TemplateVariableValue::Path(value)
             }
             _ => {
                 eyre::bail!("unknown type {ty:?}, expected \"path\"");
@@ -66,6 +93,17 @@ fn main() -> ExitCode {
         Ok(()) => ExitCode::SUCCESS,
         Err(err) => {
             eprintln!("{err:#}");
+
+            // Show source context for errors that support it
+            if let Some(diagnostic) = brioche_autopack::diagnostics::find_diagnostic(&err) {
+                let mut rendered = String::new();
+                let handler = miette::GraphicalReportHandler::new();
+                if handler.render_report(&mut rendered, diagnostic).is_ok() {
+                    eprintln!();
+                    eprint!("{rendered}");
+                }
+            }
+
             ExitCode::FAILURE
         }
     }
@@ -98,9 +136,21 @@ fn run() -> eyre::Result<()> {
         Args::Autopack(args) => {
             run_autopack(args)?;
         }
+        Args::ExplainConfig(args) => {
+            run_explain_config(args)?;
+        }
+        Args::ApplyPlan { plan, check } => {
+            let plan = std::fs::read(&plan)
+                .with_context(|| format!("failed to read plan {}", plan.display()))?;
+            let plan: api::AutopackPlan = serde_json::from_slice(&plan)?;
+            if check {
+                plan.verify_inputs()?;
+            } else {
+                api::apply(&plan)?;
+            }
+        }
         Args::Read { program } => {
-            let mut program = std::fs::File::open(program)?;
-            let extracted = brioche_pack::extract_pack(&mut program)?;
+            let extracted = brioche_autopack::read_pack(&program)?;
 
             serde_json::to_writer_pretty(std::io::stdout().lock(), &extracted.pack)?;
             println!();
@@ -108,8 +158,7 @@ fn run() -> eyre::Result<()> {
         Args::SourcePath {
             program: program_path,
         } => {
-            let mut program = std::fs::File::open(&program_path)?;
-            let extracted = brioche_pack::extract_pack(&mut program)?;
+            let extracted = brioche_autopack::read_pack(&program_path)?;
             let all_resource_dirs = brioche_resources::find_resource_dirs(&program_path, true)?;
 
             let source_path =
@@ -130,6 +179,29 @@ fn run() -> eyre::Result<()> {
         Args::UpdateSource(args) => {
             run_update_source(args)?;
         }
+        Args::Query(args) => {
+            run_query(args)?;
+        }
+        Args::Resources(args) => {
+            run_resources(args)?;
+        }
+        #[cfg(feature = "qemu")]
+        Args::QemuCheck(args) => {
+            run_qemu_check(args)?;
+        }
+        Args::Graph { path, format } => {
+            let graph = api::DependencyGraph::from_tree(&path)?;
+
+            match format {
+                GraphFormat::Dot => {
+                    print!("{}", graph.to_dot());
+                }
+                GraphFormat::Json => {
+                    serde_json::to_writer_pretty(std::io::stdout().lock(), &graph)?;
+                    println!();
+                }
+            }
+        }
     }
 
     Ok(())
@@ -144,59 +216,533 @@ struct AutopackArgs {
     #[arg(required_unless_present = "schema")]
     recipe_path: Option<PathBuf>,
 
-    #[arg(long, required_unless_present = "schema")]
+    #[arg(long, required_unless_present_any = ["schema", "config_file"])]
     config: Option<String>,
 
+    /// Read the config from a JSON or TOML file instead of `--config`
+    #[arg(long, conflicts_with = "config")]
+    config_file: Option<PathBuf>,
+
     #[arg(long = "var", value_parser)]
     variables: Vec<AutopackTemplateValue>,
+
+    /// Add resources to this resource dir instead of the one found from
+    /// the recipe path
+    #[arg(long)]
+    resource_dir: Option<PathBuf>,
+
+    /// Write a plan of what autopacking would do to this file instead of
+    /// autopacking, which can be applied with `apply-plan`
+    #[arg(long)]
+    plan: Option<PathBuf>,
+
+    /// Print the libraries in each autopacked file's closure, along with
+    /// the chain of libraries that pulled each one in
+    #[arg(long)]
+    trace_resolution: bool,
+
+    /// Ask which file to use when link dependencies provide different
+    /// files for the same library or interpreter
+    #[arg(long)]
+    interactive: bool,
+
+    /// Read choices between link dependencies from this JSON file, and
+    /// record new ones to it. Without `--interactive`, new choices use the
+    /// first link dependency
+    #[arg(long)]
+    answers_file: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
 struct AutopackTemplateValue {
     name: String,
-    value: autopack_template::TemplateVariableValue,
+    value: api::TemplateVariableValue,
 }
 
 fn run_autopack(args: AutopackArgs) -> eyre::Result<()> {
     if args.schema {
-        let schema = schemars::schema_for!(autopack_template::AutopackConfigTemplate);
+        let schema = schemars::schema_for!(api::AutopackConfigTemplate);
         serde_json::to_writer_pretty(std::io::stdout().lock(), &schema)?;
         println!();
         return Ok(());
     }
 
     let recipe_path = args.recipe_path.ok_or_eyre("missing RECIPE_PATH")?;
-    let config = args.config.ok_or_eyre("missing --config")?;
+    let config_template = read_config_template(args.config_file.as_deref(), args.config)?;
+    let mut config = build_config(
+        config_template,
+        recipe_path,
+        args.variables,
+        args.resource_dir,
+    )?;
+
+    let answers = match &args.answers_file {
+        Some(answers_file) if answers_file.exists() => CandidateAnswers::read(answers_file)?,
+        _ => CandidateAnswers::default(),
+    };
+    let answers = Arc::new(Mutex::new(answers));
+    if args.interactive || args.answers_file.is_some() {
+        let answers = answers.clone();
+        let interactive = args.interactive;
+        config.candidate_chooser = Some(api::CandidateChooser::Callback(Arc::new(
+            move |kind, name, candidates| {
+                let mut answers = answers.lock().unwrap_or_else(|error| error.into_inner());
+                answers.choose(interactive, kind, name, candidates)
+            },
+        )));
+    }
 
-    let config_template =
-        serde_json::from_str::<autopack_template::AutopackConfigTemplate>(&config);
-    let config_template = match config_template {
-        Ok(config_template) => config_template,
-        Err(err) => {
-            return Err(err)
-                .context("failed to parse config template (pass --schema to show schema)");
+    let result = match args.plan {
+        Some(plan_path) => api::plan(&config).and_then(|(plan, report)| {
+            let mut plan_file = brioche_resources::AtomicWriter::new(&plan_path, 0o666)
+                .with_context(|| format!("failed to create {}", plan_path.display()))?;
+            {
+                let mut plan_writer = std::io::BufWriter::new(&mut plan_file);
+                serde_json::to_writer(&mut plan_writer, &plan)?;
+                plan_writer.flush()?;
+            }
+            plan_file
+                .commit()
+                .with_context(|| format!("failed to write {}", plan_path.display()))?;
+            Ok(report)
+        }),
+        None => brioche_autopack::autopack(&config),
+    };
+
+    // Choices are saved even if autopacking failed, so they don't need to
+    // be made again
+    if let Some(answers_file) = &args.answers_file {
+        let answers = answers.lock().unwrap_or_else(|error| error.into_inner());
+        answers.write(answers_file)?;
+    }
+    let report = result?;
+
+    if args.trace_resolution {
+        print_resolution_trace(&report);
+    }
+
+    Ok(())
+}
+
+/// Choices between link dependencies providing the same library or
+/// interpreter, by name.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CandidateAnswers {
+    #[serde(default)]
+    libraries: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    interpreters: BTreeMap<String, PathBuf>,
+}
+
+impl CandidateAnswers {
+    fn read(path: &Path) -> eyre::Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("failed to read answers file {}", path.display()))?;
+        let answers = serde_json::from_slice(&contents)
+            .with_context(|| format!("failed to parse answers file {}", path.display()))?;
+        Ok(answers)
+    }
+
+    fn write(&self, path: &Path) -> eyre::Result<()> {
+        let mut contents = serde_json::to_vec_pretty(self)?;
+        contents.push(b'\n');
+        let mut answers_file = brioche_resources::AtomicWriter::new(path, 0o666)
+            .with_context(|| format!("failed to create answers file {}", path.display()))?;
+        answers_file
+            .write_all(&contents)
+            .and_then(|()| answers_file.commit())
+            .with_context(|| format!("failed to write answers file {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Use the recorded choice for a library or interpreter, or make and
+    /// record a new one.
+    fn choose(
+        &mut self,
+        interactive: bool,
+        kind: api::CandidateKind,
+        name: &str,
+        candidates: &[PathBuf],
+    ) -> eyre::Result<Option<PathBuf>> {
+        let choices = match kind {
+            api::CandidateKind::Library => &mut self.libraries,
+            api::CandidateKind::Interpreter => &mut self.interpreters,
+            _ => return Ok(None),
+        };
+        if let Some(choice) = choices.get(name) {
+            return Ok(Some(choice.clone()));
+        }
+
+        let choice = if interactive {
+            prompt_candidate(kind, name, candidates)?
+        } else {
+            candidates.first().ok_or_eyre("no candidates")?.clone()
+        };
+        choices.insert(name.to_string(), choice.clone());
+        Ok(Some(choice))
+    }
+}
+
+fn prompt_candidate(
+    kind: api::CandidateKind,
+    name: &str,
+    candidates: &[PathBuf],
+) -> eyre::Result<PathBuf> {
+    eprintln!("{kind} {name:?} is provided by more than one link dependency:");
+    for (index, candidate) in candidates.iter().enumerate() {
+        eprintln!("  {}) {}", index + 1, candidate.display());
+    }
+
+    loop {
+        eprint!("choose 1-{} [1]: ", candidates.len());
+        let mut answer = String::new();
+        let read = std::io::stdin().read_line(&mut answer)?;
+        eyre::ensure!(read > 0, "no choice made for {kind} {name:?}");
+
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return candidates.first().cloned().ok_or_eyre("no candidates");
+        }
+        match answer.parse::<usize>() {
+            Ok(choice) if (1..=candidates.len()).contains(&choice) => {
+                return Ok(candidates[choice - 1].clone());
+            }
+            _ => eprintln!("invalid choice {answer:?}"),
         }
+    }
+}
+
+fn read_config_template(
+    config_file: Option<&Path>,
+    config: Option<String>,
+) -> eyre::Result<api::AutopackConfigTemplate> {
+    let config_template = match (config_file, config) {
+        (Some(config_file), _) => api::AutopackConfigTemplate::from_file(config_file),
+        (None, Some(config)) => serde_json::from_str(&config).map_err(eyre::Error::from),
+        (None, None) => eyre::bail!("missing --config"),
     };
+    config_template.context("failed to parse config template (pass --schema to show schema)")
+}
 
-    let variables = args
-        .variables
+fn build_config(
+    config_template: api::AutopackConfigTemplate,
+    recipe_path: PathBuf,
+    variables: Vec<AutopackTemplateValue>,
+    resource_dir: Option<PathBuf>,
+) -> eyre::Result<api::AutopackConfig> {
+    let variables = variables
         .into_iter()
         .map(|variable| (variable.name, variable.value))
         .collect();
 
+    let resource_dir = match resource_dir {
+        Some(resource_dir) => resource_dir,
+        None => find_output_resource_dir(config_template.output_root().unwrap_or(&recipe_path))?,
+    };
+    brioche_resources::validate_output_resource_dir(&resource_dir)?;
+
+    let ctx = &api::AutopackConfigTemplateContext {
+        variables,
+        resource_dir,
+    };
+    config_template.build(ctx, recipe_path)
+}
+
+#[derive(Debug, Parser)]
+struct ExplainConfigArgs {
+    recipe_path: PathBuf,
+
+    #[arg(long, required_unless_present = "config_file")]
+    config: Option<String>,
+
+    /// Read the config from a JSON or TOML file instead of `--config`
+    #[arg(long, conflicts_with = "config")]
+    config_file: Option<PathBuf>,
+
+    #[arg(long = "var", value_parser)]
+    variables: Vec<AutopackTemplateValue>,
+
+    /// Use this resource dir instead of the one found from the recipe path
+    #[arg(long)]
+    resource_dir: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = ConfigFormat::Json)]
+    format: ConfigFormat,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ConfigFormat {
+    Json,
+    Toml,
+}
+
+/// The config `autopack` would use, after filling in defaults and merging
+/// in env files, and the paths it resolves to.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigExplanation<'a> {
+    config: &'a api::AutopackConfig,
+    resolved: ResolvedConfigPaths,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResolvedConfigPaths {
+    recipe_path: PathBuf,
+    resource_dir: PathBuf,
+    all_resource_dirs: Vec<PathBuf>,
+
+    /// Link dependencies, in search order.
+    link_dependencies: Vec<PathBuf>,
+    default_libc_dependency: Option<PathBuf>,
+
+    /// Library dirs from the link dependencies' `brioche-env.d` and
+    /// `ld.so.conf` files, in search order.
+    library_search_paths: Vec<PathBuf>,
+
+    /// Library dirs from the default libc dependency, searched last.
+    default_libc_library_search_paths: Vec<PathBuf>,
+
+    /// Dirs searched for script commands, in search order.
+    command_search_paths: Vec<PathBuf>,
+
+    /// Library dirs searched by dynamic binaries before the link
+    /// dependencies.
+    dynamic_binary_library_paths: Vec<PathBuf>,
+
+    /// Library dirs searched by shared libraries before the link
+    /// dependencies.
+    shared_library_library_paths: Vec<PathBuf>,
+}
+
+fn run_explain_config(args: ExplainConfigArgs) -> eyre::Result<()> {
+    let config_template = read_config_template(args.config_file.as_deref(), args.config)?;
+    let config = build_config(
+        config_template,
+        args.recipe_path.clone(),
+        args.variables,
+        args.resource_dir,
+    )?;
+    let resolver = api::LinkDependencyResolver::new(&config)?;
+
+    let resolved = ResolvedConfigPaths {
+        recipe_path: args.recipe_path,
+        resource_dir: config.resource_dir.clone(),
+        all_resource_dirs: config.all_resource_dirs.clone(),
+        link_dependencies: config.link_dependencies.clone(),
+        default_libc_dependency: config.default_libc_dependency.clone(),
+        library_search_paths: resolver.library_paths().to_vec(),
+        default_libc_library_search_paths: resolver.default_libc_library_paths().to_vec(),
+        command_search_paths: resolver.command_paths().to_vec(),
+        dynamic_binary_library_paths: config
+            .dynamic_binary
+            .as_ref()
+            .map(|config| config.dynamic_linking.library_paths.clone())
+            .unwrap_or_default(),
+        shared_library_library_paths: config
+            .shared_library
+            .as_ref()
+            .map(|config| config.dynamic_linking.library_paths.clone())
+            .unwrap_or_default(),
+    };
+    let explanation = ConfigExplanation {
+        config: &config,
+        resolved,
+    };
+
+    match args.format {
+        ConfigFormat::Json => {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &explanation)?;
+            println!();
+        }
+        ConfigFormat::Toml => {
+            // Converting to a value first puts plain values before tables,
+            // which TOML requires. Going through JSON turns enum variants
+            // with values into tables, which TOML can't serialize directly
+            let mut explanation = serde_json::to_value(&explanation)?;
+            remove_nulls(&mut explanation);
+            let explanation = toml::Value::try_from(explanation)?;
+            print!("{}", toml::to_string_pretty(&explanation)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove null values from JSON objects, since TOML has no null. Unset
+/// config values are left out instead.
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            object.retain(|_, value| !value.is_null());
+            object.values_mut().for_each(remove_nulls);
+        }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+/// Print each library in the closures of the autopacked files, such as
+/// `bin/foo: libfoo.so.1 -> libbar.so.2 -> libzstd.so.1` for `libzstd`
+/// pulled in by `libbar`, which is needed by `libfoo`.
+fn print_resolution_trace(report: &api::AutopackReport) {
+    for path_report in &report.paths {
+        let api::PathOutcome::Autopacked { libraries, .. } = &path_report.outcome else {
+            continue;
+        };
+
+        for library in libraries {
+            eprintln!("{}: {}", path_report.path.display(), library.chain());
+        }
+    }
+}
+
+/// Find the resource dir to add resources to when autopacking `root`.
+/// Unless `$BRIOCHE_RESOURCE_DIR` is set, the resource dir should be
+/// within `root`. Finding one further up usually means the recipe was
+/// moved or copied without its resource dir.
+fn find_output_resource_dir(root: &Path) -> eyre::Result<PathBuf> {
+    let root = std::env::current_dir()?.join(root);
+    let expected_resource_dir = root.join("brioche-resources.d");
+
     // HACK: Workaround because finding a resource dir takes a program
     // path rather than a directory path, but then gets the parent path
-    let program = recipe_path.join("program");
+    let program = root.join("program");
+
+    let resource_dir =
+        brioche_resources::find_output_resource_dir(&program).with_context(|| {
+            format!(
+                "no resource dir found for {root:?}, create {expected_resource_dir:?} or pass --resource-dir"
+            )
+        })?;
+
+    let is_from_env = std::env::var_os("BRIOCHE_RESOURCE_DIR").is_some();
+    eyre::ensure!(
+        is_from_env || resource_dir.starts_with(&root),
+        "found resource dir {resource_dir:?} outside of {root:?}, so the recipe may have been moved or copied without its resource dir; create {expected_resource_dir:?}, or pass --resource-dir {resource_dir:?} to use it anyway"
+    );
+
+    Ok(resource_dir)
+}
 
-    let resource_dir = brioche_resources::find_output_resource_dir(&program)?;
+#[derive(Debug, Parser)]
+#[command(group(clap::ArgGroup::new("query").required(true).args(["library", "resource"])))]
+struct QueryArgs {
+    path: PathBuf,
+    #[arg(long)]
+    library: Option<String>,
+    #[arg(long)]
+    resource: Option<String>,
+}
 
-    let ctx = &autopack_template::AutopackConfigTemplateContext {
-        variables,
-        resource_dir,
+fn run_query(args: QueryArgs) -> eyre::Result<()> {
+    let query = match (args.library, args.resource) {
+        (Some(library), _) => api::UsageQuery::Library(library),
+        (_, Some(resource)) => api::UsageQuery::Resource(resource),
+        (None, None) => eyre::bail!("expected --library or --resource"),
     };
-    let config = config_template.build(ctx, recipe_path)?;
 
-    brioche_autopack::autopack(&config)?;
+    let graph = api::DependencyGraph::from_tree(&args.path)?;
+    for dependent in graph.dependents(&query) {
+        println!("{}", dependent.label);
+    }
+
+    Ok(())
+}
+
+/// List the resources referenced by a packed file, with whether each one
+/// exists in the file's resource dirs, its size, and its hash
+#[derive(Debug, Parser)]
+struct ResourcesArgs {
+    program: PathBuf,
+    #[arg(long)]
+    json: bool,
+}
+
+fn run_resources(args: ResourcesArgs) -> eyre::Result<()> {
+    let resources = api::referenced_resources(&args.program)?;
+
+    if args.json {
+        serde_json::to_writer_pretty(std::io::stdout().lock(), &resources)?;
+        println!();
+    } else {
+        for resource in &resources {
+            match &resource.contents {
+                None => {
+                    println!("{}\tmissing", resource.path.display());
+                }
+                Some(api::ResourceContents::File(file)) => {
+                    println!(
+                        "{}\tfound\t{}\t{}",
+                        resource.path.display(),
+                        file.size,
+                        file.hash
+                    );
+                }
+                Some(api::ResourceContents::Directory { size, files }) => {
+                    println!("{}\tfound\t{size}\t-", resource.path.display());
+                    for file in files {
+                        println!("  {}\t\t{}\t{}", file.path.display(), file.size, file.hash);
+                    }
+                }
+                Some(_) => {
+                    println!("{}\tfound", resource.path.display());
+                }
+            }
+        }
+    }
+
+    let missing = resources
+        .iter()
+        .filter(|resource| resource.found_path.is_none())
+        .count();
+    if missing > 0 {
+        eyre::bail!("{missing} referenced resource(s) not found");
+    }
+
+    Ok(())
+}
+
+/// Run each autopacked dynamic binary under `path` with qemu-user, and
+/// check that it starts
+#[cfg(feature = "qemu")]
+#[derive(Debug, Parser)]
+struct QemuCheckArgs {
+    path: PathBuf,
+    /// The qemu-user binary to use, instead of `qemu-<arch>` from `$PATH`
+    #[arg(long)]
+    qemu: Option<PathBuf>,
+    /// Args to pass to each program
+    #[arg(last = true)]
+    args: Vec<std::ffi::OsString>,
+}
+
+#[cfg(feature = "qemu")]
+fn run_qemu_check(args: QemuCheckArgs) -> eyre::Result<()> {
+    let options = api::QemuCheckOptions {
+        qemu: args.qemu,
+        args: args.args,
+    };
+
+    let mut failed = 0;
+    for wrapped in api::find_wrapped(&args.path)? {
+        if !matches!(wrapped.pack, brioche_pack::Pack::LdLinux { .. }) {
+            continue;
+        }
+
+        match api::check_with_qemu(&wrapped.path, &options) {
+            Ok(()) => println!("ok {}", wrapped.path.display()),
+            Err(error) => {
+                println!("failed {}: {error:#}", wrapped.path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        eyre::bail!("{failed} programs failed to start under qemu-user");
+    }
 
     Ok(())
 }
@@ -212,10 +758,10 @@ struct UpdateSourceArgs {
 
 fn run_update_source(args: UpdateSourceArgs) -> eyre::Result<()> {
     let program = std::fs::File::open(&args.program)?;
-    let extracted = brioche_pack::extract_pack(program)?;
+    let extracted = brioche_resources::extract_padded_pack(program)?;
     let output_resource_dir = brioche_resources::find_output_resource_dir(&args.program)?;
 
-    let (new_pack, unpacked_len) = match extracted.pack {
+    let (new_pack, base_path, unpacked_len) = match extracted.pack {
         brioche_pack::Pack::LdLinux {
             program,
             interpreter,
@@ -256,22 +802,18 @@ fn run_update_source(args: UpdateSourceArgs) -> eyre::Result<()> {
                 library_dirs,
                 runtime_library_dirs,
             };
-            (new_pack, Some(extracted.unpacked_len))
+            (new_pack, &args.program, Some(extracted.unpacked_len))
         }
         brioche_pack::Pack::Static { library_dirs } => {
             let pack = brioche_pack::Pack::Static { library_dirs };
 
-            if args.new_source.canonicalize()? != args.program.canonicalize()? {
-                std::fs::copy(&args.new_source, &args.program)?;
-            }
-
-            let program = std::fs::File::open(&args.program)?;
-            let new_source_extracted = brioche_pack::extract_pack(program);
+            let new_source = std::fs::File::open(&args.new_source)?;
+            let new_source_extracted = brioche_resources::extract_padded_pack(new_source);
 
             if let Ok(new_source_extracted) = new_source_extracted {
-                (pack, Some(new_source_extracted.unpacked_len))
+                (pack, &args.new_source, Some(new_source_extracted.unpacked_len))
             } else {
-                (pack, None)
+                (pack, &args.new_source, None)
             }
         }
         brioche_pack::Pack::Metadata { format, .. } => {
@@ -280,15 +822,20 @@ fn run_update_source(args: UpdateSourceArgs) -> eyre::Result<()> {
         }
     };
 
-    let mut program = std::fs::OpenOptions::new()
-        .append(true)
-        .open(&args.program)?;
-    if let Some(unpacked_len) = unpacked_len {
-        program.set_len(unpacked_len.try_into()?)?;
-        program.seek(std::io::SeekFrom::End(0))?;
-    }
+    // The program gets replaced atomically, so it's never left without a
+    // pack if updating fails partway through
+    let base = std::fs::File::open(base_path)?;
+    let base_permissions = base.metadata()?.permissions();
+    let base_len = match unpacked_len {
+        Some(unpacked_len) => unpacked_len.try_into()?,
+        None => base.metadata()?.len(),
+    };
+    let mut program = brioche_resources::AtomicWriter::new(&args.program, 0o666)?;
+    program.file().set_permissions(base_permissions)?;
+    std::io::copy(&mut base.take(base_len), &mut program)?;
 
     brioche_pack::inject_pack(&mut program, &new_pack)?;
+    program.commit()?;
 
     Ok(())
 }
@@ -305,7 +852,7 @@ pub fn without_pack(
     let content_length = contents.seek(std::io::SeekFrom::End(0))?;
     contents.rewind()?;
 
-    if let Ok(extracted) = brioche_pack::extract_pack(&mut contents) {
+    if let Ok(extracted) = brioche_resources::extract_padded_pack(&mut contents) {
         Ok(contents.take(extracted.unpacked_len.try_into()?))
     } else {
         Ok(contents.take(content_length))
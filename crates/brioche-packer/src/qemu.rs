@@ -0,0 +1,140 @@
+//! Checking that autopacked programs for other architectures start, by
+//! running them under qemu-user.
+//!
+//! Programs are run the same way the packed executable runs them: the
+//! interpreter from the pack is run with the pack's library dirs, so a
+//! missing or mismatched library shows up as a failure to start.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice as _;
+use eyre::{Context as _, OptionExt as _};
+
+/// How to run programs with [`check_with_qemu`].
+#[derive(Debug, Clone, Default)]
+pub struct QemuCheckOptions {
+    /// The qemu-user binary to run programs with. By default, this is
+    /// `qemu-<arch>` from `$PATH`, based on the interpreter's architecture.
+    pub qemu: Option<PathBuf>,
+
+    /// Args to pass to each program, such as `--version`. The check fails
+    /// if the program exits with an error.
+    pub args: Vec<OsString>,
+}
+
+/// Run an autopacked dynamic binary under qemu-user, and check that it
+/// exits successfully. Only programs with an `LdLinux` pack can be
+/// checked.
+pub fn check_with_qemu(program_path: &Path, options: &QemuCheckOptions) -> eyre::Result<()> {
+    let extracted = brioche_autopack::read_pack(program_path)?;
+    let brioche_pack::Pack::LdLinux {
+        program,
+        interpreter,
+        library_dirs,
+        runtime_library_dirs,
+    } = extracted.pack
+    else {
+        eyre::bail!(
+            "{} is not an autopacked dynamic binary",
+            program_path.display()
+        );
+    };
+
+    let resource_dirs = brioche_resources::find_resource_dirs(program_path, true)?;
+    let find_resource = |resource: &[u8]| -> eyre::Result<PathBuf> {
+        let resource = resource
+            .to_path()
+            .map_err(|_| eyre::eyre!("invalid resource path {:?}", resource.as_bstr()))?;
+        brioche_resources::find_in_resource_dirs(&resource_dirs, resource)
+            .ok_or_else(|| eyre::eyre!("resource {} not found", resource.display()))
+    };
+
+    let interpreter = find_resource(&interpreter)?;
+    let program = find_resource(&program)?;
+
+    // Runtime library dirs are relative to the program, and are skipped
+    // if they don't exist, like the packed executable does
+    let program_parent_path = program_path
+        .parent()
+        .ok_or_eyre("could not get parent of program path")?;
+    let mut resolved_library_dirs = vec![];
+    for library_dir in &runtime_library_dirs {
+        let library_dir = library_dir
+            .to_path()
+            .map_err(|_| eyre::eyre!("invalid library dir {:?}", library_dir.as_bstr()))?;
+        let library_dir = program_parent_path.join(library_dir);
+        if library_dir.is_dir() {
+            resolved_library_dirs.push(library_dir);
+        }
+    }
+    for library_dir in &library_dirs {
+        resolved_library_dirs.push(find_resource(library_dir)?);
+    }
+
+    let qemu = match &options.qemu {
+        Some(qemu) => qemu.clone(),
+        None => default_qemu(&interpreter)?,
+    };
+
+    let mut command = std::process::Command::new(&qemu);
+    command.arg(&interpreter);
+    if !resolved_library_dirs.is_empty() {
+        command.arg("--library-path");
+        command.arg(std::env::join_paths(&resolved_library_dirs)?);
+    }
+    command.arg("--argv0");
+    command.arg(program_path);
+    command.arg(&program);
+    command.args(&options.args);
+
+    let output = command
+        .output()
+        .with_context(|| format!("failed to run {}", qemu.display()))?;
+    if !output.status.success() {
+        eyre::bail!(
+            "{} failed to start under {} ({}): {}",
+            program_path.display(),
+            qemu.display(),
+            output.status,
+            output.stderr.trim().as_bstr(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Get the qemu-user binary for the interpreter's architecture.
+fn default_qemu(interpreter: &Path) -> eyre::Result<PathBuf> {
+    use goblin::elf::header;
+
+    let contents = std::fs::read(interpreter)
+        .with_context(|| format!("failed to read interpreter {}", interpreter.display()))?;
+    let elf = goblin::elf::Elf::parse(&contents)
+        .with_context(|| format!("failed to parse interpreter {}", interpreter.display()))?;
+
+    let arch = match (elf.header.e_machine, elf.is_64, elf.little_endian) {
+        (header::EM_X86_64, true, _) => "x86_64",
+        (header::EM_386, false, _) => "i386",
+        (header::EM_AARCH64, true, true) => "aarch64",
+        (header::EM_AARCH64, true, false) => "aarch64_be",
+        (header::EM_ARM, false, true) => "arm",
+        (header::EM_ARM, false, false) => "armeb",
+        (header::EM_RISCV, true, _) => "riscv64",
+        (header::EM_RISCV, false, _) => "riscv32",
+        (header::EM_PPC64, true, true) => "ppc64le",
+        (header::EM_PPC64, true, false) => "ppc64",
+        (header::EM_S390, true, _) => "s390x",
+        (machine, _, _) => {
+            eyre::bail!(
+                "no qemu-user binary known for {} interpreter {}, set one explicitly",
+                header::machine_to_str(machine),
+                interpreter.display()
+            );
+        }
+    };
+
+    Ok(PathBuf::from(format!("qemu-{arch}")))
+}
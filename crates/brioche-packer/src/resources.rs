@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+
+use bstr::ByteSlice as _;
+use eyre::Context as _;
+
+/// A resource referenced by a packed file's pack.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferencedResource {
+    /// The resource's path within the resource dir.
+    pub path: PathBuf,
+
+    /// Where the resource was found, or `None` if it isn't in any of the
+    /// packed file's resource dirs.
+    pub found_path: Option<PathBuf>,
+
+    /// The resource's contents, if it was found.
+    pub contents: Option<ResourceContents>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[non_exhaustive]
+pub enum ResourceContents {
+    File(ResourceFile),
+
+    /// A directory, such as a library dir. `size` is the total size of the
+    /// files within it.
+    Directory {
+        size: u64,
+        files: Vec<ResourceFile>,
+    },
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceFile {
+    /// The file's path within the resource, which is empty for a file
+    /// resource.
+    pub path: PathBuf,
+    pub size: u64,
+
+    /// The BLAKE3 hash of the file's contents. This only matches the name
+    /// of the file's blob if the resource dir was written with the default
+    /// content hasher.
+    pub hash: String,
+}
+
+/// List the resources referenced directly by the pack of the file at
+/// `path`, checking each one against the file's resource dirs. Unlike
+/// [`DependencyGraph`](crate::graph::DependencyGraph), packs of referenced
+/// libraries aren't followed.
+pub fn referenced_resources(path: &Path) -> eyre::Result<Vec<ReferencedResource>> {
+    let extracted = brioche_autopack::read_pack(path)?;
+    let all_resource_dirs = brioche_resources::find_resource_dirs(path, true)
+        .with_context(|| format!("failed to find resource dirs for {path:?}"))?;
+
+    let resource_paths: Vec<&[u8]> = match &extracted.pack {
+        brioche_pack::Pack::LdLinux {
+            program,
+            interpreter,
+            library_dirs,
+            runtime_library_dirs: _,
+        } => [interpreter, program]
+            .into_iter()
+            .chain(library_dirs)
+            .map(|path| &path[..])
+            .collect(),
+        brioche_pack::Pack::Static { library_dirs } => {
+            library_dirs.iter().map(|path| &path[..]).collect()
+        }
+        brioche_pack::Pack::Metadata { resource_paths, .. } => {
+            resource_paths.iter().map(|path| &path[..]).collect()
+        }
+    };
+
+    resource_paths
+        .into_iter()
+        .map(|resource_path| {
+            let resource_path = resource_path.to_path().map_err(|_| {
+                eyre::eyre!("invalid resource path: {}", bstr::BStr::new(resource_path))
+            })?;
+            let found_path =
+                brioche_resources::find_in_resource_dirs(&all_resource_dirs, resource_path);
+            let contents = found_path
+                .as_deref()
+                .map(read_resource_contents)
+                .transpose()
+                .with_context(|| format!("failed to read resource {resource_path:?}"))?;
+
+            Ok(ReferencedResource {
+                path: resource_path.to_owned(),
+                found_path,
+                contents,
+            })
+        })
+        .collect()
+}
+
+fn read_resource_contents(path: &Path) -> eyre::Result<ResourceContents> {
+    if !path.is_dir() {
+        let file = read_resource_file(path, PathBuf::new())?;
+        return Ok(ResourceContents::File(file));
+    }
+
+    // Resources in library dirs are symlinks to blobs, so follow them
+    let mut files = vec![];
+    for entry in walkdir::WalkDir::new(path)
+        .follow_links(true)
+        .sort_by_file_name()
+    {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative_path = entry.path().strip_prefix(path)?.to_owned();
+        files.push(read_resource_file(entry.path(), relative_path)?);
+    }
+
+    Ok(ResourceContents::Directory {
+        size: files.iter().map(|file| file.size).sum(),
+        files,
+    })
+}
+
+fn read_resource_file(path: &Path, relative_path: PathBuf) -> eyre::Result<ResourceFile> {
+    let mut file = std::fs::File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+    let mut hasher = blake3::Hasher::new();
+    let size = std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("failed to read {path:?}"))?;
+
+    Ok(ResourceFile {
+        path: relative_path,
+        size,
+        hash: hasher.finalize().to_hex().to_string(),
+    })
+}
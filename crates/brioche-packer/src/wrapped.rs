@@ -0,0 +1,42 @@
+use std::path::{Path, PathBuf};
+
+/// A file with a pack injected into it, or with a sidecar pack.
+#[derive(Debug, Clone)]
+pub struct WrappedFile {
+    pub path: PathBuf,
+    pub pack: brioche_pack::Pack,
+}
+
+/// Find all files with a pack under `root`. `root` can also be a single
+/// file. Resource directories within `root` are skipped, since files in
+/// them are dependencies of other packed files, but `root` itself can be a
+/// resource directory.
+pub fn find_wrapped(root: &Path) -> eyre::Result<Vec<WrappedFile>> {
+    let mut wrapped = vec![];
+
+    let entries = walkdir::WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || entry.file_name() != "brioche-resources.d");
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type().is_file()
+            || brioche_resources::is_sidecar_pack_path(entry.path())
+            || brioche_resources::is_shared_packed_executable_path(entry.path())
+        {
+            continue;
+        }
+
+        // Files with a sidecar pack don't have a pack injected
+        let Ok(extracted) = brioche_autopack::read_pack(entry.path()) else {
+            continue;
+        };
+
+        wrapped.push(WrappedFile {
+            path: entry.into_path(),
+            pack: extracted.pack,
+        });
+    }
+
+    Ok(wrapped)
+}
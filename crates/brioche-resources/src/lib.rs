@@ -1,6 +1,8 @@
 use std::{
-    io::Write as _,
-    os::unix::fs::{OpenOptionsExt as _, PermissionsExt as _},
+    collections::{HashSet, VecDeque},
+    ffi::OsString,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt as _,
     path::{Path, PathBuf},
 };
 
@@ -8,6 +10,14 @@ use bstr::ByteSlice as _;
 
 const SEARCH_DEPTH_LIMIT: u32 = 64;
 
+/// The most trailing padding skipped when looking for a pack. This is
+/// enough for files padded to a multiple of common block sizes.
+const MAX_PACK_PADDING: u64 = 64 * 1024;
+
+/// The default maximum number of symlinks to follow when resolving a
+/// path, matching Linux's `MAXSYMLINKS`.
+pub const DEFAULT_SYMLINK_LIMIT: u32 = 40;
+
 pub fn find_resource_dirs(
     program: &Path,
     include_readonly: bool,
@@ -67,6 +77,267 @@ pub fn find_in_resource_dirs(resource_dirs: &[PathBuf], subpath: &Path) -> Optio
     None
 }
 
+/// Get the path of the sidecar pack for `program`, which is used instead
+/// of a pack injected into `program` itself. This lets autopacked files
+/// share one packed executable through hard links, with each file's pack
+/// stored next to it as `.<name>.brioche-pack`. The sidecar file only
+/// contains an injected pack, so it can be read like any other packed
+/// file.
+pub fn sidecar_pack_path(program: &Path) -> Option<PathBuf> {
+    let file_name = program.file_name()?;
+    let mut sidecar_name = OsString::from(".");
+    sidecar_name.push(file_name);
+    sidecar_name.push(".brioche-pack");
+    Some(program.with_file_name(sidecar_name))
+}
+
+/// Check if `path` is a sidecar pack, as named by [`sidecar_pack_path`].
+pub fn is_sidecar_pack_path(path: &Path) -> bool {
+    path.file_name().is_some_and(|file_name| {
+        let file_name = file_name.as_encoded_bytes();
+        file_name.len() > ".brioche-pack".len() + 1
+            && file_name.starts_with(b".")
+            && file_name.ends_with(b".brioche-pack")
+    })
+}
+
+/// The file name prefix of a packed executable shared by files with a
+/// sidecar pack, which are hard links to it. The prefix is followed by a
+/// hash of the packed executable.
+pub const SHARED_PACKED_EXECUTABLE_PREFIX: &str = ".brioche-packed-";
+
+/// Check if `path` is a shared packed executable, as named with
+/// [`SHARED_PACKED_EXECUTABLE_PREFIX`].
+pub fn is_shared_packed_executable_path(path: &Path) -> bool {
+    path.file_name().is_some_and(|file_name| {
+        file_name
+            .as_encoded_bytes()
+            .starts_with(SHARED_PACKED_EXECUTABLE_PREFIX.as_bytes())
+    })
+}
+
+/// Extract the pack from the packed file at `path`, or from its sidecar
+/// pack if `path` doesn't have a pack injected (see [`sidecar_pack_path`]).
+/// Returns the file the pack was read from along with the pack. If there's
+/// no sidecar pack either, `path` isn't packed and this returns
+/// [`brioche_pack::ExtractPackError::MarkerNotFound`], but any other error
+/// reading the sidecar pack is returned as-is.
+pub fn extract_pack_or_sidecar(
+    path: &Path,
+) -> Result<(std::fs::File, brioche_pack::ExtractedPack), brioche_pack::ExtractPackError> {
+    let mut file = std::fs::File::open(path)?;
+    match extract_padded_pack(&mut file) {
+        Err(brioche_pack::ExtractPackError::MarkerNotFound) => {}
+        result => return Ok((file, result?)),
+    }
+
+    let sidecar_path =
+        sidecar_pack_path(path).ok_or(brioche_pack::ExtractPackError::MarkerNotFound)?;
+    let mut sidecar = match std::fs::File::open(sidecar_path) {
+        Ok(sidecar) => sidecar,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(brioche_pack::ExtractPackError::MarkerNotFound);
+        }
+        Err(error) => return Err(error.into()),
+    };
+    let extracted = extract_padded_pack(&mut sidecar)?;
+    Ok((sidecar, extracted))
+}
+
+/// Extract the pack from `reader`, skipping trailing zero bytes after the
+/// pack. Some tools pad files to a block size (e.g. when writing
+/// filesystem images), which moves the pack's trailer away from the end of
+/// the file. At most [`MAX_PACK_PADDING`] bytes of padding are skipped.
+pub fn extract_padded_pack(
+    mut reader: impl Read + Seek,
+) -> Result<brioche_pack::ExtractedPack, brioche_pack::ExtractPackError> {
+    match brioche_pack::extract_pack(&mut reader) {
+        Err(brioche_pack::ExtractPackError::MarkerNotFound) => {}
+        result => return result,
+    }
+
+    let len = reader.seek(SeekFrom::End(0))?;
+    let window_len = len.min(MAX_PACK_PADDING);
+    reader.seek(SeekFrom::Start(len - window_len))?;
+    let mut window = vec![];
+    (&mut reader).take(window_len).read_to_end(&mut window)?;
+
+    let padding_len = window.iter().rev().take_while(|&&byte| byte == 0).count();
+    if padding_len == 0 || padding_len == window.len() {
+        return Err(brioche_pack::ExtractPackError::MarkerNotFound);
+    }
+
+    brioche_pack::extract_pack(Truncated::new(reader, len - padding_len as u64))
+}
+
+/// Inject `pack` into `writer`, with `attached` packs written before it.
+/// Reading a pack only reads the last one, so attached packs don't change
+/// how the file gets run, and can be read back with
+/// [`extract_attached_packs`].
+pub fn inject_pack_with_attached(
+    mut writer: impl Write,
+    pack: &brioche_pack::Pack,
+    attached: &[brioche_pack::Pack],
+) -> Result<(), InjectMetadataPackError> {
+    for attached_pack in attached.iter().chain([pack]) {
+        brioche_pack::inject_pack(&mut writer, attached_pack)?;
+    }
+
+    Ok(())
+}
+
+/// The packs attached before a file's pack, see
+/// [`extract_attached_packs`].
+#[derive(Debug)]
+pub struct AttachedPacks {
+    /// The attached packs, in the order they were written.
+    pub packs: Vec<brioche_pack::Pack>,
+
+    /// The length of the file before any attached packs, which is the
+    /// length of the unpacked contents.
+    pub unpacked_len: usize,
+}
+
+/// Read the metadata packs attached before the pack of a file, given the
+/// `unpacked_len` of its extracted pack. Attached packs end at the first
+/// thing before them that isn't a metadata pack.
+pub fn extract_attached_packs(
+    mut reader: impl Read + Seek,
+    unpacked_len: usize,
+) -> Result<AttachedPacks, brioche_pack::ExtractPackError> {
+    let mut packs = vec![];
+    let mut unpacked_len = unpacked_len;
+    loop {
+        let truncated = Truncated::new(&mut reader, unpacked_len.try_into()?);
+        match brioche_pack::extract_pack(truncated) {
+            Ok(extracted) if matches!(extracted.pack, brioche_pack::Pack::Metadata { .. }) => {
+                packs.push(extracted.pack);
+                unpacked_len = extracted.unpacked_len;
+            }
+            Ok(_)
+            | Err(
+                brioche_pack::ExtractPackError::MarkerNotFound
+                | brioche_pack::ExtractPackError::MalformedMarker,
+            ) => break,
+            Err(error) => return Err(error),
+        }
+    }
+
+    packs.reverse();
+    Ok(AttachedPacks {
+        packs,
+        unpacked_len,
+    })
+}
+
+/// A reader that ends after `len` bytes, even when seeking from the end.
+struct Truncated<R> {
+    inner: R,
+    len: u64,
+    position: u64,
+}
+
+impl<R: Read + Seek> Truncated<R> {
+    fn new(inner: R, len: u64) -> Self {
+        Self {
+            inner,
+            len,
+            position: 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for Truncated<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.position);
+        let max_len = usize::try_from(remaining)
+            .unwrap_or(usize::MAX)
+            .min(buf.len());
+        self.inner.seek(SeekFrom::Start(self.position))?;
+        let read_len = self.inner.read(&mut buf[..max_len])?;
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl<R: Read + Seek> Seek for Truncated<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let position = position.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek position")
+        })?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// Check that `resource_dir` can be used to add resources to. It must be
+/// a writable directory, and anything already in it must look like the
+/// contents of a resource dir (hidden entries are ignored).
+pub fn validate_output_resource_dir(resource_dir: &Path) -> Result<(), InvalidResourceDirError> {
+    if !resource_dir.is_dir() {
+        return Err(InvalidResourceDirError::NotADirectory {
+            path: resource_dir.to_owned(),
+        });
+    }
+
+    let entries =
+        std::fs::read_dir(resource_dir).map_err(|source| InvalidResourceDirError::IoError {
+            path: resource_dir.to_owned(),
+            source,
+        })?;
+    for entry in entries {
+        let entry = entry.map_err(|source| InvalidResourceDirError::IoError {
+            path: resource_dir.to_owned(),
+            source,
+        })?;
+        let name = entry.file_name();
+        let is_hidden = name.as_encoded_bytes().starts_with(b".");
+        if !is_hidden
+            && !RESOURCE_DIR_ENTRIES
+                .iter()
+                .any(|expected| name == *expected)
+        {
+            return Err(InvalidResourceDirError::UnexpectedEntry {
+                path: resource_dir.to_owned(),
+                entry: entry.path(),
+            });
+        }
+    }
+
+    // Check that the dir is writable by creating a file, since checking
+    // its permissions wouldn't account for read-only mounts
+    let check_path = resource_dir.join(format!(".write-check-{}", ulid::Ulid::new()));
+    let check_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&check_path);
+    match check_file {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&check_path);
+            Ok(())
+        }
+        Err(source) => Err(InvalidResourceDirError::NotWritable {
+            path: resource_dir.to_owned(),
+            source,
+        }),
+    }
+}
+
+/// The entries that can be found at the top level of a resource dir.
+const RESOURCE_DIR_ENTRIES: &[&str] = &[
+    "blobs",
+    "aliases",
+    "directories",
+    "build-ids",
+    "debug-links.json",
+    "namespaces",
+];
+
 fn find_resource_dirs_from_program(
     program: &Path,
     resource_dirs: &mut Vec<PathBuf>,
@@ -105,50 +376,328 @@ fn find_resource_dirs_from_program(
 
 pub fn add_named_blob(
     resource_dir: &Path,
-    mut contents: impl std::io::Seek + std::io::Read,
+    contents: impl std::io::Seek + std::io::Read,
     executable: bool,
     name: &Path,
 ) -> Result<PathBuf, AddBlobError> {
-    let mut hasher = blake3::Hasher::new();
-    std::io::copy(&mut contents, &mut hasher)?;
-    let hash = hasher.finalize();
-
-    let blob_suffix = if executable { ".x" } else { "" };
-    let blob_name = format!("{hash}{blob_suffix}");
+    add_named_blob_with_hasher(
+        &Blake3ContentHasher::default(),
+        resource_dir,
+        contents,
+        executable,
+        name,
+    )
+}
 
+/// Add a blob like [`add_named_blob`], with the blob named by `hasher`
+/// instead of by its BLAKE3 hash.
+pub fn add_named_blob_with_hasher(
+    hasher: &dyn ContentHasher,
+    resource_dir: &Path,
+    mut contents: impl std::io::Seek + std::io::Read,
+    executable: bool,
+    name: &Path,
+) -> Result<PathBuf, AddBlobError> {
+    let hash = hasher.hash(&mut contents)?;
     contents.seek(std::io::SeekFrom::Start(0))?;
+    add_named_blob_with_hash(resource_dir, contents, &hash, executable, name)
+}
 
+/// Add a blob like [`add_named_blob_with_hasher`], with a hash of the
+/// contents that was already computed. `hash` must be the hex-encoded hash
+/// of `contents`, since it names the blob.
+pub fn add_named_blob_with_hash(
+    resource_dir: &Path,
+    mut contents: impl std::io::Read,
+    hash: &str,
+    executable: bool,
+    name: &Path,
+) -> Result<PathBuf, AddBlobError> {
+    let blob_name = blob_name(hash, executable);
+
+    // Blobs and aliases are created relative to a handle to the resource
+    // dir, and each directory below it is opened without following
+    // symlinks, so concurrent changes to the resource dir's parents or
+    // subdirectories can't redirect where they get written
+    let resource_dir_fd = open_dir(resource_dir)?;
     let blob_dir = resource_dir.join("blobs");
     let blob_path = blob_dir.join(&blob_name);
     let blob_temp_id = ulid::Ulid::new();
-    let blob_temp_path = blob_dir.join(format!("{blob_name}-{blob_temp_id}"));
-    std::fs::create_dir_all(&blob_dir)?;
+    let blob_dir_fd = create_dir_all_at(&resource_dir_fd, Path::new("blobs"))?;
 
-    let mut blob_file_options = std::fs::OpenOptions::new();
-    blob_file_options.create_new(true).write(true);
-    if executable {
-        blob_file_options.mode(0o777);
-    }
-    let mut blob_file = blob_file_options.open(&blob_temp_path)?;
-    std::io::copy(&mut contents, &mut blob_file)?;
-    drop(blob_file);
-    std::fs::rename(&blob_temp_path, &blob_path)?;
+    let blob_mode = if executable { 0o777 } else { 0o666 };
+    let mut blob_writer = AtomicWriter::new_at(blob_dir_fd, blob_name.as_ref(), blob_mode)?;
+    std::io::copy(&mut contents, &mut blob_writer)?;
+    blob_writer.commit()?;
 
-    let alias_dir = resource_dir.join("aliases").join(name).join(&blob_name);
-    std::fs::create_dir_all(&alias_dir)?;
+    let alias_dir_relative = Path::new("aliases").join(name).join(&blob_name);
+    let alias_dir = resource_dir.join(&alias_dir_relative);
+    let alias_dir_fd = create_dir_all_at(&resource_dir_fd, &alias_dir_relative)?;
 
-    let temp_alias_path = alias_dir.join(format!("{}-{blob_temp_id}", name.display()));
+    let temp_alias_name = format!("{}-{blob_temp_id}", name.display());
     let alias_path = alias_dir.join(name);
     let blob_pack_relative_path = pathdiff::diff_paths(&blob_path, &alias_dir)
         .expect("blob path is not a prefix of alias path");
-    std::os::unix::fs::symlink(blob_pack_relative_path, &temp_alias_path)?;
-    std::fs::rename(&temp_alias_path, &alias_path)?;
+    rustix::fs::symlinkat(&blob_pack_relative_path, &alias_dir_fd, &temp_alias_name)
+        .map_err(std::io::Error::from)?;
+    rustix::fs::renameat(&alias_dir_fd, &temp_alias_name, &alias_dir_fd, name)
+        .map_err(std::io::Error::from)?;
 
     let alias_path = alias_path
         .strip_prefix(resource_dir)
         .expect("alias path is not in resource dir");
     Ok(alias_path.to_owned())
 }
+
+/// Get the path [`add_named_blob`] would return for a blob with the given
+/// contents, without adding it.
+pub fn named_blob_path(contents: &[u8], executable: bool, name: &Path) -> PathBuf {
+    named_blob_path_with_hasher(&Blake3ContentHasher::default(), contents, executable, name)
+}
+
+/// Get the path [`add_named_blob_with_hasher`] would return for a blob
+/// with the given contents, without adding it.
+pub fn named_blob_path_with_hasher(
+    hasher: &dyn ContentHasher,
+    contents: &[u8],
+    executable: bool,
+    name: &Path,
+) -> PathBuf {
+    let hash = hasher
+        .hash(&mut &contents[..])
+        .expect("failed to hash in-memory contents");
+    named_blob_path_with_hash(&hash, executable, name)
+}
+
+/// Get the path [`add_named_blob_with_hash`] would return for a blob with
+/// the given hash, without adding it.
+pub fn named_blob_path_with_hash(hash: &str, executable: bool, name: &Path) -> PathBuf {
+    let blob_name = blob_name(hash, executable);
+    Path::new("aliases").join(name).join(blob_name).join(name)
+}
+
+fn blob_name(hash: &str, executable: bool) -> String {
+    let blob_suffix = if executable { ".x" } else { "" };
+    format!("{hash}{blob_suffix}")
+}
+
+/// A hash function for naming blobs added to a resource dir. Blobs are
+/// named by the hex-encoded hash of their contents, so any hash function
+/// works, but the same contents get a different blob for each one.
+pub trait ContentHasher: std::fmt::Debug + Send + Sync {
+    /// The name of the hash function, such as `blake3`. Hashers with the
+    /// same name always return the same hash for the same contents.
+    fn name(&self) -> &str;
+
+    /// Hash everything read from `contents`, returning the hash as a
+    /// lowercase hex string.
+    fn hash(&self, contents: &mut dyn std::io::Read) -> std::io::Result<String>;
+}
+
+/// Names blobs by their BLAKE3 hash, which is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3ContentHasher {
+    /// Hash large blobs using multiple threads. This gives the same hashes,
+    /// but is faster for large files on machines with spare cores. Only
+    /// used with the `multithreaded` feature, which pulls in rayon.
+    pub multithreaded: bool,
+}
+
+/// How much to read at once when hashing with multiple threads. BLAKE3
+/// only splits the work within each chunk, so chunks need to be large.
+#[cfg(feature = "multithreaded")]
+const MULTITHREADED_HASH_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+impl ContentHasher for Blake3ContentHasher {
+    fn name(&self) -> &str {
+        "blake3"
+    }
+
+    fn hash(&self, contents: &mut dyn std::io::Read) -> std::io::Result<String> {
+        let mut hasher = blake3::Hasher::new();
+
+        #[cfg(feature = "multithreaded")]
+        if self.multithreaded {
+            let mut buffer = vec![0; MULTITHREADED_HASH_CHUNK_SIZE];
+            loop {
+                let length = read_chunk(contents, &mut buffer)?;
+                if length == 0 {
+                    break;
+                }
+                hasher.update_rayon(&buffer[..length]);
+            }
+
+            return Ok(hasher.finalize().to_hex().to_string());
+        }
+
+        std::io::copy(contents, &mut hasher)?;
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+}
+
+/// Names blobs by their SHA-256 hash, using the CPU's SHA extensions when
+/// available. This can be faster than BLAKE3 on machines with SHA
+/// extensions but few cores.
+#[cfg(feature = "sha256")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256ContentHasher;
+
+#[cfg(feature = "sha256")]
+impl ContentHasher for Sha256ContentHasher {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+
+    fn hash(&self, contents: &mut dyn std::io::Read) -> std::io::Result<String> {
+        let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let length = read_chunk(contents, &mut buffer)?;
+            if length == 0 {
+                break;
+            }
+            context.update(&buffer[..length]);
+        }
+
+        let digest = context.finish();
+        let hash = digest
+            .as_ref()
+            .iter()
+            .fold(String::new(), |mut hash, byte| {
+                use std::fmt::Write as _;
+                let _ = write!(hash, "{byte:02x}");
+                hash
+            });
+        Ok(hash)
+    }
+}
+
+/// Read from `reader` until `buffer` is full or the end is reached,
+/// returning the number of bytes read.
+#[cfg(any(feature = "multithreaded", feature = "sha256"))]
+fn read_chunk(reader: &mut dyn std::io::Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut length = 0;
+    while length < buffer.len() {
+        match reader.read(&mut buffer[length..]) {
+            Ok(0) => break,
+            Ok(read) => length += read,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(length)
+}
+
+/// Add another name for a blob, next to an alias returned by
+/// [`add_named_blob`]. The new name is a symlink to the existing alias, so
+/// a directory holding a library can provide it under each of its names.
+pub fn add_blob_alias(
+    resource_dir: &Path,
+    alias_path: &Path,
+    name: &Path,
+) -> Result<PathBuf, AddBlobError> {
+    let alias_name = alias_path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid alias path {alias_path:?}"),
+        )
+    })?;
+    if name.file_name() != Some(name.as_os_str()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid alias name {name:?}"),
+        )
+        .into());
+    }
+
+    let alias_dir = resource_dir.join(alias_path);
+    let alias_dir = alias_dir.parent().expect("alias path has no parent");
+    let alias_dir_fd = open_dir(alias_dir)?;
+
+    let temp_alias_name = format!("{}-{}", name.display(), ulid::Ulid::new());
+    rustix::fs::symlinkat(alias_name, &alias_dir_fd, &temp_alias_name)
+        .map_err(std::io::Error::from)?;
+    rustix::fs::renameat(&alias_dir_fd, &temp_alias_name, &alias_dir_fd, name)
+        .map_err(std::io::Error::from)?;
+
+    let new_alias_path = alias_path.with_file_name(name);
+    Ok(new_alias_path)
+}
+
+/// Index a blob by its GNU build ID, given an alias returned by
+/// [`add_named_blob`]. The blob is linked from `build-ids/<xx>/<rest>`,
+/// where `<xx>` is the first byte of the build ID in hex, like the
+/// `.build-id` directories used by debuggers.
+pub fn add_build_id(
+    resource_dir: &Path,
+    alias_path: &Path,
+    build_id: &str,
+) -> Result<PathBuf, AddBlobError> {
+    let build_id_path = build_id_path(build_id).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid build ID {build_id:?}"),
+        )
+    })?;
+
+    // Aliases are stored as `aliases/<name>/<blob name>/<name>`
+    let blob_name = alias_path
+        .parent()
+        .and_then(|blob_dir| blob_dir.file_name())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid alias path {alias_path:?}"),
+            )
+        })?;
+    let blob_path = resource_dir.join("blobs").join(blob_name);
+
+    let build_id_dir = resource_dir.join(&build_id_path);
+    let build_id_dir = build_id_dir.parent().expect("build ID path has no parent");
+    let build_id_name = build_id_path
+        .file_name()
+        .expect("build ID path has no filename");
+    std::fs::create_dir_all(build_id_dir)?;
+    let build_id_dir_fd = open_dir(build_id_dir)?;
+
+    let temp_name = format!("{}-{}", build_id_name.display(), ulid::Ulid::new());
+    let blob_relative_path = pathdiff::diff_paths(&blob_path, build_id_dir)
+        .expect("blob path is not a prefix of build ID path");
+    rustix::fs::symlinkat(&blob_relative_path, &build_id_dir_fd, &temp_name)
+        .map_err(std::io::Error::from)?;
+    rustix::fs::renameat(
+        &build_id_dir_fd,
+        &temp_name,
+        &build_id_dir_fd,
+        build_id_name,
+    )
+    .map_err(std::io::Error::from)?;
+
+    Ok(build_id_path)
+}
+
+/// Find a blob indexed by its GNU build ID with [`add_build_id`] in any of
+/// the resource dirs.
+pub fn find_by_build_id(resource_dirs: &[PathBuf], build_id: &str) -> Option<PathBuf> {
+    let build_id_path = build_id_path(build_id)?;
+    find_in_resource_dirs(resource_dirs, &build_id_path)
+}
+
+/// Get the path of a build ID within a resource dir. Returns `None` if the
+/// build ID isn't lowercase hex, or is too short to split.
+fn build_id_path(build_id: &str) -> Option<PathBuf> {
+    let is_valid = build_id.len() > 2
+        && build_id
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte));
+    if !is_valid {
+        return None;
+    }
+
+    let (prefix, rest) = build_id.split_at(2);
+    Some(Path::new("build-ids").join(prefix).join(rest))
+}
+
 pub fn add_named_resource_directory(
     resource_dir: &Path,
     source: &Path,
@@ -180,6 +729,373 @@ pub fn add_named_resource_directory(
     Ok(alias_path.to_owned())
 }
 
+/// Resolve a path to an absolute path with no symlinks, like
+/// [`std::fs::canonicalize`]. Unlike the standard version, symlink loops
+/// are reported with the chain of symlinks that form the loop, and at most
+/// `symlink_limit` symlinks are followed.
+pub fn canonicalize(path: &Path, symlink_limit: u32) -> Result<PathBuf, CanonicalizeError> {
+    let absolute_path = std::env::current_dir()
+        .map_err(|source| CanonicalizeError::IoError {
+            path: path.to_owned(),
+            source,
+        })?
+        .join(path);
+
+    let mut remaining: VecDeque<OsString> = VecDeque::new();
+    push_components(&mut remaining, &absolute_path);
+
+    let mut resolved = PathBuf::from("/");
+    let mut followed = vec![];
+    let mut seen = HashSet::new();
+
+    while let Some(component) = remaining.pop_front() {
+        if component == "." {
+            continue;
+        } else if component == ".." {
+            resolved.pop();
+            continue;
+        }
+
+        let current = resolved.join(&component);
+        let metadata =
+            std::fs::symlink_metadata(&current).map_err(|source| CanonicalizeError::IoError {
+                path: current.clone(),
+                source,
+            })?;
+        if !metadata.is_symlink() {
+            resolved = current;
+            continue;
+        }
+
+        // Reaching the same symlink with the same components left to
+        // resolve means we'd keep going around in circles
+        followed.push(current.clone());
+        if !seen.insert((current.clone(), remaining.len())) {
+            return Err(CanonicalizeError::SymlinkLoop {
+                path: path.to_owned(),
+                chain: followed,
+            });
+        }
+        if followed.len() > symlink_limit as usize {
+            return Err(CanonicalizeError::SymlinkLimitReached {
+                path: path.to_owned(),
+                limit: symlink_limit,
+            });
+        }
+
+        let target = std::fs::read_link(&current).map_err(|source| CanonicalizeError::IoError {
+            path: current.clone(),
+            source,
+        })?;
+        if target.is_absolute() {
+            resolved = PathBuf::from("/");
+        }
+        let mut target_components = VecDeque::new();
+        push_components(&mut target_components, &target);
+        target_components.extend(remaining);
+        remaining = target_components;
+    }
+
+    Ok(resolved)
+}
+
+fn push_components(components: &mut VecDeque<OsString>, path: &Path) {
+    for component in path.components() {
+        match component {
+            std::path::Component::Prefix(_) | std::path::Component::RootDir => {}
+            std::path::Component::CurDir => components.push_back(".".into()),
+            std::path::Component::ParentDir => components.push_back("..".into()),
+            std::path::Component::Normal(name) => components.push_back(name.to_owned()),
+        }
+    }
+}
+
+/// Writes a new file that only appears at its path once it's complete, so
+/// a crash never leaves a partially written file behind. Used for
+/// autopacked outputs and resources.
+///
+/// On Linux, the file is created without a name using `O_TMPFILE`, then
+/// linked into place when it's committed. Elsewhere, or on filesystems
+/// that don't support `O_TMPFILE`, it's written to a hidden temporary file
+/// next to the path, which gets renamed into place. The file is synced
+/// before it's put in place, and the directory after, so the path either
+/// has the old file or the complete new one after a crash. If the writer
+/// is dropped without being committed, the file is discarded.
+#[derive(Debug)]
+pub struct AtomicWriter {
+    file: std::fs::File,
+    dir_fd: rustix::fd::OwnedFd,
+    name: OsString,
+
+    /// The name of the temporary file, if the file wasn't created with
+    /// `O_TMPFILE`.
+    temp_name: Option<OsString>,
+}
+
+impl AtomicWriter {
+    /// Start writing a file at `path`. The file is created with `mode`,
+    /// which is masked by the umask like [`std::fs::File::create`].
+    pub fn new(path: &Path, mode: u32) -> std::io::Result<Self> {
+        let (dir_fd, name) = open_parent_dir(path)?;
+        Self::new_at(dir_fd, name, mode)
+    }
+
+    /// Start writing a file named `name` in the directory `dir_fd`. The
+    /// file is created with `mode`, like [`AtomicWriter::new`].
+    pub fn new_at(
+        dir_fd: rustix::fd::OwnedFd,
+        name: &std::ffi::OsStr,
+        mode: u32,
+    ) -> std::io::Result<Self> {
+        let mode = rustix::fs::Mode::from_raw_mode(mode);
+
+        #[cfg(target_os = "linux")]
+        if Path::new("/proc/self/fd").is_dir() {
+            let tmpfile = rustix::fs::openat(
+                &dir_fd,
+                ".",
+                rustix::fs::OFlags::TMPFILE
+                    | rustix::fs::OFlags::RDWR
+                    | rustix::fs::OFlags::CLOEXEC,
+                mode,
+            );
+            if let Ok(fd) = tmpfile {
+                return Ok(Self {
+                    file: std::fs::File::from(fd),
+                    dir_fd,
+                    name: name.to_owned(),
+                    temp_name: None,
+                });
+            }
+        }
+
+        let temp_name = temp_file_name(name);
+        let fd = rustix::fs::openat(
+            &dir_fd,
+            &temp_name,
+            rustix::fs::OFlags::CREATE
+                | rustix::fs::OFlags::EXCL
+                | rustix::fs::OFlags::RDWR
+                | rustix::fs::OFlags::CLOEXEC,
+            mode,
+        )?;
+        Ok(Self {
+            file: std::fs::File::from(fd),
+            dir_fd,
+            name: name.to_owned(),
+            temp_name: Some(temp_name),
+        })
+    }
+
+    /// Start writing a file to replace the one at `path`, keeping the
+    /// existing file's permissions. If there's no file at `path`, the file
+    /// is created like [`std::fs::File::create`].
+    pub fn replacing(path: &Path) -> std::io::Result<Self> {
+        let permissions = std::fs::metadata(path)
+            .ok()
+            .map(|metadata| metadata.permissions());
+        let writer = Self::new(path, 0o666)?;
+        if let Some(permissions) = permissions {
+            writer.file.set_permissions(permissions)?;
+        }
+
+        Ok(writer)
+    }
+
+    /// The file being written, such as to set its permissions.
+    pub fn file(&self) -> &std::fs::File {
+        &self.file
+    }
+
+    /// Put the file at its path, replacing any existing file.
+    pub fn commit(mut self) -> std::io::Result<()> {
+        self.file.sync_all()?;
+
+        match self.temp_name.take() {
+            Some(temp_name) => {
+                let result =
+                    rustix::fs::renameat(&self.dir_fd, &temp_name, &self.dir_fd, &self.name);
+                if result.is_err() {
+                    let _ = rustix::fs::unlinkat(
+                        &self.dir_fd,
+                        &temp_name,
+                        rustix::fs::AtFlags::empty(),
+                    );
+                }
+                result?;
+            }
+            None => self.link_tmpfile()?,
+        }
+
+        rustix::fs::fsync(&self.dir_fd)?;
+
+        Ok(())
+    }
+
+    /// Replace the file at `path` with a hard link to `original`,
+    /// atomically like a committed [`AtomicWriter`]. The link is made with
+    /// a temporary name, then renamed into place. If `original` can't be
+    /// hard linked, such as on a different filesystem, it's copied with
+    /// its permissions instead.
+    pub fn replace_with_link(original: &Path, path: &Path) -> std::io::Result<()> {
+        let (dir_fd, name) = open_parent_dir(path)?;
+
+        let temp_name = temp_file_name(name);
+        let linked = rustix::fs::linkat(
+            rustix::fs::CWD,
+            original,
+            &dir_fd,
+            &temp_name,
+            rustix::fs::AtFlags::empty(),
+        );
+        if linked.is_err() {
+            let mut original = std::fs::File::open(original)?;
+            let permissions = original.metadata()?.permissions();
+            let mut writer = Self::new_at(dir_fd, name, 0o666)?;
+            std::io::copy(&mut original, &mut writer)?;
+            writer.file.set_permissions(permissions)?;
+            return writer.commit();
+        }
+
+        let result = rustix::fs::renameat(&dir_fd, &temp_name, &dir_fd, name);
+
+        // Renaming does nothing if `path` is already a link to `original`,
+        // so the temporary link has to be removed either way
+        let _ = rustix::fs::unlinkat(&dir_fd, &temp_name, rustix::fs::AtFlags::empty());
+        result?;
+
+        rustix::fs::fsync(&dir_fd)?;
+
+        Ok(())
+    }
+
+    /// Link a file created with `O_TMPFILE` to its path. Linking fails if
+    /// the path already exists, so it's linked to a temporary name and
+    /// renamed over the existing file instead.
+    fn link_tmpfile(&self) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd as _;
+
+        let fd_path = format!("/proc/self/fd/{}", self.file.as_raw_fd());
+        let link = |name: &std::ffi::OsStr| {
+            rustix::fs::linkat(
+                rustix::fs::CWD,
+                &fd_path,
+                &self.dir_fd,
+                name,
+                rustix::fs::AtFlags::SYMLINK_FOLLOW,
+            )
+        };
+        match link(&self.name) {
+            Ok(()) => return Ok(()),
+            Err(rustix::io::Errno::EXIST) => {}
+            Err(error) => return Err(error.into()),
+        }
+
+        let temp_name = temp_file_name(&self.name);
+        link(&temp_name)?;
+        let result = rustix::fs::renameat(&self.dir_fd, &temp_name, &self.dir_fd, &self.name);
+        if result.is_err() {
+            let _ = rustix::fs::unlinkat(&self.dir_fd, &temp_name, rustix::fs::AtFlags::empty());
+        }
+        result?;
+
+        Ok(())
+    }
+}
+
+impl Write for AtomicWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for AtomicWriter {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Drop for AtomicWriter {
+    fn drop(&mut self) {
+        if let Some(temp_name) = &self.temp_name {
+            let _ = rustix::fs::unlinkat(&self.dir_fd, temp_name, rustix::fs::AtFlags::empty());
+        }
+    }
+}
+
+/// Get a hidden temporary name for a file named `name` in the same
+/// directory.
+fn temp_file_name(name: &std::ffi::OsStr) -> OsString {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}", ulid::Ulid::new()));
+    temp_name
+}
+
+/// Open the directory containing `path`, returning it along with the
+/// file name of `path`.
+fn open_parent_dir(path: &Path) -> std::io::Result<(rustix::fd::OwnedFd, &std::ffi::OsStr)> {
+    let name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid path {path:?}"),
+        )
+    })?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir_fd = open_dir(dir)?;
+    Ok((dir_fd, name))
+}
+
+fn open_dir(path: &Path) -> std::io::Result<rustix::fd::OwnedFd> {
+    let fd = rustix::fs::open(
+        path,
+        rustix::fs::OFlags::DIRECTORY | rustix::fs::OFlags::RDONLY | rustix::fs::OFlags::CLOEXEC,
+        rustix::fs::Mode::empty(),
+    )?;
+    Ok(fd)
+}
+
+/// Open the directory `path` relative to `dir_fd`, creating it and any
+/// missing parents. Each component is opened without following symlinks,
+/// so `path` can only resolve to a directory below `dir_fd`.
+fn create_dir_all_at(
+    dir_fd: &rustix::fd::OwnedFd,
+    path: &Path,
+) -> std::io::Result<rustix::fd::OwnedFd> {
+    let mut current = rustix::io::dup(dir_fd)?;
+    for component in path.components() {
+        let std::path::Component::Normal(component) = component else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid resource path {path:?}"),
+            ));
+        };
+
+        match rustix::fs::mkdirat(&current, component, rustix::fs::Mode::from_raw_mode(0o777)) {
+            Ok(()) | Err(rustix::io::Errno::EXIST) => {}
+            Err(error) => return Err(error.into()),
+        }
+        current = rustix::fs::openat(
+            &current,
+            component,
+            rustix::fs::OFlags::DIRECTORY
+                | rustix::fs::OFlags::NOFOLLOW
+                | rustix::fs::OFlags::RDONLY
+                | rustix::fs::OFlags::CLOEXEC,
+            rustix::fs::Mode::empty(),
+        )?;
+    }
+
+    Ok(current)
+}
+
 fn hash_directory(path: &Path) -> Result<blake3::Hash, std::io::Error> {
     let walkdir = walkdir::WalkDir::new(path).sort_by_file_name();
     let mut hasher = blake3::Hasher::new();
@@ -227,6 +1143,59 @@ pub enum PackResourceDirError {
     DepthLimitReached,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidResourceDirError {
+    #[error("resource dir {} is not a directory", path.display())]
+    NotADirectory { path: PathBuf },
+    #[error("resource dir {} is not writable: {source}", path.display())]
+    NotWritable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("resource dir {} contains unexpected entry {}", path.display(), entry.display())]
+    UnexpectedEntry { path: PathBuf, entry: PathBuf },
+    #[error("failed to read resource dir {}: {source}", path.display())]
+    IoError {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CanonicalizeError {
+    #[error("failed to resolve path {}: {source}", path.display())]
+    IoError {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("symlink loop while resolving {}: {}", path.display(), DisplayChain(chain))]
+    SymlinkLoop { path: PathBuf, chain: Vec<PathBuf> },
+    #[error("followed more than {limit} symlinks while resolving {}", path.display())]
+    SymlinkLimitReached { path: PathBuf, limit: u32 },
+}
+
+struct DisplayChain<'a>(&'a [PathBuf]);
+
+impl std::fmt::Display for DisplayChain<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, path) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" -> ")?;
+            }
+            write!(f, "{}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InjectMetadataPackError {
+    #[error(transparent)]
+    PackError(#[from] brioche_pack::InjectPackError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum AddBlobError {
     #[error(transparent)]
@@ -238,3 +1207,101 @@ pub enum AddNamedDirectoryError {
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{
+        extract_pack_or_sidecar, extract_padded_pack, sidecar_pack_path, MAX_PACK_PADDING,
+    };
+
+    /// A file with `contents` and a metadata pack, followed by
+    /// `padding_len` zero bytes.
+    fn padded_file(contents: &[u8], padding_len: usize) -> Vec<u8> {
+        let mut file = contents.to_vec();
+        brioche_pack::inject_pack(
+            &mut file,
+            &brioche_pack::Pack::Metadata {
+                resource_paths: vec![],
+                format: "test".to_string(),
+                metadata: b"metadata".to_vec(),
+            },
+        )
+        .unwrap();
+        file.resize(file.len() + padding_len, 0);
+        file
+    }
+
+    fn assert_extracted(file: &[u8], unpacked_len: usize) {
+        let extracted = extract_padded_pack(Cursor::new(file)).unwrap();
+        assert_eq!(extracted.unpacked_len, unpacked_len);
+        assert!(matches!(
+            extracted.pack,
+            brioche_pack::Pack::Metadata { format, metadata, .. }
+                if format == "test" && metadata == b"metadata"
+        ));
+    }
+
+    #[test]
+    fn test_extract_padded_pack() {
+        assert_extracted(&padded_file(b"program", 0), 7);
+        assert_extracted(&padded_file(b"program", 1), 7);
+        assert_extracted(&padded_file(b"program", 4096), 7);
+        assert_extracted(&padded_file(b"", 512), 0);
+        assert_extracted(&padded_file(b"program", MAX_PACK_PADDING as usize - 1), 7);
+    }
+
+    #[test]
+    fn test_extract_padded_pack_errors() {
+        let all_zero = vec![0; 4096];
+        assert!(matches!(
+            extract_padded_pack(Cursor::new(&all_zero)),
+            Err(brioche_pack::ExtractPackError::MarkerNotFound)
+        ));
+
+        let too_much_padding = padded_file(b"program", MAX_PACK_PADDING as usize);
+        assert!(matches!(
+            extract_padded_pack(Cursor::new(&too_much_padding)),
+            Err(brioche_pack::ExtractPackError::MarkerNotFound)
+        ));
+
+        let no_pack = [&b"program"[..], &[0; 16]].concat();
+        assert!(matches!(
+            extract_padded_pack(Cursor::new(&no_pack)),
+            Err(brioche_pack::ExtractPackError::MarkerNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_extract_pack_or_sidecar() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let packed = dir.path().join("packed");
+        std::fs::write(&packed, padded_file(b"program", 0)).unwrap();
+        let (_, extracted) = extract_pack_or_sidecar(&packed).unwrap();
+        assert_eq!(extracted.unpacked_len, 7);
+
+        let unpacked = dir.path().join("unpacked");
+        std::fs::write(&unpacked, b"program").unwrap();
+        assert!(matches!(
+            extract_pack_or_sidecar(&unpacked),
+            Err(brioche_pack::ExtractPackError::MarkerNotFound)
+        ));
+
+        let shared = dir.path().join("shared");
+        std::fs::write(&shared, b"program").unwrap();
+        std::fs::write(sidecar_pack_path(&shared).unwrap(), padded_file(b"", 0)).unwrap();
+        let (_, extracted) = extract_pack_or_sidecar(&shared).unwrap();
+        assert_eq!(extracted.unpacked_len, 0);
+
+        // A sidecar pack that can't be read isn't the same as not having one
+        let unreadable = dir.path().join("unreadable");
+        std::fs::write(&unreadable, b"program").unwrap();
+        std::fs::create_dir(sidecar_pack_path(&unreadable).unwrap()).unwrap();
+        assert!(matches!(
+            extract_pack_or_sidecar(&unreadable),
+            Err(brioche_pack::ExtractPackError::ReadPackedProgramError(_))
+        ));
+    }
+}
@@ -223,7 +223,7 @@ fn remap_files(args: &mut Vec<StripArg>, remapped_files: &mut Vec<RemapFile>) ->
         // Try to extract a pack from the input path
         let mut input = std::fs::File::open(&input_path)
             .with_context(|| format!("failed to open {}", input_path.display()))?;
-        let extracted = brioche_pack::extract_pack(&mut input);
+        let extracted = brioche_resources::extract_padded_pack(&mut input);
 
         if let Ok(extracted) = extracted {
             // If the input is a packed file, we need to remap it
@@ -306,7 +306,7 @@ fn remap_files(args: &mut Vec<StripArg>, remapped_files: &mut Vec<RemapFile>) ->
                     // Try to extract a pack from the input path
                     let mut input = std::fs::File::open(&path)
                         .with_context(|| format!("failed to open {}", path.display()))?;
-                    let extracted = brioche_pack::extract_pack(&mut input);
+                    let extracted = brioche_resources::extract_padded_pack(&mut input);
 
                     if let Ok(extracted) = extracted {
                         // If the input is a packed file, we need to remap it
@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use alloc::{borrow::Cow, vec::Vec};
 
 pub enum TickEncoded {}
 
@@ -18,7 +18,7 @@ where
 impl<'de, T> serde_with::DeserializeAs<'de, T> for TickEncoded
 where
     T: TryFrom<Vec<u8>>,
-    T::Error: std::fmt::Display,
+    T::Error: core::fmt::Display,
 {
     fn deserialize_as<D>(deserializer: D) -> Result<T, D::Error>
     where
@@ -32,6 +32,7 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> serde_with::schemars_0_8::JsonSchemaAs<T> for TickEncoded {
     fn schema_name() -> String {
         "TickEncoded".to_string()
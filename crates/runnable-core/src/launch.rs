@@ -0,0 +1,180 @@
+//! Resolving a runnable into the command a launcher runs, so every
+//! launcher runs runnables the same way.
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice as _;
+
+use crate::{ArgValue, EnvValue, Runnable, RunnableTemplateError};
+
+/// The env var the dynamic linker searches for libraries.
+#[cfg(target_os = "macos")]
+pub const LIBRARY_PATH_VAR: &str = "DYLD_LIBRARY_PATH";
+#[cfg(not(target_os = "macos"))]
+pub const LIBRARY_PATH_VAR: &str = "LD_LIBRARY_PATH";
+
+/// The value [`LIBRARY_PATH_VAR`] had before a launcher added a runnable's
+/// library dirs to it, so packed programs run by the runnable can restore
+/// it rather than inheriting those dirs.
+pub const ORIGINAL_LIBRARY_PATH_VAR: &str = "BRIOCHE_PACKED_ORIGINAL_LIBRARY_PATH";
+
+/// Env vars by name.
+pub type Env = BTreeMap<OsString, OsString>;
+
+/// The command to run for a runnable.
+#[derive(Debug)]
+pub struct LaunchCommand {
+    pub program: OsString,
+
+    /// The args to pass after `argv[0]`.
+    pub args: Vec<OsString>,
+
+    /// The command's whole environment, which replaces the launcher's.
+    pub env: Env,
+}
+
+/// Undo the library dirs a parent launcher added to the library path, see
+/// [`ORIGINAL_LIBRARY_PATH_VAR`].
+pub fn restore_library_path(env: &mut Env) {
+    let Some(original_value) = env.remove(OsStr::new(ORIGINAL_LIBRARY_PATH_VAR)) else {
+        return;
+    };
+
+    if original_value.is_empty() {
+        env.remove(OsStr::new(LIBRARY_PATH_VAR));
+    } else {
+        env.insert(LIBRARY_PATH_VAR.into(), original_value);
+    }
+}
+
+impl Runnable {
+    /// Resolve the command to run for this runnable. `program_path` is the
+    /// packed executable being run, `args` are the args it was run with
+    /// after `argv[0]`, and `inherited_env` is its environment.
+    pub fn to_launch_command(
+        &self,
+        program_path: &Path,
+        resource_dirs: &[PathBuf],
+        args: impl IntoIterator<Item = OsString>,
+        inherited_env: Env,
+    ) -> Result<LaunchCommand, RunnableTemplateError> {
+        let program = self.command.to_os_string(program_path, resource_dirs)?;
+
+        let mut command_args = vec![];
+        let mut original_args = Some(args);
+        for arg in &self.args {
+            match arg {
+                ArgValue::Arg { value } => {
+                    let value = value.to_os_string(program_path, resource_dirs)?;
+                    command_args.push(value);
+                }
+                ArgValue::Rest => {
+                    let original_args = original_args
+                        .take()
+                        .ok_or(RunnableTemplateError::RepeatedArgs)?;
+                    command_args.extend(original_args);
+                }
+                ArgValue::RestAfterSeparator { separator } => {
+                    let mut original_args = original_args
+                        .take()
+                        .ok_or(RunnableTemplateError::RepeatedArgs)?
+                        .into_iter()
+                        .peekable();
+                    if original_args.peek().is_some() {
+                        command_args.push(separator.to_os_str()?.to_owned());
+                    }
+                    command_args.extend(original_args);
+                }
+            }
+        }
+
+        let mut env = if self.clear_env {
+            Env::new()
+        } else {
+            inherited_env.clone()
+        };
+
+        for (env_name, env_value) in &self.env {
+            let inherited_value = inherited_env.get(OsStr::new(env_name));
+            let current_value = inherited_value.filter(|value| !value.is_empty());
+            match env_value {
+                EnvValue::Clear => {
+                    env.remove(OsStr::new(env_name));
+                }
+                EnvValue::Inherit => {
+                    if let Some(value) = inherited_value {
+                        env.insert(env_name.into(), value.clone());
+                    }
+                }
+                EnvValue::Set { value } => {
+                    let value = value.to_os_string(program_path, resource_dirs)?;
+                    env.insert(env_name.into(), value);
+                }
+                EnvValue::Fallback { value } => {
+                    let value = match current_value {
+                        Some(current_value) => current_value.clone(),
+                        None => value.to_os_string(program_path, resource_dirs)?,
+                    };
+                    env.insert(env_name.into(), value);
+                }
+                EnvValue::Prepend { value, separator } => {
+                    let mut value = value.to_os_string(program_path, resource_dirs)?;
+                    if let Some(current_value) = current_value {
+                        value.push(separator.to_os_str()?);
+                        value.push(current_value);
+                    }
+                    env.insert(env_name.into(), value);
+                }
+                EnvValue::Append { value, separator } => {
+                    let value = value.to_os_string(program_path, resource_dirs)?;
+                    let new_value = match current_value {
+                        Some(current_value) => {
+                            let mut current_value = current_value.clone();
+                            current_value.push(separator.to_os_str()?);
+                            current_value.push(value);
+                            current_value
+                        }
+                        None => value,
+                    };
+                    env.insert(env_name.into(), new_value);
+                }
+            }
+        }
+
+        if !self.library_dirs.is_empty() {
+            let mut library_path = OsString::new();
+            for (n, library_dir) in self.library_dirs.iter().enumerate() {
+                if n > 0 {
+                    library_path.push(":");
+                }
+                library_path.push(library_dir.to_os_string(program_path, resource_dirs)?);
+            }
+
+            // Use the value set by the runnable's env if any, otherwise
+            // the inherited value
+            let current_value = env
+                .remove(OsStr::new(LIBRARY_PATH_VAR))
+                .filter(|value| !value.is_empty());
+            if let Some(current_value) = &current_value {
+                library_path.push(":");
+                library_path.push(current_value);
+            }
+
+            env.insert(LIBRARY_PATH_VAR.into(), library_path);
+            env.insert(
+                ORIGINAL_LIBRARY_PATH_VAR.into(),
+                current_value.unwrap_or_default(),
+            );
+        }
+
+        Ok(LaunchCommand {
+            program,
+            args: command_args,
+            env,
+        })
+    }
+}
@@ -1,21 +1,29 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+#[cfg(feature = "std")]
 use std::path::{Path, PathBuf};
 
+#[cfg(feature = "std")]
 use bstr::{ByteSlice as _, ByteVec as _};
 use encoding::TickEncoded;
 
 pub mod encoding;
+#[cfg(feature = "std")]
+pub mod launch;
 
 pub const FORMAT: &str = "application/vnd.brioche.runnable-v0.1.0+json";
 
+/// The format of metadata packs holding a [`PackerFingerprint`], which get
+/// attached to packs that have no room for one.
+pub const PACKER_FINGERPRINT_FORMAT: &str =
+    "application/vnd.brioche.packer-fingerprint-v0.1.0+json";
+
 #[serde_with::serde_as]
-#[derive(
-    Debug,
-    serde::Serialize,
-    serde::Deserialize,
-    schemars::JsonSchema,
-    bincode::Encode,
-    bincode::Decode,
-)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Runnable {
     pub command: Template,
@@ -29,18 +37,35 @@ pub struct Runnable {
 
     pub clear_env: bool,
 
+    /// Dirs to load the command's dynamic libraries from. The launcher
+    /// prepends these to the dynamic linker's library path env var, and
+    /// records its original value so other packed programs the command
+    /// runs restore it, instead of inheriting these dirs like a var set
+    /// with `env` would be.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub library_dirs: Vec<Template>,
+
     #[serde(default)]
     pub source: Option<RunnableSource>,
+
+    /// The packer that created the runnable. Runnables created before this
+    /// was recorded don't have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packer: Option<PackerFingerprint>,
 }
 
-#[derive(
-    Debug,
-    serde::Serialize,
-    serde::Deserialize,
-    schemars::JsonSchema,
-    bincode::Encode,
-    bincode::Decode,
-)]
+impl Runnable {
+    /// Deserialize a runnable from the metadata of a pack with the
+    /// [`FORMAT`] format. This only needs `alloc`, so launchers read
+    /// runnables with the same code that wrote them.
+    pub fn from_metadata(metadata: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(metadata)
+    }
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum ArgValue {
@@ -49,18 +74,18 @@ pub enum ArgValue {
         value: Template,
     },
     Rest,
+    /// Like `Rest`, but preceded by `separator` (such as `--`) if there
+    /// are any args, so the command doesn't parse them as its own options.
+    #[serde(rename_all = "camelCase")]
+    RestAfterSeparator {
+        #[serde_as(as = "TickEncoded")]
+        separator: Vec<u8>,
+    },
 }
 
 #[serde_with::serde_as]
-#[derive(
-    Debug,
-    Clone,
-    serde::Serialize,
-    serde::Deserialize,
-    schemars::JsonSchema,
-    bincode::Encode,
-    bincode::Decode,
-)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum EnvValue {
@@ -90,15 +115,9 @@ pub enum EnvValue {
 
 #[serde_with::serde_as]
 #[derive(
-    Debug,
-    Clone,
-    Default,
-    serde::Serialize,
-    serde::Deserialize,
-    schemars::JsonSchema,
-    bincode::Encode,
-    bincode::Decode,
+    Debug, Clone, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode,
 )]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Template {
     pub components: Vec<TemplateComponent>,
@@ -115,6 +134,7 @@ impl Template {
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn from_resource_path(resource_path: PathBuf) -> Result<Self, RunnableTemplateError> {
         let resource = Vec::<u8>::from_path_buf(resource_path)
             .map_err(|_| RunnableTemplateError::PathError)?;
@@ -123,6 +143,7 @@ impl Template {
         })
     }
 
+    #[cfg(feature = "std")]
     pub fn to_os_string(
         &self,
         program: &Path,
@@ -154,6 +175,9 @@ impl Template {
                         })?;
                     os_string.push(resource_path);
                 }
+                TemplateComponent::SelfPath => {
+                    os_string.push(program);
+                }
             }
         }
 
@@ -162,15 +186,8 @@ impl Template {
 }
 
 #[serde_with::serde_as]
-#[derive(
-    Debug,
-    Clone,
-    serde::Serialize,
-    serde::Deserialize,
-    schemars::JsonSchema,
-    bincode::Encode,
-    bincode::Decode,
-)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum TemplateComponent {
@@ -189,31 +206,44 @@ pub enum TemplateComponent {
         #[serde_as(as = "TickEncoded")]
         resource: Vec<u8>,
     },
+    /// The absolute path of the packed executable being run, for programs
+    /// that re-execute themselves.
+    SelfPath,
 }
 #[serde_with::serde_as]
-#[derive(
-    Debug,
-    serde::Serialize,
-    serde::Deserialize,
-    schemars::JsonSchema,
-    bincode::Encode,
-    bincode::Decode,
-)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct RunnableSource {
     pub path: RunnablePath,
 }
 
-#[serde_with::serde_as]
+/// Identifies the packer version and config a runnable was created with,
+/// so outputs from an older packer or a different config can be found and
+/// packed again.
 #[derive(
     Debug,
     Clone,
+    PartialEq,
+    Eq,
     serde::Serialize,
     serde::Deserialize,
-    schemars::JsonSchema,
     bincode::Encode,
     bincode::Decode,
 )]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PackerFingerprint {
+    pub version: String,
+
+    /// A hex-encoded hash of the config used to create the runnable. Hashes
+    /// are only comparable between runnables with the same version.
+    pub config_hash: String,
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum RunnablePath {
@@ -230,6 +260,7 @@ pub enum RunnablePath {
 }
 
 impl RunnablePath {
+    #[cfg(feature = "std")]
     pub fn from_resource_path(resource_path: PathBuf) -> Result<Self, RunnableTemplateError> {
         let resource = Vec::<u8>::from_path_buf(resource_path)
             .map_err(|_| RunnableTemplateError::PathError)?;
@@ -237,6 +268,7 @@ impl RunnablePath {
     }
 }
 
+#[cfg(feature = "std")]
 #[derive(Debug, thiserror::Error)]
 pub enum RunnableTemplateError {
     #[error("invalid UTF-8 in runnable template: {0}")]
@@ -251,4 +283,6 @@ pub enum RunnableTemplateError {
     ResourceNotFound { resource: bstr::BString },
     #[error("tried prepending and appending to env var")]
     PrependAndAppend,
+    #[error("tried to pass remaining arguments more than once")]
+    RepeatedArgs,
 }